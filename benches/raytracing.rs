@@ -0,0 +1,206 @@
+// Benchmarks of the hot paths of a render, from vector arithmetic up to
+// whole frames, to catch regressions and measure optimizations:
+//
+//     cargo bench
+//     cargo bench -- bvh
+//
+// Only benchmarks whose names contain one of the given filters run.
+// Each is timed over batches of iterations for a couple of seconds, and
+// the median time per iteration reported with the spread of the batches.
+// Under `cargo test --benches` every benchmark instead runs once, so
+// that they keep building and working.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use raytracing::vec::{Vec3, Point3, Color, dot, cross, unit_vector, reflect};
+use raytracing::ray::Ray;
+use raytracing::hittable::Hittable;
+use raytracing::sphere::Sphere;
+use raytracing::bvh::Bvh;
+use raytracing::instance::Instance;
+use raytracing::motion::RigidTransform;
+use raytracing::material::Lambertian;
+use raytracing::render::{self, Settings, Integrator, Clamp};
+use raytracing::filter::Filter;
+use raytracing::lighttree::LightTree;
+use raytracing::{rng, scene, INF, Float};
+
+// Time spent warming up, then measuring, each benchmark.
+const WARM_UP: Duration = Duration::from_millis(500);
+const MEASURE: Duration = Duration::from_secs(2);
+const BATCHES: usize = 20;
+
+// Rays traced per iteration of the intersection benchmarks.
+const RAYS: usize = 1024;
+
+struct Bencher {
+    timed: bool,
+    filters: Vec<String>,
+}
+
+impl Bencher {
+    fn from_args() -> Self {
+        let mut timed = false;
+        let mut filters = Vec::new();
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                "--bench" => timed = true,
+                arg if arg.starts_with('-') => (),
+                arg => filters.push(arg.to_string()),
+            }
+        }
+        Self { timed, filters }
+    }
+
+    fn bench<T>(&self, name: &str, mut f: impl FnMut() -> T) {
+        if !self.filters.is_empty() && !self.filters.iter().any(|filter| name.contains(filter.as_str())) {
+            return
+        }
+        if !self.timed {
+            black_box(f());
+            println!("{} ... ok", name);
+            return
+        }
+
+        // Enough iterations per batch for the batches to fill the time given.
+        let start = Instant::now();
+        let mut iters = 0u64;
+        while start.elapsed() < WARM_UP {
+            black_box(f());
+            iters += 1;
+        }
+        let per_iter = start.elapsed().as_secs_f64() / iters as f64;
+        let batch = ((MEASURE.as_secs_f64() / BATCHES as f64 / per_iter) as u64).max(1);
+
+        let mut times: Vec<f64> = (0..BATCHES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..batch {
+                    black_box(f());
+                }
+                start.elapsed().as_secs_f64() / batch as f64
+            })
+            .collect();
+        times.sort_by(f64::total_cmp);
+        let (low, median, high) = (times[0], times[BATCHES / 2], times[BATCHES - 1]);
+        println!("{:<24} {:>12} [{} .. {}]", name, time(median), time(low), time(high));
+    }
+}
+
+// Seconds in the units that suit them, e.g. 12.3 µs.
+fn time(secs: f64) -> String {
+    match secs {
+        s if s >= 1.0 => format!("{:.2} s", s),
+        s if s >= 1e-3 => format!("{:.2} ms", s * 1e3),
+        s if s >= 1e-6 => format!("{:.2} µs", s * 1e6),
+        s => format!("{:.2} ns", s * 1e9),
+    }
+}
+
+fn vec3(b: &Bencher) {
+    let vs: Vec<Vec3> = (0..RAYS).map(|_| Vec3::random_bounded(-1.0, 1.0)).collect();
+    b.bench("vec3/dot", || vs.windows(2).map(|w| dot(&w[0], &w[1])).sum::<Float>());
+    b.bench("vec3/cross", || vs.windows(2).fold(Vec3::default(), |acc, w| acc + cross(&w[0], &w[1])));
+    b.bench("vec3/unit_vector", || vs.iter().fold(Vec3::default(), |acc, &v| acc + unit_vector(v)));
+    b.bench("vec3/reflect", || vs.windows(2).fold(Vec3::default(), |acc, w| acc + reflect(&w[0], &w[1])));
+}
+
+// Rays from around the origin in every direction.
+fn rays() -> Vec<Ray> {
+    (0..RAYS)
+        .map(|_| Ray::new(&Point3::random_bounded(-0.1, 0.1), &Vec3::random_bounded(-1.0, 1.0), 0.0))
+        .collect()
+}
+
+fn count_hits<T: Hittable>(world: &T, rays: &[Ray]) -> usize {
+    rays.iter().filter(|r| world.hit(r, 0.001, INF).is_some()).count()
+}
+
+fn sphere(b: &Bencher) {
+    let rays = rays();
+    let gray = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let near = Sphere::new(Point3::new(0.0, 0.0, -2.0), 1.5, gray.clone());
+    let around = Sphere::new(Point3::default(), 3.0, gray);
+    b.bench("sphere/hit-or-miss", || count_hits(&near, &rays));
+    b.bench("sphere/inside", || count_hits(&around, &rays));
+}
+
+fn bvh(b: &Bencher) {
+    let rays = rays();
+    let scene = scene::random_scene(16.0 / 9.0, true);
+    let objects = scene.world.objects;
+    b.bench("bvh/build", || Bvh::new(scene::random_scene(16.0 / 9.0, true).world.objects, 0.0, 1.0));
+    let world = Bvh::new(objects, 0.0, 1.0);
+    b.bench("bvh/random-scene", || count_hits(&world, &rays));
+
+    // A field of copies of one shared sphere around the rays' origin.
+    let gray = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let shared: Arc<dyn Hittable> = Arc::new(Sphere::new(Point3::default(), 0.2, gray));
+    let copies = (0..10000)
+        .map(|i| {
+            let placement = RigidTransform::new(Vec3::new((i % 100) as Float - 50.0, -1.0, (i / 100) as Float - 50.0), Vec3::new(0.0, 45.0, 0.0));
+            Box::new(Instance::new(shared.clone(), placement)) as Box<dyn Hittable>
+        })
+        .collect();
+    let field = Bvh::new(copies, 0.0, 1.0);
+    b.bench("bvh/instances", || count_hits(&field, &rays));
+}
+
+// Every pixel of a small frame of the scene, with a few samples each,
+// on this thread alone so that the times don't depend on the cores.
+fn frame(b: &Bencher, name: &str, width: usize, samples: usize) {
+    let scene = scene::by_name(name, 16.0 / 9.0).expect("no such scene");
+    let world = Bvh::new(scene.world.objects, 0.0, 1.0);
+    let cam = &scene.cameras[0];
+    let settings = Settings {
+        width,
+        height: (width as Float * 9.0 / 16.0) as usize,
+        max_depth: 50,
+        min_throughput: 0.0,
+        background: scene.background,
+        ray_bias: render::auto_ray_bias(&world, 0.0, 1.0),
+        split: 1,
+        irradiance: None,
+        threads: 1,
+        pin_threads: false,
+        checkerboard: false,
+        exposure: 1.0,
+        half_float: false,
+        integrator: Integrator::Path,
+        clamp: Clamp::default(),
+        filter: Filter::Box,
+        lights: Arc::new(LightTree::new(scene.lights)),
+        ambient: None,
+        sun: scene.sun,
+        punctual_lights: scene.punctual_lights,
+        focus: None,
+        seed: 0,
+        frame: 0,
+    };
+    b.bench(&format!("frame/{}", name), || {
+        rng::seed(0);
+        let mut sum = Color::default();
+        for y in 0..settings.height {
+            for x in 0..settings.width {
+                for _ in 0..samples {
+                    let u = (x as Float + 0.5) / settings.width as Float;
+                    let v = (y as Float + 0.5) / settings.height as Float;
+                    sum += render::ray_color(&cam.get_ray(u, v), &world, &settings, settings.max_depth, 1);
+                }
+            }
+        }
+        sum
+    });
+}
+
+fn main() {
+    let b = Bencher::from_args();
+    rng::seed(1);
+    vec3(&b);
+    sphere(&b);
+    bvh(&b);
+    frame(&b, "random", 64, 4);
+    frame(&b, "cornell", 48, 4);
+}
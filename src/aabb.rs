@@ -0,0 +1,101 @@
+use crate::vec::{Point3, Vec3};
+use crate::ray::Ray;
+use crate::{kernels, Float};
+
+// Axis-aligned bounding box.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    // Smallest box containing all of the given points.
+    pub fn from_points(points: &[Point3]) -> Self {
+        let mut min = Point3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = -min;
+        for p in points.iter() {
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Self { min, max }
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> bool {
+        kernels::hit_box(&self.min, &self.max, &r.orig, &r.dir, t_min, t_max)
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb::from_points(&[self.min, self.max, other.min, other.max])
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x > e.y && e.x > e.z {
+            0
+        } else if e.y > e.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn corners(&self) -> [Point3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit() {
+        let bbox = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let toward = Ray::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let away = Ray::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let beside = Ray::new(&Point3::new(2.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+
+        assert!(bbox.hit(&toward, 0.001, Float::INFINITY));
+        assert!(!bbox.hit(&toward, 0.001, 3.0));
+        assert!(!bbox.hit(&away, 0.001, Float::INFINITY));
+        assert!(!bbox.hit(&beside, 0.001, Float::INFINITY));
+    }
+
+    #[test]
+    fn test_surrounding() {
+        let a = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let b = Aabb::new(Point3::new(-1.0, 0.5, 0.5), Point3::new(0.5, 3.0, 0.5));
+        let c = a.surrounding(&b);
+        assert_eq!(Point3::new(-1.0, 0.0, 0.0), c.min);
+        assert_eq!(Point3::new(1.0, 3.0, 1.0), c.max);
+        assert_eq!(1, c.longest_axis());
+    }
+}
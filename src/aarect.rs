@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use crate::{random_float, INF, Float};
+use crate::vec::{Vec3, Point3, dot};
+use crate::hittable::{HitRecord, Hittable, HittableList, PDF_EPSILON};
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::primitive::Primitive;
+
+// Bounds are padded by this much in the direction of the
+// normal, since the slab test rejects boxes of zero thickness.
+const BOX_PADDING: Float = 1e-4;
+
+// The plane a rectangle lies in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl Plane {
+    // Indices of the two in-plane axes and the normal axis.
+    fn axes(&self) -> (usize, usize, usize) {
+        match self {
+            Plane::Xy => (0, 1, 2),
+            Plane::Xz => (0, 2, 1),
+            Plane::Yz => (1, 2, 0),
+        }
+    }
+}
+
+// Axis-aligned rectangle spanning [a0, a1] x [b0, b1] along the
+// in-plane axes, at `k` along the remaining one. Its outward normal
+// points along the positive normal axis, unless flipped. The plane
+// stands in for the book's separate XYRect, XZRect and YZRect.
+#[derive(Clone)]
+pub struct AaRect {
+    pub plane: Plane,
+    pub a0: Float,
+    pub a1: Float,
+    pub b0: Float,
+    pub b1: Float,
+    pub k: Float,
+    pub flipped: bool,
+    pub mat: Arc<dyn Material>,
+}
+
+impl AaRect {
+    pub fn new(plane: Plane, (a0, a1): (Float, Float), (b0, b1): (Float, Float), k: Float, mat: Arc<dyn Material>) -> Self {
+        Self { plane, a0, a1, b0, b1, k, flipped: false, mat }
+    }
+
+    pub fn flipped(mut self) -> Self {
+        self.flipped = !self.flipped;
+        self
+    }
+}
+
+impl Hittable for AaRect {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let (a, b, n) = self.plane.axes();
+        let t = (self.k - r.orig[n]) / r.dir[n];
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+
+        let p = r.at(t);
+        if p[a] < self.a0 || p[a] > self.a1 || p[b] < self.b0 || p[b] > self.b1 {
+            return None
+        }
+
+        let mut outward_normal = Vec3::default();
+        outward_normal[n] = if self.flipped { -1.0 } else { 1.0 };
+        let u = (p[a] - self.a0) / (self.a1 - self.a0);
+        let v = (p[b] - self.b0) / (self.b1 - self.b0);
+        let mut rec = HitRecord{ p, t, u, v, normal: Vec3::default(), front_face: false, mat: self.mat.as_ref() };
+        rec.set_face_normal(r, &outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        let (a, b, n) = self.plane.axes();
+        let mut min = Point3::default();
+        let mut max = Point3::default();
+        min[a] = self.a0;
+        max[a] = self.a1;
+        min[b] = self.b0;
+        max[b] = self.b1;
+        min[n] = self.k - BOX_PADDING;
+        max[n] = self.k + BOX_PADDING;
+        Some(Aabb::new(min, max))
+    }
+
+    fn primitive(&self) -> Option<Primitive> {
+        Some(Primitive::Rect(self.clone()))
+    }
+
+    // Points are sampled uniformly over the area, whose density is
+    // converted to solid angle by the squared distance over the cosine.
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        let rec = match self.hit(&Ray::new(origin, dir, 0.0), PDF_EPSILON, INF) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+        let distance_squared = rec.t * rec.t * dir.length_sqrd();
+        let cosine = dot(dir, &rec.normal).abs() / dir.length();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let (a, b, n) = self.plane.axes();
+        let mut p = Point3::default();
+        p[a] = self.a0 + random_float() * (self.a1 - self.a0);
+        p[b] = self.b0 + random_float() * (self.b1 - self.b0);
+        p[n] = self.k;
+        p - *origin
+    }
+}
+
+// Axis-aligned box made of six rectangles, all facing outwards.
+// Rotated boxes, like those in the Cornell box, are built at the
+// origin and placed with a `motion::Moving` transform.
+pub fn cuboid(min: Point3, max: Point3, mat: Arc<dyn Material>) -> HittableList {
+    let mut sides = HittableList::default();
+    for &plane in [Plane::Xy, Plane::Xz, Plane::Yz].iter() {
+        let (a, b, n) = plane.axes();
+        let side = |k| AaRect::new(plane, (min[a], max[a]), (min[b], max[b]), k, mat.clone());
+        sides.add(Box::new(side(min[n]).flipped()));
+        sides.add(Box::new(side(max[n])));
+    }
+    sides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+    use crate::material::Lambertian;
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn test_hit() {
+        let rect = AaRect::new(Plane::Xz, (0.0, 2.0), (0.0, 1.0), 3.0, gray());
+        let r = Ray::new(&Point3::new(1.0, 0.0, 0.5), &Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = rect.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert_eq!(3.0, rec.t);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), rec.normal);
+        assert!(!rec.front_face);
+
+        let miss = Ray::new(&Point3::new(1.0, 0.0, 1.5), &Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert!(rect.hit(&miss, 0.001, Float::INFINITY).is_none());
+        let parallel = Ray::new(&Point3::new(1.0, 0.0, 0.5), &Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(rect.hit(&parallel, 0.001, Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_hit_range() {
+        let rect = AaRect::new(Plane::Xy, (-1.0, 1.0), (-1.0, 1.0), -2.0, gray());
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(rect.hit(&r, 0.001, 2.0).is_none());
+        assert!(rect.hit(&r, 2.0, 5.0).is_none());
+        let rec = rect.hit(&r, 1.0, 3.0).unwrap();
+        assert_eq!(Point3::new(0.0, 0.0, -2.0), rec.p);
+        assert_eq!((0.5, 0.5), (rec.u, rec.v));
+
+        // Behind the origin is never hit.
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(rect.hit(&r, 0.001, Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let cube = cuboid(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0), gray());
+        assert_eq!(6, cube.objects.len());
+
+        let bbox = cube.bounding_box(0.0, 0.0).unwrap();
+        assert!((bbox.min - Point3::new(0.0, 0.0, 0.0)).length() <= 2.0 * BOX_PADDING);
+        assert!((bbox.max - Point3::new(1.0, 2.0, 3.0)).length() <= 2.0 * BOX_PADDING);
+
+        let r = Ray::new(&Point3::new(0.5, 1.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let rec = cube.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert_eq!(5.0, rec.t);
+        assert!(rec.front_face);
+        assert_eq!(Vec3::new(0.0, 0.0, -1.0), rec.normal);
+
+        // From inside every side is a back face.
+        let r = Ray::new(&Point3::new(0.5, 1.0, 1.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(!cube.hit(&r, 0.001, Float::INFINITY).unwrap().front_face);
+    }
+
+    #[test]
+    fn test_placed_cuboid() {
+        use crate::motion::{Moving, RigidTransform};
+
+        // A unit cube turned 45 degrees about y, standing at x = 10.
+        let cube = cuboid(Point3::new(-0.5, 0.0, -0.5), Point3::new(0.5, 1.0, 0.5), gray());
+        let placement = RigidTransform::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 45.0, 0.0));
+        let placed = Moving::new(Box::new(cube), placement, placement, 0.0, 1.0);
+
+        // Straight on it is hit on its front edge, half a diagonal away from its center.
+        let r = Ray::new(&Point3::new(10.0, 0.5, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = placed.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert!((rec.t - (5.0 - (0.5 as Float).sqrt())).abs() < 1e-4);
+        assert!(rec.front_face);
+
+        let bbox = placed.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.max.x - (10.0 + (0.5 as Float).sqrt())).abs() < 1e-3);
+        assert!((bbox.max.y - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,35 @@
+// Pinning worker threads to cores keeps them from migrating, and
+// with fewer threads than cores leaves the remaining ones free for
+// other work. Only supported on Linux; elsewhere it does nothing.
+
+// Pins the calling thread to the `index`-th core the process is
+// allowed to run on, wrapping around when there are fewer cores.
+// Returns whether the thread was pinned.
+#[cfg(target_os = "linux")]
+pub fn pin_to_core(index: usize) -> bool {
+    use std::mem;
+
+    // Safety: cpu_set_t is a plain bit mask, and both calls are
+    // given its exact size and only touch the current thread.
+    unsafe {
+        let mut allowed: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut allowed) != 0 {
+            return false
+        }
+        let cores: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &allowed))
+            .collect();
+        if cores.is_empty() {
+            return false
+        }
+
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cores[index % cores.len()], &mut set);
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_core(_index: usize) -> bool {
+    false
+}
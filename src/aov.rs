@@ -0,0 +1,88 @@
+use crate::vec::Color;
+use crate::image::{Accumulator, Image};
+
+// Arbitrary output variables, images of the first surface every camera
+// ray hits rendered alongside the light, as denoisers take them for
+// guidance. They are averaged over the samples of a pixel like the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    // The surface normal in world space, facing the ray, with its
+    // coordinates as red, green and blue, so negative ones need a
+    // float format to survive.
+    Normal,
+    // Distance along the ray, the same in every channel, and 0 where
+    // the ray escapes.
+    Depth,
+    // The color of diffuse surfaces, white for the others, and the
+    // background where the ray escapes.
+    Albedo,
+}
+
+impl Aov {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(Aov::Normal),
+            "depth" => Some(Aov::Depth),
+            "albedo" => Some(Aov::Albedo),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+        }
+    }
+}
+
+// The images of a list of AOVs being rendered, each accumulated on
+// its own and in the same precision as the light.
+pub struct AovImages {
+    aovs: Vec<Aov>,
+    layers: Vec<Accumulator>,
+}
+
+impl AovImages {
+    pub fn new(aovs: &[Aov], width: usize, height: usize, half_float: bool) -> Self {
+        let layer = || if half_float { Accumulator::half_float(width, height) } else { Accumulator::new(width, height) };
+        Self { aovs: aovs.to_vec(), layers: aovs.iter().map(|_| layer()).collect() }
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    // Adds a sample of every AOV, given in the order of the list.
+    pub fn add(&mut self, x: usize, y: usize, values: &[Color]) {
+        for (layer, &value) in self.layers.iter_mut().zip(values) {
+            layer.add(x, y, value);
+        }
+    }
+
+    pub fn resolve(&self) -> Vec<(Aov, Image)> {
+        self.aovs.iter().copied().zip(self.layers.iter().map(|layer| layer.resolve())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aov_images() {
+        let aovs = [Aov::Depth, Aov::Normal];
+        assert_eq!(Some(Aov::Albedo), Aov::parse(Aov::Albedo.name()));
+        assert_eq!(None, Aov::parse("position"));
+
+        let mut images = AovImages::new(&aovs, 2, 1, false);
+        images.add(1, 0, &[Color::new(2.0, 2.0, 2.0), Color::new(0.0, 1.0, 0.0)]);
+        images.add(1, 0, &[Color::new(4.0, 4.0, 4.0), Color::new(0.0, 0.0, 1.0)]);
+        let resolved = images.resolve();
+        assert_eq!(Aov::Depth, resolved[0].0);
+        assert_eq!(Color::new(3.0, 3.0, 3.0), resolved[0].1.pixels()[1]);
+        assert_eq!(Color::new(0.0, 0.5, 0.5), resolved[1].1.pixels()[1]);
+        assert_eq!(Color::default(), resolved[1].1.pixels()[0]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use crate::Float;
+use crate::vec::{Vec3, Point3, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::texture::Texture;
+use crate::aabb::Aabb;
+
+// Width of the lines of a grid, as a fraction of their spacing.
+const LINE_WIDTH: Float = 0.04;
+
+// An endless floor at y = 0 facing up, for product-style renders of a
+// single object. Having no bounds, it is kept out of the hierarchy and
+// tested against every ray. Texture coordinates repeat every unit
+// along x and z.
+pub struct Backdrop {
+    pub mat: Arc<dyn Material>,
+}
+
+impl Backdrop {
+    pub fn new(mat: Arc<dyn Material>) -> Self {
+        Self { mat }
+    }
+}
+
+impl Hittable for Backdrop {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let t = -r.orig.y / r.dir.y;
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+
+        let p = r.at(t);
+        let (u, v) = (p.x - p.x.floor(), p.z - p.z.floor());
+        let mut rec = HitRecord{ p, t, u, v, normal: Vec3::default(), front_face: false, mat: self.mat.as_ref() };
+        rec.set_face_normal(r, &Vec3::new(0.0, 1.0, 0.0));
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        None
+    }
+}
+
+// What a backdrop's floor is patterned with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Plain,
+    // Thin lines along x and z.
+    Grid,
+    Checker,
+}
+
+impl Pattern {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Pattern::Plain),
+            "grid" => Some(Pattern::Grid),
+            "checker" => Some(Pattern::Checker),
+            _ => None,
+        }
+    }
+}
+
+// A floor of one color patterned in another, `spacing` apart, around
+// the origin. The pattern fades out from half of the `fade` distance
+// to all of it, before it would shimmer into moiré towards the horizon,
+// and the floor may then blend into a `horizon` color the same way
+// over its own distance, e.g. that of the background behind it.
+pub struct BackdropTexture {
+    pub pattern: Pattern,
+    pub color: Color,
+    pub lines: Color,
+    pub spacing: Float,
+    pub fade: Float,
+    pub horizon: Option<(Color, Float)>,
+}
+
+impl Default for BackdropTexture {
+    fn default() -> Self {
+        Self {
+            pattern: Pattern::Grid,
+            color: Color::new(0.7, 0.7, 0.7),
+            lines: Color::new(0.4, 0.4, 0.4),
+            spacing: 1.0,
+            fade: 20.0,
+            horizon: None,
+        }
+    }
+}
+
+// From 0 at half the distance to 1 at all of it, eased at both ends.
+fn fade_in(r: Float, distance: Float) -> Float {
+    let t = ((2.0 * r / distance) - 1.0).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Texture for BackdropTexture {
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        let (x, z) = (p.x / self.spacing, p.z / self.spacing);
+        let marked = match self.pattern {
+            Pattern::Plain => false,
+            Pattern::Grid => {
+                let near_line = |c: Float| (c - c.round()).abs() < 0.5 * LINE_WIDTH;
+                near_line(x) || near_line(z)
+            }
+            Pattern::Checker => (x.floor() as i64 + z.floor() as i64).rem_euclid(2) == 1,
+        };
+
+        let r = (p.x * p.x + p.z * p.z).sqrt();
+        let mut color = self.color;
+        if marked {
+            let f = fade_in(r, self.fade);
+            color = (1.0 - f) * self.lines + f * self.color;
+        }
+        match self.horizon {
+            Some((horizon, distance)) => {
+                let f = fade_in(r, distance);
+                (1.0 - f) * color + f * horizon
+            }
+            None => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn test_hit() {
+        let floor = Backdrop::new(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let down = Ray::new(&Point3::new(2.5, 2.0, -1.25), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = floor.hit(&down, 0.001, Float::INFINITY).unwrap();
+        assert_eq!((2.0, 0.5, 0.75), (rec.t, rec.u, rec.v));
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), rec.normal);
+
+        // Far away towards the horizon, but never parallel or above it.
+        let grazing = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, -1e-4, 0.0), 0.0);
+        assert!(floor.hit(&grazing, 0.001, Float::INFINITY).unwrap().p.x > 1e3);
+        let level = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(floor.hit(&level, 0.001, Float::INFINITY).is_none());
+        assert!(floor.bounding_box(0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_texture() {
+        let horizon = Color::new(0.0, 0.0, 1.0);
+        let tex = BackdropTexture { horizon: Some((horizon, 100.0)), ..Default::default() };
+        let at = |x: Float, z: Float| tex.value(0.0, 0.0, &Point3::new(x, 0.0, z));
+        assert_eq!(tex.lines, at(1.0, 0.5));
+        assert_eq!(tex.color, at(0.5, 0.5));
+        // Lines fade out between 10 and 20 away, and the floor into the horizon from 50.
+        assert_eq!(tex.color, at(30.0, 0.5));
+        assert_eq!(tex.color, at(30.5, 0.5));
+        assert_eq!(horizon, at(0.0, 100.0));
+
+        let checker = BackdropTexture { pattern: Pattern::Checker, ..Default::default() };
+        let at = |x: Float, z: Float| checker.value(0.0, 0.0, &Point3::new(x, 0.0, z));
+        assert_eq!(checker.color, at(0.5, 0.5));
+        assert_eq!(checker.lines, at(-0.5, 0.5));
+    }
+}
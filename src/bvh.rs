@@ -0,0 +1,318 @@
+use crate::Float;
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::primitive::Primitive;
+
+// Leaves hold at most this many objects.
+const MAX_LEAF_SIZE: usize = 4;
+
+// Cost of visiting a node relative to intersecting an object,
+// for estimating the quality of a tree.
+const TRAVERSAL_COST: Float = 0.5;
+
+// A refitted tree is rebuilt from scratch once its estimated
+// cost has grown by this factor over that of its last build.
+const REBUILD_THRESHOLD: Float = 1.3;
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Leaf { bbox: Aabb, start: usize, count: usize },
+    Interior { bbox: Aabb, left: usize, right: usize },
+}
+
+impl Node {
+    fn bbox(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bbox, .. } | Node::Interior { bbox, .. } => bbox,
+        }
+    }
+}
+
+// Bounding volume hierarchy over a set of objects. Nodes are stored
+// flat in a single vector and every leaf refers to a contiguous run
+// of objects, kept as primitives. Boxes are built over the given time
+// interval, so moving objects are bounded by the whole volume they
+// sweep through.
+pub struct Bvh {
+    objects: Vec<Primitive>,
+    // Objects without finite bounds, tested against every ray.
+    unbounded: Vec<Primitive>,
+    nodes: Vec<Node>,
+    // Estimated cost of the tree when it was last built.
+    build_cost: Float,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Hittable>>, time0: Float, time1: Float) -> Self {
+        Self::from_primitives(objects.into_iter().map(Primitive::new).collect(), time0, time1)
+    }
+
+    fn from_primitives(objects: Vec<Primitive>, time0: Float, time1: Float) -> Self {
+        let mut bounded = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for obj in objects.into_iter() {
+            match obj.bounding_box(time0, time1) {
+                Some(bbox) => bounded.push((bbox, obj)),
+                None => unbounded.push(obj),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        if !bounded.is_empty() {
+            build(&mut bounded, 0, &mut nodes);
+        }
+        let objects = bounded.into_iter().map(|(_, obj)| obj).collect();
+
+        let mut bvh = Self { objects, unbounded, nodes, build_cost: 0.0 };
+        bvh.build_cost = bvh.cost();
+        bvh
+    }
+
+    // Moves the hierarchy to a new time interval, e.g. the next frame
+    // of an animation. The existing tree is kept and only its boxes are
+    // recomputed, which is much cheaper than building it again. As
+    // objects move the tree gets worse, since objects that are grouped
+    // together may drift apart, so it is rebuilt once its estimated
+    // cost degrades too far. Returns whether it was rebuilt.
+    pub fn update(&mut self, time0: Float, time1: Float) -> bool {
+        if self.refit(time0, time1) && self.cost() <= REBUILD_THRESHOLD * self.build_cost {
+            return false
+        }
+
+        let mut objects = std::mem::take(&mut self.objects);
+        objects.append(&mut self.unbounded);
+        *self = Bvh::from_primitives(objects, time0, time1);
+        true
+    }
+
+    // Recomputes every box bottom up; children are always stored after
+    // their parents. Fails if an object no longer has bounds.
+    fn refit(&mut self, time0: Float, time1: Float) -> bool {
+        for idx in (0..self.nodes.len()).rev() {
+            let bbox = match self.nodes[idx] {
+                Node::Leaf { start, count, .. } => {
+                    let mut boxes = self.objects[start..start + count].iter()
+                        .map(|obj| obj.bounding_box(time0, time1));
+                    let first = match boxes.next().flatten() {
+                        Some(b) => b,
+                        None => return false,
+                    };
+                    match boxes.try_fold(first, |acc, b| Some(acc.surrounding(&b?))) {
+                        Some(b) => b,
+                        None => return false,
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    self.nodes[left].bbox().surrounding(self.nodes[right].bbox())
+                }
+            };
+            match &mut self.nodes[idx] {
+                Node::Leaf { bbox: b, .. } | Node::Interior { bbox: b, .. } => *b = bbox,
+            }
+        }
+        true
+    }
+
+    // Surface area heuristic: the chance of a ray through the root
+    // hitting a node is the ratio of their surface areas, so this is
+    // the expected cost of tracing a ray through the tree.
+    fn cost(&self) -> Float {
+        let root = match self.nodes.first() {
+            Some(node) if node.bbox().surface_area() > 0.0 => node.bbox().surface_area(),
+            _ => return 0.0,
+        };
+        self.nodes.iter()
+            .map(|node| {
+                let p = node.bbox().surface_area() / root;
+                match node {
+                    Node::Leaf { count, .. } => p * *count as Float,
+                    Node::Interior { .. } => p * TRAVERSAL_COST,
+                }
+            })
+            .sum()
+    }
+}
+
+// Approximate memory of a hierarchy over the given objects,
+// including the objects themselves. Nested objects like lists
+// only count their top level.
+pub fn memory_estimate(objects: &[Box<dyn Hittable>]) -> usize {
+    let nodes = 2 * objects.len() * std::mem::size_of::<Node>();
+    let build = objects.len() * std::mem::size_of::<(Aabb, Primitive)>();
+    // Primitives are kept by value, the rest boxed.
+    let objects: usize = objects.iter().map(|o| std::mem::size_of::<Primitive>().max(std::mem::size_of_val(o.as_ref()))).sum();
+    nodes + build + objects
+}
+
+// Recursively splits the objects at the median centroid along the
+// longest axis of their centroids' bounds. Objects are reordered in
+// place so that each leaf covers a contiguous range. Returns the
+// index of the created node.
+fn build(items: &mut [(Aabb, Primitive)], offset: usize, nodes: &mut Vec<Node>) -> usize {
+    let bbox = items.iter()
+        .skip(1)
+        .fold(items[0].0, |acc, (b, _)| acc.surrounding(b));
+
+    let idx = nodes.len();
+    if items.len() <= MAX_LEAF_SIZE {
+        nodes.push(Node::Leaf { bbox, start: offset, count: items.len() });
+        return idx
+    }
+
+    let centroids: Vec<_> = items.iter().map(|(b, _)| b.centroid()).collect();
+    let axis = Aabb::from_points(&centroids).longest_axis();
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+        a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    // Reserve this node's slot before its children are pushed.
+    nodes.push(Node::Leaf { bbox, start: 0, count: 0 });
+    let (lo, hi) = items.split_at_mut(mid);
+    let left = build(lo, offset, nodes);
+    let right = build(hi, offset + mid, nodes);
+    nodes[idx] = Node::Interior { bbox, left, right };
+    idx
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+
+        for obj in self.unbounded.iter() {
+            if let Some(rec) = obj.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some(rec);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest
+        }
+
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !node.bbox().hit(r, t_min, closest_so_far) {
+                continue
+            }
+
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for obj in self.objects[start..start + count].iter() {
+                        if let Some(rec) = obj.hit(r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None
+        }
+        self.nodes.first().map(|n| *n.bbox())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::vec::{Vec3, Point3, Color};
+    use crate::sphere::Sphere;
+    use crate::hittable::HittableList;
+    use crate::material::Lambertian;
+    use crate::motion::{Moving, RigidTransform};
+
+    fn spheres() -> Vec<Box<dyn Hittable>> {
+        let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+        for i in 0..20 {
+            let center = Point3::new(i as Float * 1.5 - 15.0, (i % 3) as Float, (i % 5) as Float);
+            let mat = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+            objects.push(Box::new(Sphere::new(center, 0.5, Arc::new(mat))));
+        }
+        objects
+    }
+
+    #[test]
+    fn test_matches_list() {
+        let bvh = Bvh::new(spheres(), 0.0, 1.0);
+        let list = HittableList { objects: spheres() };
+
+        for i in 0..50 {
+            let dir = Vec3::new(i as Float * 0.6 - 15.0, 0.5, 2.0);
+            let r = Ray::new(&Point3::new(0.0, 1.0, -10.0), &dir, 0.0);
+            let a = bvh.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            let b = list.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            assert_eq!(b, a);
+        }
+    }
+
+    // Spheres sliding along x between times 0 and 1, each by its own
+    // distance so that their order along the axis changes over time.
+    fn sliding_spheres() -> Vec<Box<dyn Hittable>> {
+        spheres().into_iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let start = RigidTransform::new(Vec3::default(), Vec3::default());
+                let end = RigidTransform::new(Vec3::new((i % 4) as Float * 10.0, 0.0, 0.0), Vec3::default());
+                Box::new(Moving::new(s, start, end, 0.0, 1.0)) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_update() {
+        let mut bvh = Bvh::new(sliding_spheres(), 0.0, 0.01);
+        let list = HittableList { objects: sliding_spheres() };
+
+        // A small step keeps the tree and only refits its boxes.
+        assert!(!bvh.update(0.01, 0.02));
+        assert_eq!(list.bounding_box(0.01, 0.02), bvh.bounding_box(0.01, 0.02));
+
+        // A large one shuffles the spheres enough to force a rebuild.
+        assert!(bvh.update(0.99, 1.0));
+        for i in 0..50 {
+            let dir = Vec3::new(i as Float * 1.2 - 15.0, 0.5, 2.0);
+            let r = Ray::new(&Point3::new(10.0, 1.0, -10.0), &dir, 0.995);
+            let a = bvh.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            let b = list.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            assert_eq!(b, a);
+        }
+    }
+
+    #[test]
+    fn test_refit_matches_list() {
+        let mut bvh = Bvh::new(sliding_spheres(), 0.0, 0.0);
+        let list = HittableList { objects: sliding_spheres() };
+        assert!(bvh.refit(0.5, 0.5));
+
+        for i in 0..50 {
+            let dir = Vec3::new(i as Float * 1.2 - 15.0, 0.5, 2.0);
+            let r = Ray::new(&Point3::new(10.0, 1.0, -10.0), &dir, 0.5);
+            let a = bvh.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            let b = list.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t);
+            assert_eq!(b, a);
+        }
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let bvh = Bvh::new(spheres(), 0.0, 1.0);
+        let bbox = bvh.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(Point3::new(-15.5, -0.5, -0.5), bbox.min);
+        assert_eq!(Point3::new(14.0, 2.5, 4.5), bbox.max);
+    }
+}
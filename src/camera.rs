@@ -1,7 +1,110 @@
-use crate::vec::{Vec3, Point3, unit_vector, cross, random_in_unit_disk};
+use crate::vec::{Vec3, Point3, cross, dot, random_in_unit_disk};
+use crate::mat::Mat4;
 use crate::ray::Ray;
-use crate::degrees_to_radians;
+use crate::hittable::Hittable;
+use crate::clip::{Clip, Clipped};
+use crate::{degrees_to_radians, random_float, PI, Float};
 
+// Default field of view of fisheye projections, in degrees.
+pub const FISHEYE_FOV: Float = 180.0;
+
+// How the directions around a camera are laid out on the image.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // Straight lines stay straight, as through a thin lens.
+    #[default]
+    Perspective,
+    // An equidistant fisheye, where the angle away from the view grows
+    // with the distance from the image center, reaching half the field of
+    // view in degrees at the left and right edges. It may exceed 180.
+    Fisheye(Float),
+    // Every direction, 360 degrees around the up direction across the
+    // width and from straight down to straight up across the height,
+    // with the view in the center, as panoramas and environment maps
+    // are laid out.
+    Equirectangular,
+}
+
+impl Projection {
+    // Projections are named `perspective`, `fisheye` with an optional
+    // field of view, e.g. `fisheye:220`, or `equirectangular`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next()?, parts.next()) {
+            ("perspective", None) => Some(Projection::Perspective),
+            ("equirectangular", None) => Some(Projection::Equirectangular),
+            ("fisheye", None) => Some(Projection::Fisheye(FISHEYE_FOV)),
+            ("fisheye", Some(fov)) => fov.parse().ok().filter(|&fov| fov > 0.0 && fov <= 360.0).map(Projection::Fisheye),
+            _ => None,
+        }
+    }
+
+    // The name `parse` takes back.
+    pub fn name(&self) -> String {
+        match *self {
+            Projection::Perspective => "perspective".to_string(),
+            Projection::Fisheye(fov) => format!("fisheye:{}", fov),
+            Projection::Equirectangular => "equirectangular".to_string(),
+        }
+    }
+}
+
+// How far rotation matrices may be from orthonormal, as exported by
+// other tools in single precision.
+const MATRIX_TOLERANCE: Float = 1e-4;
+
+// Where a camera with the given camera-to-world matrix is, a point one
+// unit ahead of it and its up direction. Cameras look down their -z axis
+// with their y axis up, as in Blender and OpenGL, so the matrix columns
+// are the camera's right, up and backward directions and its position.
+// Matrices may only rotate and translate.
+pub fn look_from_matrix(m: &Mat4) -> Result<(Point3, Point3, Vec3), String> {
+    let (x, y, z, origin) = (m.column(0), m.column(1), m.column(2), m.column(3));
+    let unit = |a: &Vec3| (a.length() - 1.0).abs() < MATRIX_TOLERANCE;
+    let orthogonal = |a: &Vec3, b: &Vec3| dot(a, b).abs() < MATRIX_TOLERANCE;
+    let rigid = m[3] == [0.0, 0.0, 0.0, 1.0]
+        && unit(&x) && unit(&y) && unit(&z)
+        && orthogonal(&x, &y) && orthogonal(&y, &z) && orthogonal(&z, &x)
+        && dot(&cross(&x, &y), &z) > 0.0;
+    if !rigid {
+        return Err("expected a rotation and translation, without scale, shear or mirroring".to_string())
+    }
+    Ok((origin, origin - z, y))
+}
+
+// A viewpoint given on the command line, turned into a
+// full camera once the image dimensions are known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vfov: Float,
+    // The direction that is up in the image, leaning it to the side
+    // when not square to the view.
+    pub up: Vec3,
+    pub aperture: Float,
+    // Distance to the plane in focus, the distance
+    // to the point looked at when absent.
+    pub focus_dist: Option<Float>,
+    pub projection: Projection,
+}
+
+impl View {
+    pub fn camera(&self, aspect_ratio: Float) -> Camera {
+        let focus_dist = self.focus_dist.unwrap_or_else(|| (self.look_from - self.look_at).length());
+        Camera::new(
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            focus_dist,
+            self.look_from,
+            self.look_at,
+            self.up,
+        ).with_shutter(0.0, 1.0).with_projection(self.projection)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -9,16 +112,25 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
+    // Unit vector pointing away from the view direction.
     w: Vec3,
-    lens_radius: f32,
+    lens_radius: Float,
+    // As given, for exporting the camera.
+    vfov: Float,
+    focus_dist: Float,
+    // Shutter open and close times.
+    time0: Float,
+    time1: Float,
+    clip: Option<Clip>,
+    projection: Projection,
 }
 
 impl Camera {
     pub fn new(
-        vfov: f32, 
-        aspect_ratio: f32, 
-        aperture: f32,
-        focus_dist: f32,
+        vfov: Float, 
+        aspect_ratio: Float, 
+        aperture: Float,
+        focus_dist: Float,
         look_from: Point3, 
         look_at: Point3, 
         vup: Vec3
@@ -28,9 +140,8 @@ impl Camera {
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = unit_vector(look_from - look_at);
-        let u = unit_vector(cross(&vup, &w));
-        let v = cross(&w, &u);
+        let placement = Mat4::look_at(look_from, look_at, vup);
+        let (u, v, w) = (placement.column(0), placement.column(1), placement.column(2));
 
         let origin = look_from;
         let horizontal = focus_dist * viewport_width * u;
@@ -44,19 +155,242 @@ impl Camera {
             lower_left_corner,
             horizontal,
             vertical,
-            w,
             v,
             u,
+            w,
             lens_radius,
+            vfov,
+            focus_dist,
+            time0: 0.0,
+            time1: 0.0,
+            clip: None,
+            projection: Projection::Perspective,
+        }
+    }
+
+    // Rays are sent at random times between opening
+    // and closing the shutter, blurring moving objects.
+    pub fn with_shutter(mut self, time0: Float, time1: Float) -> Self {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    pub fn shutter(&self) -> (Float, Float) {
+        (self.time0, self.time1)
+    }
+
+    // Only geometry within the clip's distances along the view is seen.
+    pub fn with_clip(mut self, clip: Clip) -> Self {
+        self.clip = Some(clip);
+        self
+    }
+
+    // Panoramic projections see through a pinhole, with everything in
+    // focus whatever the aperture.
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    // The world as the camera sees it, cut down to its clip if it has one.
+    pub fn clipped<'a, T: Hittable>(&self, world: &'a T) -> Option<Clipped<'a, T>> {
+        self.clip.map(|clip| Clipped::new(world, self.origin, -self.w, clip))
+    }
+
+    // Angle covered by a pixel at the center of an image of the given height.
+    pub fn pixel_angle(&self, height: usize) -> Float {
+        match self.projection {
+            Projection::Perspective => {
+                let center = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+                self.vertical.length() / (center - self.origin).length() / height as Float
+            }
+            Projection::Fisheye(fov) => degrees_to_radians(fov) / self.aspect_ratio() / height as Float,
+            Projection::Equirectangular => PI / height as Float,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+    // Angle the circle of confusion of a point covers, negative in front
+    // of the plane in focus and positive behind it. Panoramic projections
+    // see through a pinhole, which keeps everything sharp.
+    pub fn defocus(&self, p: Point3) -> Float {
+        if self.projection != Projection::Perspective {
+            return 0.0
+        }
+        let center = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+        let focus_dist = dot(&(self.origin - center), &self.w);
+        let depth = dot(&(self.origin - p), &self.w);
+        2.0 * self.lens_radius * (depth - focus_dist) / (depth * focus_dist)
+    }
+
+    // The camera-to-world matrix of the camera, see `look_from_matrix`.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_columns(self.u, self.v, self.w, self.origin)
+    }
+
+    // The camera as an entry of the cameras of a scene file, placed by
+    // its camera-to-world matrix, which other tools can import.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self.matrix().0.iter()
+            .map(|row| format!("[{}, {}, {}, {}]", row[0], row[1], row[2], row[3]))
+            .collect();
+        let mut fields = vec![
+            format!("\"matrix\": [{}]", rows.join(", ")),
+            format!("\"vfov\": {}", self.vfov),
+            format!("\"aperture\": {}", 2.0 * self.lens_radius),
+            format!("\"focus_dist\": {}", self.focus_dist),
+        ];
+        if self.time1 > self.time0 {
+            fields.push(format!("\"shutter\": [{}, {}]", self.time0, self.time1));
+        }
+        if self.projection != Projection::Perspective {
+            fields.push(format!("\"projection\": \"{}\"", self.projection.name()));
+        }
+        format!("{{ {} }}", fields.join(", "))
+    }
+
+    fn aspect_ratio(&self) -> Float {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    pub fn get_ray(&self, u: Float, v: Float) -> Ray {
+        let dir = match self.projection {
+            Projection::Perspective => return self.lens_ray(u, v),
+            Projection::Fisheye(fov) => {
+                let (x, y) = (2.0 * u - 1.0, (2.0 * v - 1.0) / self.aspect_ratio());
+                let r = (x * x + y * y).sqrt();
+                if r == 0.0 {
+                    -self.w
+                } else {
+                    let theta = (0.5 * r * degrees_to_radians(fov)).min(PI);
+                    theta.sin() / r * (x * self.u + y * self.v) - theta.cos() * self.w
+                }
+            }
+            Projection::Equirectangular => {
+                let (phi, lat) = ((u - 0.5) * 2.0 * PI, (v - 0.5) * PI);
+                lat.cos() * (phi.sin() * self.u - phi.cos() * self.w) + lat.sin() * self.v
+            }
+        };
+        Ray { primary: true, ..Ray::new(&self.origin, &dir, self.random_time()) }
+    }
+
+    fn random_time(&self) -> Float {
+        self.time0 + random_float() * (self.time1 - self.time0)
+    }
+
+    fn lens_ray(&self, u: Float, v: Float) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk();
         let offset = self.u * rd.x + self.v * rd.y;
         
         let o = self.origin + offset;
         let v = self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset;
-        Ray::new(&o, &v)
+        Ray { primary: true, ..Ray::new(&o, &v, self.random_time()) }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::unit_vector;
+
+    #[test]
+    fn test_shutter() {
+        let cam = Camera::new(
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_eq!(0.0, cam.get_ray(0.5, 0.5).time);
+
+        // Rays spread over the whole interval the shutter is open.
+        let cam = cam.with_shutter(2.0, 3.0);
+        let times: Vec<Float> = (0..1000).map(|_| cam.get_ray(0.5, 0.5).time).collect();
+        assert!(times.iter().all(|t| (2.0..=3.0).contains(t)));
+        assert!(times.iter().any(|&t| t < 2.1) && times.iter().any(|&t| t > 2.9));
+    }
+
+    #[test]
+    fn test_projections() {
+        assert_eq!(Some(Projection::Fisheye(FISHEYE_FOV)), Projection::parse("fisheye"));
+        assert_eq!(Some(Projection::Fisheye(220.0)), Projection::parse("fisheye:220"));
+        assert_eq!(None, Projection::parse("fisheye:0"));
+        assert_eq!(None, Projection::parse("equirectangular:90"));
+
+        let cam = |projection| Camera::new(
+            90.0,
+            2.0,
+            0.5,
+            1.0,
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ).with_projection(projection);
+        let dir = |projection, u, v| {
+            let r = cam(projection).get_ray(u, v);
+            assert_eq!(Point3::default(), r.orig);
+            unit_vector(r.dir)
+        };
+        let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-5;
+
+        // The center looks ahead, and a 180 degree fisheye sees
+        // straight to the side at the left and right edges.
+        let fisheye = Projection::Fisheye(180.0);
+        assert!(close(Vec3::new(0.0, 0.0, -1.0), dir(fisheye, 0.5, 0.5)));
+        assert!(close(Vec3::new(1.0, 0.0, 0.0), dir(fisheye, 1.0, 0.5)));
+        assert!(close(Vec3::new(0.0, 1.0, -1.0) / (2.0 as Float).sqrt(), dir(fisheye, 0.5, 1.0)));
+
+        // Panoramas see behind at the left and right
+        // edges, and straight up at the top.
+        let pano = Projection::Equirectangular;
+        assert!(close(Vec3::new(0.0, 0.0, -1.0), dir(pano, 0.5, 0.5)));
+        assert!(close(Vec3::new(1.0, 0.0, 0.0), dir(pano, 0.75, 0.5)));
+        assert!(close(Vec3::new(0.0, 0.0, 1.0), dir(pano, 1.0, 0.5)));
+        assert!(close(Vec3::new(0.0, 1.0, 0.0), dir(pano, 0.5, 1.0)));
+        assert!((cam(pano).pixel_angle(100) - PI / 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_defocus() {
+        let cam = Camera::new(
+            40.0,
+            1.0,
+            0.5,
+            2.0,
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_eq!(0.0, cam.defocus(Point3::new(1.0, 0.0, -2.0)));
+        assert!((cam.defocus(Point3::new(0.0, 0.0, -1.0)) + 0.25).abs() < 1e-6);
+        assert!((cam.defocus(Point3::new(0.0, 0.0, -4.0)) - 0.125).abs() < 1e-6);
+        assert_eq!(0.0, cam.with_projection(Projection::Equirectangular).defocus(Point3::new(0.0, 0.0, -4.0)));
+    }
+
+    #[test]
+    fn test_matrix() {
+        // Blender's default camera, rolled a little to the side.
+        let up = unit_vector(Vec3::new(0.2, 1.0, 0.0));
+        let cam = Camera::new(30.0, 1.5, 0.0, 4.0, Point3::new(7.0, -6.0, 5.0), Point3::default(), up);
+        let m = cam.matrix();
+        let (from, at, vup) = look_from_matrix(&m).unwrap();
+        assert_eq!(Point3::new(7.0, -6.0, 5.0), from);
+        assert!((unit_vector(from - at) - unit_vector(Point3::new(7.0, -6.0, 5.0))).length() < 1e-6);
+        let again = Camera::new(30.0, 1.5, 0.0, 4.0, from, at, vup).matrix();
+        for (a, b) in m.0.iter().flatten().zip(again.0.iter().flatten()) {
+            assert!((a - b).abs() < 1e-6, "{:?} vs {:?}", m, again);
+        }
+
+        // A view matrix takes the camera's position to the origin.
+        let view = m.rigid_inverse();
+        assert!(look_from_matrix(&view).is_ok());
+        assert!((look_from_matrix(&view.rigid_inverse()).unwrap().0 - from).length() < 1e-5);
+        assert!(cam.to_json().starts_with("{ \"matrix\": [[") && cam.to_json().contains("\"vfov\": 30, \"aperture\": 0, \"focus_dist\": 4"));
+        let mut scaled = m;
+        scaled[0][0] *= 2.0;
+        assert!(look_from_matrix(&scaled).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{INF, Float};
+use crate::vec::{Vec3, dot};
+use crate::hittable::Hittable;
+use crate::camera::Camera;
+use crate::image::Image;
+use crate::render::Settings;
+
+// How quickly a neighbor's weight falls off with its relative
+// difference in depth, and how sharply with its normal.
+const DEPTH_SIGMA: Float = 0.05;
+const NORMAL_POWER: i32 = 8;
+
+// First-hit values of a pixel, used to tell whether two
+// neighboring pixels see the same surface.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Guide {
+    // Distance to the first hit, infinite for the sky.
+    pub depth: Float,
+    pub normal: Vec3,
+}
+
+// Traces a single ray through the center of every pixel.
+// These are far cheaper than the full paths being skipped.
+pub fn guides<T: Hittable>(world: &T, cam: &Camera, settings: &Settings) -> Vec<Guide> {
+    let (width, height) = (settings.width, settings.height);
+    let mut guides = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let j = height - 1 - y;
+        for i in 0..width {
+            let u = (i as Float + 0.5) / (width - 1) as Float;
+            let v = (j as Float + 0.5) / (height - 1) as Float;
+            let r = cam.get_ray(u, v);
+            let guide = match world.hit(&r, settings.ray_bias, INF) {
+                Some(rec) => Guide { depth: rec.t * r.dir.length(), normal: rec.normal },
+                None => Guide { depth: INF, normal: Vec3::default() },
+            };
+            guides.push(guide);
+        }
+    }
+    guides
+}
+
+fn similarity(a: &Guide, b: &Guide) -> Float {
+    match (a.depth.is_finite(), b.depth.is_finite()) {
+        (false, false) => 1.0,
+        (true, true) => {
+            let depth = (-(a.depth - b.depth).abs() / (DEPTH_SIGMA * a.depth.min(b.depth))).exp();
+            let normal = Float::max(dot(&a.normal, &b.normal), 0.0).powi(NORMAL_POWER);
+            depth * normal
+        }
+        _ => 0.0,
+    }
+}
+
+// Fills in every pixel that was not traced from its traced
+// horizontal and vertical neighbors, weighting each by how
+// similar its depth and normal are so that edges stay sharp.
+// Falls back to a plain average when no neighbor is similar.
+pub fn reconstruct<F: Fn(usize, usize) -> bool>(img: &mut Image, guides: &[Guide], traced: F) {
+    let (width, height) = (img.width, img.height);
+    let source = img.pixels().to_vec();
+
+    for y in 0..height {
+        for x in 0..width {
+            if traced(x, y) {
+                continue
+            }
+
+            let center = &guides[y * width + x];
+            let mut neighbors = Vec::with_capacity(4);
+            if x > 0 { neighbors.push((x - 1, y)) };
+            if x + 1 < width { neighbors.push((x + 1, y)) };
+            if y > 0 { neighbors.push((x, y - 1)) };
+            if y + 1 < height { neighbors.push((x, y + 1)) };
+            neighbors.retain(|&(nx, ny)| traced(nx, ny));
+            if neighbors.is_empty() {
+                continue
+            }
+
+            let mut sum = Vec3::default();
+            let mut total = 0.0;
+            for &(nx, ny) in neighbors.iter() {
+                let w = similarity(center, &guides[ny * width + nx]);
+                sum += w * source[ny * width + nx];
+                total += w;
+            }
+
+            if total < 1e-4 {
+                sum = Vec3::default();
+                for &(nx, ny) in neighbors.iter() {
+                    sum += source[ny * width + nx];
+                }
+                total = neighbors.len() as Float;
+            }
+            img.set(x, y, sum / total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+
+    fn checker(x: usize, y: usize) -> bool {
+        (x + y).is_multiple_of(2)
+    }
+
+    #[test]
+    fn test_reconstruct_flat() {
+        let mut img = Image::new(3, 3);
+        for y in 0..3 {
+            for x in 0..3 {
+                if checker(x, y) {
+                    img.set(x, y, Color::new(1.0, 0.5, 0.25));
+                }
+            }
+        }
+        let guides = vec![Guide { depth: 1.0, normal: Vec3::new(0.0, 1.0, 0.0) }; 9];
+
+        reconstruct(&mut img, &guides, checker);
+        assert_eq!(Color::new(1.0, 0.5, 0.25), img.pixels()[1]);
+        assert_eq!(Color::new(1.0, 0.5, 0.25), img.pixels()[3]);
+    }
+
+    #[test]
+    fn test_reconstruct_respects_edges() {
+        // The left column is a near surface, the rest is sky.
+        let mut img = Image::new(3, 1);
+        img.set(0, 0, Color::new(1.0, 0.0, 0.0));
+        img.set(2, 0, Color::new(0.0, 0.0, 1.0));
+        let near = Guide { depth: 1.0, normal: Vec3::new(0.0, 0.0, 1.0) };
+        let sky = Guide { depth: INF, normal: Vec3::default() };
+
+        reconstruct(&mut img, &[near, sky, sky], checker);
+        assert_eq!(Color::new(0.0, 0.0, 1.0), img.pixels()[1]);
+    }
+}
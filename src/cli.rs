@@ -0,0 +1,1171 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::{INF, Float};
+use crate::camera::{Projection, View};
+use crate::clip::Clip;
+use crate::aov::Aov;
+use crate::filter::Filter;
+use crate::glare::Glare;
+use crate::vec::Vec3;
+use crate::motion::RigidTransform;
+use crate::photometry::{Exposure, Metering};
+use crate::colorspace::ColorSpace;
+use crate::image::Format;
+use crate::tonemap::ToneMap;
+use crate::render::{Ambient, Clamp, Focus, Integrator};
+use crate::scenefile::Override;
+
+pub const USAGE: &str = "\
+usage: raytracing [options]
+       raytracing matpreview <material> [options]
+       raytracing bake <texture> [options]
+       raytracing gallery [<directory>] [options]
+       raytracing pick <x>,<y> --scene <path> [options]
+       raytracing daemon <socket> [options]
+       raytracing request <socket> [options]
+       raytracing merge <output.exr> <input.exr>...
+       raytracing selftest
+
+matpreview renders a material on a ball on a checkered floor in a studio,
+from a fixed camera and in a square image by default. The material is given
+as in scene files, as a JSON object or the path of a file holding one, e.g.
+'{\"type\": \"metal\", \"albedo\": [0.8, 0.6, 0.2], \"roughness\": 0.2}'.
+
+bake writes a texture, given the same way, over texture coordinates 0 to 1
+into an image, square by default, which an image texture maps back the
+same way. Textures of points in space are taken at (u, v, 0). Only the
+--output, --format, --width, --height and --color-space options apply.
+
+gallery renders a small thumbnail of every built-in scene, or of every scene
+file in the directory, each to the --output with the scene's name appended,
+and tiles them into a contact sheet written to the --output itself. The
+thumbnails are 192 pixels wide with 16 samples unless given otherwise.
+
+pick prints what the pixel x from the left and y from the top of the render
+of a scene file shows: the object the ray through the pixel's center hits
+first, as its place among the objects of the file with its type and the
+\"name\" it may be given there, the name of its material, and how far away
+and where the hit is. The first camera of the file is looked through, or
+the first --camera view.
+
+daemon loads the scene of its options once and renders it for every request
+to the Unix socket, which request sends. The options of a request are added
+to the daemon's, and may change anything but the scene: --camera views
+replace the daemon's, and --scene, --scene-name, --strict, --set, --sweep, the
+--obj options, --frames and --fps can't be given.
+
+options:
+  -o, --output <path>       image to write (.exr, .hdr, .png, otherwise PPM); stdout
+                            when absent
+      --format <name>       write ppm, png, exr or hdr whatever the extension;
+                            exr and hdr keep the unclamped float radiance
+      --width <pixels>      image width, 256 by default
+      --height <pixels>     image height, from the width at 16:9 by default
+      --samples <n>         samples per pixel, 100 by default
+      --max-depth <n>       maximum bounces per path, 50 by default
+      --min-throughput <t>  end paths once they carry less than this fraction of the
+                            light they would find in every channel, e.g. 0.01, so
+                            deep stacks of glass don't trace rays adding nothing
+      --split <k>           trace k continuations from the first mirror or glass
+                            bounce of every path, to reduce noise from them
+      --clamp <max>         limit every channel of the light a path brings after
+                            more than one bounce to this, cutting off fireflies
+                            of indirect light at the cost of some energy
+      --clamp-direct <max>  limit the light of lights seen directly or after one
+                            bounce too, off by default to keep highlights crisp
+      --irradiance-cache <error>
+                            interpolate indirect light on diffuse surfaces between
+                            sparse points, allowing this much error (0 to 1), e.g. 0.2
+      --ray-bias <dist|auto>
+                            ignore hits closer than this along every ray, to keep
+                            rays from hitting the surface they leave; auto scales
+                            it with the size of the scene and is the default
+      --scene-name <name>   built-in scene to render: random, random-jittered,
+                            motion, spin, lanterns, cornell, cornell-smoke,
+                            textures, or from the books three-spheres,
+                            checkered-spheres, simple-light or book2-final
+      --scene <path>        render the scene described in a JSON file instead,
+                            see examples/scene.json
+      --strict              fail instead of warning about likely mistakes in the
+                            scene, such as degenerate triangles, spheres of zero
+                            radius, unused materials or meshes without normals
+      --set <material.name.field=value>
+                            change a field of a material of the --scene file, the
+                            value in JSON, e.g. material.gold.roughness=0.3; may
+                            be repeated
+      --sweep <material.name.field=values>
+                            render the --scene file once for each of a comma
+                            separated list of values, each to --output with its
+                            index appended, e.g. out_sweep2.png, and all of them
+                            in a grid to --output
+      --obj <path>          add the triangles of a Wavefront OBJ mesh to the scene;
+                            may be repeated
+      --obj-texture <path>  PNG or JPEG image to map onto the --obj meshes
+      --obj-cull            let camera rays pass through the back faces of the
+                            --obj meshes, for closed meshes or rasterizer assets
+      --obj-cull-shadows    let shadow rays pass through the back faces of the
+                            --obj meshes, so that single-sided geometry only
+                            casts shadows from the side it faces
+      --obj-at <x,y,z[:rx,ry,rz]>
+                            place an instance of every --obj mesh here, rotated
+                            by the given degrees; may be repeated
+      --camera <from:at[:vfov]>
+                            render from this viewpoint instead; may be repeated
+      --export-cameras <path>
+                            also write the cameras as JSON, placed by their
+                            camera-to-world matrices, to paste into scene files
+                            or import into other tools
+      --aperture <size>     lens aperture of --camera views, 0.1 by default
+      --focus-dist <dist>   focus distance of --camera views, |from - at| by default
+      --shutter <open,close>
+                            shutter interval of every camera
+      --projection <name>   project every camera's view as perspective (the
+                            default), fisheye[:fov] with a field of view across
+                            the width of 180 degrees by default, or
+                            equirectangular for 360 degree panoramas
+      --clip <near[:far]>   only show geometry between these distances along the
+                            view of every camera, cutting open what is nearer
+      --clip-cap            close solids cut open by --clip with a flat face
+      --frames <n|a..b>     split the shutter interval into n frames of an animation,
+                            or render only frames a to b of one of b frames; the
+                            scene time t of frame i is i / n, or i / fps with --fps,
+                            and images are numbered by frame, e.g. out_007.png
+      --fps <rate>          frames per second, making the scene time in seconds
+      --time-budget <time>  render passes until the time is up, e.g. 10m or 1h30m;
+                            shared by all cameras, frames and sweep values
+      --spp-range <a..b>    only render samples a to b, see merge
+      --seed <n>            seed of the random numbers, which makes renders
+                            repeatable; random by default
+      --threads <n>         worker threads, one per available core by default
+      --pin-threads         pin each worker thread to its own core (Linux only)
+      --memory-limit <size> refuse renders estimated to need more, e.g. 512M or 16G;
+                            4G by default
+      --checkerboard        trace half the pixels and reconstruct the rest
+      --preview             first write quick renders at 1/8, 1/4 and 1/2 of the
+                            resolution to --output, replaced by the full render
+      --focus <x,y[:w,h]>   render the tiles around this point or rectangle first
+                            in every pass, so it converges first under a time
+                            budget; in fractions of the image from its top left
+      --exposure <f-number,shutter,iso>
+                            expose like a physical camera, e.g. 8,1/60,400, for
+                            scenes with lights in physical units
+      --auto-exposure <metering>
+                            expose 8-bit output to bring the log average luminance,
+                            with average or center weighted metering, to middle gray
+      --integrator <name>   what every sample computes: path, the light along it
+                            and the default, uv, the texture coordinates of the
+                            first hit as red and green for debugging, or focus,
+                            a quick preview of the depth of field, tinting what
+                            will be blurred blue in front of the plane in focus
+                            and red behind it
+      --ambient <r,g,b[:r,g,b]>
+                            add this light at every diffuse hit, or blend from the
+                            second color facing down to the first facing up
+      --filter <name[:radius]>
+                            weight samples into the pixels around them with a
+                            box (the default), tent or gaussian filter, with a
+                            radius in pixels of 1 and 1.5 by default
+      --glare <strength[:blades]>
+                            spread this fraction, from 0 to 1, of the light brighter
+                            than white into the diffraction spikes of an aperture
+                            of 6 blades by default, and a halo, before tone mapping
+      --tonemap <name>      compress the radiance of 8-bit output with clamp (the
+                            default), reinhard, exposure or aces
+      --color-space <name>  convert the output to srgb, display-p3, rec709 or, for
+                            float formats only, acescg; by default 8-bit images use a
+                            plain 2.0 gamma and OpenEXR linear sRGB
+      --half-float          accumulate in half floats to halve framebuffer memory
+                            with the box filter, a little less with others,
+                            and write half float OpenEXR output
+      --deep <path>         also write a deep OpenEXR image
+      --aov <names>         also write the first hit's normal, depth or albedo, a
+                            comma separated list, next to --output with the name
+                            appended, e.g. out_normal.exr; best as OpenEXR
+      --stats <path>        write per-channel statistics as JSON
+      --histogram <path>    write a luminance histogram as PNG
+  -h, --help                print this message
+";
+
+pub enum Command {
+    Render(Box<Args>),
+    // Averages partial renders, weighted by their sample counts.
+    Merge { output: String, inputs: Vec<String> },
+    // Loads the scene once and renders it for requests to a socket,
+    // with the options kept to add those of every request to.
+    Daemon { socket: String, args: Box<Args>, options: Vec<String> },
+    // Sends render options to a daemon.
+    Request { socket: String, options: Vec<String> },
+    // Writes a texture into an image.
+    Bake { texture: String, args: Box<Args> },
+    // Renders every built-in scene, or every scene file in the directory,
+    // into a grid of thumbnails.
+    Gallery { dir: Option<String>, args: Box<Args> },
+    // Tells which object of a scene file the pixel of its render shows.
+    Pick { pixel: (usize, usize), args: Box<Args> },
+    // Runs quick sanity checks of the renderer and reports pass/fail.
+    SelfTest,
+    Help,
+}
+
+impl Command {
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut args = args.peekable();
+        match args.peek().map(String::as_str) {
+            Some("merge") => {
+                args.next();
+                let output = args.next().ok_or("merge requires an output path")?;
+                let inputs: Vec<String> = args.collect();
+                if inputs.is_empty() {
+                    return Err("merge requires at least one input".to_string())
+                }
+                Ok(Command::Merge { output, inputs })
+            }
+            Some("-h") | Some("--help") => Ok(Command::Help),
+            Some("matpreview") => {
+                args.next();
+                let material = args.next().ok_or("matpreview requires a material")?;
+                let mut parsed = Args::parse(args)?;
+                if parsed.scene_name.is_some() || parsed.scene_file.is_some() {
+                    return Err("matpreview renders its own scene, without --scene-name or --scene".to_string())
+                }
+                parsed.material = Some(material);
+                Ok(Command::Render(Box::new(parsed)))
+            }
+            Some("bake") => {
+                args.next();
+                let texture = args.next().ok_or("bake requires a texture")?;
+                let parsed = Args::parse(args)?;
+                if parsed.scene_name.is_some() || parsed.scene_file.is_some() {
+                    return Err("bake writes a texture, without --scene-name or --scene".to_string())
+                }
+                Ok(Command::Bake { texture, args: Box::new(parsed) })
+            }
+            Some("gallery") => {
+                args.next();
+                let dir = args.next_if(|arg| !arg.starts_with('-'));
+                let parsed = Args::parse(args)?;
+                if parsed.scene_name.is_some() || parsed.scene_file.is_some() || parsed.material.is_some() {
+                    return Err("gallery renders its own scenes, without --scene-name or --scene".to_string())
+                }
+                if !parsed.sweep.is_empty() || parsed.frames.is_some() {
+                    return Err("gallery renders single images, without --sweep or --frames".to_string())
+                }
+                if parsed.output.is_none() {
+                    return Err("gallery requires --output".to_string())
+                }
+                Ok(Command::Gallery { dir, args: Box::new(parsed) })
+            }
+            Some("pick") => {
+                args.next();
+                let pixel = args.next().ok_or("pick requires a pixel")?;
+                let pixel = parse_pixel(&pixel)?;
+                let parsed = Args::parse(args)?;
+                if parsed.scene_file.is_none() {
+                    return Err("pick requires --scene".to_string())
+                }
+                if !parsed.sweep.is_empty() || parsed.frames.is_some() || !parsed.meshes.is_empty() {
+                    return Err("pick looks at a single scene file, without --sweep, --frames or --obj".to_string())
+                }
+                Ok(Command::Pick { pixel, args: Box::new(parsed) })
+            }
+            Some("daemon") => {
+                args.next();
+                let socket = args.next().ok_or("daemon requires a socket path")?;
+                let options: Vec<String> = args.collect();
+                let parsed = Args::parse(options.iter().cloned())?;
+                if !parsed.sweep.is_empty() || parsed.frames.is_some() {
+                    return Err("a daemon renders single images, without --sweep or --frames".to_string())
+                }
+                Ok(Command::Daemon { socket, args: Box::new(parsed), options })
+            }
+            Some("request") => {
+                args.next();
+                let socket = args.next().ok_or("request requires a socket path")?;
+                Ok(Command::Request { socket, options: args.collect() })
+            }
+            Some("selftest") => {
+                args.next();
+                match args.next() {
+                    Some(arg) => Err(format!("unexpected argument '{}' to selftest", arg)),
+                    None => Ok(Command::SelfTest),
+                }
+            }
+            _ => Ok(Command::Render(Box::new(Args::parse(args)?))),
+        }
+    }
+}
+
+// Command line options. Parsing is done by hand
+// to keep the dependency list short.
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    // Path to write per-channel statistics to, as JSON.
+    pub stats: Option<String>,
+    // Path to write a plotted histogram to, as PNG.
+    pub histogram: Option<String>,
+    // Where to write the rendered image; stdout when absent.
+    pub output: Option<String>,
+    // Format of the image, overriding the one of the output extension.
+    pub format: Option<Format>,
+    // Path to additionally write a deep OpenEXR image to.
+    pub deep: Option<String>,
+    // First-hit images to write next to the output.
+    pub aovs: Vec<Aov>,
+    // Keep rendering passes until this much wall-clock time has passed.
+    pub time_budget: Option<Duration>,
+    // Only render this slice of each pixel's samples, so that
+    // several processes can share the work on a single frame.
+    pub spp_range: Option<Range<usize>>,
+    // Trace only half the pixels and reconstruct the others.
+    pub checkerboard: bool,
+    // Write low resolution previews before every full render.
+    pub preview: bool,
+    // Part of the image whose tiles are rendered first.
+    pub focus: Option<Focus>,
+    // Metering of automatic exposure of 8-bit output, which is off when absent.
+    pub auto_exposure: Option<Metering>,
+    // Physical camera settings scaling luminance to the display range.
+    pub exposure: Option<Exposure>,
+    // What every camera sample computes, the light along it by default.
+    pub integrator: Integrator,
+    // Reconstruction filter the samples are splatted with.
+    pub filter: Filter,
+    // Light added at every diffuse hit, which is off when absent.
+    pub ambient: Option<Ambient>,
+    // Glare around the highlights of the output, which is off when absent.
+    pub glare: Option<Glare>,
+    // Operator fitting the radiance into the range of 8-bit output.
+    pub tonemap: ToneMap,
+    // Color space to convert the output image to.
+    pub color_space: Option<ColorSpace>,
+    // Keep the framebuffer and OpenEXR output in half floats.
+    pub half_float: bool,
+    // Name of the built-in scene to render.
+    pub scene_name: Option<String>,
+    // JSON file describing the scene to render instead.
+    pub scene_file: Option<String>,
+    // Fail on the problems found in the scene instead of warning.
+    pub strict: bool,
+    // Material to render on the preview ball instead of a scene.
+    pub material: Option<String>,
+    // Changes to the materials of the scene file.
+    pub overrides: Vec<Override>,
+    // Alternative changes to render the scene file with, one image each.
+    pub sweep: Vec<Override>,
+    // Wavefront OBJ meshes to add to the scene.
+    pub meshes: Vec<String>,
+    // Let camera rays, and shadow rays, through the back faces of the meshes.
+    pub cull_backfaces: bool,
+    pub cull_shadow_backfaces: bool,
+    // Image texture applied to the meshes through their texture coordinates.
+    pub mesh_texture: Option<String>,
+    // Placements of the meshes; every one shares the geometry of the
+    // others. The meshes are placed as loaded when there are none.
+    pub mesh_placements: Vec<RigidTransform>,
+    // Animation frames to render, of as many frames as the end of the
+    // range, which the shutter interval is split into.
+    pub frames: Option<Range<usize>>,
+    // Frames per second of the animation, whose scene time is otherwise
+    // the fraction of the frames gone by.
+    pub fps: Option<Float>,
+    // Shutter open and close times overriding every camera's own.
+    pub shutter: Option<(Float, Float)>,
+    // Projection overriding every camera's own.
+    pub projection: Option<Projection>,
+    // Distances along every camera's view between which geometry is seen.
+    pub clip: Option<Clip>,
+    // Viewpoints overriding the scene's own cameras.
+    pub cameras: Vec<View>,
+    // Path to write the cameras rendered from to, as JSON.
+    pub export_cameras: Option<String>,
+    // Image dimensions; when only one is given the
+    // other follows from the default aspect ratio.
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<usize>,
+    pub max_depth: Option<usize>,
+    // Throughput below which paths end early.
+    pub min_throughput: Option<Float>,
+    // Continuations traced from the first specular bounce of every path.
+    pub split: Option<usize>,
+    // Limits on the direct and indirect light of every path sample.
+    pub clamp: Clamp,
+    // Error tolerance of the irradiance cache, which is off when absent.
+    pub irradiance_cache: Option<Float>,
+    // Minimum distance of hits along a ray; scaled to the scene when absent.
+    pub ray_bias: Option<Float>,
+    // Lens settings of the `--camera` views.
+    pub aperture: Option<Float>,
+    pub focus_dist: Option<Float>,
+    // Seed of the random numbers; renders with the same seed are the same.
+    pub seed: Option<u64>,
+    // Number of render threads, and whether to pin them to cores.
+    pub threads: Option<usize>,
+    pub pin_threads: bool,
+    // Refuse to render when the estimated memory use exceeds this many bytes.
+    pub memory_limit: Option<usize>,
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut clip_cap = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--stats" => parsed.stats = Some(value(&mut args, &arg)?),
+                "--histogram" => parsed.histogram = Some(value(&mut args, &arg)?),
+                "-o" | "--output" => parsed.output = Some(value(&mut args, &arg)?),
+                "--format" => parsed.format = Some(parse_format(&value(&mut args, &arg)?)?),
+                "--deep" => parsed.deep = Some(value(&mut args, &arg)?),
+                "--aov" => parsed.aovs = parse_aovs(&value(&mut args, &arg)?)?,
+                "--time-budget" => parsed.time_budget = Some(parse_duration(&value(&mut args, &arg)?)?),
+                "--spp-range" => parsed.spp_range = Some(parse_range(&value(&mut args, &arg)?)?),
+                "--checkerboard" => parsed.checkerboard = true,
+                "--preview" => parsed.preview = true,
+                "--focus" => parsed.focus = Some(parse_focus(&value(&mut args, &arg)?)?),
+                "--exposure" => parsed.exposure = Some(parse_exposure(&value(&mut args, &arg)?)?),
+                "--auto-exposure" => parsed.auto_exposure = Some(parse_metering(&value(&mut args, &arg)?)?),
+                "--integrator" => parsed.integrator = parse_integrator(&value(&mut args, &arg)?)?,
+                "--filter" => parsed.filter = parse_filter(&value(&mut args, &arg)?)?,
+                "--ambient" => parsed.ambient = Some(parse_ambient(&value(&mut args, &arg)?)?),
+                "--glare" => parsed.glare = Some(parse_glare(&value(&mut args, &arg)?)?),
+                "--tonemap" => parsed.tonemap = parse_tonemap(&value(&mut args, &arg)?)?,
+                "--color-space" => parsed.color_space = Some(parse_color_space(&value(&mut args, &arg)?)?),
+                "--half-float" => parsed.half_float = true,
+                "--scene-name" => parsed.scene_name = Some(value(&mut args, &arg)?),
+                "--scene" => parsed.scene_file = Some(value(&mut args, &arg)?),
+                "--strict" => parsed.strict = true,
+                "--set" => parsed.overrides.push(Override::parse(&value(&mut args, &arg)?)?),
+                "--sweep" => parsed.sweep = Override::parse_sweep(&value(&mut args, &arg)?)?,
+                "--obj" => parsed.meshes.push(value(&mut args, &arg)?),
+                "--obj-cull" => parsed.cull_backfaces = true,
+                "--obj-cull-shadows" => parsed.cull_shadow_backfaces = true,
+                "--obj-texture" => parsed.mesh_texture = Some(value(&mut args, &arg)?),
+                "--obj-at" => parsed.mesh_placements.push(parse_placement(&value(&mut args, &arg)?)?),
+                "--frames" => parsed.frames = Some(parse_frames(&value(&mut args, &arg)?)?),
+                "--fps" => parsed.fps = Some(parse_positive(&value(&mut args, &arg)?, &arg)?),
+                "--shutter" => parsed.shutter = Some(parse_shutter(&value(&mut args, &arg)?)?),
+                "--projection" => parsed.projection = Some(parse_projection(&value(&mut args, &arg)?)?),
+                "--clip" => parsed.clip = Some(parse_clip(&value(&mut args, &arg)?)?),
+                "--clip-cap" => clip_cap = true,
+                "--camera" => parsed.cameras.push(parse_view(&value(&mut args, &arg)?)?),
+                "--export-cameras" => parsed.export_cameras = Some(value(&mut args, &arg)?),
+                "--width" => parsed.width = Some(parse_count(&value(&mut args, &arg)?, &arg, 2)?),
+                "--height" => parsed.height = Some(parse_count(&value(&mut args, &arg)?, &arg, 2)?),
+                "--samples" => parsed.samples = Some(parse_count(&value(&mut args, &arg)?, &arg, 1)?),
+                "--max-depth" => parsed.max_depth = Some(parse_count(&value(&mut args, &arg)?, &arg, 1)?),
+                "--min-throughput" => parsed.min_throughput = Some(parse_fraction(&value(&mut args, &arg)?, &arg)?),
+                "--split" => parsed.split = Some(parse_count(&value(&mut args, &arg)?, &arg, 1)?),
+                "--clamp" => parsed.clamp.indirect = Some(parse_length(&value(&mut args, &arg)?, &arg)?),
+                "--clamp-direct" => parsed.clamp.direct = Some(parse_length(&value(&mut args, &arg)?, &arg)?),
+                "--irradiance-cache" => parsed.irradiance_cache = Some(parse_fraction(&value(&mut args, &arg)?, &arg)?),
+                "--ray-bias" => parsed.ray_bias = parse_ray_bias(&value(&mut args, &arg)?, &arg)?,
+                "--aperture" => parsed.aperture = Some(parse_length(&value(&mut args, &arg)?, &arg)?),
+                "--focus-dist" => parsed.focus_dist = Some(parse_length(&value(&mut args, &arg)?, &arg)?),
+                "--seed" => parsed.seed = Some(parse_seed(&value(&mut args, &arg)?)?),
+                "--threads" => parsed.threads = Some(parse_count(&value(&mut args, &arg)?, &arg, 1)?),
+                "--pin-threads" => parsed.pin_threads = true,
+                "--memory-limit" => parsed.memory_limit = Some(parse_size(&value(&mut args, &arg)?)?),
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        if parsed.spp_range.is_some() && parsed.time_budget.is_some() {
+            return Err("--spp-range and --time-budget cannot be combined".to_string())
+        }
+        if parsed.samples.is_some() && (parsed.spp_range.is_some() || parsed.time_budget.is_some()) {
+            return Err("--samples cannot be combined with --spp-range or --time-budget".to_string())
+        }
+        if parsed.preview && parsed.output.is_none() {
+            return Err("--preview requires --output".to_string())
+        }
+        if parsed.exposure.is_some() && parsed.auto_exposure.is_some() {
+            return Err("--exposure and --auto-exposure cannot be combined".to_string())
+        }
+        if !parsed.aovs.is_empty() && parsed.output.is_none() {
+            return Err("--aov requires --output".to_string())
+        }
+        let format = parsed.format.unwrap_or_else(|| parsed.output.as_deref().map_or(Format::Ppm, Format::from_path));
+        if parsed.color_space.is_some_and(|space| space.is_linear()) && !format.is_float() {
+            return Err("linear color spaces require OpenEXR or HDR output".to_string())
+        }
+        if parsed.scene_name.is_some() && parsed.scene_file.is_some() {
+            return Err("--scene-name and --scene cannot be combined".to_string())
+        }
+        if (!parsed.overrides.is_empty() || !parsed.sweep.is_empty()) && parsed.scene_file.is_none() {
+            return Err("--set and --sweep require --scene".to_string())
+        }
+        if !parsed.sweep.is_empty() && parsed.output.is_none() {
+            return Err("--sweep requires --output".to_string())
+        }
+        match parsed.clip.as_mut() {
+            Some(clip) => clip.cap = clip_cap,
+            None if clip_cap => return Err("--clip-cap requires --clip".to_string()),
+            None => {}
+        }
+        if parsed.cameras.is_empty() && (parsed.aperture.is_some() || parsed.focus_dist.is_some()) {
+            return Err("--aperture and --focus-dist only apply to --camera views".to_string())
+        }
+        for view in parsed.cameras.iter_mut() {
+            view.aperture = parsed.aperture.unwrap_or(view.aperture);
+            view.focus_dist = parsed.focus_dist.or(view.focus_dist);
+        }
+        Ok(parsed)
+    }
+}
+
+// Options that change the scene, which a daemon loads once when it starts.
+const SCENE_OPTIONS: [&str; 12] = [
+    "--scene", "--scene-name", "--strict", "--set", "--sweep", "--obj", "--obj-cull", "--obj-cull-shadows", "--obj-texture",
+    "--obj-at", "--frames", "--fps",
+];
+
+// The options of a request to a daemon started with the given options,
+// added to those. Views given with --camera replace the daemon's.
+pub fn parse_request(daemon: &[String], request: &[String]) -> Result<Args, String> {
+    if let Some(option) = request.iter().find(|option| SCENE_OPTIONS.contains(&option.as_str())) {
+        return Err(format!("'{}' changes the scene, which is loaded when the daemon starts", option))
+    }
+    let mut parsed = Args::parse(daemon.iter().chain(request).cloned())?;
+    let views = request.iter().filter(|option| *option == "--camera").count();
+    if views > 0 {
+        parsed.cameras.drain(..parsed.cameras.len() - views);
+    }
+    if parsed.output.is_none() {
+        return Err("requests require --output, as the daemon has no terminal to write to".to_string())
+    }
+    Ok(parsed)
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("missing value for '{}'", flag))
+}
+
+fn parse_count(s: &str, flag: &str, min: usize) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if n >= min => Ok(n),
+        _ => Err(format!("invalid value '{}' for '{}', expected an integer of at least {}", s, flag, min)),
+    }
+}
+
+fn parse_seed(s: &str) -> Result<u64, String> {
+    s.parse().map_err(|_| format!("invalid seed '{}', expected a non-negative integer", s))
+}
+
+fn parse_length(s: &str, flag: &str) -> Result<Float, String> {
+    match s.parse::<Float>() {
+        Ok(x) if x >= 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("invalid value '{}' for '{}', expected a non-negative number", s, flag)),
+    }
+}
+
+fn parse_positive(s: &str, flag: &str) -> Result<Float, String> {
+    match s.parse::<Float>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("invalid value '{}' for '{}', expected a positive number", s, flag)),
+    }
+}
+
+fn parse_fraction(s: &str, flag: &str) -> Result<Float, String> {
+    match s.parse::<Float>() {
+        Ok(x) if x > 0.0 && x <= 1.0 => Ok(x),
+        _ => Err(format!("invalid value '{}' for '{}', expected a number in (0, 1]", s, flag)),
+    }
+}
+
+fn parse_ray_bias(s: &str, flag: &str) -> Result<Option<Float>, String> {
+    match s {
+        "auto" => Ok(None),
+        _ => parse_length(s, flag).map(Some),
+    }
+}
+
+// Pixels are given as `x,y`, counted from the top left.
+fn parse_pixel(s: &str) -> Result<(usize, usize), String> {
+    match s.split_once(',').map(|(x, y)| (x.trim().parse(), y.trim().parse())) {
+        Some((Ok(x), Ok(y))) => Ok((x, y)),
+        _ => Err(format!("invalid pixel '{}', expected x,y", s)),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts = s.split(',')
+        .map(|p| p.trim().parse::<Float>())
+        .collect::<Result<Vec<Float>, _>>()
+        .map_err(|_| format!("invalid vector '{}'", s))?;
+    match parts[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected three components in '{}'", s)),
+    }
+}
+
+// Ambient light is given as one color, or as `sky:ground`.
+fn parse_ambient(s: &str) -> Result<Ambient, String> {
+    let mut parts = s.splitn(2, ':');
+    let sky = parse_vec3(parts.next().unwrap_or(""))?;
+    let ground = match parts.next() {
+        Some(g) => parse_vec3(g)?,
+        None => sky,
+    };
+    if [sky, ground].iter().any(|c| c.x < 0.0 || c.y < 0.0 || c.z < 0.0) {
+        return Err(format!("invalid ambient light '{}', expected non-negative colors", s))
+    }
+    Ok(Ambient { sky, ground })
+}
+
+// Placements are given as `x,y,z[:rx,ry,rz]`, the rotation in
+// degrees about each axis.
+fn parse_placement(s: &str) -> Result<RigidTransform, String> {
+    let mut parts = s.splitn(2, ':');
+    let translation = parse_vec3(parts.next().unwrap_or(""))?;
+    let rotation = match parts.next() {
+        Some(r) => parse_vec3(r)?,
+        None => Vec3::default(),
+    };
+    Ok(RigidTransform::new(translation, rotation))
+}
+
+// Shutter intervals are given as `open,close`, e.g. `0,0.5`.
+fn parse_shutter(s: &str) -> Result<(Float, Float), String> {
+    let invalid = || format!("invalid shutter '{}', expected open,close", s);
+    let mut parts = s.splitn(2, ',');
+    let open: Float = parts.next().and_then(|p| p.trim().parse().ok()).ok_or_else(invalid)?;
+    let close: Float = parts.next().and_then(|p| p.trim().parse().ok()).ok_or_else(invalid)?;
+    if close < open {
+        return Err(invalid())
+    }
+    Ok((open, close))
+}
+
+// Clips are given as `near[:far]`, with nothing cut away beyond when
+// there is no far distance.
+fn parse_clip(s: &str) -> Result<Clip, String> {
+    let invalid = || format!("invalid clip '{}', expected near[:far]", s);
+    let mut parts = s.splitn(2, ':');
+    let near: Float = parts.next().and_then(|p| p.trim().parse().ok()).ok_or_else(invalid)?;
+    let far: Float = match parts.next() {
+        Some(p) => p.trim().parse().map_err(|_| invalid())?,
+        None => INF,
+    };
+    if !(near >= 0.0 && far > near) {
+        return Err(invalid())
+    }
+    Ok(Clip::new(near, far, false))
+}
+
+// Exposures are given as `f-number,shutter,iso`, where the shutter
+// time in seconds may be a fraction, e.g. `8,1/60,400`.
+fn parse_exposure(s: &str) -> Result<Exposure, String> {
+    let invalid = || format!("invalid exposure '{}', expected f-number,shutter,iso", s);
+    let number = |p: &str| -> Option<Float> {
+        let mut parts = p.trim().splitn(2, '/');
+        let x: Float = parts.next()?.parse().ok()?;
+        let x = match parts.next() {
+            Some(d) => x / d.parse::<Float>().ok()?,
+            None => x,
+        };
+        if x > 0.0 && x.is_finite() { Some(x) } else { None }
+    };
+    match s.split(',').map(number).collect::<Option<Vec<Float>>>() {
+        Some(parts) if parts.len() == 3 => Ok(Exposure { f_number: parts[0], shutter: parts[1], iso: parts[2] }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    Format::parse(s).ok_or_else(|| format!("unknown image format '{}'", s))
+}
+
+fn parse_integrator(s: &str) -> Result<Integrator, String> {
+    match s {
+        "path" => Ok(Integrator::Path),
+        "uv" => Ok(Integrator::Uv),
+        "focus" => Ok(Integrator::Focus),
+        _ => Err(format!("unknown integrator '{}'", s)),
+    }
+}
+
+// Focus points are given as `x,y` and rectangles as `x,y:w,h`, in
+// fractions of the image width and height, e.g. `0.25,0.25:0.5,0.5`.
+fn parse_focus(s: &str) -> Result<Focus, String> {
+    let invalid = || format!("invalid focus '{}', expected x,y[:w,h] within 0 to 1", s);
+    let pair = |p: &str| -> Option<(Float, Float)> {
+        let mut parts = p.splitn(2, ',');
+        let a = parts.next()?.trim().parse().ok()?;
+        let b = parts.next()?.trim().parse().ok()?;
+        Some((a, b))
+    };
+    let mut parts = s.splitn(2, ':');
+    let (x, y) = parts.next().and_then(pair).ok_or_else(invalid)?;
+    let (width, height) = match parts.next() {
+        Some(p) => pair(p).ok_or_else(invalid)?,
+        None => (0.0, 0.0),
+    };
+    let within = |a: Float, b: Float| a >= 0.0 && b >= 0.0 && a + b <= 1.0;
+    if !(within(x, width) && within(y, height)) {
+        return Err(invalid())
+    }
+    Ok(Focus { x, y, width, height })
+}
+
+fn parse_aovs(s: &str) -> Result<Vec<Aov>, String> {
+    s.split(',')
+        .map(|name| Aov::parse(name.trim()).ok_or_else(|| format!("unknown AOV '{}'", name)))
+        .collect()
+}
+
+fn parse_metering(s: &str) -> Result<Metering, String> {
+    Metering::parse(s).ok_or_else(|| format!("unknown metering '{}', expected average or center", s))
+}
+
+fn parse_filter(s: &str) -> Result<Filter, String> {
+    Filter::parse(s).ok_or_else(|| format!("unknown filter '{}', expected box, tent or gaussian with a radius of at least 0.5", s))
+}
+
+fn parse_glare(s: &str) -> Result<Glare, String> {
+    Glare::parse(s).ok_or_else(|| format!("invalid glare '{}', expected a strength from 0 to 1 and at least 3 blades", s))
+}
+
+fn parse_tonemap(s: &str) -> Result<ToneMap, String> {
+    ToneMap::parse(s).ok_or_else(|| format!("unknown tone mapping operator '{}'", s))
+}
+
+fn parse_color_space(s: &str) -> Result<ColorSpace, String> {
+    ColorSpace::parse(s).ok_or_else(|| format!("unknown color space '{}'", s))
+}
+
+// A number of frames, or a range of them.
+fn parse_frames(s: &str) -> Result<Range<usize>, String> {
+    if s.contains("..") {
+        return parse_range(s)
+    }
+    Ok(0..parse_count(s, "--frames", 1)?)
+}
+
+// Ranges are half open, e.g. `0..25`.
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid range '{}', expected start..end", s);
+    let mut parts = s.splitn(2, "..");
+    let start = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+    let end = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+    if start >= end {
+        return Err(invalid())
+    }
+    Ok(start..end)
+}
+
+// Durations are a sequence of numbers with h, m or s units,
+// e.g. `10m` or `1h30m`. A bare number is taken as seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{}'", s);
+    // Every part is finite and not negative, and the total has to fit
+    // in a `Duration`.
+    let seconds = |n: f64| if n >= 0.0 && n.is_finite() { Ok(n) } else { Err(invalid()) };
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds(secs)?).map_err(|_| invalid());
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in s.chars() {
+        let unit = match c {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => {
+                number.push(c);
+                continue
+            }
+        };
+        let n: f64 = number.parse().map_err(|_| invalid())?;
+        total += seconds(n)? * unit;
+        number.clear();
+    }
+    if !number.is_empty() || s.is_empty() {
+        return Err(invalid())
+    }
+    Duration::try_from_secs_f64(total).map_err(|_| invalid())
+}
+
+// Sizes are a number of bytes with an optional binary
+// K, M, G or T suffix, e.g. `512M` or `1.5G`.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size '{}'", s);
+    let (number, shift) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 30),
+        Some((i, 'T')) | Some((i, 't')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    let n: f64 = number.parse().map_err(|_| invalid())?;
+    if !(n >= 0.0 && n.is_finite()) {
+        return Err(invalid())
+    }
+    Ok((n * (1u64 << shift) as f64) as usize)
+}
+
+// Views are given as `from:at[:vfov]`, e.g. `13,2,3:0,0,0:20`.
+fn parse_view(s: &str) -> Result<View, String> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(format!("invalid camera '{}', expected from:at[:vfov]", s));
+    }
+
+    let vfov = match parts.get(2) {
+        Some(v) => v.parse().map_err(|_| format!("invalid field of view '{}'", v))?,
+        None => 20.0,
+    };
+    Ok(View {
+        look_from: parse_vec3(parts[0])?,
+        look_at: parse_vec3(parts[1])?,
+        vfov,
+        up: Vec3::new(0.0, 1.0, 0.0),
+        aperture: 0.1,
+        focus_dist: None,
+        projection: Projection::Perspective,
+    })
+}
+
+fn parse_projection(s: &str) -> Result<Projection, String> {
+    Projection::parse(s).ok_or_else(|| format!("unknown projection '{}', expected perspective, fisheye[:fov] or equirectangular", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Args, String> {
+        Args::parse(s.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let args = parse("--stats out.json --histogram hist.png").unwrap();
+        assert_eq!(Some("out.json".to_string()), args.stats);
+        assert_eq!(Some("hist.png".to_string()), args.histogram);
+    }
+
+    #[test]
+    fn test_parse_focus() {
+        let args = parse("--focus 0.25,0.5:0.5,0.25").unwrap();
+        assert_eq!(Some(Focus { x: 0.25, y: 0.5, width: 0.5, height: 0.25 }), args.focus);
+        assert_eq!(Some(Focus { x: 1.0, y: 0.0, width: 0.0, height: 0.0 }), parse("--focus 1,0").unwrap().focus);
+        assert!(parse("--focus 0.8,0.5:0.5,0.1").is_err());
+        assert!(parse("--focus 0.5").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(Filter::Box, parse("").unwrap().filter);
+        assert_eq!(Filter::Gaussian(2.0), parse("--filter gaussian:2").unwrap().filter);
+        assert!(parse("--filter tent:0").is_err());
+    }
+
+    #[test]
+    fn test_parse_glare() {
+        assert_eq!(None, parse("").unwrap().glare);
+        assert_eq!(Some(Glare { strength: 0.2, blades: 8 }), parse("--glare 0.2:8").unwrap().glare);
+        assert!(parse("--glare 0.2:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_aovs() {
+        let args = parse("--aov normal,albedo -o out.exr").unwrap();
+        assert_eq!(vec![Aov::Normal, Aov::Albedo], args.aovs);
+        assert!(parse("--aov depth").is_err());
+        assert!(parse("--aov depth,position -o out.exr").is_err());
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        let args = parse("--scene s.json --set material.gold.roughness=0.3 --sweep material.gold.albedo=[1,0,0],[0,1,0] -o out.png").unwrap();
+        assert_eq!(vec![Override::parse("material.gold.roughness=0.3").unwrap()], args.overrides);
+        assert_eq!(2, args.sweep.len());
+        assert_eq!(Override::parse("material.gold.albedo=[0,1,0]").unwrap(), args.sweep[1]);
+        assert!(parse("--set material.gold.roughness=0.3").is_err());
+        assert!(parse("--scene s.json --sweep material.gold.roughness=0,1").is_err());
+        assert!(parse("--scene s.json --set gold.roughness=0.3").is_err());
+        assert!(parse("--scene s.json --set material.gold.type=metal").is_err());
+    }
+
+    #[test]
+    fn test_parse_projection() {
+        assert_eq!(None, parse("").unwrap().projection);
+        assert_eq!(Some(Projection::Fisheye(200.0)), parse("--projection fisheye:200").unwrap().projection);
+        assert!(parse("--projection fisheye:400").is_err());
+        assert!(parse("--projection cylindrical").is_err());
+    }
+
+    #[test]
+    fn test_parse_cameras() {
+        let args = parse("--camera 13,2,3:0,0,0 --camera 0,1,5:0,1,0:40 -o out.ppm --export-cameras cams.json").unwrap();
+        assert_eq!(2, args.cameras.len());
+        assert_eq!(Some("cams.json".to_string()), args.export_cameras);
+        assert_eq!(Vec3::new(13.0, 2.0, 3.0), args.cameras[0].look_from);
+        assert_eq!(20.0, args.cameras[0].vfov);
+        assert_eq!(40.0, args.cameras[1].vfov);
+        assert_eq!(Some("out.ppm".to_string()), args.output);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Duration::from_secs(600), parse_duration("10m").unwrap());
+        assert_eq!(Duration::from_secs(5400), parse_duration("1h30m").unwrap());
+        assert_eq!(Duration::from_secs(45), parse_duration("45").unwrap());
+        assert_eq!(Duration::from_millis(1500), parse_duration("1.5s").unwrap());
+        assert!(parse_duration("10").is_ok());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("inf").is_err());
+        assert!(parse_duration("NaN").is_err());
+        assert!(parse_duration("infh").is_err());
+        assert!(parse_duration("1e30m").is_err());
+        assert!(parse_duration("1e300h1e300h").is_err());
+        assert!(parse_duration("-5").is_err());
+        assert!(parse_duration("2h-30m").is_err());
+    }
+
+    #[test]
+    fn test_parse_spp_range() {
+        let args = parse("--spp-range 25..50").unwrap();
+        assert_eq!(Some(25..50), args.spp_range);
+        assert!(parse("--spp-range 50..25").is_err());
+        assert!(parse("--spp-range 0..").is_err());
+        assert!(parse("--spp-range 0..5 --time-budget 1m").is_err());
+    }
+
+    #[test]
+    fn test_parse_merge() {
+        let cmd = Command::parse("merge out.exr a.exr b.exr".split_whitespace().map(String::from)).unwrap();
+        match cmd {
+            Command::Merge { output, inputs } => {
+                assert_eq!("out.exr", output);
+                assert_eq!(vec!["a.exr", "b.exr"], inputs);
+            }
+            _ => panic!("expected merge"),
+        }
+        assert!(Command::parse("merge out.exr".split_whitespace().map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_parse_matpreview() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("matpreview gold.json --samples 16") {
+            Ok(Command::Render(args)) => {
+                assert_eq!(Some("gold.json".to_string()), args.material);
+                assert_eq!(Some(16), args.samples);
+            }
+            _ => panic!("expected a render"),
+        }
+        assert!(command("matpreview").is_err());
+        assert!(command("matpreview gold.json --scene-name cornell").is_err());
+    }
+
+    #[test]
+    fn test_parse_bake() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("bake marble.json --width 512 -o marble.png") {
+            Ok(Command::Bake { texture, args }) => {
+                assert_eq!("marble.json", texture);
+                assert_eq!((Some(512), Some("marble.png".to_string())), (args.width, args.output));
+            }
+            _ => panic!("expected bake"),
+        }
+        assert!(command("bake").is_err());
+        assert!(command("bake marble.json --scene examples/scene.json").is_err());
+    }
+
+    #[test]
+    fn test_parse_pick() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("pick 120,45 --scene scene.json --width 400") {
+            Ok(Command::Pick { pixel, args }) => {
+                assert_eq!((120, 45), pixel);
+                assert_eq!((Some("scene.json".to_string()), Some(400)), (args.scene_file, args.width));
+            }
+            _ => panic!("expected pick"),
+        }
+        assert!(command("pick 120,45").is_err());
+        assert!(command("pick 120 --scene scene.json").is_err());
+        assert!(command("pick -1,4 --scene scene.json").is_err());
+        assert!(command("pick 1,4 --scene scene.json --frames 0..4").is_err());
+    }
+
+    #[test]
+    fn test_parse_gallery() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("gallery examples -o sheet.png --samples 4") {
+            Ok(Command::Gallery { dir, args }) => {
+                assert_eq!(Some("examples".to_string()), dir);
+                assert_eq!(Some(4), args.samples);
+            }
+            _ => panic!("expected gallery"),
+        }
+        assert!(matches!(command("gallery -o sheet.png"), Ok(Command::Gallery { dir: None, .. })));
+        assert!(command("gallery").is_err());
+        assert!(command("gallery -o sheet.png --scene-name cornell").is_err());
+        assert!(command("gallery -o sheet.png --frames 0..4").is_err());
+    }
+
+    #[test]
+    fn test_parse_daemon() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("daemon /tmp/rt.sock --scene-name cornell --width 300") {
+            Ok(Command::Daemon { socket, args, options }) => {
+                assert_eq!("/tmp/rt.sock", socket);
+                assert_eq!(Some(300), args.width);
+                assert_eq!(4, options.len());
+            }
+            _ => panic!("expected a daemon"),
+        }
+        assert!(command("daemon").is_err());
+        assert!(command("daemon /tmp/rt.sock --frames 4").is_err());
+        assert!(matches!(command("request /tmp/rt.sock -o a.png"), Ok(Command::Request { .. })));
+
+        let words = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<String>>();
+        let daemon = words("--scene-name cornell --camera 0,0,0:0,0,-1 --samples 16 -o out.png");
+        let args = parse_request(&daemon, &words("--samples 64 --camera 1,2,3:0,0,0 --aperture 0.5")).unwrap();
+        assert_eq!(Some(64), args.samples);
+        assert_eq!(1, args.cameras.len());
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0), args.cameras[0].look_from);
+        assert_eq!(0.5, args.cameras[0].aperture);
+        assert_eq!(Some("cornell".to_string()), args.scene_name);
+        assert_eq!(1, parse_request(&daemon, &[]).unwrap().cameras.len());
+        assert!(parse_request(&daemon, &words("--scene-name random")).is_err());
+        assert!(parse_request(&daemon[..6], &words("--samples 4")).is_err());
+    }
+
+    #[test]
+    fn test_parse_selftest() {
+        let cmd = Command::parse("selftest".split_whitespace().map(String::from)).unwrap();
+        assert!(matches!(cmd, Command::SelfTest));
+        assert!(Command::parse("selftest --bogus".split_whitespace().map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_parse_shutter() {
+        let args = parse("--scene-name motion --shutter 0,0.5 --obj a.obj --obj b.obj").unwrap();
+        assert_eq!(vec!["a.obj", "b.obj"], args.meshes);
+        assert_eq!(None, args.mesh_texture);
+        assert!(args.mesh_placements.is_empty());
+        let placed = parse("--obj a.obj --obj-at 1,2,3 --obj-at 0,0,0:0,90,0").unwrap();
+        assert_eq!(RigidTransform::new(Vec3::new(1.0, 2.0, 3.0), Vec3::default()), placed.mesh_placements[0]);
+        assert_eq!(RigidTransform::new(Vec3::default(), Vec3::new(0.0, 90.0, 0.0)), placed.mesh_placements[1]);
+        assert!(parse("--obj-at 1,2").is_err());
+        let culled = parse("--obj a.obj --obj-cull-shadows").unwrap();
+        assert!(culled.cull_shadow_backfaces && !culled.cull_backfaces && !args.cull_shadow_backfaces);
+        assert_eq!(Some("motion".to_string()), args.scene_name);
+        assert_eq!(Some("s.json".to_string()), parse("--scene s.json").unwrap().scene_file);
+        assert!(parse("--scene s.json --scene-name random").is_err());
+        assert!(parse("--scene s.json --strict").unwrap().strict);
+        assert_eq!(Some((0.0, 0.5)), args.shutter);
+        assert_eq!(Some(0..24), parse("--frames 24").unwrap().frames);
+        assert_eq!(Some(100..240), parse("--frames 100..240").unwrap().frames);
+        assert!(parse("--frames 0").is_err());
+        assert!(parse("--frames 5..5").is_err());
+        assert_eq!(Some(24.0), parse("--frames 48 --fps 24").unwrap().fps);
+        assert!(parse("--fps 0").is_err());
+        assert!(parse("--shutter 1,0").is_err());
+        assert!(parse("--shutter 1").is_err());
+
+        assert_eq!(Some(Clip::new(2.0, INF, false)), parse("--clip 2").unwrap().clip);
+        assert_eq!(Some(Clip::new(2.0, 5.0, true)), parse("--clip 2:5 --clip-cap").unwrap().clip);
+        assert!(parse("--clip 5:2").is_err());
+        assert!(parse("--clip-cap").is_err());
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let args = parse("--width 640 --height 360 --samples 8 --max-depth 4").unwrap();
+        assert_eq!((Some(640), Some(360)), (args.width, args.height));
+        assert_eq!(Some(8), args.samples);
+        assert_eq!(Some(4), args.max_depth);
+        assert_eq!(None, args.ray_bias);
+        assert_eq!(Some(0.01), parse("--min-throughput 0.01").unwrap().min_throughput);
+        assert!(parse("--min-throughput 0").is_err());
+        assert_eq!(Some(8), parse("--split 8").unwrap().split);
+        assert!(parse("--split 0").is_err());
+        assert!(parse("--preview -o out.png").unwrap().preview);
+        assert!(parse("--preview").is_err());
+        assert!(parse("--half-float").unwrap().half_float);
+        let exposure = Exposure { f_number: 8.0, shutter: 1.0 / 60.0, iso: 400.0 };
+        assert_eq!(Some(exposure), parse("--exposure 8,1/60,400").unwrap().exposure);
+        assert!(parse("--exposure 8,0,400").is_err());
+        assert!(parse("--exposure 8,1/60").is_err());
+        assert_eq!(Some(Metering::Average), parse("--auto-exposure average").unwrap().auto_exposure);
+        assert!(parse("--auto-exposure spot").is_err());
+        assert!(parse("--exposure 8,1/60,400 --auto-exposure center").is_err());
+        assert_eq!(Some(ColorSpace::DisplayP3), parse("--color-space display-p3").unwrap().color_space);
+        assert!(parse("--color-space acescg -o out.exr").is_ok());
+        assert!(parse("--color-space acescg -o out.png").is_err());
+        assert!(parse("--color-space acescg -o out.png --format hdr").is_ok());
+        assert_eq!(Some(Format::Exr), parse("--format exr").unwrap().format);
+        assert!(parse("--format tiff").is_err());
+        assert_eq!(ToneMap::Clamp, parse("").unwrap().tonemap);
+        assert_eq!(ToneMap::Aces, parse("--tonemap aces").unwrap().tonemap);
+        assert!(parse("--tonemap hable").is_err());
+        assert_eq!(None, parse("").unwrap().ambient);
+        let ambient = parse("--ambient 0.1,0.1,0.1").unwrap().ambient.unwrap();
+        assert_eq!(ambient.sky, ambient.ground);
+        let ambient = parse("--ambient 0.5,0.6,1:0.2,0.1,0").unwrap().ambient.unwrap();
+        assert_eq!((Vec3::new(0.5, 0.6, 1.0), Vec3::new(0.2, 0.1, 0.0)), (ambient.sky, ambient.ground));
+        assert!(parse("--ambient -1,0,0").is_err());
+        assert!(parse("--ambient 1,1").is_err());
+        assert_eq!(Integrator::Uv, parse("--integrator uv").unwrap().integrator);
+        assert_eq!(Integrator::Focus, parse("--integrator focus").unwrap().integrator);
+        assert!(parse("--integrator normals").is_err());
+        assert_eq!(Some(42), parse("--seed 42").unwrap().seed);
+        assert!(parse("--seed -1").is_err());
+        assert!(parse("--color-space xyz").is_err());
+        assert_eq!(Some(0.2), parse("--irradiance-cache 0.2").unwrap().irradiance_cache);
+        assert_eq!(Clamp { direct: None, indirect: Some(10.0) }, parse("--clamp 10").unwrap().clamp);
+        assert_eq!(Clamp { direct: Some(50.0), indirect: Some(4.0) }, parse("--clamp-direct 50 --clamp 4").unwrap().clamp);
+        assert!(parse("--clamp -1").is_err());
+        assert!(parse("--irradiance-cache 0").is_err());
+        assert!(parse("--irradiance-cache 2").is_err());
+        assert_eq!(Some(0.5), parse("--ray-bias 0.5").unwrap().ray_bias);
+        assert_eq!(None, parse("--ray-bias auto").unwrap().ray_bias);
+        assert!(parse("--ray-bias -1").is_err());
+        assert!(parse("--width 0").is_err());
+        assert!(parse("--samples -1").is_err());
+        assert!(parse("--samples 4 --time-budget 1m").is_err());
+    }
+
+    #[test]
+    fn test_parse_threads() {
+        let args = parse("--threads 3 --pin-threads").unwrap();
+        assert_eq!(Some(3), args.threads);
+        assert!(args.pin_threads);
+        assert!(parse("--threads 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(1000, parse_size("1000").unwrap());
+        assert_eq!(512 << 20, parse_size("512M").unwrap());
+        assert_eq!(3 << 29, parse_size("1.5g").unwrap());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-1G").is_err());
+        assert!(parse_size("12X").is_err());
+    }
+
+    #[test]
+    fn test_parse_lens() {
+        let args = parse("--camera 0,0,5:0,0,0 --aperture 0.5 --focus-dist 3").unwrap();
+        assert_eq!(0.5, args.cameras[0].aperture);
+        assert_eq!(Some(3.0), args.cameras[0].focus_dist);
+        assert_eq!(0.1, parse("--camera 0,0,5:0,0,0").unwrap().cameras[0].aperture);
+        assert!(parse("--aperture 0.5").is_err());
+        assert!(parse("--camera 0,0,5:0,0,0 --aperture -1").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("--camera 1,2:0,0,0").is_err());
+        assert!(parse("--camera 1,2,3").is_err());
+        assert!(parse("--stats").is_err());
+        assert!(parse("--bogus").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{INF, Float};
+use crate::vec::{Vec3, Point3, dot};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::onb::Onb;
+
+// Distances along a camera's view direction between which geometry is
+// kept, for cutaway renders. A near distance past the camera works as a
+// section plane, cutting open whatever it passes through. With capping,
+// solids cut open are closed by a face on the plane, made of the
+// material of the solid behind it, rather than showing their insides.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clip {
+    pub near: Float,
+    pub far: Float,
+    pub cap: bool,
+}
+
+impl Clip {
+    pub fn new(near: Float, far: Float, cap: bool) -> Self {
+        Self { near, far, cap }
+    }
+}
+
+// An object cut down to the slab of a clip placed in front of a camera.
+// Every ray is clipped, not only those from the camera, so that what is
+// cut away casts no shadows and shows in no reflections either.
+pub struct Clipped<'a, T: Hittable> {
+    object: &'a T,
+    // Camera position and unit view direction the distances are along.
+    origin: Point3,
+    forward: Vec3,
+    clip: Clip,
+}
+
+impl<'a, T: Hittable> Clipped<'a, T> {
+    pub fn new(object: &'a T, origin: Point3, forward: Vec3, clip: Clip) -> Self {
+        Self { object, origin, forward, clip }
+    }
+
+    // The interval of the ray within the slab, if it crosses it.
+    fn span(&self, r: &Ray) -> Option<(Float, Float)> {
+        let along = dot(&(r.orig - self.origin), &self.forward);
+        let speed = dot(&r.dir, &self.forward);
+        if speed == 0.0 {
+            let inside = (self.clip.near..=self.clip.far).contains(&along);
+            return if inside { Some((-INF, INF)) } else { None }
+        }
+        let t0 = (self.clip.near - along) / speed;
+        let t1 = (self.clip.far - along) / speed;
+        Some((t0.min(t1), t0.max(t1)))
+    }
+}
+
+impl<T: Hittable> Hittable for Clipped<'_, T> {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let (enter, exit) = self.span(r)?;
+        let (start, end) = (t_min.max(enter), t_max.min(exit));
+        if start >= end {
+            return None
+        }
+        let rec = self.object.hit(r, start, end)?;
+
+        // Leaving a solid before entering it means the ray was already
+        // inside it where it crossed into the slab.
+        if self.clip.cap && enter > t_min && !rec.front_face {
+            // Caps are textured by the position on the plane, across the
+            // view, repeating every unit of the scene.
+            let p = r.at(enter);
+            let across = Onb::from_w(&self.forward);
+            let offset = p - self.origin;
+            let mut cap = HitRecord {
+                p,
+                normal: Vec3::default(),
+                t: enter,
+                u: dot(&offset, &across.u).rem_euclid(1.0),
+                v: dot(&offset, &across.v).rem_euclid(1.0),
+                front_face: true,
+                mat: rec.mat,
+            };
+            let outward = if dot(&r.dir, &self.forward) > 0.0 { -self.forward } else { self.forward };
+            cap.set_face_normal(r, &outward);
+            return Some(cap)
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+    use crate::vec::Color;
+
+    #[test]
+    fn test_clip() {
+        let ball = Sphere::new(Point3::new(0.0, 0.0, -5.0), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let r = Ray::new(&Point3::default(), &forward, 0.0);
+        let clipped = |near, far, cap| Clipped::new(&ball, Point3::default(), forward, Clip::new(near, far, cap));
+
+        // Outside the slab the sphere is gone, inside it is untouched.
+        assert_eq!(4.0, clipped(0.0, INF, false).hit(&r, 0.001, INF).unwrap().t);
+        assert!(clipped(0.0, 3.0, false).hit(&r, 0.001, INF).is_none());
+        assert!(clipped(7.0, INF, false).hit(&r, 0.001, INF).is_none());
+
+        // Cut open, the far side is seen from within, unless capped.
+        let hit = |cap| clipped(5.0, INF, cap).hit(&r, 0.001, INF).map(|rec| (rec.t, rec.front_face, rec.normal));
+        assert_eq!(Some((6.0, false, Vec3::new(0.0, 0.0, 1.0))), hit(false));
+        assert_eq!(Some((5.0, true, Vec3::new(0.0, 0.0, 1.0))), hit(true));
+
+        // Caps are textured across the plane, not by one texel.
+        let off_center = Ray::new(&Point3::new(0.25, 0.5, 0.0), &forward, 0.0);
+        let capped = clipped(5.0, INF, true);
+        let uv = |r| capped.hit(r, 0.001, INF).map(|rec| (rec.u, rec.v)).unwrap();
+        let (u, v) = uv(&off_center);
+        assert_ne!(uv(&r), (u, v));
+        assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+    }
+}
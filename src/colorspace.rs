@@ -0,0 +1,130 @@
+use crate::Float;
+use crate::vec::Color;
+
+// Rendering happens in linear sRGB, that is with the primaries and D65
+// white point shared by sRGB and Rec.709. Output color spaces convert
+// from it to their own primaries and apply their own transfer function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    // The wide gamut of recent displays, with the sRGB transfer function.
+    DisplayP3,
+    // HDTV video, with the primaries of sRGB and the camera transfer function.
+    Rec709,
+    // The linear working space of ACES with the wide AP1 primaries and
+    // a D60 white point, only meaningful for OpenEXR output.
+    AcesCg,
+}
+
+// Linear sRGB to Display P3, both with a D65 white point.
+const SRGB_TO_P3: [[Float; 3]; 3] = [
+    [0.822_462, 0.177_538, 0.0],
+    [0.033_194, 0.966_806, 0.0],
+    [0.017_083, 0.072_397, 0.910_520],
+];
+
+// Linear sRGB to ACEScg, adapting the white point from D65 to D60
+// with the Bradford transform as ACES does.
+const SRGB_TO_ACESCG: [[Float; 3]; 3] = [
+    [0.613_097, 0.339_523, 0.047_380],
+    [0.070_194, 0.916_354, 0.013_452],
+    [0.020_616, 0.109_570, 0.869_815],
+];
+
+impl ColorSpace {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "display-p3" => Some(ColorSpace::DisplayP3),
+            "rec709" => Some(ColorSpace::Rec709),
+            "acescg" => Some(ColorSpace::AcesCg),
+            _ => None,
+        }
+    }
+
+    // Linear spaces are only written to float formats, since 8 bits
+    // without a transfer function band badly in the shadows.
+    pub fn is_linear(&self) -> bool {
+        *self == ColorSpace::AcesCg
+    }
+
+    // A linear color of the working space in this space's primaries.
+    pub fn convert(&self, c: Color) -> Color {
+        let m = match self {
+            ColorSpace::Srgb | ColorSpace::Rec709 => return c,
+            ColorSpace::DisplayP3 => &SRGB_TO_P3,
+            ColorSpace::AcesCg => &SRGB_TO_ACESCG,
+        };
+        let row = |r: &[Float; 3]| r[0] * c.x + r[1] * c.y + r[2] * c.z;
+        Color::new(row(&m[0]), row(&m[1]), row(&m[2]))
+    }
+
+    // Applies the transfer function to a linear value in [0, 1].
+    pub fn encode(&self, v: Float) -> Float {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => {
+                if v <= 0.003_130_8 { 12.92 * v } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+            }
+            ColorSpace::Rec709 => {
+                if v < 0.018 { 4.5 * v } else { 1.099 * v.powf(0.45) - 0.099 }
+            }
+            ColorSpace::AcesCg => v,
+        }
+    }
+
+    // Converts and encodes a working space color, quantized to 8 bits.
+    pub fn to_display(self, c: Color) -> [u8; 3] {
+        let c = self.convert(c);
+        let q = |v: Float| (256.0 * self.encode(v.clamp(0.0, 1.0)).min(0.999)) as u8;
+        [q(c.x), q(c.y), q(c.z)]
+    }
+
+    // CIE xy chromaticities of the red, green and blue primaries and of
+    // the white point, in the layout of the OpenEXR attribute.
+    pub fn chromaticities(&self) -> [f32; 8] {
+        match self {
+            ColorSpace::Srgb | ColorSpace::Rec709 => [0.64, 0.33, 0.30, 0.60, 0.15, 0.06, 0.3127, 0.3290],
+            ColorSpace::DisplayP3 => [0.680, 0.320, 0.265, 0.690, 0.150, 0.060, 0.3127, 0.3290],
+            ColorSpace::AcesCg => [0.713, 0.293, 0.165, 0.830, 0.128, 0.044, 0.32168, 0.33767],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Color, b: Color) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn test_primaries() {
+        // White stays white, and the pure sRGB primaries lie inside the
+        // wider gamuts, so they get positive but mixed components.
+        let white = Color::new(1.0, 1.0, 1.0);
+        for &space in [ColorSpace::Srgb, ColorSpace::DisplayP3, ColorSpace::Rec709, ColorSpace::AcesCg].iter() {
+            assert!(close(white, space.convert(white)), "{:?}", space);
+        }
+        let red = ColorSpace::DisplayP3.convert(Color::new(1.0, 0.0, 0.0));
+        assert!(close(Color::new(0.822_462, 0.033_194, 0.017_083), red), "{:?}", red);
+        let green = ColorSpace::AcesCg.convert(Color::new(0.0, 1.0, 0.0));
+        assert!(green.y < 1.0 && green.x > 0.0 && green.z > 0.0, "{:?}", green);
+    }
+
+    #[test]
+    fn test_transfer_functions() {
+        let srgb = ColorSpace::Srgb;
+        assert_eq!(0.0, srgb.encode(0.0));
+        assert!((srgb.encode(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb.encode(0.18) - 0.4614).abs() < 1e-3);
+        // Both pieces of the curves meet at the break point.
+        assert!((srgb.encode(0.003_130_8) - srgb.encode(0.003_130_9)).abs() < 1e-5);
+        let rec709 = ColorSpace::Rec709;
+        assert!((rec709.encode(0.018) - 0.081).abs() < 1e-3);
+        assert!((rec709.encode(1.0) - 1.0).abs() < 1e-6);
+        assert_eq!(0.5, ColorSpace::AcesCg.encode(0.5));
+
+        assert_eq!([0, 118, 255], srgb.to_display(Color::new(-1.0, 0.18, 4.0)));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{random_float, Float};
+use crate::ray::Ray;
+use crate::hittable::{HitRecord, Hittable};
+use crate::aabb::Aabb;
+
+// An object of a masked material, whose hits rays may pass through as
+// its opacity map says; see `Masked`. Hits let through are skipped for
+// the next one along the ray, so the same hit test serves camera rays,
+// bounces and shadow rays alike, without the bounce glass would cost.
+// Each ray decides anew at every hit, which averages out over samples
+// to the opacity.
+pub struct Cutout {
+    object: Box<dyn Hittable>,
+}
+
+impl Cutout {
+    pub fn new(object: Box<dyn Hittable>) -> Self {
+        Self { object }
+    }
+}
+
+impl Hittable for Cutout {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut t_min = t_min;
+        loop {
+            let rec = self.object.hit(r, t_min, t_max)?;
+            let opacity = rec.mat.opacity(&rec);
+            if opacity >= 1.0 || (opacity > 0.0 && random_float() < opacity) {
+                return Some(rec)
+            }
+            // Strictly past the hit, which some objects would find again.
+            t_min = rec.t.next_up();
+        }
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::vec::{Vec3, Point3, Color};
+    use crate::aarect::{AaRect, Plane};
+    use crate::hittable::HittableList;
+    use crate::material::{Lambertian, Masked};
+    use crate::texture::{CheckerTexture, SolidColor};
+
+    #[test]
+    fn test_cutout() {
+        // Two fences with every other unit cut out, one behind the other,
+        // where the checkers of the near one have holes the far one fills.
+        let fence = |z: Float| -> Box<dyn Hittable> {
+            let holes = Arc::new(CheckerTexture::new(
+                Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0))),
+                Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0))),
+                1.0,
+            ));
+            let mat = Arc::new(Masked::new(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))), holes, 0.5));
+            Box::new(AaRect::new(Plane::Xy, (-4.0, 4.0), (-4.0, 4.0), z, mat))
+        };
+        let mut fences = HittableList::default();
+        fences.add(fence(0.0));
+        fences.add(fence(-1.0));
+        let fences = Cutout::new(Box::new(fences));
+
+        let rays: Vec<Ray> = (0..8).map(|i| {
+            let x = i as Float - 3.5;
+            Ray { shadow: true, ..Ray::new(&Point3::new(x, 0.5, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0) }
+        }).collect();
+        let hits: Vec<Option<Float>> = rays.iter().map(|r| fences.hit(r, 0.001, Float::INFINITY).map(|rec| rec.p.z)).collect();
+        assert_eq!(4, hits.iter().filter(|&&z| z == Some(0.0)).count());
+        assert_eq!(4, hits.iter().filter(|&&z| z == Some(-1.0)).count());
+
+        // Half opaque, half of the rays get through.
+        let glass = Arc::new(Masked::new(
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            Arc::new(SolidColor::new(Color::new(0.5, 0.5, 0.5))),
+            0.0,
+        ));
+        let pane = Cutout::new(Box::new(AaRect::new(Plane::Xy, (-1.0, 1.0), (-1.0, 1.0), 0.0, glass)));
+        let r = Ray::new(&Point3::new(0.0, 0.0, 1.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hits = (0..10000).filter(|_| pane.hit(&r, 0.001, Float::INFINITY).is_some()).count();
+        assert!((hits as i64 - 5000).abs() < 300, "{}", hits);
+    }
+}
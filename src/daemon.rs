@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+// A daemon keeps a scene loaded between renders, so that rendering it
+// again from another camera or with other settings skips loading it and
+// building its hierarchy. Requests come over a Unix socket, one for each
+// connection, as the options of the render one on each line, ended by an
+// empty line. Once the render is done the daemon answers with a line of
+// `ok` and a message, or of `error:` and what went wrong.
+
+// Listens on the socket at the path. A socket left behind by a daemon
+// that is gone is replaced, while one still in use is an error.
+pub fn bind(path: &str) -> io::Result<UnixListener> {
+    if Path::new(path).exists() && UnixStream::connect(path).is_err() {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+// Handles the requests to the socket one after the other, as every
+// render takes all of the cores anyway. Requests that can't be read
+// are reported and dropped.
+pub fn serve<F>(listener: UnixListener, mut handle: F) -> io::Result<()>
+where
+    F: FnMut(Vec<String>) -> Result<String, String>,
+{
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let options = match read_request(&stream) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("error: failed to read request: {}", e);
+                continue
+            }
+        };
+        let reply = match handle(options) {
+            Ok(msg) => format!("ok {}\n", msg),
+            Err(e) => format!("error: {}\n", e),
+        };
+        if let Err(e) = stream.write_all(reply.as_bytes()) {
+            eprintln!("error: failed to answer request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn read_request(stream: &UnixStream) -> io::Result<Vec<String>> {
+    let mut options = Vec::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.is_empty() {
+            return Ok(options)
+        }
+        options.push(line);
+    }
+    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request not ended by an empty line"))
+}
+
+// Sends the options to the daemon listening on the socket and waits for
+// the render, returning the daemon's message or the error it gave.
+pub fn request(path: &str, options: &[String]) -> io::Result<Result<String, String>> {
+    if options.iter().any(|option| option.is_empty() || option.contains('\n')) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "options must be single lines of text"))
+    }
+    let mut stream = UnixStream::connect(path)?;
+    let text: String = options.iter().map(|option| format!("{}\n", option)).collect();
+    stream.write_all(format!("{}\n", text).as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    let reply = reply.trim_end();
+    if let Some(msg) = reply.strip_prefix("error: ") {
+        return Ok(Err(msg.to_string()))
+    }
+    match reply.strip_prefix("ok") {
+        Some(msg) => Ok(Ok(msg.trim_start().to_string())),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply '{}'", reply))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_requests() {
+        let path = std::env::temp_dir().join(format!("raytracing-test-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let listener = bind(path).unwrap();
+        // A second daemon can't take over the socket.
+        assert!(bind(path).is_err());
+
+        thread::spawn(move || serve(listener, |options| match options.first().map(String::as_str) {
+            Some("--bogus") => Err("unknown argument '--bogus'".to_string()),
+            _ => Ok(options.join(" ")),
+        }));
+        let options = ["--camera".to_string(), "0,0,0:0,0,-1".to_string()];
+        assert_eq!(Ok("--camera 0,0,0:0,0,-1".to_string()), request(path, &options).unwrap());
+        assert_eq!(Err("unknown argument '--bogus'".to_string()), request(path, &["--bogus".to_string()]).unwrap());
+        assert!(request(path, &["two\nlines".to_string()]).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// Evaluates an arithmetic expression of the scene time `t`, such as
+// `45 * t` or `4 + 2 * sin(2 * pi * t)`. Expressions have numbers, `t`,
+// `pi`, the operators + - * / and ^ (power), parentheses, and the
+// functions sin, cos, abs, sqrt, floor, min and max. Also returns
+// whether the value depends on the time.
+pub fn evaluate(text: &str, t: f64) -> Result<(f64, bool), String> {
+    let mut parser = Parser { text, chars: text.char_indices().peekable(), t, uses_time: false };
+    let value = parser.sum()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok((value, parser.uses_time)),
+        Some(_) => Err(parser.error("unexpected character")),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    t: f64,
+    uses_time: bool,
+}
+
+impl Parser<'_> {
+    fn error(&mut self, msg: &str) -> String {
+        let offset = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        format!("{} at column {} of '{}'", msg, offset + 1, self.text)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|&(_, c)| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    // The next character after any whitespace, taken if it is one of the given.
+    fn operator(&mut self, ops: &str) -> Option<char> {
+        self.skip_whitespace();
+        let c = self.chars.peek().map(|&(_, c)| c).filter(|&c| ops.contains(c))?;
+        self.chars.next();
+        Some(c)
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(op) = self.operator("+-") {
+            let rhs = self.product()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.operator("*/") {
+            let rhs = self.unary()?;
+            value = if op == '*' { value * rhs } else { value / rhs };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.operator("-").is_some() {
+            return Ok(-self.unary()?)
+        }
+        let base = self.atom()?;
+        // Powers bind tighter than negation and group to the right.
+        match self.operator("^") {
+            Some(_) => Ok(base.powf(self.unary()?)),
+            None => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|&(_, c)| c) {
+            Some('(') => {
+                self.chars.next();
+                let value = self.sum()?;
+                self.close()?;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.name(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.operator(")") {
+            Some(_) => Ok(()),
+            None => Err(self.error("expected ')'")),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let start = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        let mut end = start;
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !(c.is_ascii_digit() || c == '.') {
+                break
+            }
+            end = i + 1;
+            self.chars.next();
+        }
+        let digits = &self.text[start..end];
+        digits.parse().map_err(|_| format!("invalid number '{}' in '{}'", digits, self.text))
+    }
+
+    fn name(&mut self) -> Result<f64, String> {
+        let start = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        let mut end = start;
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !c.is_ascii_alphanumeric() {
+                break
+            }
+            end = i + 1;
+            self.chars.next();
+        }
+        let name = &self.text[start..end];
+        match name {
+            "t" => {
+                self.uses_time = true;
+                return Ok(self.t)
+            }
+            "pi" => return Ok(std::f64::consts::PI),
+            _ => {}
+        }
+
+        if self.operator("(").is_none() {
+            return Err(format!("unknown name '{}' in '{}'", name, self.text))
+        }
+        let mut args = vec![self.sum()?];
+        while self.operator(",").is_some() {
+            args.push(self.sum()?);
+        }
+        self.close()?;
+        match (name, &args[..]) {
+            ("sin", &[x]) => Ok(x.sin()),
+            ("cos", &[x]) => Ok(x.cos()),
+            ("abs", &[x]) => Ok(x.abs()),
+            ("sqrt", &[x]) => Ok(x.sqrt()),
+            ("floor", &[x]) => Ok(x.floor()),
+            ("min", &[a, b]) => Ok(a.min(b)),
+            ("max", &[a, b]) => Ok(a.max(b)),
+            _ => Err(format!("unknown function '{}' of {} arguments in '{}'", name, args.len(), self.text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(Ok((7.0, false)), evaluate("1 + 2 * 3", 0.0));
+        assert_eq!(Ok((-8.0, false)), evaluate("-2^3", 0.0));
+        assert_eq!(Ok((512.0, false)), evaluate("2^3^2", 0.0));
+        assert_eq!(Ok((22.5, true)), evaluate("45 * t", 0.5));
+        assert_eq!(Ok((1.5, true)), evaluate("max(1, (t - 1) / 2) + floor(0.5)", 4.0));
+        let (value, _) = evaluate("4 + 2 * sin(2 * pi * t)", 0.25).unwrap();
+        assert!((value - 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Err("expected ')' at column 7 of '(1 + 2'".to_string()), evaluate("(1 + 2", 0.0));
+        assert!(evaluate("1 +", 0.0).is_err());
+        assert!(evaluate("2 x", 0.0).is_err());
+        assert!(evaluate("time", 0.0).unwrap_err().contains("unknown name 'time'"));
+        assert!(evaluate("sin(1, 2)", 0.0).unwrap_err().contains("unknown function"));
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::{half, to_f32, Float};
+use crate::image::Image;
+use crate::vec::Color;
+use crate::colorspace::ColorSpace;
+
+pub const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+// File format version 2 with the "non-image" (deep data) flag set.
+const DEEP_VERSION: u32 = VERSION | 0x800;
+// Tiled, long name, deep and multi-part flags.
+const UNSUPPORTED_FLAGS: u32 = 0x200 | 0x400 | 0x800 | 0x1000;
+
+const PIXEL_TYPE_HALF: i32 = 1;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+const COMPRESSION_NONE: u8 = 0;
+const LINE_ORDER_INCREASING_Y: u8 = 0;
+
+// Samples whose depths differ by less than this fraction
+// of their distance are merged into a single deep sample.
+const MERGE_TOLERANCE: f32 = 0.01;
+
+// Rays that escape to the sky are stored at the far end of
+// the float range so they composite behind everything else.
+pub const SKY_DEPTH: f32 = f32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeepSample {
+    pub z: f32,
+    // Sum of the radiance of every camera sample merged here.
+    pub color: Color,
+    pub count: usize,
+}
+
+// A deep image keeps a depth-sorted list of samples for each
+// pixel instead of one merged color, so it can be composited
+// correctly with holdouts and volumetrics after rendering.
+pub struct DeepImage {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Vec<DeepSample>>,
+    counts: Vec<usize>,
+}
+
+impl DeepImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Vec::new(); width * height],
+            counts: vec![0; width * height],
+        }
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, z: f32, color: Color) {
+        let idx = y * self.width + x;
+        self.counts[idx] += 1;
+
+        let samples = &mut self.pixels[idx];
+        let close = samples.iter_mut()
+            .find(|s| (s.z - z).abs() <= MERGE_TOLERANCE * s.z.min(z));
+        match close {
+            Some(s) => {
+                s.color += color;
+                s.count += 1;
+            }
+            None => {
+                let pos = samples.iter().position(|s| s.z > z).unwrap_or(samples.len());
+                samples.insert(pos, DeepSample { z, color, count: 1 });
+            }
+        }
+    }
+
+    fn max_samples_per_pixel(&self) -> usize {
+        self.pixels.iter().map(|p| p.len()).max().unwrap_or(0)
+    }
+
+    // Writes an uncompressed single-part deep scanline OpenEXR file
+    // with premultiplied R, G, B, alpha A and depth Z channels.
+    pub fn write_exr<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&DEEP_VERSION.to_le_bytes());
+
+        write_attr(&mut header, "channels", "chlist", &chlist(&["A", "B", "G", "R", "Z"], PIXEL_TYPE_FLOAT));
+        write_attr(&mut header, "chunkCount", "int", &(self.height as i32).to_le_bytes());
+        write_window_attrs(&mut header, self.width, self.height);
+        let max_samples = self.max_samples_per_pixel() as i32;
+        write_attr(&mut header, "maxSamplesPerPixel", "int", &max_samples.to_le_bytes());
+        write_attr(&mut header, "name", "string", b"beauty");
+        write_attr(&mut header, "type", "string", b"deepscanline");
+        write_attr(&mut header, "version", "int", &1i32.to_le_bytes());
+        header.push(0);
+
+        let chunks: Vec<Vec<u8>> = (0..self.height).map(|y| self.scanline_chunk(y)).collect();
+
+        write_chunks(out, &header, &chunks)
+    }
+
+    fn scanline_chunk(&self, y: usize) -> Vec<u8> {
+        let row = &self.pixels[y * self.width..(y + 1) * self.width];
+        let counts = &self.counts[y * self.width..(y + 1) * self.width];
+
+        // Cumulative sample counts, one entry per pixel.
+        let mut offsets = Vec::with_capacity(self.width * 4);
+        let mut total = 0i32;
+        for samples in row.iter() {
+            total += samples.len() as i32;
+            offsets.extend_from_slice(&total.to_le_bytes());
+        }
+
+        // Each deep sample covers a fraction of the camera samples. Since
+        // compositing front to back with "over" attenuates later samples by
+        // the coverage already in front of them, alpha and color are scaled
+        // by the remaining transmittance so the flattened deep pixel equals
+        // the ordinary averaged pixel.
+        let mut values: Vec<[f32; 5]> = Vec::with_capacity(total as usize);
+        for (samples, &n) in row.iter().zip(counts) {
+            let weight = 1.0 / n.max(1) as f32;
+            let mut transmittance = 1.0;
+            for s in samples.iter() {
+                let coverage = s.count as f32 * weight;
+                let scale = weight / transmittance;
+                let c = s.color * scale as Float;
+                values.push([coverage / transmittance, to_f32(c.z), to_f32(c.y), to_f32(c.x), s.z]);
+                transmittance = f32::max(transmittance - coverage, f32::EPSILON);
+            }
+        }
+
+        // Sample data is stored channel by channel, in the
+        // same alphabetical order as the channel list.
+        let mut data = Vec::with_capacity(values.len() * 5 * 4);
+        for channel in 0..5 {
+            for v in values.iter() {
+                data.extend_from_slice(&v[channel].to_le_bytes());
+            }
+        }
+
+        let mut chunk = Vec::with_capacity(28 + offsets.len() + data.len());
+        chunk.extend_from_slice(&(y as i32).to_le_bytes());
+        chunk.extend_from_slice(&(offsets.len() as u64).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        chunk.extend_from_slice(&offsets);
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+}
+
+// Writes a flat, uncompressed scanline OpenEXR file with R, G and B
+// channels holding the linear radiance, as 32-bit floats or, with
+// `half_float`, 16-bit ones taking half the space. When known, the
+// number of samples per pixel is stored in a custom "samples" attribute
+// so partial renders can be merged with the right weights. With a
+// color space the radiance is converted to its primaries, which are
+// recorded in the standard "chromaticities" attribute.
+pub fn write_exr<W: Write>(
+    img: &Image,
+    samples: Option<f32>,
+    half_float: bool,
+    color_space: Option<ColorSpace>,
+    out: &mut W,
+) -> io::Result<()> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    let (pixel_type, channel_size) = if half_float { (PIXEL_TYPE_HALF, 2) } else { (PIXEL_TYPE_FLOAT, 4) };
+    write_attr(&mut header, "channels", "chlist", &chlist(&["B", "G", "R"], pixel_type));
+    write_window_attrs(&mut header, img.width, img.height);
+    if let Some(samples) = samples {
+        write_attr(&mut header, "samples", "float", &samples.to_le_bytes());
+    }
+    if let Some(space) = color_space {
+        let xy: Vec<u8> = space.chromaticities().iter().flat_map(|v| v.to_le_bytes()).collect();
+        write_attr(&mut header, "chromaticities", "chromaticities", &xy);
+    }
+    header.push(0);
+
+    let chunks: Vec<Vec<u8>> = img.pixels()
+        .chunks(img.width.max(1))
+        .take(img.height)
+        .enumerate()
+        .map(|(y, row)| {
+            let size = row.len() * 3 * channel_size;
+            let mut chunk = Vec::with_capacity(8 + size);
+            chunk.extend_from_slice(&(y as i32).to_le_bytes());
+            chunk.extend_from_slice(&(size as i32).to_le_bytes());
+            for channel in 0..3 {
+                for &p in row.iter() {
+                    let p = color_space.map_or(p, |space| space.convert(p));
+                    let v = to_f32(match channel {
+                        0 => p.z,
+                        1 => p.y,
+                        _ => p.x,
+                    });
+                    if half_float {
+                        chunk.extend_from_slice(&half::from_f32(v).to_le_bytes());
+                    } else {
+                        chunk.extend_from_slice(&v.to_le_bytes());
+                    }
+                }
+            }
+            chunk
+        })
+        .collect();
+
+    write_chunks(out, &header, &chunks)
+}
+
+// Reads a flat scanline OpenEXR file with R, G and B channels, as
+// written by `write_exr`. Only uncompressed half and float channels are
+// supported. Returns the image along with its "samples" attribute.
+pub fn read_exr<R: Read>(input: &mut R) -> io::Result<(Image, Option<f32>)> {
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf)?;
+    let mut r = Reader { buf: &buf, pos: 0 };
+
+    if r.take(4)? != MAGIC {
+        return Err(invalid("not an OpenEXR file"))
+    }
+    let version = r.u32()?;
+    if version & 0xff != VERSION || version & UNSUPPORTED_FLAGS != 0 {
+        return Err(invalid("only single-part scanline OpenEXR files are supported"))
+    }
+
+    let mut attrs = HashMap::new();
+    loop {
+        let name = r.cstr()?;
+        if name.is_empty() {
+            break
+        }
+        let _kind = r.cstr()?;
+        let size = r.u32()? as usize;
+        attrs.insert(name, r.take(size)?);
+    }
+
+    let attr = |name: &str| attrs.get(name).copied().ok_or_else(|| invalid(&format!("missing '{}' attribute", name)));
+    if attr("compression")?.first() != Some(&COMPRESSION_NONE) {
+        return Err(invalid("only uncompressed OpenEXR files are supported"))
+    }
+
+    let window = attr("dataWindow")?;
+    let mut w = Reader { buf: window, pos: 0 };
+    let (xmin, ymin, xmax, ymax) = (w.i32()?, w.i32()?, w.i32()?, w.i32()?);
+    let width = (xmax - xmin + 1).max(0) as usize;
+    let height = (ymax - ymin + 1).max(0) as usize;
+
+    // Channel names and byte sizes, in file order.
+    let mut channels = Vec::new();
+    let mut c = Reader { buf: attr("channels")?, pos: 0 };
+    loop {
+        let name = c.cstr()?;
+        if name.is_empty() {
+            break
+        }
+        let size = match c.i32()? {
+            PIXEL_TYPE_HALF => 2,
+            PIXEL_TYPE_FLOAT => 4,
+            _ => return Err(invalid("unsupported channel type")),
+        };
+        c.take(4)?;
+        if c.i32()? != 1 || c.i32()? != 1 {
+            return Err(invalid("subsampled channels are not supported"))
+        }
+        channels.push((name, size));
+    }
+    for required in ["R", "G", "B"].iter() {
+        if !channels.iter().any(|(name, _)| name == required) {
+            return Err(invalid(&format!("missing channel '{}'", required)))
+        }
+    }
+
+    let samples = match attrs.get("samples") {
+        Some(v) => Some(Reader { buf: v, pos: 0 }.f32()?),
+        None => None,
+    };
+
+    // Skip the offset table, chunks follow in increasing y order.
+    r.take(height * 8)?;
+    let mut img = Image::new(width, height);
+    for _ in 0..height {
+        let y = r.i32()? - ymin;
+        r.u32()?;
+        if y < 0 || y as usize >= height {
+            return Err(invalid("scanline outside of the data window"))
+        }
+
+        let mut row = vec![Color::default(); width];
+        for (name, size) in channels.iter() {
+            for p in row.iter_mut() {
+                let v = if *size == 2 { half::to_f32(r.u16()?) } else { r.f32()? };
+                match name.as_str() {
+                    "R" => p.x = v as Float,
+                    "G" => p.y = v as Float,
+                    "B" => p.z = v as Float,
+                    _ => {}
+                }
+            }
+        }
+        for (x, p) in row.into_iter().enumerate() {
+            img.set(x, y as usize, p);
+        }
+    }
+
+    Ok((img, samples))
+}
+
+fn chlist(names: &[&str], pixel_type: i32) -> Vec<u8> {
+    let mut chlist = Vec::new();
+    for name in names.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and three reserved bytes, then x/y sampling.
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    chlist
+}
+
+// Attributes every OpenEXR header requires besides the channel list.
+fn write_window_attrs(header: &mut Vec<u8>, width: usize, height: usize) {
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let mut screen_center = Vec::new();
+    screen_center.extend_from_slice(&0f32.to_le_bytes());
+    screen_center.extend_from_slice(&0f32.to_le_bytes());
+
+    write_attr(header, "compression", "compression", &[COMPRESSION_NONE]);
+    write_attr(header, "dataWindow", "box2i", &window);
+    write_attr(header, "displayWindow", "box2i", &window);
+    write_attr(header, "lineOrder", "lineOrder", &[LINE_ORDER_INCREASING_Y]);
+    write_attr(header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    write_attr(header, "screenWindowCenter", "v2f", &screen_center);
+    write_attr(header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+}
+
+// Writes the header, the offset table holding the absolute
+// file position of every chunk, and then the chunks themselves.
+fn write_chunks<W: Write>(out: &mut W, header: &[u8], chunks: &[Vec<u8>]) -> io::Result<()> {
+    let mut offset = (header.len() + chunks.len() * 8) as u64;
+    let mut table = Vec::with_capacity(chunks.len() * 8);
+    for chunk in chunks.iter() {
+        table.extend_from_slice(&offset.to_le_bytes());
+        offset += chunk.len() as u64;
+    }
+
+    out.write_all(header)?;
+    out.write_all(&table)?;
+    for chunk in chunks.iter() {
+        out.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+fn write_attr(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Little-endian cursor over an in-memory file.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated OpenEXR file"))
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn cstr(&mut self) -> io::Result<String> {
+        let len = self.buf[self.pos..].iter().position(|&b| b == 0)
+            .ok_or_else(|| invalid("unterminated string"))?;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_merge_and_sort() {
+        let mut img = DeepImage::new(1, 1);
+        img.add(0, 0, 10.0, Color::new(1.0, 0.0, 0.0));
+        img.add(0, 0, 2.0, Color::new(0.0, 1.0, 0.0));
+        img.add(0, 0, 10.05, Color::new(1.0, 0.0, 0.0));
+
+        let samples = &img.pixels[0];
+        assert_eq!(2, samples.len());
+        assert_eq!(2.0, samples[0].z);
+        assert_eq!(2, samples[1].count);
+        assert_eq!(Color::new(2.0, 0.0, 0.0), samples[1].color);
+    }
+
+    #[test]
+    fn test_write_exr_layout() {
+        let mut img = DeepImage::new(2, 1);
+        img.add(0, 0, 1.0, Color::new(1.0, 1.0, 1.0));
+        img.add(0, 0, 5.0, Color::new(1.0, 1.0, 1.0));
+
+        let mut buf = Vec::new();
+        img.write_exr(&mut buf).unwrap();
+        assert_eq!(MAGIC, buf[..4]);
+        assert_eq!(DEEP_VERSION.to_le_bytes(), buf[4..8]);
+
+        // A single chunk: y, three sizes, two pixel offsets and
+        // two samples of five float channels.
+        let chunk_len = 4 + 3 * 8 + 2 * 4 + 2 * 5 * 4;
+        let table_pos = buf.len() - chunk_len - 8;
+        let offset = u64::from_le_bytes([
+            buf[table_pos], buf[table_pos + 1], buf[table_pos + 2], buf[table_pos + 3],
+            buf[table_pos + 4], buf[table_pos + 5], buf[table_pos + 6], buf[table_pos + 7],
+        ]);
+        assert_eq!((buf.len() - chunk_len) as u64, offset);
+
+        // Alpha channel: the front sample covers half the pixel and the
+        // back sample everything that remains behind it.
+        let data = &buf[buf.len() - 2 * 5 * 4..];
+        let alpha = |i: usize| f32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+        assert_eq!(0.5, alpha(0));
+        assert_eq!(1.0, alpha(1));
+    }
+
+    #[test]
+    fn test_flat_round_trip() {
+        let mut img = Image::new(3, 2);
+        img.set(0, 0, Color::new(1.0, 2.0, 3.0));
+        img.set(2, 1, Color::new(0.5, 100.0, 0.0));
+
+        let mut buf = Vec::new();
+        write_exr(&img, Some(25.0), false, None, &mut buf).unwrap();
+        let (read, samples) = read_exr(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(Some(25.0), samples);
+        assert_eq!((3, 2), (read.width, read.height));
+        assert_eq!(img.pixels(), read.pixels());
+
+        // Half floats hold these values exactly, in half the space.
+        let mut half_buf = Vec::new();
+        write_exr(&img, Some(25.0), true, None, &mut half_buf).unwrap();
+        assert_eq!(buf.len() - 3 * 2 * 3 * 2, half_buf.len());
+        let (read, _) = read_exr(&mut half_buf.as_slice()).unwrap();
+        assert_eq!(img.pixels(), read.pixels());
+    }
+
+    #[test]
+    fn test_color_space() {
+        let mut img = Image::new(1, 1);
+        img.set(0, 0, Color::new(0.0, 1.0, 0.5));
+
+        let mut buf = Vec::new();
+        write_exr(&img, None, false, Some(ColorSpace::AcesCg), &mut buf).unwrap();
+        assert!(buf.windows(14).any(|w| w == b"chromaticities"));
+        let (read, _) = read_exr(&mut buf.as_slice()).unwrap();
+        let expected = ColorSpace::AcesCg.convert(img.pixels()[0]);
+        assert!((read.pixels()[0] - expected).length() < 1e-6, "{:?}", read.pixels()[0]);
+    }
+
+    #[test]
+    fn test_read_rejects_deep() {
+        let mut buf = Vec::new();
+        DeepImage::new(1, 1).write_exr(&mut buf).unwrap();
+        assert!(read_exr(&mut buf.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::Float;
+
+// Reconstruction filters, which weight the samples of the pixels around
+// a sample's position into every pixel. Samples are splatted into all of
+// the pixels within the filter's radius, so each pixel blends in a few
+// samples of its neighbors, which smooths edges more than averaging only
+// the samples within the pixel does.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Filter {
+    // Every sample counts fully in its own pixel and nowhere else.
+    #[default]
+    Box,
+    // Weights falling linearly to zero at the radius, in pixels.
+    Tent(Float),
+    // A Gaussian with a standard deviation of a third of the radius,
+    // shifted down to reach zero at the radius.
+    Gaussian(Float),
+}
+
+// Default radii of the filters, in pixels.
+pub const TENT_RADIUS: Float = 1.0;
+pub const GAUSSIAN_RADIUS: Float = 1.5;
+
+impl Filter {
+    // Filters are named `box`, `tent` or `gaussian`, with an optional
+    // radius in pixels, e.g. `gaussian:2`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next()?;
+        let radius = match parts.next() {
+            Some(r) => Some(r.parse::<Float>().ok().filter(|&r| r >= 0.5 && r.is_finite())?),
+            None => None,
+        };
+        match (name, radius) {
+            ("box", None) => Some(Filter::Box),
+            ("tent", r) => Some(Filter::Tent(r.unwrap_or(TENT_RADIUS))),
+            ("gaussian", r) => Some(Filter::Gaussian(r.unwrap_or(GAUSSIAN_RADIUS))),
+            _ => None,
+        }
+    }
+
+    pub fn radius(&self) -> Float {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent(radius) | Filter::Gaussian(radius) => radius,
+        }
+    }
+
+    // Weight of a sample at the given offset from a pixel's center.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        match *self {
+            Filter::Box => if dx.abs() <= 0.5 && dy.abs() <= 0.5 { 1.0 } else { 0.0 },
+            Filter::Tent(radius) => {
+                let tent = |d: Float| (1.0 - d.abs() / radius).max(0.0);
+                tent(dx) * tent(dy)
+            }
+            Filter::Gaussian(radius) => {
+                let sigma = radius / 3.0;
+                let gaussian = |d: Float| (-d * d / (2.0 * sigma * sigma)).exp();
+                let edge = gaussian(radius);
+                (gaussian(dx) - edge).max(0.0) * (gaussian(dy) - edge).max(0.0)
+            }
+        }
+    }
+
+    // The pixels of an image a sample at the given position, in pixels
+    // from the top left corner, contributes to, with their weights.
+    pub fn footprint(&self, x: Float, y: Float, width: usize, height: usize) -> Vec<(usize, usize, Float)> {
+        let radius = self.radius();
+        let range = |c: Float, size: usize| {
+            // Casts of negative floats saturate at zero.
+            let first = (c - radius - 0.5).ceil() as usize;
+            let last = ((c + radius - 0.5).floor() + 1.0) as usize;
+            first..last.min(size)
+        };
+        let mut pixels = Vec::new();
+        for py in range(y, height) {
+            for px in range(x, width) {
+                let weight = self.weight(px as Float + 0.5 - x, py as Float + 0.5 - y);
+                if weight > 0.0 {
+                    pixels.push((px, py, weight));
+                }
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Filter::Box), Filter::parse("box"));
+        assert_eq!(Some(Filter::Tent(TENT_RADIUS)), Filter::parse("tent"));
+        assert_eq!(Some(Filter::Gaussian(2.0)), Filter::parse("gaussian:2"));
+        assert_eq!(None, Filter::parse("box:2"));
+        assert_eq!(None, Filter::parse("tent:0.1"));
+        assert_eq!(None, Filter::parse("mitchell"));
+    }
+
+    #[test]
+    fn test_footprint() {
+        // A box keeps a sample in its own pixel.
+        assert_eq!(vec![(3, 1, 1.0)], Filter::Box.footprint(3.2, 1.7, 10, 10));
+
+        // A tent of radius 1 keeps a sample at a pixel's center in
+        // that pixel, and shares one on an edge between two pixels.
+        let pixels = Filter::Tent(1.0).footprint(3.5, 1.5, 10, 10);
+        assert_eq!(vec![(3, 1, 1.0)], pixels);
+        let pixels = Filter::Tent(1.0).footprint(4.0, 1.5, 10, 10);
+        assert_eq!(vec![(3, 1, 0.5), (4, 1, 0.5)], pixels);
+
+        // Pixels outside the image are dropped.
+        let pixels = Filter::Gaussian(1.5).footprint(0.2, 0.2, 10, 10);
+        assert!(pixels.iter().all(|&(x, y, _)| x < 2 && y < 2));
+        assert_eq!(4, pixels.len());
+        assert!(pixels[0].2 > pixels[3].2);
+    }
+}
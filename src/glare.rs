@@ -0,0 +1,330 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::{PI, Float};
+use crate::image::Image;
+
+// Glare spreads the light of highlights over the image around them, as a
+// lens does: the edges of the aperture's blades diffract it into a star
+// of spikes, and light scattered inside the lens rings it with a faint
+// halo. The pattern is the far field diffraction of the aperture, the
+// power spectrum of its shape, scaled per channel by wavelength so that
+// its rims turn colored. It is computed in linear radiance, before tone
+// mapping, so that only light far brighter than white visibly glares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glare {
+    // Fraction of the highlights' light spread into the glare.
+    pub strength: Float,
+    // Number of aperture blades. An even number of blades gives as many
+    // spikes, an odd one twice as many.
+    pub blades: usize,
+}
+
+pub const GLARE_BLADES: usize = 6;
+
+// Light above this, in display units where 1 is white, glares.
+pub const GLARE_THRESHOLD: Float = 1.0;
+
+// Wavelengths of red, green and blue light in nanometers. Diffraction
+// spreads longer wavelengths farther.
+const WAVELENGTHS: [Float; 3] = [650.0, 550.0, 450.0];
+
+// Radius of the aperture and of the halo, as fractions of the kernel's size.
+const APERTURE_RADIUS: Float = 1.0 / 4.0;
+const HALO_RADIUS: Float = 0.3;
+
+// Fraction of the glare's light in the halo rather than the star.
+const HALO: Float = 0.15;
+
+impl Glare {
+    // Glare is given as a strength from 0 to 1 with an optional number
+    // of blades of at least 3, e.g. `0.1:8`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+        let strength = parts.next()?.parse::<Float>().ok().filter(|s| (0.0..=1.0).contains(s))?;
+        let blades = match parts.next() {
+            Some(b) => b.parse::<usize>().ok().filter(|&b| b >= 3)?,
+            None => GLARE_BLADES,
+        };
+        Some(Self { strength, blades })
+    }
+
+    // The image with the light of every channel above the threshold
+    // spread into the glare. The light is moved rather than added, so the
+    // image keeps its total radiance.
+    pub fn apply(&self, img: &Image, threshold: Float) -> Image {
+        let mut out = img.scaled(1.0);
+        let (width, height) = (img.width, img.height);
+        if width == 0 || height == 0 || self.strength == 0.0 {
+            return out
+        }
+
+        let size = kernel_size(width, height);
+        let half = size / 2;
+        // Padded so that the glare of one edge doesn't wrap around onto
+        // the opposite one.
+        let (w, h) = ((width + half).next_power_of_two(), (height + half).next_power_of_two());
+        for (c, kernel) in self.kernels(size).iter().enumerate() {
+            let mut light = vec![Complex::default(); w * h];
+            for y in 0..height {
+                for x in 0..width {
+                    light[y * w + x].re = (img.pixels()[y * width + x][c] - threshold).max(0.0);
+                }
+            }
+            // The kernel centered on the origin, wrapping around.
+            let mut spread = vec![Complex::default(); w * h];
+            for ky in 0..size {
+                for kx in 0..size {
+                    let (x, y) = ((kx + w - half) % w, (ky + h - half) % h);
+                    spread[y * w + x].re = kernel[ky * size + kx];
+                }
+            }
+
+            fft2(&mut light, w, h, false);
+            fft2(&mut spread, w, h, false);
+            for (l, &s) in light.iter_mut().zip(spread.iter()) {
+                *l = *l * s;
+            }
+            fft2(&mut light, w, h, true);
+
+            for y in 0..height {
+                for x in 0..width {
+                    let p = &mut out.pixels_mut()[y * width + x];
+                    let highlight = (p[c] - threshold).max(0.0);
+                    p[c] += self.strength * (light[y * w + x].re.max(0.0) - highlight);
+                }
+            }
+        }
+        out
+    }
+
+    // The glare of a point of light in red, green and blue, over a square
+    // of the size centered on it, each summing to 1.
+    fn kernels(&self, size: usize) -> [Vec<Float>; 3] {
+        let mut aperture = vec![Complex::default(); size * size];
+        let radius = size as Float * APERTURE_RADIUS;
+        let center = size as Float / 2.0;
+        // Supersampled, as jagged edges would diffract into spikes of
+        // their own.
+        const SUBSAMPLES: usize = 4;
+        for y in 0..size {
+            for x in 0..size {
+                let mut covered = 0;
+                for sy in 0..SUBSAMPLES {
+                    for sx in 0..SUBSAMPLES {
+                        let px = x as Float + (sx as Float + 0.5) / SUBSAMPLES as Float - center;
+                        let py = y as Float + (sy as Float + 0.5) / SUBSAMPLES as Float - center;
+                        if self.in_aperture(px, py, radius) {
+                            covered += 1;
+                        }
+                    }
+                }
+                aperture[y * size + x].re = covered as Float / (SUBSAMPLES * SUBSAMPLES) as Float;
+            }
+        }
+        fft2(&mut aperture, size, size, false);
+
+        // The power spectrum, moved from the corners to the center.
+        let half = size / 2;
+        let mut star = vec![0.0; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let a = aperture[((y + half) % size) * size + (x + half) % size];
+                star[y * size + x] = a.re * a.re + a.im * a.im;
+            }
+        }
+
+        let kernel = |wavelength: Float| {
+            // Longer wavelengths spread the same pattern wider.
+            let scale = WAVELENGTHS[1] / wavelength;
+            let mut rays = vec![0.0; size * size];
+            let mut halo = vec![0.0; size * size];
+            let (ring, width) = (size as Float * HALO_RADIUS / scale, size as Float / 64.0);
+            for y in 0..size {
+                for x in 0..size {
+                    let (dx, dy) = (x as Float - half as Float, y as Float - half as Float);
+                    rays[y * size + x] = bilinear(&star, size, half as Float + dx * scale, half as Float + dy * scale);
+                    let r = (dx * dx + dy * dy).sqrt() - ring;
+                    halo[y * size + x] = (-r * r / (2.0 * width * width)).exp();
+                }
+            }
+            let (rays_sum, halo_sum): (Float, Float) = (rays.iter().sum(), halo.iter().sum());
+            rays.iter().zip(halo.iter()).map(|(r, h)| (1.0 - HALO) * r / rays_sum + HALO * h / halo_sum).collect()
+        };
+        [kernel(WAVELENGTHS[0]), kernel(WAVELENGTHS[1]), kernel(WAVELENGTHS[2])]
+    }
+
+    // Whether the offset from the center is inside the regular polygon
+    // of the blades, with a corner at the top.
+    fn in_aperture(&self, x: Float, y: Float, radius: Float) -> bool {
+        let n = self.blades as Float;
+        let apothem = radius * (PI / n).cos();
+        (0..self.blades).all(|k| {
+            let angle = PI / 2.0 + PI / n + 2.0 * PI * k as Float / n;
+            x * angle.cos() + y * angle.sin() <= apothem
+        })
+    }
+}
+
+// The glare reaches about a quarter of the image's larger side away from
+// a highlight.
+fn kernel_size(width: usize, height: usize) -> usize {
+    (width.max(height) / 2).next_power_of_two().clamp(64, 1024)
+}
+
+fn bilinear(values: &[Float], size: usize, x: Float, y: Float) -> Float {
+    if x < 0.0 || y < 0.0 || x >= (size - 1) as Float || y >= (size - 1) as Float {
+        return 0.0
+    }
+    let (x0, y0) = (x as usize, y as usize);
+    let (fx, fy) = (x - x0 as Float, y - y0 as Float);
+    let at = |x, y| values[y * size + x];
+    let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+    let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Complex {
+    re: Float,
+    im: Float,
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, o: Complex) -> Complex {
+        Complex { re: self.re + o.re, im: self.im + o.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, o: Complex) -> Complex {
+        Complex { re: self.re - o.re, im: self.im - o.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, o: Complex) -> Complex {
+        Complex { re: self.re * o.re - self.im * o.im, im: self.re * o.im + self.im * o.re }
+    }
+}
+
+// In place radix-2 fast Fourier transform of a power of two values, the
+// inverse one scaled down by their number.
+fn fft(values: &mut [Complex], inverse: bool) {
+    let n = values.len();
+    // Into bit reversed order.
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                // Twiddles in double precision, as f32 ones drift over
+                // large transforms.
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let w = Complex { re: cos as Float, im: sin as Float };
+                let (a, b) = (values[start + k], values[start + k + len / 2] * w);
+                values[start + k] = a + b;
+                values[start + k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for v in values.iter_mut() {
+            *v = Complex { re: v.re / n as Float, im: v.im / n as Float };
+        }
+    }
+}
+
+// Transform of a grid of values, row by row and then column by column.
+fn fft2(values: &mut [Complex], width: usize, height: usize, inverse: bool) {
+    for row in values.chunks_mut(width) {
+        fft(row, inverse);
+    }
+    let mut column = vec![Complex::default(); height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = values[y * width + x];
+        }
+        fft(&mut column, inverse);
+        for y in 0..height {
+            values[y * width + x] = column[y];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Glare { strength: 0.1, blades: GLARE_BLADES }), Glare::parse("0.1"));
+        assert_eq!(Some(Glare { strength: 0.5, blades: 5 }), Glare::parse("0.5:5"));
+        assert_eq!(None, Glare::parse("2"));
+        assert_eq!(None, Glare::parse("0.1:2"));
+        assert_eq!(None, Glare::parse("bright"));
+    }
+
+    #[test]
+    fn test_fft() {
+        let values: Vec<Complex> = (0..16).map(|i| Complex { re: (i * i % 7) as Float, im: 0.0 }).collect();
+        let mut transformed = values.clone();
+        fft2(&mut transformed, 4, 4, false);
+        // The first coefficient is the sum.
+        assert!((transformed[0].re - values.iter().map(|v| v.re).sum::<Float>()).abs() < 1e-4);
+        fft2(&mut transformed, 4, 4, true);
+        for (a, b) in values.iter().zip(transformed.iter()) {
+            assert!((a.re - b.re).abs() < 1e-4 && b.im.abs() < 1e-4, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut img = Image::new(64, 64);
+        for p in img.pixels_mut().iter_mut() {
+            *p = Color::new(0.5, 0.5, 0.5);
+        }
+        img.set(32, 32, Color::new(101.0, 101.0, 101.0));
+        let glare = Glare { strength: 0.5, blades: 6 };
+        let out = glare.apply(&img, GLARE_THRESHOLD);
+        let at = |x: usize, y: usize| out.pixels()[y * 64 + x].y;
+
+        // Half of the highlight's light leaves its pixel, and none of the
+        // light under the threshold does.
+        assert!(at(32, 32) < 60.0 && at(32, 32) > 1.0, "{}", at(32, 32));
+        assert!(at(0, 0) >= 0.5);
+        let total: Float = out.pixels().iter().map(|p| p.y).sum();
+        let before: Float = img.pixels().iter().map(|p| p.y).sum();
+        assert!((total - before).abs() < 0.5, "{} {}", total, before);
+
+        // Six blades spread the light into spikes across their edges,
+        // the one to the right among them, and less between them.
+        assert!(at(44, 32) > at(42, 26), "{} {}", at(44, 32), at(42, 26));
+
+        // Nothing glares under the threshold.
+        let dim = glare.apply(&img, 200.0);
+        assert_eq!(img.pixels(), dim.pixels());
+    }
+}
@@ -0,0 +1,121 @@
+// Conversions between single precision floats and the bits of IEEE 754
+// half precision floats, with a sign bit, five exponent bits and ten
+// mantissa bits. Halves hold about three significant decimal digits
+// and values up to 65504, which is plenty for radiance but halves the
+// memory of large buffers.
+
+// The largest finite half.
+pub const MAX: f32 = 65504.0;
+
+pub fn to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    match exp {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exp - 15),
+    }
+}
+
+// Rounds to the nearest half, ties to even. Values too large for a
+// half become infinite and values too small become zero.
+pub fn from_f32(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exp == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan
+    }
+
+    let exp = exp - 127 + 15;
+    if exp >= 31 {
+        return sign | 0x7c00
+    }
+    // Below the smallest normal half the implicit leading one becomes
+    // explicit and the mantissa is shifted into the subnormal range.
+    let (half, shift) = if exp <= 0 {
+        if exp < -10 {
+            return sign
+        }
+        let shift = (14 - exp) as u32;
+        ((mantissa | 0x80_0000) >> shift, shift)
+    } else {
+        (((exp as u32) << 10) | (mantissa >> 13), 13)
+    };
+    let rest = (mantissa | 0x80_0000) & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // A carry out of the mantissa correctly moves on to the next
+    // exponent, or to infinity past the largest half.
+    let round = rest > halfway || (rest == halfway && half & 1 == 1);
+    sign | (half + round as u32) as u16
+}
+
+// Rounds to one of the two nearest halves at random, the farther one
+// with a probability proportional to the distance to the nearer one,
+// given `u` uniform in [0, 1). The result is right on average, which
+// matters when rounding the same value over and over, where always
+// rounding to nearest would drop every change smaller than half a step.
+pub fn from_f32_dithered(x: f32, u: f32) -> u16 {
+    let nearest = from_f32(x);
+    let rounded = to_f32(nearest);
+    if !x.is_finite() || rounded == x || rounded.is_infinite() {
+        return nearest
+    }
+    // Halves of the same sign are ordered like their bits.
+    let other = if rounded.abs() < x.abs() { nearest + 1 } else { nearest - 1 };
+    let p = (x - rounded) / (to_f32(other) - rounded);
+    if u < p { other } else { nearest }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_f32() {
+        assert_eq!(1.0, to_f32(0x3c00));
+        assert_eq!(-2.0, to_f32(0xc000));
+        assert_eq!(65504.0, to_f32(0x7bff));
+        assert_eq!(f32::INFINITY, to_f32(0x7c00));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &x in [0.0, 1.0, -2.5, 0.1, 65504.0, 6.1e-5, 3.0e-7].iter() {
+            let back = to_f32(from_f32(x));
+            assert!((back - x).abs() <= x.abs() / 1024.0 + 6e-8, "{} became {}", x, back);
+        }
+        assert_eq!(0x3c00, from_f32(1.0));
+        assert_eq!(0xc000, from_f32(-2.0));
+        assert_eq!(0x0001, from_f32(2f32.powi(-24)));
+        assert_eq!(0x7c00, from_f32(1e6));
+        assert_eq!(0x7c00, from_f32(65520.0));
+        assert_eq!(0x0000, from_f32(1e-10));
+        assert!(to_f32(from_f32(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_ties_to_even() {
+        // Halfway between 1 and the next half up, and between
+        // that one and the next, round to the even mantissa.
+        assert_eq!(0x3c00, from_f32(1.0 + 0.5 / 1024.0));
+        assert_eq!(0x3c02, from_f32(1.0 + 1.5 / 1024.0));
+        assert_eq!(0x3c01, from_f32(1.0 + 0.6 / 1024.0));
+    }
+
+    #[test]
+    fn test_dithered() {
+        // A quarter of the way to the next half up is rounded up a quarter
+        // of the time, so the average of many roundings is exact.
+        let x = -(1.0 + 0.25 / 1024.0);
+        let mean: f32 = (0..1000).map(|i| to_f32(from_f32_dithered(x, i as f32 / 1000.0))).sum::<f32>() / 1000.0;
+        assert!((mean - x).abs() < 1e-6, "{}", mean);
+        assert_eq!(0xbc01, from_f32_dithered(x, 0.1));
+        assert_eq!(0xbc00, from_f32_dithered(x, 0.3));
+        assert_eq!(0x3c00, from_f32_dithered(1.0, 0.0));
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+
+use crate::{to_f32, Float};
+use crate::image::Image;
+use crate::colorspace::ColorSpace;
+use crate::vec::Color;
+
+// Writes a Radiance HDR (.hdr) file holding the linear radiance. Every
+// pixel is stored in four bytes, RGBE: three 8-bit mantissas sharing an
+// exponent, which keeps about two significant digits over a huge range.
+// Scanlines are written flat, without run-length encoding, which every
+// reader accepts. With a color space the radiance is converted to its
+// primaries, which are recorded in the PRIMARIES header line.
+pub fn write_hdr<W: Write>(img: &Image, color_space: Option<ColorSpace>, out: &mut W) -> io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n")?;
+    if let Some(space) = color_space {
+        let xy: Vec<String> = space.chromaticities().iter().map(|v| v.to_string()).collect();
+        writeln!(out, "PRIMARIES= {}", xy.join(" "))?;
+    }
+    write!(out, "\n-Y {} +X {}\n", img.height, img.width)?;
+
+    let mut data = Vec::with_capacity(img.pixels().len() * 4);
+    for &p in img.pixels() {
+        let p = color_space.map_or(p, |space| space.convert(p));
+        data.extend_from_slice(&to_rgbe(p));
+    }
+    out.write_all(&data)
+}
+
+// Reads a Radiance HDR file with the usual top to bottom, left to right
+// orientation, as written by `write_hdr` or by most other programs,
+// with flat or run-length encoded scanlines. Colors are returned as
+// stored, whatever the PRIMARIES in the header.
+pub fn read_hdr(data: &[u8]) -> io::Result<Image> {
+    if !data.starts_with(b"#?") {
+        return Err(invalid("not a Radiance HDR file"))
+    }
+    let mut pos = 0;
+    let mut line = || -> io::Result<&[u8]> {
+        let len = data[pos..].iter().position(|&b| b == b'\n').ok_or_else(|| invalid("truncated header"))?;
+        let line = &data[pos..pos + len];
+        pos += len + 1;
+        Ok(line)
+    };
+    loop {
+        let header = line()?;
+        if header.is_empty() {
+            break
+        }
+        if header.starts_with(b"FORMAT=") && header != b"FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("only RGBE pixels are supported"))
+        }
+    }
+    let resolution = String::from_utf8_lossy(line()?).into_owned();
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => match (w.parse(), h.parse()) {
+            (Ok(w), Ok(h)) => (w, h),
+            _ => return Err(invalid("invalid resolution")),
+        },
+        _ => return Err(invalid("only top to bottom, left to right images are supported")),
+    };
+
+    let mut img = Image::new(width, height);
+    let mut rest = &data[pos..];
+    let mut scanline = vec![0u8; width * 4];
+    for y in 0..height {
+        rest = read_scanline(rest, &mut scanline)?;
+        for (x, p) in scanline.chunks(4).enumerate() {
+            img.set(x, y, from_rgbe(p));
+        }
+    }
+    Ok(img)
+}
+
+// Decodes one scanline into `out`, returning the data after it. Run-length
+// encoded scanlines start with 2, 2 and their width, and store the four
+// components one after another, each as runs of a repeated byte (a count
+// above 128) or of literal bytes.
+fn read_scanline<'a>(data: &'a [u8], out: &mut [u8]) -> io::Result<&'a [u8]> {
+    let width = out.len() / 4;
+    let truncated = || invalid("truncated pixel data");
+    let encoded = (8..0x8000).contains(&width) && data.len() >= 4 && data[0] == 2 && data[1] == 2 && data[2] & 0x80 == 0;
+    if !encoded {
+        let flat = data.get(..out.len()).ok_or_else(truncated)?;
+        out.copy_from_slice(flat);
+        return Ok(&data[out.len()..])
+    }
+    if ((data[2] as usize) << 8 | data[3] as usize) != width {
+        return Err(invalid("scanline width mismatch"))
+    }
+
+    let mut pos = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            let (run, literal) = if count > 128 { (count - 128, false) } else { (count, true) };
+            if run == 0 || x + run > width {
+                return Err(invalid("invalid run length"))
+            }
+            for i in 0..run {
+                let b = *data.get(if literal { pos + i } else { pos }).ok_or_else(truncated)?;
+                out[(x + i) * 4 + channel] = b;
+            }
+            pos += if literal { run } else { 1 };
+            x += run;
+        }
+    }
+    Ok(&data[pos..])
+}
+
+// Components are taken at the middle of their quantization step.
+fn from_rgbe(p: &[u8]) -> Color {
+    if p[3] == 0 {
+        return Color::default()
+    }
+    let scale = 2f32.powi(p[3] as i32 - 128 - 8);
+    let c = |v: u8| ((v as f32 + 0.5) * scale) as Float;
+    Color::new(c(p[0]), c(p[1]), c(p[2]))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Negative components can't be stored and become zero, as do
+// values too small for the shared exponent.
+fn to_rgbe(c: Color) -> [u8; 4] {
+    let (r, g, b) = (to_f32(c.x).max(0.0), to_f32(c.y).max(0.0), to_f32(c.z).max(0.0));
+    let max = r.max(g).max(b);
+    if max < 1e-32 || !max.is_finite() {
+        return [0; 4]
+    }
+    // The exponent puts the largest mantissa in [128, 256).
+    let exp = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exp);
+    let q = |v: f32| (v * scale).min(255.0) as u8;
+    [q(r), q(g), q(b), (exp + 128) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgbe() {
+        assert_eq!([128, 64, 0, 129], to_rgbe(Color::new(1.0, 0.5, 0.0)));
+        assert_eq!([0; 4], to_rgbe(Color::default()));
+        for &c in [Color::new(1000.0, 0.3, 2.0), Color::new(0.001, 0.002, 0.0015)].iter() {
+            let back = from_rgbe(&to_rgbe(c));
+            let max = c.x.max(c.y).max(c.z);
+            assert!((back - c).length() < max / 64.0, "{:?} became {:?}", c, back);
+        }
+    }
+
+    #[test]
+    fn test_write_hdr() {
+        let mut img = Image::new(3, 2);
+        img.set(0, 0, Color::new(1.0, 2.0, 3.0));
+        img.set(2, 1, Color::new(0.5, 100.0, 0.0));
+
+        let mut buf = Vec::new();
+        write_hdr(&img, None, &mut buf).unwrap();
+        let header_end = buf.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+        let header = String::from_utf8_lossy(&buf[..header_end]);
+        assert!(header.starts_with("#?RADIANCE\n"));
+        let rest = &buf[header_end..];
+        let resolution = b"-Y 2 +X 3\n";
+        assert_eq!(resolution, &rest[..resolution.len()]);
+
+        let pixels = &rest[resolution.len()..];
+        assert_eq!(3 * 2 * 4, pixels.len());
+        let last = from_rgbe(&pixels[20..]);
+        assert!((last - Color::new(0.5, 100.0, 0.0)).length() < 1.0, "{:?}", last);
+
+        let mut buf = Vec::new();
+        write_hdr(&img, Some(ColorSpace::AcesCg), &mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf).contains("PRIMARIES= 0.713 0.293"));
+    }
+
+    #[test]
+    fn test_read_hdr() {
+        let mut img = Image::new(3, 2);
+        img.set(0, 0, Color::new(1.0, 2.0, 3.0));
+        img.set(2, 1, Color::new(0.5, 100.0, 0.0));
+        let mut buf = Vec::new();
+        write_hdr(&img, Some(ColorSpace::Srgb), &mut buf).unwrap();
+        let read = read_hdr(&buf).unwrap();
+        assert_eq!((3, 2), (read.width, read.height));
+        for (a, b) in img.pixels().iter().zip(read.pixels()) {
+            assert!((*a - *b).length() < 1.0, "{:?} became {:?}", a, b);
+        }
+        assert!(read_hdr(b"P3\n").is_err());
+        assert!(read_hdr(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_run_length_encoding() {
+        // Eight pixels of [128, 64, 0, 129], each component a
+        // single run except the last, which is eight literals.
+        let mut data = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        data.extend_from_slice(&[2, 2, 0, 8, 136, 128, 136, 64, 136, 0, 8]);
+        data.extend_from_slice(&[129; 8]);
+        let img = read_hdr(&data).unwrap();
+        let expected = from_rgbe(&[128, 64, 0, 129]);
+        assert!(img.pixels().iter().all(|&p| p == expected));
+        assert!((expected - Color::new(1.0, 0.5, 0.0)).length() < 0.01);
+    }
+}
@@ -1,27 +1,61 @@
+use crate::{random_float, Float};
 use crate::ray::Ray;
 use crate::vec::{Vec3, Point3, dot};
 use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::primitive::Primitive;
 
-pub struct HitRecord {
+// The material is borrowed from the object that was hit, so that
+// intersecting a ray doesn't allocate.
+pub struct HitRecord<'a> {
     pub p: Point3,
     pub normal: Vec3,
-    pub t: f32,
+    pub t: Float,
+    // Texture coordinates of the hit point.
+    pub u: Float,
+    pub v: Float,
     pub front_face: bool,
-    pub mat: Box<dyn Material>,
+    pub mat: &'a dyn Material,
 }
 
-impl HitRecord {
+impl HitRecord<'_> {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = dot(&r.dir, outward_normal) < 0.0;
         self.normal = if self.front_face { *outward_normal } else { -*outward_normal };
     }
 }
 
+// Hits closer than this to the origin of a direction are ignored when
+// finding the density of sampling the direction towards an object.
+pub const PDF_EPSILON: Float = 0.001;
+
 // Used to determine if a given
 // object is "hittable" and therefore
 // if it is hit by a given ray.
-pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>>;
+
+    // Box enclosing the object over the whole time interval,
+    // or None for objects that are unbounded.
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb>;
+
+    // The density per unit solid angle of `random` giving the direction
+    // `dir` from `origin`, for objects that lights can be sampled on.
+    // Others never give a direction, and have a density of zero.
+    fn pdf_value(&self, _origin: &Point3, _dir: &Vec3) -> Float {
+        0.0
+    }
+
+    // A random direction from `origin` towards the object.
+    fn random(&self, _origin: &Point3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    // A copy of the object as one of the primitives hierarchies keep by
+    // value, sharing its material, if it is one.
+    fn primitive(&self) -> Option<Primitive> {
+        None
+    }
 }
 
 #[derive(Default)]
@@ -33,14 +67,10 @@ impl HittableList {
     pub fn add(&mut self, obj: Box<dyn Hittable>) {
         self.objects.push(obj);
     }
-
-    pub fn clear(&mut self) {
-        self.objects.clear();
-    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
         let mut hit_anything: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
         for obj in self.objects.iter() {
@@ -52,4 +82,67 @@ impl Hittable for HittableList {
 
         hit_anything
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        let mut boxes = self.objects.iter().map(|obj| obj.bounding_box(time0, time1));
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.surrounding(&b?)))
+    }
+
+    // Picks one of the objects with equal odds.
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        let sum: Float = self.objects.iter().map(|obj| obj.pdf_value(origin, dir)).sum();
+        sum / self.objects.len() as Float
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let i = (random_float() * self.objects.len() as Float) as usize;
+        self.objects[i.min(self.objects.len() - 1)].random(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::INF;
+    use crate::sphere::Sphere;
+    use crate::vec::Color;
+    use crate::material::Lambertian;
+
+    fn sphere(z: Float) -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Point3::new(0.0, 0.0, z), 0.5, Arc::new(Lambertian::new(Color::default()))))
+    }
+
+    #[test]
+    fn test_closest_hit() {
+        // Added farthest first, so the order of the list doesn't decide.
+        let mut world = HittableList::default();
+        world.add(sphere(-10.0));
+        world.add(sphere(-2.0));
+        world.add(sphere(-5.0));
+
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = world.hit(&r, 0.001, INF).unwrap();
+        assert!((rec.t - 1.5).abs() < 1e-5, "{}", rec.t);
+        assert!(rec.front_face);
+        // Nearer hits outside the interval are skipped.
+        let rec = world.hit(&r, 3.0, INF).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-5, "{}", rec.t);
+    }
+
+    #[test]
+    fn test_miss() {
+        let mut world = HittableList::default();
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(world.hit(&r, 0.001, INF).is_none());
+
+        world.add(sphere(-2.0));
+        let away = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(world.hit(&away, 0.001, INF).is_none());
+        let past = Ray::new(&Point3::default(), &Vec3::new(1.0, 0.0, -1.0), 0.0);
+        assert!(world.hit(&past, 0.001, INF).is_none());
+        // The sphere lies beyond t_max.
+        assert!(world.hit(&r, 0.001, 1.0).is_none());
+    }
+}
@@ -0,0 +1,355 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{clamp, random_float, to_f32, Float};
+use crate::png;
+use crate::half;
+use crate::vec::Color;
+use crate::colorspace::ColorSpace;
+
+// Formats images can be written in. OpenEXR and Radiance HDR keep
+// the float radiance, the others are 8-bit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ppm,
+    Png,
+    Exr,
+    Hdr,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png),
+            "exr" => Some(Format::Exr),
+            "hdr" => Some(Format::Hdr),
+            _ => None,
+        }
+    }
+
+    // Picked from the file extension, PPM for anything unknown.
+    pub fn from_path(path: &str) -> Self {
+        Path::new(path).extension()
+            .and_then(|e| e.to_str())
+            .and_then(Format::parse)
+            .unwrap_or(Format::Ppm)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Format::Exr | Format::Hdr)
+    }
+}
+
+// A linear (unclamped) color buffer holding the
+// final averaged radiance of every pixel. Rows are
+// stored top to bottom to match the output formats.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![Color::default(); width * height] }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    // Every pixel multiplied by the factor.
+    pub fn scaled(&self, factor: Float) -> Image {
+        Image { pixels: self.pixels.iter().map(|&p| factor * p).collect(), ..*self }
+    }
+
+    // Resized with nearest neighbor sampling, which keeps
+    // the blocky look of a low resolution preview.
+    pub fn upscaled(&self, width: usize, height: usize) -> Image {
+        let mut out = Image::new(width, height);
+        for y in 0..height {
+            let sy = (y * self.height / height).min(self.height - 1);
+            for x in 0..width {
+                let sx = (x * self.width / width).min(self.width - 1);
+                out.set(x, y, self.pixels[sy * self.width + sx]);
+            }
+        }
+        out
+    }
+
+    // Images of the same size laid out left to right and top to bottom
+    // in rows of the given number, with the cells left over black.
+    pub fn grid(images: &[Image], columns: usize) -> Image {
+        let (width, height) = images.first().map_or((0, 0), |img| (img.width, img.height));
+        let rows = images.len().div_ceil(columns);
+        let mut out = Image::new(width * columns, height * rows);
+        for (i, img) in images.iter().enumerate() {
+            let (x0, y0) = ((i % columns) * width, (i / columns) * height);
+            for y in 0..height {
+                for x in 0..width {
+                    out.set(x0 + x, y0 + y, img.pixels[y * width + x]);
+                }
+            }
+        }
+        out
+    }
+
+    // Gamma-corrected 8-bit RGB triples, rows top to bottom, encoded
+    // for the given color space or else with a plain 2.0 gamma.
+    pub fn to_rgb8(&self, space: Option<ColorSpace>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pixels.len() * 3);
+        for &p in self.pixels.iter() {
+            let rgb = match space {
+                Some(space) => space.to_display(p),
+                None => to_display(p),
+            };
+            out.extend_from_slice(&rgb);
+        }
+        out
+    }
+
+    pub fn write_ppm<W: Write>(&self, space: Option<ColorSpace>, out: &mut W) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+        for rgb in self.to_rgb8(space).chunks(3) {
+            writeln!(out, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
+        }
+        Ok(())
+    }
+
+    pub fn write_png<W: Write>(&self, space: Option<ColorSpace>, out: &mut W) -> io::Result<()> {
+        png::write_rgb8(out, self.width, self.height, &self.to_rgb8(space))
+    }
+}
+
+// Running per-pixel sums of radiance samples. Pixels may
+// receive different numbers of samples, e.g. when a render
+// is stopped part way through a pass, so each pixel keeps
+// its own count and is normalized individually. Samples may
+// also be splatted into pixels around their own with weights,
+// which pixels are normalized by instead. Until the first one
+// is, the weight of every pixel is its count, and the weights
+// take no memory.
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    sums: Sums,
+    counts: Vec<u32>,
+    weights: Vec<Float>,
+}
+
+enum Sums {
+    Full(Vec<Color>),
+    // Running means instead of sums, stored as half floats. A sum
+    // would soon grow too large for a half to register another
+    // sample in it, while a mean stays at the scale of the samples.
+    Half(Vec<[u16; 3]>),
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: Sums::Full(vec![Color::default(); width * height]),
+            counts: vec![0; width * height],
+            weights: Vec::new(),
+        }
+    }
+
+    // Keeps the sums of every pixel in 6 bytes rather than the 16 of a
+    // color, 24 with `f64`, so that with its count a pixel takes half the
+    // memory or less, for very large images. Filters that splat add the
+    // weights either way.
+    // Samples are still averaged in single precision and only the
+    // running means are rounded, at random so they stay unbiased.
+    pub fn half_float(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: Sums::Half(vec![[0; 3]; width * height]),
+            counts: vec![0; width * height],
+            weights: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, sample: Color) {
+        let idx = y * self.width + x;
+        self.counts[idx] += 1;
+        let total = match self.weights.get_mut(idx) {
+            Some(weight) => {
+                *weight += 1.0;
+                *weight
+            }
+            None => self.counts[idx] as Float,
+        };
+        self.accumulate(idx, sample, 1.0, total);
+    }
+
+    // Counts a sample taken in the pixel, which `splat` spreads.
+    pub fn count(&mut self, x: usize, y: usize) {
+        self.spread();
+        self.counts[y * self.width + x] += 1;
+    }
+
+    // Adds a weighted contribution of a sample to the pixel.
+    pub fn splat(&mut self, x: usize, y: usize, sample: Color, weight: Float) {
+        self.spread();
+        let idx = y * self.width + x;
+        self.weights[idx] += weight;
+        self.accumulate(idx, sample, weight, self.weights[idx]);
+    }
+
+    // Gives the pixels weights of their own, from the samples added so
+    // far, each of which counted once.
+    fn spread(&mut self) {
+        if self.weights.is_empty() {
+            self.weights = self.counts.iter().map(|&count| count as Float).collect();
+        }
+    }
+
+    // Adds a sample with the given weight to a pixel whose weights now
+    // total the given one, this sample's included.
+    fn accumulate(&mut self, idx: usize, sample: Color, weight: Float, total: Float) {
+        match &mut self.sums {
+            Sums::Full(sums) => sums[idx] += weight * sample,
+            Sums::Half(means) => {
+                let mean = to_color(means[idx]);
+                let mean = mean + (sample - mean) * (weight / total);
+                means[idx] = [to_half(mean.x), to_half(mean.y), to_half(mean.z)];
+            }
+        }
+    }
+
+    // Samples per pixel, averaged over the whole image.
+    pub fn mean_samples(&self) -> Float {
+        let total: u64 = self.counts.iter().map(|&c| c as u64).sum();
+        total as Float / self.counts.len().max(1) as Float
+    }
+
+    // Averages every pixel over the weights of its samples. Pixels
+    // that never received a sample are left black.
+    pub fn resolve(&self) -> Image {
+        let mut img = Image::new(self.width, self.height);
+        for (idx, &count) in self.counts.iter().enumerate() {
+            let weight = self.weights.get(idx).copied().unwrap_or(count as Float);
+            if weight > 0.0 {
+                img.pixels[idx] = match &self.sums {
+                    Sums::Full(sums) => sums[idx] / weight,
+                    Sums::Half(means) => to_color(means[idx]),
+                };
+            }
+        }
+        img
+    }
+}
+
+// Means beyond the range of halves are clamped rather than made infinite,
+// since an infinite mean would turn into NaN with the next sample.
+fn to_half(v: Float) -> u16 {
+    half::from_f32_dithered(to_f32(v).clamp(-half::MAX, half::MAX), to_f32(random_float()))
+}
+
+fn to_color(h: [u16; 3]) -> Color {
+    Color::new(half::to_f32(h[0]) as Float, half::to_f32(h[1]) as Float, half::to_f32(h[2]) as Float)
+}
+
+// Gamma-correct for gamma=2.0 and quantize to 8 bits.
+pub fn to_display(c: Color) -> [u8; 3] {
+    [
+        (256.0 * clamp(c.x.sqrt(), 0.0, 0.999)) as u8,
+        (256.0 * clamp(c.y.sqrt(), 0.0, 0.999)) as u8,
+        (256.0 * clamp(c.z.sqrt(), 0.0, 0.999)) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulator_normalizes_per_pixel() {
+        let mut acc = Accumulator::new(2, 1);
+        acc.add(0, 0, Color::new(1.0, 1.0, 1.0));
+        acc.add(0, 0, Color::new(0.0, 0.0, 0.0));
+        acc.add(1, 0, Color::new(0.25, 0.25, 0.25));
+
+        let img = acc.resolve();
+        assert_eq!(Color::new(0.5, 0.5, 0.5), img.pixels()[0]);
+        assert_eq!(Color::new(0.25, 0.25, 0.25), img.pixels()[1]);
+    }
+
+    #[test]
+    fn test_half_float_accumulator() {
+        let mut acc = Accumulator::half_float(2, 1);
+        let samples = [0.2, 1.0, 3.0];
+        for i in 0..999 {
+            acc.add(0, 0, Color::new(samples[i % 3], 0.0, 1e5));
+        }
+
+        // Rounding adds a little noise, far less than that of the samples.
+        let img = acc.resolve();
+        assert!((img.pixels()[0].x - 1.4).abs() < 0.05, "{:?}", img.pixels()[0]);
+        // Beyond the range of half floats.
+        assert_eq!(half::MAX as Float, img.pixels()[0].z);
+        assert_eq!(Color::default(), img.pixels()[1]);
+    }
+
+    #[test]
+    fn test_splatted_after_added() {
+        // Samples added before any is splatted count with a weight of 1.
+        let mut acc = Accumulator::new(2, 1);
+        acc.add(0, 0, Color::new(1.0, 1.0, 1.0));
+        assert!(acc.weights.is_empty());
+        acc.count(1, 0);
+        acc.splat(0, 0, Color::new(4.0, 4.0, 4.0), 0.5);
+        acc.splat(1, 0, Color::new(2.0, 2.0, 2.0), 0.5);
+        acc.add(0, 0, Color::new(1.0, 1.0, 1.0));
+
+        let img = acc.resolve();
+        assert_eq!(Color::new(1.6, 1.6, 1.6), img.pixels()[0]);
+        assert_eq!(Color::new(2.0, 2.0, 2.0), img.pixels()[1]);
+        assert_eq!(1.5, acc.mean_samples());
+    }
+
+    #[test]
+    fn test_upscaled() {
+        let mut img = Image::new(2, 1);
+        img.set(1, 0, Color::new(1.0, 1.0, 1.0));
+        let big = img.upscaled(5, 2);
+        let row: Vec<Float> = big.pixels()[5..].iter().map(|p| p.x).collect();
+        assert_eq!(vec![0.0, 0.0, 0.0, 1.0, 1.0], row);
+        assert_eq!(big.pixels()[..5], big.pixels()[5..]);
+    }
+
+    #[test]
+    fn test_grid() {
+        let cell = |c: Float| Image { pixels: vec![Color::new(c, c, c); 2], ..Image::new(2, 1) };
+        let grid = Image::grid(&[cell(1.0), cell(2.0), cell(3.0)], 2);
+        assert_eq!((4, 2), (grid.width, grid.height));
+        let row: Vec<Float> = grid.pixels().iter().map(|p| p.x).collect();
+        assert_eq!(vec![1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 0.0, 0.0], row);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::Exr, Format::from_path("out/frame.exr"));
+        assert_eq!(Format::Hdr, Format::from_path("frame.hdr"));
+        assert_eq!(Format::Ppm, Format::from_path("frame"));
+        assert_eq!(Format::Ppm, Format::from_path("frame.tga"));
+        assert!(Format::Hdr.is_float() && !Format::Png.is_float());
+    }
+
+    #[test]
+    fn test_to_display() {
+        assert_eq!([0, 128, 255], to_display(Color::new(0.0, 0.25, 4.0)));
+    }
+}
@@ -0,0 +1,266 @@
+use std::io;
+
+// Base lengths and extra bits for length codes 257..285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base offsets and extra bits for distance codes 0..29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order in which code length code lengths are stored.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let v = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman code, as the number of codes of each
+// length and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &l in lengths.iter() {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = sym as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, br: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= br.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+// Decompresses a zlib stream (RFC 1950) holding deflate data (RFC 1951).
+pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        return Err(invalid("invalid zlib header"))
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(invalid("zlib preset dictionaries are not supported"))
+    }
+    inflate(&data[2..])
+}
+
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut br = BitReader { data, pos: 0, buf: 0, count: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = br.bits(1)? == 1;
+        match br.bits(2)? {
+            0 => {
+                br.align();
+                let header = data.get(br.pos..br.pos + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if len != !nlen & 0xffff {
+                    return Err(invalid("corrupt stored block length"))
+                }
+                br.pos += 4;
+                let block = data.get(br.pos..br.pos + len).ok_or_else(|| invalid("truncated stored block"))?;
+                out.extend_from_slice(block);
+                br.pos += len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (i, l) in lengths.iter_mut().enumerate() {
+                    *l = match i {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5u8; 30]);
+                inflate_block(&mut br, &lit, &dist, &mut out)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut br)?;
+                inflate_block(&mut br, &lit, &dist, &mut out)?;
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(out)
+        }
+    }
+}
+
+fn dynamic_tables(br: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let nlen = br.bits(5)? as usize + 257;
+    let ndist = br.bits(5)? as usize + 1;
+    let ncode = br.bits(4)? as usize + 4;
+
+    let mut clens = [0u8; 19];
+    for &idx in CLEN_ORDER.iter().take(ncode) {
+        clens[idx] = br.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clens);
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let sym = clen.decode(br)?;
+        let (value, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths.get(i.wrapping_sub(1)).ok_or_else(|| invalid("repeat without a length"))?;
+                (prev, 3 + br.bits(2)? as usize)
+            }
+            17 => (0, 3 + br.bits(3)? as usize),
+            _ => (0, 11 + br.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(invalid("too many code lengths"))
+        }
+        for l in lengths[i..i + repeat].iter_mut() {
+            *l = value;
+        }
+        i += repeat;
+    }
+
+    Ok((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+}
+
+fn inflate_block(br: &mut BitReader, lit: &Huffman, dist: &Huffman, out: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let sym = lit.decode(br)? as usize;
+        match sym {
+            0..=255 => out.push(sym as u8),
+            256 => return Ok(()),
+            _ => {
+                let idx = sym - 257;
+                if idx >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length code"))
+                }
+                let len = LENGTH_BASE[idx] as usize + br.bits(LENGTH_EXTRA[idx] as u32)? as usize;
+
+                let d = dist.decode(br)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(invalid("invalid distance code"))
+                }
+                let distance = DIST_BASE[d] as usize + br.bits(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"))
+                }
+
+                // Copies may overlap their own output, so go byte by byte.
+                let start = out.len() - distance;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_huffman() {
+        // zlib.compress(b"hello hello hello hello")
+        let data = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01,
+            0x68, 0x03, 0x08, 0xb1,
+        ];
+        assert_eq!(b"hello hello hello hello".to_vec(), zlib_decompress(&data).unwrap());
+    }
+
+    #[test]
+    fn test_dynamic_huffman() {
+        let data = [
+            0x78, 0xda, 0x7d, 0x8b, 0xd1, 0x09, 0x80, 0x30, 0x0c, 0x05, 0x57, 0x79, 0x03, 0x14, 0x07, 0xd0,
+            0x2d, 0xdc, 0x20, 0xea, 0xab, 0x16, 0xd3, 0x54, 0xda, 0x88, 0xb8, 0xbd, 0x0e, 0x20, 0xfe, 0xdd,
+            0x71, 0xdc, 0x28, 0x37, 0xbc, 0xca, 0x9c, 0x6c, 0x45, 0x32, 0x14, 0x23, 0x2e, 0x72, 0xa7, 0x2d,
+            0x3d, 0xda, 0xb1, 0xb1, 0xb2, 0x85, 0x0f, 0xc8, 0x74, 0xd1, 0x80, 0x55, 0xa5, 0xbd, 0xa6, 0x92,
+            0x27, 0x56, 0x4f, 0x62, 0xc3, 0x4f, 0xf1, 0x8d, 0x78, 0x67, 0x47, 0x89, 0xb8, 0xcb, 0x59, 0xa1,
+            0x29, 0xb2, 0x7b, 0x00, 0xc5, 0xf0, 0x2e, 0x2c,
+        ];
+        let expected = b"Ray tracing in one weekend: spheres, spheres, spheres, metal, glass, \
+            lambertian; metal, glass, lambertian; the rest of your life.";
+        assert_eq!(expected.to_vec(), zlib_decompress(&data).unwrap());
+    }
+
+    #[test]
+    fn test_stored() {
+        let data = [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27];
+        assert_eq!(b"abc".to_vec(), zlib_decompress(&data).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(zlib_decompress(&[0x78, 0x9c, 0xff, 0xff]).is_err());
+        assert!(zlib_decompress(&[0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use crate::Float;
+use crate::ray::Ray;
+use crate::vec::{Vec3, Point3};
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::motion::{RigidTransform, Pose};
+
+// A shared object placed in the scene with a transform of its own,
+// covering the book's Translate and RotateY wrappers as well as
+// rotations about the other axes. Objects moving while the shutter
+// is open are placed with `motion::Moving` instead. Meshes get a
+// hierarchy of their own (the bottom level) which all of their
+// instances refer to, and the scene hierarchy (the top level) only
+// holds the instances. Moving an instance then only touches the small
+// top level hierarchy, and the bounds of an instance come from the
+// root box of its mesh's hierarchy without visiting any triangles.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    pose: Pose,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: RigidTransform) -> Self {
+        Self { object, pose: Pose::new(&transform) }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        self.pose.hit(self.object.as_ref(), r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        let local = self.object.bounding_box(time0, time1)?;
+        Some(self.pose.apply_box(&local))
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        self.object.pdf_value(&self.pose.invert(*origin), &self.pose.rotation.conjugate().rotate(*dir))
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.pose.rotation.rotate(self.object.random(&self.pose.invert(*origin)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+    use crate::sphere::Sphere;
+    use crate::bvh::Bvh;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn test_instances_share_geometry() {
+        let mat = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let objects: Vec<Box<dyn Hittable>> = vec![Box::new(Sphere::new(Point3::new(1.0, 0.0, 0.0), 0.5, Arc::new(mat)))];
+        let blas: Arc<dyn Hittable> = Arc::new(Bvh::new(objects, 0.0, 0.0));
+
+        let a = Instance::new(blas.clone(), RigidTransform::default());
+        let b = Instance::new(blas.clone(), RigidTransform::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 0.0, 90.0)));
+        assert_eq!(3, Arc::strong_count(&blas));
+
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        let r = Ray::new(&Point3::new(1.0, 0.0, 5.0), &down, 0.0);
+        assert_eq!(Some(4.5), a.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t));
+
+        // Rotated a quarter turn about z, the sphere ends up above the origin.
+        let r = Ray::new(&Point3::new(0.0, 6.0, 5.0), &down, 0.0);
+        let rec = b.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-4);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+
+        let bbox = b.bounding_box(0.0, 0.0).unwrap();
+        assert!((bbox.min - Point3::new(-0.5, 5.5, -0.5)).length() < 1e-4);
+        assert!((bbox.max - Point3::new(0.5, 6.5, 0.5)).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_rotations() {
+        // A sphere at x = 2, turned a quarter about each axis and then moved up by 10.
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Point3::new(2.0, 0.0, 0.0), 0.5, Arc::new(Lambertian::new(Color::default()))));
+        let cases = vec![
+            (Vec3::new(90.0, 0.0, 0.0), Point3::new(2.0, 10.0, 0.0)),
+            (Vec3::new(0.0, 90.0, 0.0), Point3::new(0.0, 10.0, -2.0)),
+            (Vec3::new(0.0, 0.0, 90.0), Point3::new(0.0, 12.0, 0.0)),
+        ];
+        for (rotation, center) in cases {
+            let placed = Instance::new(sphere.clone(), RigidTransform::new(Vec3::new(0.0, 10.0, 0.0), rotation));
+            let bbox = placed.bounding_box(0.0, 0.0).unwrap();
+            assert!((bbox.centroid() - center).length() < 1e-4, "{:?}", rotation);
+
+            let r = Ray::new(&(center + Vec3::new(0.0, 0.0, 5.0)), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let rec = placed.hit(&r, 0.001, Float::INFINITY).unwrap();
+            assert!((rec.t - 4.5).abs() < 1e-4);
+        }
+    }
+}
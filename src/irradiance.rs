@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::Float;
+use crate::vec::{Vec3, Point3, Color, dot};
+
+// Records are used at least this many pixels away from where they
+// were taken. Without a lower bound corners would need records
+// arbitrarily close together, many more than there are pixels.
+const MIN_REACH_PIXELS: Float = 4.0;
+
+// Upper bound of the distance over which a record is valid, relative
+// to the largest coordinate in the scene, so that a record seeing
+// nothing but sky isn't valid everywhere.
+const MAX_RADIUS_SCALE: Float = 0.1;
+
+// Indirect irradiance at a point, sampled over its hemisphere, and
+// the harmonic mean distance to the surfaces seen from it. The closer
+// other surfaces are, the faster irradiance changes around the point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Record {
+    p: Point3,
+    normal: Vec3,
+    irradiance: Color,
+    radius: Float,
+    // Distance within which the record may be used.
+    reach: Float,
+}
+
+impl Record {
+    // Ward's weight of the record at a point, growing without bound as
+    // the point approaches the record's own position and orientation.
+    fn weight(&self, p: &Point3, normal: &Vec3) -> Float {
+        let distance = (*p - self.p).length() / self.radius;
+        let bend = (1.0 - dot(normal, &self.normal)).max(0.0).sqrt();
+        1.0 / (distance + bend).max(1e-6)
+    }
+
+    // Whether the record lies in front of the point, along their normals,
+    // e.g. on top of a step above the floor the point is on. It may see
+    // surfaces the point doesn't.
+    fn ahead_of(&self, p: &Point3, normal: &Vec3) -> bool {
+        dot(&(*p - self.p), &(*normal + self.normal)) < -1e-3 * self.radius
+    }
+}
+
+// Records are kept in hash grids of several cell sizes, each record in
+// the grid whose cells are at least twice as large as the distance over
+// which it is used. The records near a point are then found in the 2x2x2
+// cells nearest to it on every level.
+#[derive(Default)]
+struct Grid {
+    count: usize,
+    cells: HashMap<(i32, [i64; 3]), Vec<Record>>,
+    levels: Vec<i32>,
+}
+
+fn cell(p: &Point3, level: i32) -> [i64; 3] {
+    let size = (2 as Float).powi(level);
+    [(p.x / size).floor() as i64, (p.y / size).floor() as i64, (p.z / size).floor() as i64]
+}
+
+// Caches indirect diffuse lighting at sparse points on surfaces and
+// interpolates between them, since it mostly changes slowly across a
+// surface. `tolerance` is the largest error allowed when reusing a
+// record, between 0 and 1; the higher it is, the fewer records are
+// needed and the blurrier the lighting gets. Records are shared by
+// every thread of a render.
+pub struct IrradianceCache {
+    tolerance: Float,
+    pixel_angle: Float,
+    max_radius: Float,
+    grid: RwLock<Grid>,
+}
+
+impl IrradianceCache {
+    // `scale` is the largest coordinate in the scene, and `pixel_angle`
+    // the angle a pixel of the image being rendered covers.
+    pub fn new(tolerance: Float, scale: Float, pixel_angle: Float) -> Self {
+        Self {
+            tolerance,
+            pixel_angle,
+            max_radius: scale * MAX_RADIUS_SCALE,
+            grid: RwLock::new(Grid::default()),
+        }
+    }
+
+    pub fn records(&self) -> usize {
+        self.grid.read().unwrap().count
+    }
+
+    // Interpolated irradiance at a point, if any records are close enough.
+    pub fn lookup(&self, p: &Point3, normal: &Vec3) -> Option<Color> {
+        let grid = self.grid.read().unwrap();
+        let mut sum = Color::default();
+        let mut total = 0.0;
+        for &level in grid.levels.iter() {
+            let size = (2 as Float).powi(level);
+            let [x, y, z] = cell(p, level);
+            // The neighbors on the side of the cell the point is closest to.
+            let side = |c: Float, i: i64| if c / size - i as Float >= 0.5 { 1 } else { -1 };
+            let (sx, sy, sz) = (side(p.x, x), side(p.y, y), side(p.z, z));
+            for &dx in [0, sx].iter() {
+                for &dy in [0, sy].iter() {
+                    for &dz in [0, sz].iter() {
+                        let records = match grid.cells.get(&(level, [x + dx, y + dy, z + dz])) {
+                            Some(records) => records,
+                            None => continue,
+                        };
+                        for rec in records.iter() {
+                            // Most records are out of reach, which is quicker to check.
+                            if (*p - rec.p).length_sqrd() >= rec.reach * rec.reach {
+                                continue
+                            }
+                            let weight = rec.weight(p, normal);
+                            if weight > 1.0 / self.tolerance && !rec.ahead_of(p, normal) {
+                                sum += weight * rec.irradiance;
+                                total += weight;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if total > 0.0 { Some(sum / total) } else { None }
+    }
+
+    // Adds a record, given the harmonic mean distance to the surfaces
+    // seen from the point (infinite when it sees none), and the point's
+    // distance from the camera.
+    pub fn insert(&self, p: Point3, normal: Vec3, irradiance: Color, distance: Float, depth: Float) {
+        let min_radius = MIN_REACH_PIXELS * self.pixel_angle * depth / self.tolerance;
+        let radius = distance.max(min_radius).min(self.max_radius);
+        let reach = self.tolerance * radius;
+        let level = (2.0 * reach).log2().ceil() as i32;
+
+        let mut grid = self.grid.write().unwrap();
+        grid.count += 1;
+        grid.cells.entry((level, cell(&p, level))).or_default().push(Record { p, normal, irradiance, radius, reach });
+        if !grid.levels.contains(&level) {
+            grid.levels.push(level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation() {
+        let cache = IrradianceCache::new(0.5, 1000.0, 1e-3);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        cache.insert(Point3::new(0.0, 0.0, 0.0), up, Color::new(1.0, 1.0, 1.0), 2.0, 1.0);
+        cache.insert(Point3::new(0.5, 0.0, 0.0), up, Color::new(3.0, 3.0, 3.0), 2.0, 1.0);
+        assert_eq!(2, cache.records());
+
+        // Exactly on a record, it dominates.
+        let on = cache.lookup(&Point3::new(0.0, 0.0, 0.0), &up).unwrap();
+        assert!((on.x - 1.0).abs() < 1e-3);
+        // Halfway between two equal records they are averaged.
+        let between = cache.lookup(&Point3::new(0.25, 0.0, 0.0), &up).unwrap();
+        assert!((between.x - 2.0).abs() < 1e-4);
+
+        // Too far away, or facing another way, the records don't apply.
+        assert_eq!(None, cache.lookup(&Point3::new(5.0, 0.0, 0.0), &up));
+        assert_eq!(None, cache.lookup(&Point3::new(0.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_neighbor_cells() {
+        // A record right across a cell boundary from the point is found.
+        let cache = IrradianceCache::new(0.5, 1000.0, 1e-3);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        cache.insert(Point3::new(-0.01, 0.0, -0.01), up, Color::new(1.0, 1.0, 1.0), 1.0, 1.0);
+        assert!(cache.lookup(&Point3::new(0.01, 0.0, 0.01), &up).is_some());
+    }
+
+    #[test]
+    fn test_ahead() {
+        // Records ahead of a point, e.g. on a step above the floor, don't apply.
+        let cache = IrradianceCache::new(0.5, 1000.0, 1e-3);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        cache.insert(Point3::new(0.0, 0.0, 0.0), up, Color::new(1.0, 1.0, 1.0), 4.0, 1.0);
+        assert!(cache.lookup(&Point3::new(0.0, 0.1, 0.0), &up).is_some());
+        assert_eq!(None, cache.lookup(&Point3::new(0.0, -0.1, 0.0), &up));
+    }
+}
@@ -0,0 +1,464 @@
+use std::io;
+
+// Position in the 8x8 block of each coefficient, in the zig-zag
+// order they are stored in.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Huffman table in the form of the decoding procedure of the
+// standard (F.2.2.3): the largest code of each length, and where
+// the values of each length start.
+#[derive(Clone, Default)]
+struct Huffman {
+    max_code: [i32; 17],
+    min_code: [i32; 17],
+    offsets: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Self {
+        let mut table = Self { values: values.to_vec(), ..Self::default() };
+        let (mut code, mut k) = (0i32, 0usize);
+        for len in 1..17 {
+            let n = counts[len - 1] as usize;
+            table.offsets[len] = k;
+            table.min_code[len] = code;
+            code += n as i32;
+            k += n;
+            table.max_code[len] = if n > 0 { code - 1 } else { -1 };
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, br: &mut BitReader) -> io::Result<u8> {
+        let mut code = 0i32;
+        for len in 1..17 {
+            code = (code << 1) | br.bit() as i32;
+            if code <= self.max_code[len] {
+                let idx = self.offsets[len] + (code - self.min_code[len]) as usize;
+                return self.values.get(idx).copied().ok_or_else(|| invalid("invalid Huffman code"))
+            }
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+// Reads entropy-coded data most significant bit first, with stuffed
+// zero bytes already removed. Past the end it yields one bits, the
+// same as the padding at the end of a segment.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> u32 {
+        let byte = match self.data.get(self.pos) {
+            Some(&b) => b,
+            None => return 1,
+        };
+        let v = (byte >> (7 - self.bit)) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        v as u32
+    }
+
+    // Reads an n-bit magnitude and extends its sign (F.2.2.1).
+    fn signed(&mut self, n: u8) -> i32 {
+        if n == 0 {
+            return 0
+        }
+        let mut v = 0i32;
+        for _ in 0..n {
+            v = (v << 1) | self.bit() as i32;
+        }
+        if v < 1 << (n - 1) { v - (1 << n) + 1 } else { v }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    // Decoded samples, padded to whole blocks.
+    stride: usize,
+    samples: Vec<u8>,
+}
+
+// Decodes a baseline (sequential, Huffman coded, 8-bit) JPEG with
+// one (grayscale) or three (YCbCr) components into 8-bit RGBA, rows
+// top to bottom. Chroma is upsampled by pixel replication.
+// Progressive and arithmetic coded files are not supported.
+pub fn decode_rgba8(data: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err(invalid("not a JPEG file"))
+    }
+
+    let mut quant = [[0u16; 64]; 4];
+    let mut dc_tables: [Huffman; 4] = Default::default();
+    let mut ac_tables: [Huffman; 4] = Default::default();
+    let mut components: Vec<Component> = Vec::new();
+    let (mut width, mut height) = (0, 0);
+    let mut restart_interval = 0;
+
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes.
+        while data.get(pos) == Some(&0xff) && data.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = match data.get(pos..pos + 2) {
+            Some([0xff, m]) => *m,
+            _ => return Err(invalid("expected a JPEG marker")),
+        };
+        pos += 2;
+        if marker == 0xd9 {
+            break
+        }
+
+        let len = match data.get(pos..pos + 2) {
+            Some(b) => u16::from_be_bytes([b[0], b[1]]) as usize,
+            None => return Err(invalid("truncated JPEG segment")),
+        };
+        let segment = data.get(pos + 2..pos + len).ok_or_else(|| invalid("truncated JPEG segment"))?;
+        pos += len;
+
+        match marker {
+            // Define quantization tables.
+            0xdb => {
+                let mut s = segment;
+                while !s.is_empty() {
+                    let (precision, id) = ((s[0] >> 4) as usize, (s[0] & 15) as usize);
+                    let size = 64 * (precision + 1);
+                    let values = s.get(1..1 + size).ok_or_else(|| invalid("truncated quantization table"))?;
+                    let table = quant.get_mut(id).ok_or_else(|| invalid("invalid quantization table"))?;
+                    for (k, q) in table.iter_mut().enumerate() {
+                        *q = match precision {
+                            0 => values[k] as u16,
+                            _ => u16::from_be_bytes([values[2 * k], values[2 * k + 1]]),
+                        };
+                    }
+                    s = &s[1 + size..];
+                }
+            }
+            // Define Huffman tables.
+            0xc4 => {
+                let mut s = segment;
+                while s.len() >= 17 {
+                    let (class, id) = (s[0] >> 4, (s[0] & 15) as usize);
+                    let counts = &s[1..17];
+                    let n: usize = counts.iter().map(|&c| c as usize).sum();
+                    let values = s.get(17..17 + n).ok_or_else(|| invalid("truncated Huffman table"))?;
+                    let tables = if class == 0 { &mut dc_tables } else { &mut ac_tables };
+                    *tables.get_mut(id).ok_or_else(|| invalid("invalid Huffman table"))? = Huffman::new(counts, values);
+                    s = &s[17 + n..];
+                }
+            }
+            // Baseline and extended sequential frames.
+            0xc0 | 0xc1 => {
+                if segment.len() < 6 || segment[0] != 8 {
+                    return Err(invalid("only 8-bit JPEGs are supported"))
+                }
+                height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+                width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+                let count = segment[5] as usize;
+                if count != 1 && count != 3 {
+                    return Err(invalid("only grayscale and YCbCr JPEGs are supported"))
+                }
+                for c in 0..count {
+                    let spec = segment.get(6 + 3 * c..9 + 3 * c).ok_or_else(|| invalid("truncated JPEG frame"))?;
+                    let (h, v) = ((spec[1] >> 4) as usize, (spec[1] & 15) as usize);
+                    if !(1..=4).contains(&h) || !(1..=4).contains(&v) || spec[2] > 3 {
+                        return Err(invalid("invalid JPEG component"))
+                    }
+                    components.push(Component { id: spec[0], h, v, quant: spec[2] as usize, ..Component::default() });
+                }
+                if width == 0 || height == 0 {
+                    return Err(invalid("JPEGs without a height are not supported"))
+                }
+
+                let (hmax, vmax) = max_sampling(&components);
+                let (mcus_x, mcus_y) = (width.div_ceil(8 * hmax), height.div_ceil(8 * vmax));
+                for c in components.iter_mut() {
+                    c.stride = mcus_x * c.h * 8;
+                    c.samples = vec![0; c.stride * mcus_y * c.v * 8];
+                }
+            }
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err(invalid("only baseline JPEGs are supported"))
+            }
+            // Define restart interval.
+            0xdd => {
+                if segment.len() < 2 {
+                    return Err(invalid("truncated restart interval"))
+                }
+                restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as usize;
+            }
+            // Start of scan, followed by the entropy-coded data.
+            0xda => {
+                if components.is_empty() {
+                    return Err(invalid("JPEG scan before frame header"))
+                }
+                let count = segment.first().copied().unwrap_or(0) as usize;
+                let mut scan = Vec::with_capacity(count);
+                for i in 0..count {
+                    let spec = segment.get(1 + 2 * i..3 + 2 * i).ok_or_else(|| invalid("truncated JPEG scan"))?;
+                    let idx = components.iter().position(|c| c.id == spec[0])
+                        .ok_or_else(|| invalid("scan of unknown JPEG component"))?;
+                    scan.push((idx, (spec[1] >> 4) as usize & 3, (spec[1] & 15) as usize & 3));
+                }
+
+                let (intervals, end) = entropy_data(data, pos);
+                pos = end;
+                let tables = Tables { quant: &quant, dc: &dc_tables, ac: &ac_tables };
+                decode_scan(&mut components, &scan, &intervals, restart_interval, (width, height), &tables)?;
+            }
+            _ => {}
+        }
+    }
+
+    if components.is_empty() {
+        return Err(invalid("missing JPEG frame header"))
+    }
+    Ok((width, height, to_rgba8(&components, width, height)))
+}
+
+fn max_sampling(components: &[Component]) -> (usize, usize) {
+    let hmax = components.iter().map(|c| c.h).max().unwrap_or(1);
+    let vmax = components.iter().map(|c| c.v).max().unwrap_or(1);
+    (hmax, vmax)
+}
+
+// Splits the entropy-coded data starting at `pos` into restart
+// intervals, removing stuffed bytes. Returns them along with the
+// position of the marker ending the scan.
+fn entropy_data(data: &[u8], mut pos: usize) -> (Vec<Vec<u8>>, usize) {
+    let mut intervals = vec![Vec::new()];
+    while pos < data.len() {
+        if data[pos] != 0xff {
+            intervals.last_mut().unwrap().push(data[pos]);
+            pos += 1;
+            continue
+        }
+        match data.get(pos + 1) {
+            Some(0x00) => {
+                intervals.last_mut().unwrap().push(0xff);
+                pos += 2;
+            }
+            Some(0xd0..=0xd7) => {
+                intervals.push(Vec::new());
+                pos += 2;
+            }
+            Some(0xff) => pos += 1,
+            _ => break,
+        }
+    }
+    (intervals, pos)
+}
+
+struct Tables<'a> {
+    quant: &'a [[u16; 64]; 4],
+    dc: &'a [Huffman; 4],
+    ac: &'a [Huffman; 4],
+}
+
+// Decodes every block of a scan. Scans of a single component cover
+// just that component's blocks in raster order; otherwise each MCU
+// holds h x v blocks of every component in turn.
+fn decode_scan(
+    components: &mut [Component],
+    scan: &[(usize, usize, usize)],
+    intervals: &[Vec<u8>],
+    restart_interval: usize,
+    (width, height): (usize, usize),
+    tables: &Tables,
+) -> io::Result<()> {
+    let (hmax, vmax) = max_sampling(components);
+    let (mcus_x, mcus_y, blocks_per_mcu) = if scan.len() == 1 {
+        let c = &components[scan[0].0];
+        let w = (width * c.h).div_ceil(hmax);
+        let h = (height * c.v).div_ceil(vmax);
+        (w.div_ceil(8), h.div_ceil(8), 1)
+    } else {
+        (width.div_ceil(8 * hmax), height.div_ceil(8 * vmax), 0)
+    };
+
+    let total = mcus_x * mcus_y;
+    let per_interval = if restart_interval > 0 { restart_interval } else { total };
+    let mut block = [0f32; 64];
+    for (n, data) in intervals.iter().enumerate() {
+        let mut br = BitReader { data, pos: 0, bit: 0 };
+        let mut predictors = vec![0i32; scan.len()];
+        for mcu in n * per_interval..((n + 1) * per_interval).min(total) {
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for (s, &(idx, dc, ac)) in scan.iter().enumerate() {
+                let (h, v) = if blocks_per_mcu == 1 { (1, 1) } else { (components[idx].h, components[idx].v) };
+                for by in 0..v {
+                    for bx in 0..h {
+                        let c = &mut components[idx];
+                        decode_block(&mut br, &tables.dc[dc], &tables.ac[ac], &tables.quant[c.quant], &mut predictors[s], &mut block)?;
+                        let x0 = (mx * h + bx) * 8;
+                        let y0 = (my * v + by) * 8;
+                        idct_into(&block, &mut c.samples, c.stride, x0, y0);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_block(
+    br: &mut BitReader,
+    dc: &Huffman,
+    ac: &Huffman,
+    quant: &[u16; 64],
+    predictor: &mut i32,
+    block: &mut [f32; 64],
+) -> io::Result<()> {
+    *block = [0.0; 64];
+
+    let size = dc.decode(br)?;
+    *predictor += br.signed(size);
+    block[0] = (*predictor * quant[0] as i32) as f32;
+
+    let mut k = 1;
+    while k < 64 {
+        let rs = ac.decode(br)?;
+        let (run, size) = ((rs >> 4) as usize, rs & 15);
+        if size == 0 {
+            if run != 15 {
+                break
+            }
+            k += 16;
+            continue
+        }
+        k += run;
+        if k > 63 {
+            return Err(invalid("JPEG coefficient out of range"))
+        }
+        block[ZIGZAG[k]] = (br.signed(size) * quant[k] as i32) as f32;
+        k += 1;
+    }
+    Ok(())
+}
+
+// Inverse DCT of a block of coefficients, written level shifted
+// to the samples at (x0, y0). Separable, one dimension at a time.
+fn idct_into(block: &[f32; 64], samples: &mut [u8], stride: usize, x0: usize, y0: usize) {
+    let mut cos = [[0f32; 8]; 8];
+    for (x, row) in cos.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+            *c = scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+
+    let mut rows = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| cos[x][u] * block[v * 8 + u]).sum::<f32>() / 2.0;
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let value = (0..8).map(|v| cos[y][v] * rows[v * 8 + x]).sum::<f32>() / 2.0;
+            samples[(y0 + y) * stride + x0 + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn to_rgba8(components: &[Component], width: usize, height: usize) -> Vec<u8> {
+    let (hmax, vmax) = max_sampling(components);
+    let sample = |c: &Component, x: usize, y: usize| {
+        c.samples[(y * c.v / vmax) * c.stride + x * c.h / hmax] as f32
+    };
+
+    let mut out = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let luma = sample(&components[0], x, y);
+            let rgb = match components {
+                [_, cb, cr] => {
+                    let (cb, cr) = (sample(cb, x, y) - 128.0, sample(cr, x, y) - 128.0);
+                    [luma + 1.402 * cr, luma - 0.344_136 * cb - 0.714_136 * cr, luma + 1.772 * cb]
+                }
+                _ => [luma; 3],
+            };
+            for c in rgb.iter() {
+                out.push(c.round().clamp(0.0, 255.0) as u8);
+            }
+            out.push(255);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two 16x16 MCUs of solid (200, 30, 40) and (20, 60, 220), with
+    // 4:2:0 chroma subsampling and a restart marker between them.
+    const TWO_COLORS: [u8; 185] = [
+        0xff, 0xd8, 0xff, 0xdb, 0x00, 0x43, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x20,
+        0x03, 0x01, 0x22, 0x00, 0x02, 0x11, 0x00, 0x03, 0x11, 0x00, 0xff, 0xc4, 0x00, 0x1f, 0x00, 0x00,
+        0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0xff, 0xc4, 0x00, 0x14, 0x10,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xff, 0xdd, 0x00, 0x04, 0x00, 0x01, 0xff, 0xda, 0x00, 0x0c, 0x03, 0x01, 0x00, 0x02, 0x00,
+        0x03, 0x00, 0x00, 0x3f, 0x00, 0x94, 0x78, 0x00, 0x04, 0x1f, 0xaa, 0xa0, 0x7f, 0xff, 0xd0, 0x90,
+        0x78, 0x00, 0x05, 0x57, 0x09, 0x7b, 0xbf, 0xff, 0xd9,
+    ];
+
+    #[test]
+    fn test_decode() {
+        let (width, height, rgba) = decode_rgba8(&TWO_COLORS).unwrap();
+        assert_eq!((32, 16), (width, height));
+
+        let close = |x: usize, y: usize, expected: [u8; 3]| {
+            let p = &rgba[(y * width + x) * 4..][..4];
+            p[3] == 255 && (0..3).all(|c| (p[c] as i32 - expected[c] as i32).abs() <= 2)
+        };
+        assert!(close(0, 0, [200, 30, 40]));
+        assert!(close(15, 15, [200, 30, 40]));
+        assert!(close(16, 0, [20, 60, 220]));
+        assert!(close(31, 15, [20, 60, 220]));
+    }
+
+    #[test]
+    fn test_idct_constant() {
+        let mut block = [0.0; 64];
+        block[0] = 80.0;
+        let mut samples = [0u8; 64];
+        idct_into(&block, &mut samples, 8, 0, 0);
+        assert!(samples.iter().all(|&s| s == 138));
+    }
+
+    #[test]
+    fn test_rejects_unsupported() {
+        assert!(decode_rgba8(b"GIF89a").is_err());
+        assert!(decode_rgba8(&[0xff, 0xd8, 0xff, 0xc2, 0x00, 0x02, 0xff, 0xd9]).is_err());
+        assert!(decode_rgba8(&TWO_COLORS[..100]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// A parsed JSON value. Objects keep their keys in file order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Json> {
+        match self {
+            Json::Object(fields) => fields.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+// Parses a complete JSON document. Errors give the line and
+// column where parsing stopped.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, chars: text.char_indices().peekable() };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error("unexpected trailing characters")),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&mut self, msg: &str) -> String {
+        let offset = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        format!("{} at line {}, column {}", msg, line, column)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some(&(_, c)) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|&(_, c)| c) {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            match self.chars.peek() {
+                Some(&(_, c)) if c == expected => {
+                    self.chars.next();
+                }
+                _ => return Err(self.error(&format!("invalid literal, expected '{}'", word))),
+            }
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        let mut end = start;
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                break
+            }
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+        match self.text[start..end].parse() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(_) => Err(self.error(&format!("invalid number '{}'", &self.text[start..end]))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.chars.next() {
+                Some((_, c)) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = match self.chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'u')) => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // The four hex digits after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let mut code = self.hex4()?;
+        if (0xd800..0xdc00).contains(&code) {
+            self.literal("\\u", Json::Null)?;
+            let low = self.hex4()?;
+            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+        }
+        std::char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|(_, c)| c.to_digit(16));
+            match digit {
+                Some(d) => code = code * 16 + d,
+                None => return Err(self.error("invalid unicode escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if let Some(&(_, ']')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Json::Array(items))
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.peek().map(|&(_, c)| c) {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some(']') => {
+                    self.chars.next();
+                    return Ok(Json::Array(items))
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if let Some(&(_, '}')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Json::Object(fields))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.peek().map(|&(_, c)| c) {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some('}') => {
+                    self.chars.next();
+                    return Ok(Json::Object(fields))
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = parse(r#" {"a": [1, -2.5e1, true, null], "b": {"c": "x\"é\n"}, "d": []} "#).unwrap();
+        let a = json.get("a").and_then(Json::as_array).unwrap();
+        assert_eq!(Some(1.0), a[0].as_f64());
+        assert_eq!(Some(-25.0), a[1].as_f64());
+        assert_eq!(Some(true), a[2].as_bool());
+        assert_eq!(Json::Null, a[3]);
+        assert_eq!(Some("x\"é\n"), json.get("b").and_then(|b| b.get("c")).and_then(Json::as_str));
+        assert_eq!(Some(&[][..]), json.get("d").and_then(Json::as_array));
+        assert_eq!(None, json.get("e"));
+        assert_eq!(Some("😀"), parse(r#""😀""#).unwrap().as_str());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Err("expected ',' or '}' at line 2, column 3".to_string()), parse("{\"a\": 1\n  \"b\": 2}"));
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("[1, 2] 3").is_err());
+        assert!(parse("tru").is_err());
+        assert!(parse("\"abc").is_err());
+        assert!(parse("1.2.3").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,537 @@
+use std::sync::OnceLock;
+
+use crate::{random_float, Float};
+use crate::vec::{Vec3, Point3, cross, dot};
+
+// The innermost intersection tests, of a ray against a box, a sphere, a
+// packet of eight spheres or a triangle, in plain scalar code and in SIMD
+// for CPUs with AVX2 and FMA or with NEON. Other x86_64 CPUs test the
+// packet four spheres at a time in SSE2, which they all have, and run
+// the scalar code for the rest. Which of them to run is
+// decided once, by asking the CPU the program runs on, so a build for a
+// generic target is as fast as one made with `target-cpu=native`
+// wherever it is run. The SIMD kernels work in f32, so with the `f64`
+// feature the scalar ones always run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Isa {
+    Scalar,
+    #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+    Avx2,
+    #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+    Sse2,
+    #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+    Neon,
+}
+
+static ISA: OnceLock<Isa> = OnceLock::new();
+
+fn isa() -> Isa {
+    *ISA.get_or_init(detect)
+}
+
+fn detect() -> Isa {
+    #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            return Isa::Avx2
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Isa::Neon
+        }
+    }
+    FALLBACK
+}
+
+// What runs where the CPU has none of the extensions asked for.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+const FALLBACK: Isa = Isa::Sse2;
+#[cfg(not(all(target_arch = "x86_64", not(feature = "f64"))))]
+const FALLBACK: Isa = Isa::Scalar;
+
+// Name of the instruction set the kernels run on.
+pub fn isa_name() -> &'static str {
+    match isa() {
+        Isa::Scalar => "scalar",
+        #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+        Isa::Avx2 => "avx2",
+        #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+        Isa::Sse2 => "sse2",
+        #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+        Isa::Neon => "neon",
+    }
+}
+
+// The variants other than `Scalar` are only ever made by `detect`, once
+// the CPU is known to support them, which makes calling them sound.
+impl Isa {
+    fn hit_box(self, min: &Point3, max: &Point3, orig: &Point3, dir: &Vec3, t_min: Float, t_max: Float) -> bool {
+        match self {
+            Isa::Scalar => scalar::hit_box(min, max, orig, dir, t_min, t_max),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Sse2 => scalar::hit_box(min, max, orig, dir, t_min, t_max),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Avx2 => unsafe { avx2::hit_box(min, max, orig, dir, t_min, t_max) },
+            #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+            Isa::Neon => unsafe { neon::hit_box(min, max, orig, dir, t_min, t_max) },
+        }
+    }
+
+    fn sphere(self, center: &Point3, orig: &Point3, dir: &Vec3) -> [Float; 3] {
+        match self {
+            Isa::Scalar => scalar::sphere(center, orig, dir),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Sse2 => scalar::sphere(center, orig, dir),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Avx2 => unsafe { avx2::sphere(center, orig, dir) },
+            #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+            Isa::Neon => unsafe { neon::sphere(center, orig, dir) },
+        }
+    }
+
+    fn triangle(self, v0: &Point3, edge1: &Vec3, edge2: &Vec3, orig: &Point3, dir: &Vec3) -> [Float; 4] {
+        match self {
+            Isa::Scalar => scalar::triangle(v0, edge1, edge2, orig, dir),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Sse2 => scalar::triangle(v0, edge1, edge2, orig, dir),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Avx2 => unsafe { avx2::triangle(v0, edge1, edge2, orig, dir) },
+            #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+            Isa::Neon => unsafe { neon::triangle(v0, edge1, edge2, orig, dir) },
+        }
+    }
+
+    fn spheres(self, lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: Float, t_max: Float) -> [Float; LANES] {
+        match self {
+            Isa::Scalar => scalar::spheres(lanes, orig, dir, t_min, t_max),
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Avx2 => unsafe { avx2::spheres(lanes, orig, dir, t_min, t_max) },
+            #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+            Isa::Sse2 => sse2::spheres(lanes, orig, dir, t_min, t_max),
+            #[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+            Isa::Neon => unsafe { neon::spheres(lanes, orig, dir, t_min, t_max) },
+        }
+    }
+}
+
+// Spheres in a packet, the width of an AVX2 register.
+pub const LANES: usize = 8;
+
+// The centers and squared radii of a packet of spheres, a coordinate of
+// every sphere after the other, as SIMD registers load them. Lanes with
+// no sphere have a NaN center, which no ray hits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SphereLanes {
+    pub x: [Float; LANES],
+    pub y: [Float; LANES],
+    pub z: [Float; LANES],
+    pub radius_sqrd: [Float; LANES],
+}
+
+impl SphereLanes {
+    pub fn new(spheres: &[(Point3, Float)]) -> Self {
+        assert!(spheres.len() <= LANES);
+        let mut lanes = Self { x: [Float::NAN; LANES], y: [0.0; LANES], z: [0.0; LANES], radius_sqrd: [0.0; LANES] };
+        for (i, (center, radius)) in spheres.iter().enumerate() {
+            lanes.x[i] = center.x;
+            lanes.y[i] = center.y;
+            lanes.z[i] = center.z;
+            lanes.radius_sqrd[i] = radius * radius;
+        }
+        lanes
+    }
+}
+
+// Slab test: the ray hits the box if the intervals in which it is
+// between each pair of planes overlap within (t_min, t_max).
+pub fn hit_box(min: &Point3, max: &Point3, orig: &Point3, dir: &Vec3, t_min: Float, t_max: Float) -> bool {
+    isa().hit_box(min, max, orig, dir, t_min, t_max)
+}
+
+// The terms of the quadratic in t whose roots are where the ray meets a
+// sphere around the center: the squared length of the direction, the
+// half linear term, and the squared distance of the origin from the
+// center, from which the caller takes the squared radius.
+pub fn sphere(center: &Point3, orig: &Point3, dir: &Vec3) -> [Float; 3] {
+    isa().sphere(center, orig, dir)
+}
+
+// Moeller-Trumbore for a triangle with a corner at v0 and the given edges
+// from it: the determinant, which is 0 for rays parallel to the triangle
+// and negative for those seeing its back, followed by the barycentric
+// coordinates u and v and the ray parameter, all times the determinant.
+pub fn triangle(v0: &Point3, edge1: &Vec3, edge2: &Vec3, orig: &Point3, dir: &Vec3) -> [Float; 4] {
+    isa().triangle(v0, edge1, edge2, orig, dir)
+}
+
+// Where the ray first meets each sphere of the packet within (t_min,
+// t_max), or infinity where it doesn't, solving the same quadratic as
+// `Sphere::hit` with the terms of `sphere` in every lane at once.
+pub fn spheres(lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: Float, t_max: Float) -> [Float; LANES] {
+    isa().spheres(lanes, orig, dir, t_min, t_max)
+}
+
+mod scalar {
+    use super::*;
+
+    pub fn hit_box(min: &Point3, max: &Point3, orig: &Point3, dir: &Vec3, mut t_min: Float, mut t_max: Float) -> bool {
+        for a in 0..3 {
+            let inv_d = 1.0 / dir[a];
+            let mut t0 = (min[a] - orig[a]) * inv_d;
+            let mut t1 = (max[a] - orig[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return false
+            }
+        }
+        true
+    }
+
+    pub fn sphere(center: &Point3, orig: &Point3, dir: &Vec3) -> [Float; 3] {
+        let oc = *orig - *center;
+        [dir.length_sqrd(), dot(&oc, dir), oc.length_sqrd()]
+    }
+
+    pub fn triangle(v0: &Point3, edge1: &Vec3, edge2: &Vec3, orig: &Point3, dir: &Vec3) -> [Float; 4] {
+        let pvec = cross(dir, edge2);
+        let tvec = *orig - *v0;
+        let qvec = cross(&tvec, edge1);
+        [dot(edge1, &pvec), dot(&tvec, &pvec), dot(dir, &qvec), dot(edge2, &qvec)]
+    }
+
+    pub fn spheres(lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: Float, t_max: Float) -> [Float; LANES] {
+        let mut ts = [Float::INFINITY; LANES];
+        let a = dir.length_sqrd();
+        for (i, t) in ts.iter_mut().enumerate() {
+            let oc = *orig - Point3::new(lanes.x[i], lanes.y[i], lanes.z[i]);
+            let half_b = dot(&oc, dir);
+            let c = oc.length_sqrd() - lanes.radius_sqrd[i];
+            let discriminant = half_b * half_b - a * c;
+            if discriminant > 0.0 {
+                let root = discriminant.sqrt();
+                let (near, far) = ((-half_b - root) / a, (-half_b + root) / a);
+                if near < t_max && near > t_min {
+                    *t = near;
+                } else if far < t_max && far > t_min {
+                    *t = far;
+                }
+            }
+        }
+        ts
+    }
+}
+
+// Vectors take the four lanes of an SSE register, the last one 0. The
+// 128-bit operations are encoded in AVX, and the products in the cross
+// products are fused.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use crate::vec::{Vec3, Point3};
+    use super::{SphereLanes, LANES};
+
+    // The lanes of a vector rotated to y, z, x.
+    const YZX: i32 = 0b11_00_10_01;
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn load(v: &Vec3) -> __m128 {
+        _mm_set_ps(0.0, v.z, v.y, v.x)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn cross(a: __m128, b: __m128) -> __m128 {
+        let a_yzx = _mm_shuffle_ps(a, a, YZX);
+        let b_yzx = _mm_shuffle_ps(b, b, YZX);
+        let c = _mm_fmsub_ps(a, b_yzx, _mm_mul_ps(a_yzx, b));
+        _mm_shuffle_ps(c, c, YZX)
+    }
+
+    // The sums of the lanes of each of the four vectors.
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn sums(a: __m128, b: __m128, c: __m128, d: __m128) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        _mm_storeu_ps(out.as_mut_ptr(), _mm_hadd_ps(_mm_hadd_ps(a, b), _mm_hadd_ps(c, d)));
+        out
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn hit_box(min: &Point3, max: &Point3, orig: &Point3, dir: &Vec3, t_min: f32, t_max: f32) -> bool {
+        let orig = load(orig);
+        let inv_d = _mm_div_ps(_mm_set1_ps(1.0), load(dir));
+        let t0 = _mm_mul_ps(_mm_sub_ps(load(min), orig), inv_d);
+        let t1 = _mm_mul_ps(_mm_sub_ps(load(max), orig), inv_d);
+        let flip = _mm_cmplt_ps(inv_d, _mm_setzero_ps());
+        // Where a lane is NaN, as in the unused one, the maximum and
+        // minimum keep the bound given as their second operand.
+        let near = _mm_max_ps(_mm_blendv_ps(t0, t1, flip), _mm_set1_ps(t_min));
+        let far = _mm_min_ps(_mm_blendv_ps(t1, t0, flip), _mm_set1_ps(t_max));
+
+        let near = _mm_max_ps(near, _mm_movehl_ps(near, near));
+        let near = _mm_max_ss(near, _mm_shuffle_ps(near, near, 1));
+        let far = _mm_min_ps(far, _mm_movehl_ps(far, far));
+        let far = _mm_min_ss(far, _mm_shuffle_ps(far, far, 1));
+        _mm_comigt_ss(far, near) == 1
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn sphere(center: &Point3, orig: &Point3, dir: &Vec3) -> [f32; 3] {
+        let oc = _mm_sub_ps(load(orig), load(center));
+        let dir = load(dir);
+        let [a, half_b, c, _] = sums(_mm_mul_ps(dir, dir), _mm_mul_ps(oc, dir), _mm_mul_ps(oc, oc), _mm_setzero_ps());
+        [a, half_b, c]
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn triangle(v0: &Point3, edge1: &Vec3, edge2: &Vec3, orig: &Point3, dir: &Vec3) -> [f32; 4] {
+        let (edge1, edge2, dir) = (load(edge1), load(edge2), load(dir));
+        let pvec = cross(dir, edge2);
+        let tvec = _mm_sub_ps(load(orig), load(v0));
+        let qvec = cross(tvec, edge1);
+        sums(_mm_mul_ps(edge1, pvec), _mm_mul_ps(tvec, pvec), _mm_mul_ps(dir, qvec), _mm_mul_ps(edge2, qvec))
+    }
+
+    // Every sphere in a lane of 256-bit registers. The products are not
+    // fused, to round as the scalar kernel does.
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn spheres(lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: f32, t_max: f32) -> [f32; LANES] {
+        let load = |a: &[f32; LANES]| _mm256_loadu_ps(a.as_ptr());
+        let (dx, dy, dz) = (_mm256_set1_ps(dir.x), _mm256_set1_ps(dir.y), _mm256_set1_ps(dir.z));
+        let ocx = _mm256_sub_ps(_mm256_set1_ps(orig.x), load(&lanes.x));
+        let ocy = _mm256_sub_ps(_mm256_set1_ps(orig.y), load(&lanes.y));
+        let ocz = _mm256_sub_ps(_mm256_set1_ps(orig.z), load(&lanes.z));
+        let dot = |ax, ay, az, bx, by, bz| _mm256_add_ps(_mm256_add_ps(_mm256_mul_ps(ax, bx), _mm256_mul_ps(ay, by)), _mm256_mul_ps(az, bz));
+        let a = _mm256_set1_ps(dir.length_sqrd());
+        let half_b = dot(ocx, ocy, ocz, dx, dy, dz);
+        let c = _mm256_sub_ps(dot(ocx, ocy, ocz, ocx, ocy, ocz), load(&lanes.radius_sqrd));
+        let discriminant = _mm256_sub_ps(_mm256_mul_ps(half_b, half_b), _mm256_mul_ps(a, c));
+        let hit = _mm256_cmp_ps(discriminant, _mm256_setzero_ps(), _CMP_GT_OQ);
+
+        let root = _mm256_sqrt_ps(discriminant);
+        let neg_b = _mm256_sub_ps(_mm256_setzero_ps(), half_b);
+        let near = _mm256_div_ps(_mm256_sub_ps(neg_b, root), a);
+        let far = _mm256_div_ps(_mm256_add_ps(neg_b, root), a);
+        let (lo, hi) = (_mm256_set1_ps(t_min), _mm256_set1_ps(t_max));
+        let within = |t| _mm256_and_ps(_mm256_cmp_ps(t, hi, _CMP_LT_OQ), _mm256_cmp_ps(t, lo, _CMP_GT_OQ));
+        let (near_ok, far_ok) = (_mm256_and_ps(hit, within(near)), _mm256_and_ps(hit, within(far)));
+        let t = _mm256_blendv_ps(_mm256_blendv_ps(_mm256_set1_ps(f32::INFINITY), far, far_ok), near, near_ok);
+        let mut out = [0.0; LANES];
+        _mm256_storeu_ps(out.as_mut_ptr(), t);
+        out
+    }
+}
+
+// The packet of spheres in the four lanes of an SSE register, for x86_64
+// CPUs without AVX2. SSE2 has no blend, so the hits are picked with
+// masks, and no fused products either, so it rounds as the scalar
+// kernel does without trying.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    use crate::vec::{Vec3, Point3};
+    use super::{SphereLanes, LANES};
+
+    // Lanes of `a` where the mask is set and of `b` elsewhere.
+    fn select(mask: __m128, a: __m128, b: __m128) -> __m128 {
+        unsafe { _mm_or_ps(_mm_and_ps(mask, a), _mm_andnot_ps(mask, b)) }
+    }
+
+    // The packet as two halves of four lanes. Safe, as every x86_64 CPU
+    // has SSE2.
+    pub fn spheres(lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: f32, t_max: f32) -> [f32; LANES] {
+        let mut out = [0.0; LANES];
+        unsafe {
+            let a = _mm_set1_ps(dir.length_sqrd());
+            for half in 0..2 {
+                let load = |v: &[f32; LANES]| _mm_loadu_ps(v[4 * half..].as_ptr());
+                let ocx = _mm_sub_ps(_mm_set1_ps(orig.x), load(&lanes.x));
+                let ocy = _mm_sub_ps(_mm_set1_ps(orig.y), load(&lanes.y));
+                let ocz = _mm_sub_ps(_mm_set1_ps(orig.z), load(&lanes.z));
+                let dot = |ax, ay, az, bx, by, bz| _mm_add_ps(_mm_add_ps(_mm_mul_ps(ax, bx), _mm_mul_ps(ay, by)), _mm_mul_ps(az, bz));
+                let half_b = dot(ocx, ocy, ocz, _mm_set1_ps(dir.x), _mm_set1_ps(dir.y), _mm_set1_ps(dir.z));
+                let c = _mm_sub_ps(dot(ocx, ocy, ocz, ocx, ocy, ocz), load(&lanes.radius_sqrd));
+                let discriminant = _mm_sub_ps(_mm_mul_ps(half_b, half_b), _mm_mul_ps(a, c));
+                let hit = _mm_cmpgt_ps(discriminant, _mm_setzero_ps());
+
+                let root = _mm_sqrt_ps(discriminant);
+                let neg_b = _mm_sub_ps(_mm_setzero_ps(), half_b);
+                let near = _mm_div_ps(_mm_sub_ps(neg_b, root), a);
+                let far = _mm_div_ps(_mm_add_ps(neg_b, root), a);
+                let within = |t| _mm_and_ps(_mm_cmplt_ps(t, _mm_set1_ps(t_max)), _mm_cmpgt_ps(t, _mm_set1_ps(t_min)));
+                let (near_ok, far_ok) = (_mm_and_ps(hit, within(near)), _mm_and_ps(hit, within(far)));
+                let t = select(near_ok, near, select(far_ok, far, _mm_set1_ps(f32::INFINITY)));
+                _mm_storeu_ps(out[4 * half..].as_mut_ptr(), t);
+            }
+        }
+        out
+    }
+}
+
+// The same kernels as for AVX2 on the four lanes of a NEON register.
+#[cfg(all(target_arch = "aarch64", not(feature = "f64")))]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use crate::vec::{Vec3, Point3};
+    use super::{SphereLanes, LANES};
+
+    #[target_feature(enable = "neon")]
+    unsafe fn lanes(x: f32, y: f32, z: f32) -> float32x4_t {
+        let v = [x, y, z, 0.0];
+        vld1q_f32(v.as_ptr())
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn load(v: &Vec3) -> float32x4_t {
+        lanes(v.x, v.y, v.z)
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn cross(a: &Vec3, b: &Vec3) -> float32x4_t {
+        let c = vmulq_f32(lanes(a.y, a.z, a.x), lanes(b.z, b.x, b.y));
+        vfmsq_f32(c, lanes(a.z, a.x, a.y), lanes(b.y, b.z, b.x))
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn hit_box(min: &Point3, max: &Point3, orig: &Point3, dir: &Vec3, t_min: f32, t_max: f32) -> bool {
+        let orig = load(orig);
+        let inv_d = vdivq_f32(vdupq_n_f32(1.0), load(dir));
+        let t0 = vmulq_f32(vsubq_f32(load(min), orig), inv_d);
+        let t1 = vmulq_f32(vsubq_f32(load(max), orig), inv_d);
+        let flip = vcltq_f32(inv_d, vdupq_n_f32(0.0));
+        // The maximum and minimum of a NaN and a number are the number.
+        let near = vmaxnmq_f32(vbslq_f32(flip, t1, t0), vdupq_n_f32(t_min));
+        let far = vminnmq_f32(vbslq_f32(flip, t0, t1), vdupq_n_f32(t_max));
+        vminvq_f32(far) > vmaxvq_f32(near)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn sphere(center: &Point3, orig: &Point3, dir: &Vec3) -> [f32; 3] {
+        let oc = vsubq_f32(load(orig), load(center));
+        let dir = load(dir);
+        [vaddvq_f32(vmulq_f32(dir, dir)), vaddvq_f32(vmulq_f32(oc, dir)), vaddvq_f32(vmulq_f32(oc, oc))]
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn triangle(v0: &Point3, edge1: &Vec3, edge2: &Vec3, orig: &Point3, dir: &Vec3) -> [f32; 4] {
+        let tvec = *orig - *v0;
+        let (pvec, qvec) = (cross(dir, edge2), cross(&tvec, edge1));
+        [
+            vaddvq_f32(vmulq_f32(load(edge1), pvec)),
+            vaddvq_f32(vmulq_f32(load(&tvec), pvec)),
+            vaddvq_f32(vmulq_f32(load(dir), qvec)),
+            vaddvq_f32(vmulq_f32(load(edge2), qvec)),
+        ]
+    }
+
+    // The packet as two halves of four lanes, without fused products.
+    #[target_feature(enable = "neon")]
+    pub unsafe fn spheres(lanes: &SphereLanes, orig: &Point3, dir: &Vec3, t_min: f32, t_max: f32) -> [f32; LANES] {
+        let mut out = [0.0; LANES];
+        let a = vdupq_n_f32(dir.length_sqrd());
+        for half in 0..2 {
+            let load = |v: &[f32; LANES]| vld1q_f32(v[4 * half..].as_ptr());
+            let ocx = vsubq_f32(vdupq_n_f32(orig.x), load(&lanes.x));
+            let ocy = vsubq_f32(vdupq_n_f32(orig.y), load(&lanes.y));
+            let ocz = vsubq_f32(vdupq_n_f32(orig.z), load(&lanes.z));
+            let dot = |ax, ay, az, bx: float32x4_t, by, bz| vaddq_f32(vaddq_f32(vmulq_f32(ax, bx), vmulq_f32(ay, by)), vmulq_f32(az, bz));
+            let half_b = dot(ocx, ocy, ocz, vdupq_n_f32(dir.x), vdupq_n_f32(dir.y), vdupq_n_f32(dir.z));
+            let c = vsubq_f32(dot(ocx, ocy, ocz, ocx, ocy, ocz), load(&lanes.radius_sqrd));
+            let discriminant = vsubq_f32(vmulq_f32(half_b, half_b), vmulq_f32(a, c));
+            let hit = vcgtq_f32(discriminant, vdupq_n_f32(0.0));
+
+            let root = vsqrtq_f32(discriminant);
+            let neg_b = vnegq_f32(half_b);
+            let near = vdivq_f32(vsubq_f32(neg_b, root), a);
+            let far = vdivq_f32(vaddq_f32(neg_b, root), a);
+            let within = |t| vandq_u32(vcltq_f32(t, vdupq_n_f32(t_max)), vcgtq_f32(t, vdupq_n_f32(t_min)));
+            let (near_ok, far_ok) = (vandq_u32(hit, within(near)), vandq_u32(hit, within(far)));
+            let t = vbslq_f32(near_ok, near, vbslq_f32(far_ok, far, vdupq_n_f32(f32::INFINITY)));
+            vst1q_f32(out[4 * half..].as_mut_ptr(), t);
+        }
+        out
+    }
+}
+
+// Compares the kernels the CPU runs with the scalar ones on random rays
+// and shapes, and on rays along the axes, whose reciprocal directions
+// are infinite. Fused products round differently, so triangles only
+// need to agree closely.
+pub fn match_scalar() -> Result<(), String> {
+    let random = |scale: Float| Vec3::random_bounded(-scale, scale);
+    let close = |a: Float, b: Float| (a - b).abs() <= 1e-4 * (1.0 + a.abs().max(b.abs()));
+
+    for _ in 0..10000 {
+        let (orig, dir) = (random(5.0), random(1.0));
+        let (a, b) = (random(2.0), random(2.0));
+        let min = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        if Isa::Scalar.hit_box(&min, &max, &orig, &dir, 0.001, 10.0) != hit_box(&min, &max, &orig, &dir, 0.001, 10.0) {
+            return Err(format!("box tests of {:?} to {:?} disagree", min, max))
+        }
+        let (expected, got) = (Isa::Scalar.sphere(&a, &orig, &dir), sphere(&a, &orig, &dir));
+        if expected != got {
+            return Err(format!("sphere terms {:?} are not {:?}", got, expected))
+        }
+        let centers: Vec<(Point3, Float)> = (0..LANES - 1).map(|_| (random(3.0), random_float())).collect();
+        let lanes = SphereLanes::new(&centers);
+        let (expected, got) = (Isa::Scalar.spheres(&lanes, &orig, &dir, 0.001, 10.0), spheres(&lanes, &orig, &dir, 0.001, 10.0));
+        if expected != got {
+            return Err(format!("packed sphere hits {:?} are not {:?}", got, expected))
+        }
+        let (v0, edge1, edge2) = (random(2.0), random(2.0), random(2.0));
+        let (expected, got) = (Isa::Scalar.triangle(&v0, &edge1, &edge2, &orig, &dir), triangle(&v0, &edge1, &edge2, &orig, &dir));
+        if !expected.iter().zip(got.iter()).all(|(&a, &b)| close(a, b)) {
+            return Err(format!("triangle terms {:?} are not {:?}", got, expected))
+        }
+    }
+
+    // Along the z axis through the box, the edge of one of its faces and
+    // beside it, and along x starting on one of its faces.
+    let (min, max) = (Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+    let rays = [
+        (Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0)),
+        (Point3::new(1.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0)),
+        (Point3::new(2.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0)),
+        (Point3::new(0.0, -1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+    ];
+    for (orig, dir) in rays.iter() {
+        if Isa::Scalar.hit_box(&min, &max, orig, dir, 0.001, Float::INFINITY) != hit_box(&min, &max, orig, dir, 0.001, Float::INFINITY) {
+            return Err(format!("box tests of a ray from {:?} along {:?} disagree", orig, dir))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_scalar() {
+        crate::rng::seed(11);
+        assert_eq!(Ok(()), match_scalar());
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+    #[test]
+    fn test_sse2_spheres() {
+        // Run whatever the CPU has, as `match_scalar` only tries one.
+        crate::rng::seed(12);
+        let random = |scale: Float| Vec3::random_bounded(-scale, scale);
+        for n in 0..1000 {
+            let (orig, dir) = (random(5.0), random(1.0));
+            let centers: Vec<(Point3, Float)> = (0..n % LANES + 1).map(|_| (random(3.0), random_float())).collect();
+            let lanes = SphereLanes::new(&centers);
+            let expected = Isa::Scalar.spheres(&lanes, &orig, &dir, 0.001, 10.0);
+            assert_eq!(expected, Isa::Sse2.spheres(&lanes, &orig, &dir, 0.001, 10.0));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+pub mod vec;
+pub mod mat;
+pub mod ray;
+pub mod hittable;
+pub mod sphere;
+pub mod triangle;
+pub mod aarect;
+pub mod backdrop;
+pub mod primitive;
+pub mod mesh;
+pub mod camera;
+pub mod clip;
+pub mod material;
+pub mod texture;
+pub mod perlin;
+pub mod image;
+pub mod colorspace;
+mod half;
+pub mod rng;
+mod png;
+pub mod hdr;
+mod inflate;
+mod jpeg;
+pub mod stats;
+pub mod tonemap;
+pub mod photometry;
+mod onb;
+pub mod sun;
+pub mod light;
+pub mod lighttree;
+mod pdf;
+pub mod cli;
+pub mod exr;
+pub mod scene;
+mod scatter;
+pub mod scenefile;
+mod json;
+mod expr;
+pub mod aabb;
+mod kernels;
+pub mod bvh;
+pub mod motion;
+pub mod instance;
+pub mod transform;
+pub mod medium;
+pub mod cutout;
+pub mod render;
+pub mod aov;
+pub mod filter;
+pub mod glare;
+pub mod irradiance;
+pub mod checkerboard;
+pub mod selftest;
+pub mod progress;
+mod affinity;
+pub mod memory;
+#[cfg(unix)]
+pub mod daemon;
+
+pub use render::Renderer;
+
+// The floating point type of the renderer's geometry, colors and
+// sampling: f32 by default, or f64 with the `f64` feature, which trades
+// speed and memory for precision in scenes far from the origin or
+// spanning many orders of magnitude, where f32 hits wander off their
+// surfaces. Image files are written in f32 either way, and the SIMD
+// intersection kernels are only used with f32.
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+pub const INF: Float = Float::INFINITY;
+pub const PI: Float = consts::PI;
+
+pub fn random_float() -> Float {
+    rng::next_float()
+}
+
+// Narrows to f32, for file formats and SIMD, which hold f32 whatever
+// the precision of the renderer, or widens to f64, for sums and
+// expressions kept in f64 either way.
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(x: Float) -> f32 {
+    x as f32
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f64(x: Float) -> f64 {
+    x as f64
+}
+
+fn degrees_to_radians(degrees: Float) -> Float {
+    degrees * PI / 180.0
+}
+
+fn clamp(x: Float, min: Float, max: Float) -> Float {
+    if x < min { return min };
+    if x > max { return max };
+    x
+}
+
@@ -0,0 +1,152 @@
+use crate::{PI, INF, degrees_to_radians, Float};
+use crate::vec::{Vec3, Point3, Color, dot, unit_vector};
+use crate::ray::Ray;
+use crate::hittable::{HitRecord, Hittable};
+
+// Lights of no size, which no ray can hit, so that they only light the
+// scene through shadow rays cast towards them from every diffuse hit.
+// Every such ray finds the light, where a path has to be lucky to hit
+// emissive geometry, so hard-lit scenes converge far faster with them,
+// and their shadows are perfectly sharp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    // Light spreading evenly in all directions from a point, whose
+    // intensity is the irradiance it gives at a distance of 1, falling
+    // off with the square of the distance.
+    Point { position: Point3, intensity: Color },
+    // Light from infinitely far away, arriving along parallel rays from
+    // the direction, as from a sun of no size. The irradiance is that
+    // of surfaces facing it.
+    Directional { direction: Vec3, irradiance: Color },
+    // A point light only shining into a cone around the direction it
+    // points in, full within the inner cosine and fading out towards
+    // the outer one.
+    Spot { position: Point3, direction: Vec3, intensity: Color, cos_inner: Float, cos_outer: Float },
+}
+
+impl Light {
+    pub fn point(position: Point3, intensity: Color) -> Self {
+        Light::Point { position, intensity }
+    }
+
+    // The direction is the one towards the light.
+    pub fn directional(direction: Vec3, irradiance: Color) -> Self {
+        Light::Directional { direction: unit_vector(direction), irradiance }
+    }
+
+    // The angles are those of the cone's sides from its axis, in degrees.
+    pub fn spot(position: Point3, direction: Vec3, intensity: Color, inner_angle: Float, outer_angle: Float) -> Self {
+        Light::Spot {
+            position,
+            direction: unit_vector(direction),
+            intensity,
+            cos_inner: degrees_to_radians(inner_angle.min(outer_angle)).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+
+    // The unit direction from the point towards the light, the distance
+    // to it, and the irradiance it gives on a surface facing it, if any.
+    pub fn illuminate(&self, p: &Point3) -> Option<(Vec3, Float, Color)> {
+        match *self {
+            Light::Point { position, intensity } => {
+                let (dir, distance) = towards(p, &position)?;
+                Some((dir, distance, intensity / (distance * distance)))
+            }
+            Light::Directional { direction, irradiance } => Some((direction, INF, irradiance)),
+            Light::Spot { position, direction, intensity, cos_inner, cos_outer } => {
+                let (dir, distance) = towards(p, &position)?;
+                let cos = -dot(&dir, &direction);
+                if cos <= cos_outer {
+                    return None
+                }
+                // Smoothly from the rim of the cone to the inner one.
+                let x = if cos_inner > cos_outer { ((cos - cos_outer) / (cos_inner - cos_outer)).min(1.0) } else { 1.0 };
+                let falloff = x * x * (3.0 - 2.0 * x);
+                Some((dir, distance, falloff * intensity / (distance * distance)))
+            }
+        }
+    }
+}
+
+fn towards(p: &Point3, position: &Point3) -> Option<(Vec3, Float)> {
+    let offset = *position - *p;
+    let distance = offset.length();
+    if distance == 0.0 {
+        return None
+    }
+    Some((offset / distance, distance))
+}
+
+// Irradiance at a diffuse hit from the lights not in shadow, over pi,
+// which the surface's albedo turns into the light it scatters towards
+// every direction. Shadow rays start and stop short of their ends by
+// the bias, like every other ray.
+pub fn direct<T: Hittable>(lights: &[Light], world: &T, rec: &HitRecord, time: Float, bias: Float) -> Color {
+    let mut sum = Color::default();
+    for light in lights.iter() {
+        let (dir, distance, irradiance) = match light.illuminate(&rec.p) {
+            Some(lit) => lit,
+            None => continue,
+        };
+        let cos = dot(&rec.normal, &dir);
+        if cos <= 0.0 {
+            continue
+        }
+        let shadow = Ray { shadow: true, ..Ray::new(&rec.p, &dir, time) };
+        if world.hit(&shadow, bias, distance - bias).is_none() {
+            sum += cos * irradiance;
+        }
+    }
+    sum / PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+    use crate::hittable::HittableList;
+
+    #[test]
+    fn test_illuminate() {
+        let p = Point3::default();
+        let white = Color::new(1.0, 1.0, 1.0);
+        let (dir, distance, irradiance) = Light::point(Point3::new(0.0, 2.0, 0.0), white).illuminate(&p).unwrap();
+        assert_eq!((Vec3::new(0.0, 1.0, 0.0), 2.0, Color::new(0.25, 0.25, 0.25)), (dir, distance, irradiance));
+        let (dir, distance, _) = Light::directional(Vec3::new(0.0, 0.0, 3.0), white).illuminate(&p).unwrap();
+        assert_eq!((Vec3::new(0.0, 0.0, 1.0), INF), (dir, distance));
+
+        // A spot pointing down shines fully below it, fades towards the
+        // rim of its cone and is dark outside.
+        let spot = Light::spot(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), white, 20.0, 30.0);
+        assert_eq!(Color::new(1.0, 1.0, 1.0), spot.illuminate(&p).unwrap().2);
+        let at = |degrees: Float| Point3::new(degrees_to_radians(degrees).tan(), 0.0, 0.0);
+        let distance_sqrd = (at(25.0) - Point3::new(0.0, 1.0, 0.0)).length_sqrd();
+        let falloff = spot.illuminate(&at(25.0)).unwrap().2.x * distance_sqrd;
+        assert!(falloff > 0.0 && falloff < 1.0, "{}", falloff);
+        assert!(spot.illuminate(&at(31.0)).is_none());
+    }
+
+    #[test]
+    fn test_direct() {
+        let gray = || Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let floor = Sphere::new(Point3::new(0.0, -100.0, 0.0), 100.0, gray());
+        let down = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = floor.hit(&down, 0.001, INF).unwrap();
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(Point3::new(0.0, -100.0, 0.0), 100.0, gray())));
+
+        // A light overhead at 45 degrees, then with a ball in the way.
+        let lights = [Light::directional(Vec3::new(1.0, 1.0, 0.0), Color::new(PI, PI, PI))];
+        let lit = direct(&lights, &world, &rec, 0.0, 0.001);
+        assert!((lit.x - (0.5 as Float).sqrt()).abs() < 1e-4, "{:?}", lit);
+        world.add(Box::new(Sphere::new(Point3::new(2.0, 2.0, 0.0), 0.5, gray())));
+        assert_eq!(Color::default(), direct(&lights, &world, &rec, 0.0, 0.001));
+
+        // Lights below the surface don't light it.
+        let below = [Light::point(Point3::new(0.0, -1.0, 0.0), Color::new(1.0, 1.0, 1.0))];
+        assert_eq!(Color::default(), direct(&below, &world, &rec, 0.0, 0.001));
+    }
+}
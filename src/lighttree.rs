@@ -0,0 +1,237 @@
+use crate::{random_float, INF, Float};
+use crate::vec::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::stats::luminance;
+
+// Picks which of a scene's lights to sample from a point by how much
+// light each could give it, rather than with equal odds. Bounded lights
+// are grouped into a binary tree of boxes, each knowing the power of the
+// lights within it, and a light is picked by walking down from the root,
+// taking each branch with odds proportional to its power over its
+// squared distance. With hundreds of lights, points are lit mostly by
+// those near them, which are picked nearly every time while the far ones
+// are picked far less. Unbounded lights, such as the sun, are picked
+// with the same odds as the whole tree.
+#[derive(Default)]
+pub struct LightTree {
+    lights: Vec<Box<dyn Hittable>>,
+    unbounded: Vec<usize>,
+    // The root is the first node, when there are bounded lights.
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bbox: Aabb,
+    power: Float,
+    kind: Kind,
+}
+
+enum Kind {
+    Leaf(usize),
+    Inner(usize, usize),
+}
+
+impl LightTree {
+    pub fn new(lights: HittableList) -> Self {
+        let lights = lights.objects;
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounding_box(0.0, 1.0) {
+                Some(bbox) => bounded.push((i, bbox, power(light.as_ref(), &bbox))),
+                None => unbounded.push(i),
+            }
+        }
+        let mut tree = Self { lights, unbounded, nodes: Vec::new() };
+        if !bounded.is_empty() {
+            tree.build(&mut bounded);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // Adds the nodes of the lights, splitting them in half along the
+    // longest axis of their centers, and returns the index of the first.
+    fn build(&mut self, lights: &mut [(usize, Aabb, Float)]) -> usize {
+        let index = self.nodes.len();
+        let bbox = lights[1..].iter().fold(lights[0].1, |acc, l| acc.surrounding(&l.1));
+        let power = lights.iter().map(|l| l.2).sum();
+        if let [(light, _, _)] = *lights {
+            self.nodes.push(Node { bbox, power, kind: Kind::Leaf(light) });
+            return index
+        }
+
+        let centers: Vec<Point3> = lights.iter().map(|l| l.1.centroid()).collect();
+        let axis = Aabb::from_points(&centers).longest_axis();
+        lights.sort_by(|a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+        self.nodes.push(Node { bbox, power, kind: Kind::Leaf(0) });
+        let (near, far) = lights.split_at_mut(lights.len() / 2);
+        let left = self.build(near);
+        let right = self.build(far);
+        self.nodes[index].kind = Kind::Inner(left, right);
+        index
+    }
+
+    // Odds of walking into each child of a node from the point.
+    fn odds(&self, left: usize, right: usize, origin: &Point3) -> (Float, Float) {
+        let (a, b) = (self.nodes[left].importance(origin), self.nodes[right].importance(origin));
+        if a + b > 0.0 { (a / (a + b), b / (a + b)) } else { (0.5, 0.5) }
+    }
+
+    // Odds of sampling the tree rather than any one unbounded light.
+    fn tree_odds(&self) -> Float {
+        if self.nodes.is_empty() { 0.0 } else { 1.0 / (1 + self.unbounded.len()) as Float }
+    }
+
+    // Density of sampling the direction within the node, which only
+    // branches it points into can give.
+    fn node_pdf(&self, node: usize, origin: &Point3, dir: &Vec3) -> Float {
+        match self.nodes[node].kind {
+            Kind::Leaf(light) => self.lights[light].pdf_value(origin, dir),
+            Kind::Inner(left, right) => {
+                let r = Ray::new(origin, dir, 0.0);
+                let (a, b) = self.odds(left, right, origin);
+                let branch = |child: usize, odds: Float| {
+                    if odds > 0.0 && self.nodes[child].bbox.hit(&r, 0.0, INF) { odds * self.node_pdf(child, origin, dir) } else { 0.0 }
+                };
+                branch(left, a) + branch(right, b)
+            }
+        }
+    }
+}
+
+impl Node {
+    // Power over the squared distance to the box, which stops growing
+    // once the point is within the box's radius.
+    fn importance(&self, p: &Point3) -> Float {
+        let radius_sqrd = 0.25 * self.bbox.extent().length_sqrd();
+        let distance_sqrd = (self.bbox.centroid() - *p).length_sqrd();
+        self.power / distance_sqrd.max(radius_sqrd).max(1e-6)
+    }
+}
+
+// Rough power of a light: the luminance of its emission times the area
+// of its surface, taken as half that of its box, which is exact for
+// flat lights and close for spheres. The emission is found by looking at
+// the light from outside of its box.
+fn power(light: &dyn Hittable, bbox: &Aabb) -> Float {
+    let reach = bbox.extent().length() + 1.0;
+    let origin = bbox.centroid() + reach * Vec3::new(0.267, 0.802, 0.535);
+    let r = Ray::new(&origin, &light.random(&origin), 0.0);
+    let emitted = light.hit(&r, 0.0, INF).map_or(0.0, |rec| luminance(rec.mat.emitted()));
+    emitted * (0.5 * bbox.surface_area()).max(1e-6)
+}
+
+impl Hittable for LightTree {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord> = None;
+        for light in self.lights.iter() {
+            if let Some(rec) = light.hit(r, t_min, closest.as_ref().map_or(t_max, |rec| rec.t)) {
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        let mut boxes = self.lights.iter().map(|light| light.bounding_box(time0, time1));
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.surrounding(&b?)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        let each = (1.0 - self.tree_odds()) / self.unbounded.len().max(1) as Float;
+        let unbounded: Float = self.unbounded.iter().map(|&i| each * self.lights[i].pdf_value(origin, dir)).sum();
+        match self.nodes.is_empty() {
+            true => unbounded,
+            false => unbounded + self.tree_odds() * self.node_pdf(0, origin, dir),
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let u = random_float();
+        let tree_odds = self.tree_odds();
+        if u >= tree_odds {
+            let i = ((u - tree_odds) / (1.0 - tree_odds) * self.unbounded.len() as Float) as usize;
+            return self.lights[self.unbounded[i.min(self.unbounded.len() - 1)]].random(origin)
+        }
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                Kind::Leaf(light) => return self.lights[light].random(origin),
+                Kind::Inner(left, right) => {
+                    let (a, _) = self.odds(left, right, origin);
+                    node = if random_float() < a { left } else { right };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::vec::{Color, dot, unit_vector};
+    use crate::sphere::Sphere;
+    use crate::material::DiffuseLight;
+    use crate::sun::Sun;
+
+    fn lamp(x: Float, emit: Float) -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Point3::new(x, 0.0, 0.0), 0.5, Arc::new(DiffuseLight::new(Color::new(emit, emit, emit)))))
+    }
+
+    #[test]
+    fn test_picks_near_and_bright_lights() {
+        let mut lights = HittableList::default();
+        for i in 0..16 {
+            lights.add(lamp(4.0 * i as Float, 1.0));
+        }
+        lights.add(lamp(-8.0, 100.0));
+        let tree = LightTree::new(lights);
+
+        // From next to the first lamp, it and the bright one get most samples.
+        crate::rng::seed(3);
+        let origin = Point3::new(0.0, 1.0, 0.0);
+        let towards = |x: Float| unit_vector(Point3::new(x, 0.0, 0.0) - origin);
+        let n = 4000;
+        let (mut near, mut bright) = (0, 0);
+        for _ in 0..n {
+            let dir = tree.random(&origin);
+            if dot(&dir, &towards(0.0)) > 0.86 {
+                near += 1;
+            } else if dot(&dir, &towards(-8.0)) > 0.99 {
+                bright += 1;
+            }
+        }
+        assert!(near > n / 3 && bright > n / 4, "{} {}", near, bright);
+    }
+
+    #[test]
+    fn test_pdf_matches_sampling() {
+        let mut lights = HittableList::default();
+        let centers = [Point3::new(4.0, 0.0, 0.0), Point3::new(-4.0, 0.0, 0.0), Point3::new(0.0, 0.0, 4.0), Point3::new(0.0, 0.0, -3.0), Point3::new(3.0, -3.0, 3.0)];
+        for (i, &center) in centers.iter().enumerate() {
+            let emit = Color::new(1.0, 1.0, 1.0) * (1.0 + i as Float);
+            lights.add(Box::new(Sphere::new(center, 0.5, Arc::new(DiffuseLight::new(emit)))));
+        }
+        lights.add(Box::new(Sun::new(Vec3::new(0.0, 1.0, 0.0), 10.0, Color::new(1.0, 1.0, 1.0), 0.0)));
+        let tree = LightTree::new(lights);
+
+        // Directions drawn with the density the tree claims for them
+        // average 1 over the density to the solid angle the lights cover.
+        let cap = |cos: Float| 2.0 * crate::PI * (1.0 - cos);
+        let spheres: Float = centers.iter().map(|c| cap((1.0 - 0.25 / c.length_sqrd()).sqrt())).sum();
+        let covered = spheres + cap(crate::degrees_to_radians(5.0).cos());
+        crate::rng::seed(7);
+        let n = 40000;
+        let origin = Point3::default();
+        let mean = (0..n).map(|_| 1.0 / tree.pdf_value(&origin, &tree.random(&origin))).sum::<Float>() / n as Float;
+        assert!((mean / covered - 1.0).abs() < 0.03, "{} vs {}", mean, covered);
+    }
+}
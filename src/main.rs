@@ -1,160 +1,889 @@
-use std::io::{stderr, Write};
-use rand::Rng;
-
-mod vec;
-mod ray;
-mod hittable;
-mod sphere;
-mod camera;
-mod material;
-
-use vec::{Vec3, Point3, Color, unit_vector};
-use ray::Ray;
-use sphere::Sphere;
-use hittable::{Hittable, HittableList};
-use camera::Camera;
-use material::{Metal, Lambertian, Dielectric, Material};
-
-const ASPECT_RATIO: f32 = 16.0 / 9.0;
+use std::fs::{self, File};
+use std::io::{stdout, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use raytracing::{bvh, checkerboard, cli, exr, hdr, instance, irradiance, material, memory};
+use raytracing::{mesh, motion, render, rng, scene, scenefile, selftest, texture, vec, to_f32, Float, INF};
+use raytracing::hittable::{Hittable, HittableList};
+use raytracing::scenefile::FileSettings;
+use raytracing::sun::Sun;
+use raytracing::light::Light;
+use raytracing::lighttree::LightTree;
+use raytracing::triangle::Culling;
+use raytracing::image::{Format, Image};
+use raytracing::exr::DeepImage;
+use raytracing::aov::AovImages;
+use raytracing::stats::ImageStats;
+use raytracing::glare::GLARE_THRESHOLD;
+use raytracing::cli::{Args, Command};
+use raytracing::scene::Scene;
+use raytracing::camera::Camera;
+use raytracing::bvh::Bvh;
+use raytracing::render::{render, Background, Schedule, Settings};
+
+// Defaults for settings not given on the command line.
+const ASPECT_RATIO: Float = 16.0 / 9.0;
 const IMAGE_WIDTH: usize = 256;
-const IMAGE_HEIGHT: usize = (IMAGE_WIDTH as f32 / ASPECT_RATIO) as usize;
 const SAMPLES_PER_PIXEL: usize = 100;
 const MAX_DEPTH: usize = 50;
-const INF: f32 = std::f32::INFINITY;
-const PI: f32 = std::f32::consts::PI;
+const MEMORY_LIMIT: usize = 4 << 30;
+// Fractions of the resolution, and samples per pixel, of previews.
+const PREVIEW_FACTORS: [usize; 3] = [8, 4, 2];
+const PREVIEW_SAMPLES: usize = 4;
+// Width, and samples per pixel, of gallery thumbnails.
+const GALLERY_WIDTH: usize = 192;
+const GALLERY_SAMPLES: usize = 16;
+
+// With several cameras or frames each image gets the camera
+// and frame index appended to its file stem, e.g. out_1.ppm.
+fn output_path(base: &str, index: usize, count: usize) -> String {
+    if count <= 1 {
+        return base.to_string()
+    }
+    with_suffix(base, &index.to_string())
+}
+
+// Frames are numbered with as many digits as the last frame of the
+// animation takes, so that they sort in order, e.g. out_007.png.
+fn frame_path(base: &str, frame: usize, frames: &Range<usize>) -> String {
+    if *frames == (0..1) {
+        return base.to_string()
+    }
+    let digits = (frames.end - 1).to_string().len();
+    with_suffix(base, &format!("{:0digits$}", frame, digits = digits))
+}
+
+// Appends to the file stem of a path, e.g. out_normal.exr.
+fn with_suffix(base: &str, suffix: &str) -> String {
+    let path = Path::new(base);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{}.{}", stem, suffix, ext),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
 
-pub fn random_f32() -> f32 {
-    rand::thread_rng().gen_range(0.0, 1.0)
+// The format is picked from the file extension unless given. OpenEXR
+// keeps the float radiance, in half floats with `--half-float`, and
+// Radiance HDR keeps it in shared exponent form. 8-bit formats are
+// tone mapped first, after automatic exposure if it is on. Glare is added
+// to every format, around light brighter than white once exposed. Every
+// format is converted to the color space when one is given.
+// Images without an output path are written to stdout.
+fn write_image(img: &Image, samples: Float, args: &Args, output: Option<&str>) -> std::io::Result<()> {
+    let format = args.format.unwrap_or_else(|| output.map_or(Format::Ppm, Format::from_path));
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(stdout().lock()),
+    };
+    let color_space = args.color_space;
+    let exposure = args.auto_exposure.map_or(1.0, |metering| metering.exposure(img));
+    let glared;
+    let img = match args.glare {
+        Some(glare) => {
+            glared = glare.apply(img, GLARE_THRESHOLD / exposure);
+            &glared
+        }
+        None => img,
+    };
+    match format {
+        Format::Exr => exr::write_exr(img, Some(to_f32(samples)), args.half_float, color_space, &mut out),
+        Format::Hdr => hdr::write_hdr(img, color_space, &mut out),
+        Format::Png => args.tonemap.apply_image(&img.scaled(exposure)).write_png(color_space, &mut out),
+        Format::Ppm => args.tonemap.apply_image(&img.scaled(exposure)).write_ppm(color_space, &mut out),
+    }?;
+    out.flush()
 }
 
-fn degrees_to_radians(degrees: f32) -> f32 {
-    degrees * PI / 180.0
+// Averages partial renders of the same frame, each weighted by the
+// number of samples per pixel it was rendered with.
+fn merge(output: &str, inputs: &[String]) -> std::io::Result<()> {
+    let mut sum: Option<Image> = None;
+    let mut total = 0.0;
+    for input in inputs.iter() {
+        let (img, samples) = exr::read_exr(&mut BufReader::new(File::open(input)?))?;
+        let weight = samples.map_or(1.0, |samples| samples as Float);
+        let acc = sum.get_or_insert_with(|| Image::new(img.width, img.height));
+        if (acc.width, acc.height) != (img.width, img.height) {
+            let msg = format!("'{}' is {}x{}, expected {}x{}", input, img.width, img.height, acc.width, acc.height);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+        }
+
+        for (p, &q) in acc.pixels_mut().iter_mut().zip(img.pixels()) {
+            *p += q * weight;
+        }
+        total += weight;
+    }
+
+    let mut img = sum.unwrap_or_else(|| Image::new(0, 0));
+    for p in img.pixels_mut().iter_mut() {
+        *p = *p / total;
+    }
+    eprintln!("Merged {} partial renders, {} samples per pixel.", inputs.len(), total);
+    write_image(&img, total, &Args::default(), Some(output))
 }
 
-fn clamp(x: f32, min: f32, max: f32) -> f32 {
-    if x < min { return min };
-    if x > max { return max };
-    x
+// Bakes the texture into an image of the --width, 512 by default, and
+// the --height, as wide by default.
+fn bake(def: &str, args: &Args) {
+    let tex = match scenefile::load_texture(def) {
+        Ok(tex) => tex,
+        Err(e) => {
+            eprintln!("error: failed to load texture: {}", e);
+            process::exit(1);
+        }
+    };
+    let width = args.width.unwrap_or(512);
+    let img = texture::bake(tex.as_ref(), width, args.height.unwrap_or(width));
+    if let Err(e) = write_image(&img, 1.0, args, args.output.as_deref()) {
+        eprintln!("error: failed to write image: {}", e);
+        process::exit(1);
+    }
+}
+
+fn write_stats<F: Fn(&str) -> String>(img: &Image, args: &Args, path: F) -> std::io::Result<()> {
+    if args.stats.is_none() && args.histogram.is_none() {
+        return Ok(())
+    }
+
+    let stats = ImageStats::compute(img);
+    if let Some(base) = &args.stats {
+        File::create(path(base))?.write_all(stats.to_json().as_bytes())?;
+    }
+    if let Some(base) = &args.histogram {
+        stats.plot().write_png(None, &mut File::create(path(base))?)?;
+    }
+    Ok(())
+}
+
+// Writes the cameras as the cameras of a scene file.
+fn write_cameras(cameras: &[Camera], args: &Args) -> std::io::Result<()> {
+    let path = match &args.export_cameras {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let entries: Vec<String> = cameras.iter().map(|cam| format!("    {}", cam.to_json())).collect();
+    let json = format!("{{\n  \"cameras\": [\n{}\n  ]\n}}\n", entries.join(",\n"));
+    File::create(path)?.write_all(json.as_bytes())
+}
+
+fn main() {
+    let args = match Command::parse(std::env::args().skip(1)) {
+        Ok(Command::Render(args)) => *args,
+        Ok(Command::Merge { output, inputs }) => {
+            if let Err(e) = merge(&output, &inputs) {
+                eprintln!("error: failed to merge: {}", e);
+                process::exit(1);
+            }
+            return
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return
+        }
+        Ok(Command::Daemon { socket, args, options }) => {
+            daemon(&socket, *args, &options);
+            return
+        }
+        Ok(Command::Request { socket, options }) => {
+            request(&socket, &options);
+            return
+        }
+        Ok(Command::Bake { texture, args }) => {
+            bake(&texture, &args);
+            return
+        }
+        Ok(Command::Gallery { dir, args }) => {
+            gallery(dir.as_deref(), *args);
+            eprintln!("Done.");
+            return
+        }
+        Ok(Command::Pick { pixel, args }) => {
+            pick(pixel, &args);
+            return
+        }
+        Ok(Command::SelfTest) => process::exit(if selftest::run() { 0 } else { 1 }),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("run with --help for usage");
+            process::exit(1);
+        }
+    };
+
+    if args.sweep.is_empty() {
+        run(args);
+    } else {
+        sweep(args);
+    }
+    eprintln!("Done.");
 }
 
-fn ray_color<T: Hittable>(r: &Ray, world: &T, depth: usize) -> Color {
-    // We have exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0)
+// Renders the scene file once for each value of the sweep, with the same
+// seed, to the output with the value's index appended, e.g. out_sweep2.png.
+// The images are then put together in a grid written to the output itself,
+// or one grid for each camera and frame, in the order they are rendered.
+fn sweep(mut args: Args) {
+    let output = args.output.clone().unwrap_or_default();
+    args.seed = Some(args.seed.unwrap_or_else(rand::random));
+    let count = args.sweep.len();
+    let start = Instant::now();
+    // The cells of every grid, and their samples per pixel.
+    let mut grids: Vec<(Vec<Image>, Float)> = Vec::new();
+    for (i, change) in args.sweep.iter().enumerate() {
+        eprintln!("Rendering sweep value {}/{}", i + 1, count);
+        let mut single = args.clone();
+        single.overrides.push(change.clone());
+        single.time_budget = share(args.time_budget, start, count - i);
+        single.output = Some(with_suffix(&output, &format!("sweep{}", i)));
+        for (j, (img, samples)) in run(single).into_iter().enumerate() {
+            if j == grids.len() {
+                grids.push((Vec::new(), samples));
+            }
+            grids[j].0.push(img);
+        }
     }
 
-    if let Some(rec) = world.hit(r, 0.001, INF) {
-        let mut scattered = Ray::default();
-        let mut attenuation = Color::default();
-        if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-            return attenuation * ray_color(&scattered, world, depth-1)
+    let columns = (count as Float).sqrt().ceil() as usize;
+    for (j, (cells, samples)) in grids.iter().enumerate() {
+        let path = output_path(&output, j, grids.len());
+        if let Err(e) = write_image(&Image::grid(cells, columns), *samples, &args, Some(&path)) {
+            eprintln!("error: failed to write sweep grid: {}", e);
+            process::exit(1);
         }
+    }
+}
+
+// An even share of what is left since `start` of a time budget given to
+// several renders, for the next of the `renders` left.
+fn share(budget: Option<Duration>, start: Instant, renders: usize) -> Option<Duration> {
+    budget.map(|budget| budget.saturating_sub(start.elapsed()) / renders.max(1) as u32)
+}
 
-        return Color::new(0.0, 0.0, 0.0)
+// Renders the first camera of every built-in scene, or of every scene
+// file in the directory in the order of their names, to the output with
+// the scene's name appended, e.g. out_cornell.png, and tiles them into a
+// grid written to the output itself. All of them are the same size, set
+// by the options or else a small 16:9 one.
+fn gallery(dir: Option<&str>, mut args: Args) {
+    let width = args.width.unwrap_or(GALLERY_WIDTH);
+    args.width = Some(width);
+    args.height = Some(args.height.unwrap_or(((width as Float / ASPECT_RATIO) as usize).max(2)));
+    args.samples = Some(args.samples.unwrap_or(GALLERY_SAMPLES));
+    args.seed = Some(args.seed.unwrap_or_else(rand::random));
+    let scenes: Vec<(String, Args)> = match dir {
+        Some(dir) => scene_files(dir).into_iter()
+            .map(|path| {
+                let name = Path::new(&path).file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+                (name, Args { scene_file: Some(path), ..args.clone() })
+            })
+            .collect(),
+        None => scene::NAMES.iter()
+            .map(|name| (name.to_string(), Args { scene_name: Some(name.to_string()), ..args.clone() }))
+            .collect(),
+    };
+
+    let output = args.output.clone().unwrap_or_default();
+    let start = Instant::now();
+    let mut cells = Vec::new();
+    let mut samples = 0.0;
+    for (i, (name, scene_args)) in scenes.iter().enumerate() {
+        eprintln!("Rendering scene {}/{}, {}", i + 1, scenes.len(), name);
+        let time_budget = share(args.time_budget, start, scenes.len() - i);
+        let single = Args { output: Some(with_suffix(&output, name)), time_budget, ..scene_args.clone() };
+        if let Some((img, mean)) = run(single).into_iter().next() {
+            cells.push(img);
+            samples = mean;
+        }
     }
 
-    let unit_dir = unit_vector(r.dir);
-    let t = 0.5 * (unit_dir.y + 1.0);
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    let columns = (cells.len() as Float).sqrt().ceil() as usize;
+    if let Err(e) = write_image(&Image::grid(&cells, columns.max(1)), samples, &args, Some(&output)) {
+        eprintln!("error: failed to write gallery: {}", e);
+        process::exit(1);
+    }
 }
 
-fn write_color(pixel_color: Color, samples_per_pixel: usize) {
-    let mut r = pixel_color.x;
-    let mut g = pixel_color.y;
-    let mut b = pixel_color.z;
+// Prints which object of the scene file the pixel of its render shows,
+// from the ray through the pixel's center, and which material it has.
+fn pick((x, y): (usize, usize), args: &Args) {
+    let path = args.scene_file.as_deref().unwrap_or_default();
+    let file = load_scene_file(path, &args.overrides, 0.0);
+    report(file.warnings(), args.strict);
+    let (width, height) = image_size(args, &file.settings);
+    if x >= width || y >= height {
+        eprintln!("error: pixel ({}, {}) is outside the {}x{} image", x, y, width, height);
+        process::exit(1);
+    }
 
-    // Divide the color total by the number of samples and gamma-correct for gamma=2.0.
-    let scale = 1.0 / samples_per_pixel as f32;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
+    let aspect_ratio = width as Float / height as Float;
+    let names = file.names().clone();
+    let scene = file.into_scene(aspect_ratio);
+    let cam = match cameras(args, &scene.cameras, aspect_ratio).into_iter().next() {
+        Some(cam) => cam,
+        None => {
+            eprintln!("error: the scene has no camera");
+            process::exit(1);
+        }
+    };
+    rng::seed(args.seed.unwrap_or_else(rand::random));
+    let u = (x as Float + 0.5) / (width - 1) as Float;
+    let v = ((height - 1 - y) as Float + 0.5) / (height - 1) as Float;
+    let r = cam.get_ray(u, v);
+    match scene.pick(&r) {
+        Some((i, rec)) => {
+            let material = match names.material(rec.mat) {
+                Some(name) => format!("material \"{}\"", name),
+                None => "an inline material".to_string(),
+            };
+            println!(
+                "{}, {}, {:.3} away at ({:.3}, {:.3}, {:.3})",
+                names.object(i).unwrap_or("an object"), material, rec.t * r.dir.length(), rec.p.x, rec.p.y, rec.p.z,
+            );
+        }
+        None => println!("nothing at ({}, {}), only the background", x, y),
+    }
+}
 
-    println!(
-        "{} {} {}",
-        (256.0 * clamp(r, 0.0, 0.999)) as usize,
-        (256.0 * clamp(g, 0.0, 0.999)) as usize,
-        (256.0 * clamp(b, 0.0, 0.999)) as usize,
-    );
+// The paths of the scene files in the directory, in order, exiting when
+// it can't be read or holds none.
+fn scene_files(dir: &str) -> Vec<String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("error: failed to read '{}': {}", dir, e);
+            process::exit(1);
+        }
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        eprintln!("error: no scene files in '{}'", dir);
+        process::exit(1);
+    }
+    paths
 }
 
-fn random_scene() -> HittableList {
-    let mut world = HittableList::default();
+// Loads the scene of the options and renders it for every request to the
+// socket, with the request's options added to the daemon's. The hierarchy
+// is kept, and only refitted when a request's shutter interval differs.
+#[cfg(unix)]
+fn daemon(socket: &str, mut args: Args, options: &[String]) {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    rng::seed(seed);
+    let Loaded {
+        world, cameras: scene_cameras, background, lights, sun, punctual_lights,
+        file_settings, width, height, mesh_memory, ..
+    } = load(&mut args);
+    let start = Instant::now();
+    let mut times = interval(&cameras(&args, &scene_cameras, width as Float / height as Float));
+    let scene_memory = bvh::memory_estimate(&world.objects) + mesh_memory;
+    let mut world = Bvh::new(world.objects, times.0, times.1);
+    let lights = Arc::new(LightTree::new(lights));
+    eprintln!("Built the hierarchy in {:.1?}", start.elapsed());
 
-    // let ground_material = Material::Lambertian(Color::new(0.5, 0.5, 0.5));
-    let ground_material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
-    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material.clone())));
+    let listener = match raytracing::daemon::bind(socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("error: failed to listen on '{}': {}", socket, e);
+            process::exit(1);
+        }
+    };
+    eprintln!("Listening on '{}'", socket);
+    let handle = |request: Vec<String>| -> Result<String, String> {
+        let start = Instant::now();
+        let args = cli::parse_request(options, &request)?;
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let schedule = schedule(&args, &file_settings);
+        let mut settings = settings(&args, &file_settings, seed, background.clone(), lights.clone(), sun, punctual_lights.clone());
+        // The scene's own cameras are made for the daemon's image size.
+        let (w, h) = (settings.width, settings.height);
+        if args.cameras.is_empty() && w * height != h * width {
+            return Err(format!("the scene's cameras are made for {}x{} images, give --camera views for other shapes", width, height))
+        }
+        let cameras = cameras(&args, &scene_cameras, w as Float / h as Float);
 
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = random_f32();
-            let center = Point3::new(a as f32 + 0.9 * random_f32(), 0.2, b as f32 + 0.9 * random_f32());
+        let mut estimate = memory::Estimate::new(&settings, args.deep.is_some(), args.aovs.len(), &[]);
+        estimate.scene = scene_memory;
+        check_memory(&estimate, &args)?;
+        if cameras.is_empty() {
+            return Err("the scene has no cameras, add one with --camera".to_string())
+        }
+        write_cameras(&cameras, &args).map_err(|e| format!("failed to export the cameras: {}", e))?;
 
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    let albedo = Color::random() * Color::random();
-                    let sphere_mat = Lambertian::new(albedo);
-                    world.add(Box::new(Sphere::new(center, 0.2, Box::new(sphere_mat))));
-                } else if choose_mat > 0.95 {
-                    let albedo = Color::random_bounded(0.5, 1.0);
-                    let fuzz = rand::thread_rng().gen_range(0.0, 0.5);
-                    let sphere_mat = Metal::new(albedo, fuzz);
-                    world.add(Box::new(Sphere::new(center, 0.2, Box::new(sphere_mat))));
-                } else {
-                    let sphere_mat = Dielectric::new(1.5);
-                    world.add(Box::new(Sphere::new(center, 0.2, Box::new(sphere_mat))));
-                }
+        if interval(&cameras) != times {
+            times = interval(&cameras);
+            let rebuilt = world.update(times.0, times.1);
+            let action = if rebuilt { "Rebuilt" } else { "Refitted" };
+            eprintln!("{} the hierarchy in {:.1?}", action, start.elapsed());
+        }
+        settings.ray_bias = args.ray_bias.unwrap_or_else(|| render::auto_ray_bias(&world, times.0, times.1));
+        let count = cameras.len();
+        let mut written = Vec::new();
+        for (idx, cam) in cameras.iter().enumerate() {
+            eprintln!("Rendering camera {}/{}", idx + 1, count);
+            let path = |base: &str| output_path(base, idx, count);
+            let schedule = schedule.share(start.elapsed(), count - idx);
+            render_camera(&world, cam, &mut settings, &schedule, &args, times, path)?;
+            written.push(path(args.output.as_deref().unwrap_or_default()));
+        }
+        Ok(format!("wrote {} in {:.1?}", written.join(", "), start.elapsed()))
+    };
+    if let Err(e) = raytracing::daemon::serve(listener, handle) {
+        eprintln!("error: failed to accept requests on '{}': {}", socket, e);
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn request(socket: &str, options: &[String]) {
+    match raytracing::daemon::request(socket, options) {
+        Ok(Ok(msg)) => eprintln!("The daemon {}.", msg),
+        Ok(Err(e)) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: failed to reach the daemon at '{}': {}", socket, e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn daemon(_socket: &str, _args: Args, _options: &[String]) {
+    eprintln!("error: daemons listen on Unix sockets, which this platform lacks");
+    process::exit(1);
+}
+
+#[cfg(not(unix))]
+fn request(_socket: &str, _options: &[String]) {
+    eprintln!("error: daemons listen on Unix sockets, which this platform lacks");
+    process::exit(1);
+}
+
+fn load_scene_file(path: &str, overrides: &[scenefile::Override], time: Float) -> scenefile::SceneFile {
+    match scenefile::SceneFile::load(path, overrides, time) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("error: failed to load scene '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+// The scene of the options, with their meshes added to its objects and
+// its cameras made for the image size of the options.
+struct Loaded {
+    world: HittableList,
+    cameras: Vec<Camera>,
+    background: Background,
+    // The lights to sample, with the sun.
+    lights: HittableList,
+    sun: Option<Sun>,
+    punctual_lights: Vec<Light>,
+    file_settings: FileSettings,
+    width: usize,
+    height: usize,
+    meshes: Vec<Arc<dyn Hittable>>,
+    mesh_memory: usize,
+    // Whether the scene file changes with the scene time.
+    animated: bool,
+}
+
+// Loads the scene of the options, exiting on errors. Random scenes and
+// textures are built from the random numbers, which are seeded first.
+fn load(args: &mut Args) -> Loaded {
+    // Settings from a scene file apply unless given on the command line.
+    let scene_file = args.scene_file.as_deref().map(|path| load_scene_file(path, &args.overrides, 0.0));
+    let animated = scene_file.as_ref().is_some_and(|f| f.animated());
+    let mut warnings = scene_file.as_ref().map_or_else(Vec::new, |f| f.warnings().to_vec());
+    let file_settings = scene_file.as_ref().map(|f| f.settings).unwrap_or_default();
+    if args.exposure.is_none() {
+        args.auto_exposure = args.auto_exposure.or(file_settings.auto_exposure);
+    }
+
+    let (width, height) = image_size(args, &file_settings);
+    let aspect_ratio = width as Float / height as Float;
+    let scene_name = args.scene_name.as_deref().unwrap_or("random");
+    let scene = match (scene_file, &args.material) {
+        (Some(file), _) => Some(file.into_scene(aspect_ratio)),
+        (None, Some(def)) => match scenefile::load_material(def) {
+            Ok(mat) => Some(scene::material_preview(mat, aspect_ratio)),
+            Err(e) => {
+                eprintln!("error: failed to load material: {}", e);
+                process::exit(1);
             }
+        },
+        (None, None) => scene::by_name(scene_name, aspect_ratio),
+    };
+    let Scene { mut world, cameras, background, mut lights, sun, punctual_lights } = match scene {
+        Some(scene) => scene,
+        None => {
+            eprintln!("error: unknown scene '{}'", scene_name);
+            process::exit(1);
         }
+    };
+    // The sun can't be hit, and is only found by sampling it.
+    if let Some(sun) = sun {
+        lights.add(Box::new(sun));
     }
 
-    let mat1 = Dielectric::new(1.5);
-    world.add(Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Box::new(mat1))));
+    let mesh_material: Arc<dyn material::Material> = match &args.mesh_texture {
+        Some(path) => match texture::ImageTexture::load(path) {
+            Ok(tex) => Arc::new(material::Lambertian::textured(Arc::new(tex))),
+            Err(e) => {
+                eprintln!("error: failed to load texture '{}': {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => Arc::new(material::Lambertian::new(vec::Color::new(0.5, 0.5, 0.5))),
+    };
+    // Every mesh gets a hierarchy of its own, shared by all of its
+    // instances, while the scene hierarchy only holds the instances.
+    // Animating the scene then never touches the meshes' triangles.
+    let mut mesh_memory = 0;
+    let mut meshes: Vec<Arc<dyn Hittable>> = Vec::new();
+    for path in args.meshes.iter() {
+        let mat = &mesh_material;
+        let culling = Culling { camera: args.cull_backfaces, shadow: args.cull_shadow_backfaces };
+        match mesh::load_obj_file(path, mat, culling) {
+            Ok((triangles, problems)) => {
+                eprintln!("Loaded {} triangles from '{}'", triangles.len(), path);
+                warnings.extend(problems.into_iter().map(|problem| format!("'{}': {}", path, problem)));
+                mesh_memory += bvh::memory_estimate(&triangles);
+                meshes.push(Arc::new(Bvh::new(triangles, 0.0, 0.0)));
+            }
+            Err(e) => {
+                eprintln!("error: failed to load '{}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    world.objects.extend(mesh_instances(&meshes, args));
+    report(&warnings, args.strict);
 
-    let mat2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
-    world.add(Box::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Box::new(mat2))));
+    Loaded { world, cameras, background, lights, sun, punctual_lights, file_settings, width, height, meshes, mesh_memory, animated }
+}
 
-    let mat3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.add(Box::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Box::new(mat3))));
+// Prints the problems found while loading the scene, exiting when the
+// options say to be strict about them.
+fn report(warnings: &[String], strict: bool) {
+    for warning in warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    if strict && !warnings.is_empty() {
+        eprintln!("error: the scene has problems, and --strict is given");
+        process::exit(1);
+    }
+}
 
-    world
+fn mesh_instances(meshes: &[Arc<dyn Hittable>], args: &Args) -> Vec<Box<dyn Hittable>> {
+    let placements = match args.mesh_placements.len() {
+        0 => vec![motion::RigidTransform::default()],
+        _ => args.mesh_placements.clone(),
+    };
+    meshes.iter()
+        .flat_map(|mesh| placements.iter().map(move |&placement| {
+            Box::new(instance::Instance::new(mesh.clone(), placement)) as Box<dyn Hittable>
+        }))
+        .collect()
 }
 
-fn main() {
-    println!("P3\n{} {}\n255", IMAGE_WIDTH, IMAGE_HEIGHT);
-
-    let world = random_scene();
-
-    let look_from = Point3::new(13.0, 2.0, 3.0);
-    let look_at = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let cam = Camera::new(
-        20.0, 
-        IMAGE_WIDTH as f32 / IMAGE_HEIGHT as f32, 
-        aperture,
-        dist_to_focus,
-        look_from, 
-        look_at, 
-        vup,
-    );
-
-    for j in (0..IMAGE_HEIGHT).rev() {
-        eprint!("\rScanelines remaining: {}", j);
-        let _ = stderr().flush();
-        for i in 0..IMAGE_WIDTH {
-            let mut color = Color::new(0.0, 0.0, 0.0);
-            for _ in 0..SAMPLES_PER_PIXEL {
-                let u = (i as f32 + random_f32()) / (IMAGE_WIDTH - 1) as f32;
-                let v = (j as f32 + random_f32()) / (IMAGE_HEIGHT - 1) as f32;
-                let r = cam.get_ray(u, v);
-                color += ray_color(&r, &world, MAX_DEPTH);
+fn image_size(args: &Args, file_settings: &FileSettings) -> (usize, usize) {
+    // Material previews are square by default.
+    let default_aspect = if args.material.is_some() { 1.0 } else { ASPECT_RATIO };
+    match (args.width.or(file_settings.width), args.height.or(file_settings.height)) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as Float / default_aspect) as usize).max(2)),
+        (None, Some(h)) => (((h as Float * default_aspect) as usize).max(2), h),
+        (None, None) => (IMAGE_WIDTH, (IMAGE_WIDTH as Float / default_aspect) as usize),
+    }
+}
+
+fn schedule(args: &Args, file_settings: &FileSettings) -> Schedule {
+    match (args.time_budget, &args.spp_range) {
+        (Some(budget), _) => Schedule::Budget(budget),
+        (None, Some(range)) => Schedule::Samples(range.clone()),
+        (None, None) => Schedule::Samples(0..args.samples.or(file_settings.samples).unwrap_or(SAMPLES_PER_PIXEL)),
+    }
+}
+
+// The settings of the options for a scene with the given lights.
+fn settings(
+    args: &Args,
+    file_settings: &FileSettings,
+    seed: u64,
+    background: Background,
+    lights: Arc<LightTree>,
+    sun: Option<Sun>,
+    punctual_lights: Vec<Light>,
+) -> Settings {
+    let (width, height) = image_size(args, file_settings);
+    Settings {
+        width,
+        height,
+        max_depth: args.max_depth.or(file_settings.max_depth).unwrap_or(MAX_DEPTH),
+        min_throughput: args.min_throughput.or(file_settings.min_throughput).unwrap_or(0.0),
+        background,
+        // Fixed once the hierarchy is built and the scene's bounds are known.
+        ray_bias: render::DEFAULT_RAY_BIAS,
+        split: args.split.unwrap_or(1),
+        irradiance: None,
+        threads: args.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        pin_threads: args.pin_threads,
+        checkerboard: args.checkerboard,
+        exposure: args.exposure.map_or(1.0, |e| e.scale()),
+        half_float: args.half_float,
+        seed,
+        frame: 0,
+        integrator: args.integrator,
+        clamp: args.clamp,
+        filter: args.filter,
+        lights,
+        ambient: args.ambient,
+        sun,
+        punctual_lights,
+        focus: args.focus,
+    }
+}
+
+// The scene's cameras, or the views of the options instead, changed as
+// the options ask.
+fn cameras(args: &Args, scene_cameras: &[Camera], aspect_ratio: Float) -> Vec<Camera> {
+    let mut cameras = match args.cameras.len() {
+        0 => scene_cameras.to_vec(),
+        _ => args.cameras.iter().map(|view| view.camera(aspect_ratio)).collect(),
+    };
+    if let Some((open, close)) = args.shutter {
+        cameras = cameras.into_iter().map(|cam| cam.with_shutter(open, close)).collect();
+    }
+    if let Some(projection) = args.projection {
+        cameras = cameras.into_iter().map(|cam| cam.with_projection(projection)).collect();
+    }
+    if let Some(clip) = args.clip {
+        cameras = cameras.into_iter().map(|cam| cam.with_clip(clip)).collect();
+    }
+    cameras
+}
+
+// The times every camera's shutter is open within, together.
+fn interval(cameras: &[Camera]) -> (Float, Float) {
+    cameras.iter()
+        .map(|cam| cam.shutter())
+        .fold((INF, -INF), |(t0, t1), (a, b)| (t0.min(a), t1.max(b)))
+}
+
+// Refuses renders that would need more memory than the limit allows.
+fn check_memory(estimate: &memory::Estimate, args: &Args) -> Result<(), String> {
+    let limit = args.memory_limit.unwrap_or(MEMORY_LIMIT);
+    if estimate.total() <= limit {
+        return Ok(())
+    }
+    Err(format!(
+        "the render needs an estimated {} (image {}, deep image {}, scene {}), over the limit of {}",
+        memory::format_bytes(estimate.total()),
+        memory::format_bytes(estimate.framebuffer),
+        memory::format_bytes(estimate.deep),
+        memory::format_bytes(estimate.scene),
+        memory::format_bytes(limit),
+    ))
+}
+
+// Renders every camera and frame of the scene, writing each image as it
+// is done, and returns the images with their mean samples per pixel.
+fn run(mut args: Args) -> Vec<(Image, Float)> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    rng::seed(seed);
+    let Loaded {
+        world, cameras: scene_cameras, background, lights, sun, punctual_lights,
+        file_settings, width, height, meshes, mesh_memory, animated,
+    } = load(&mut args);
+    // A time budget covers every camera and frame, loading included.
+    let start = Instant::now();
+    let schedule = schedule(&args, &file_settings);
+    let mut settings = settings(&args, &file_settings, seed, background, Arc::new(LightTree::new(lights)), sun, punctual_lights);
+    let cameras = cameras(&args, &scene_cameras, width as Float / height as Float);
+
+    let mut estimate = memory::Estimate::new(&settings, args.deep.is_some(), args.aovs.len(), &world.objects);
+    estimate.scene += mesh_memory;
+    if let Err(e) = check_memory(&estimate, &args) {
+        eprintln!("error: {}", e);
+        eprintln!("check the image size, or raise the limit with --memory-limit");
+        process::exit(1);
+    }
+
+    let frames = args.frames.clone().unwrap_or(0..1);
+    let total = frames.end;
+    if cameras.is_empty() {
+        eprintln!("error: the scene has no cameras, add one with --camera");
+        process::exit(1);
+    }
+    if (cameras.len() > 1 || frames.len() > 1) && args.output.is_none() {
+        eprintln!("error: rendering several cameras or frames requires --output");
+        process::exit(1);
+    }
+    // Frames go into the output's directory, which is made if missing.
+    if let (Some(_), Some(dir)) = (&args.frames, args.output.as_deref().and_then(|o| Path::new(o).parent())) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("error: failed to create '{}': {}", dir.display(), e);
+            process::exit(1);
+        }
+    }
+    if let Err(e) = write_cameras(&cameras, &args) {
+        eprintln!("error: failed to export the cameras: {}", e);
+        process::exit(1);
+    }
+
+    // Every frame covers an equal slice of each camera's shutter
+    // interval. The hierarchy bounds the objects over the slices
+    // of all cameras, and is shared by them.
+    let frame_cameras = |frame: usize, cameras: &[Camera]| -> Vec<Camera> {
+        cameras.iter()
+            .map(|cam| {
+                let (open, close) = cam.shutter();
+                let step = (close - open) / total as Float;
+                let start = open + frame as Float * step;
+                cam.with_shutter(start, start + step)
+            })
+            .collect()
+    };
+
+    let (time0, time1) = interval(&frame_cameras(0, &cameras));
+    let mut world = Bvh::new(world.objects, time0, time1);
+
+    // The scene time of a frame, in seconds with a frame rate.
+    let scene_time = |frame: usize| frame as Float / args.fps.unwrap_or(total as Float);
+
+    let mut cameras = cameras;
+    let mut rendered = Vec::new();
+    for frame in frames.clone() {
+        if frame > 0 {
+            let start = Instant::now();
+            match args.scene_file.as_deref().filter(|_| animated) {
+                // Scene files animated through the scene time, their
+                // cameras too, are built again for every frame.
+                Some(path) => {
+                    let time = scene_time(frame);
+                    rng::seed(seed);
+                    let scene = load_scene_file(path, &args.overrides, time).into_scene(width as Float / height as Float);
+                    cameras = crate::cameras(&args, &scene.cameras, width as Float / height as Float);
+                    let (time0, time1) = interval(&frame_cameras(frame, &cameras));
+                    let (mut objects, mut lights) = (scene.world.objects, scene.lights);
+                    objects.extend(mesh_instances(&meshes, &args));
+                    if let Some(sun) = scene.sun {
+                        lights.add(Box::new(sun));
+                    }
+                    settings.background = scene.background;
+                    settings.lights = Arc::new(LightTree::new(lights));
+                    settings.sun = scene.sun;
+                    settings.punctual_lights = scene.punctual_lights;
+                    world = Bvh::new(objects, time0, time1);
+                    eprintln!("Rebuilt the scene at t = {} in {:.1?}", time, start.elapsed());
+                }
+                None => {
+                    let (time0, time1) = interval(&frame_cameras(frame, &cameras));
+                    let rebuilt = world.update(time0, time1);
+                    let action = if rebuilt { "Rebuilt" } else { "Refitted" };
+                    eprintln!("{} the hierarchy in {:.1?}", action, start.elapsed());
+                }
             }
+        }
+        let frame_cameras = frame_cameras(frame, &cameras);
+        let (time0, time1) = interval(&frame_cameras);
+        settings.frame = frame;
+        settings.ray_bias = args.ray_bias.unwrap_or_else(|| render::auto_ray_bias(&world, time0, time1));
 
-            write_color(color, SAMPLES_PER_PIXEL);
+        let count = frame_cameras.len();
+        for (idx, cam) in frame_cameras.iter().enumerate() {
+            eprintln!("Rendering frame {}/{}, camera {}/{}", frame + 1, total, idx + 1, count);
+            let path = |base: &str| frame_path(&output_path(base, idx, count), frame, &frames);
+            let schedule = schedule.share(start.elapsed(), (frames.end - frame) * count - idx);
+            match render_camera(&world, cam, &mut settings, &schedule, &args, (time0, time1), path) {
+                Ok(image) => rendered.push(image),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+            }
         }
     }
+    rendered
+}
 
-    eprintln!("\nDone.");
+// Renders from one camera and writes the image to the output, with
+// its deep image, AOVs and statistics if asked for, and returns it
+// with its mean samples per pixel. The output paths are made by `path`
+// from the base paths of the options.
+fn render_camera<F: Fn(&str) -> String>(
+    world: &Bvh,
+    cam: &Camera,
+    settings: &mut Settings,
+    schedule: &Schedule,
+    args: &Args,
+    (time0, time1): (Float, Float),
+    path: F,
+) -> Result<(Image, Float), String> {
+    let (width, height) = (settings.width, settings.height);
+    // Previews give early feedback on composition. Each is
+    // scaled up to full size and written where the final
+    // image goes, so a viewer watching the file shows them.
+    if args.preview {
+        for &factor in PREVIEW_FACTORS.iter() {
+            let start = Instant::now();
+            let small = settings.downscaled(factor);
+            let acc = render(world, cam, &small, &Schedule::Samples(0..PREVIEW_SAMPLES), None, None);
+            let img = acc.resolve().upscaled(width, height);
+            let output = args.output.as_deref().map(&path);
+            write_image(&img, acc.mean_samples(), args, output.as_deref())
+                .map_err(|e| format!("failed to write preview: {}", e))?;
+            eprintln!("Wrote a 1/{} resolution preview in {:.1?}", factor, start.elapsed());
+        }
+    }
+
+    settings.irradiance = args.irradiance_cache.map(|tolerance| {
+        let scale = render::scene_scale(world, time0, time1).unwrap_or(1.0);
+        irradiance::IrradianceCache::new(tolerance, scale, cam.pixel_angle(height))
+    });
+    let mut deep = args.deep.as_ref().map(|_| DeepImage::new(width, height));
+    let mut aovs = match args.aovs.len() {
+        0 => None,
+        _ => Some(AovImages::new(&args.aovs, width, height, settings.half_float)),
+    };
+    let acc = render(world, cam, settings, schedule, deep.as_mut(), aovs.as_mut());
+    if let Some(cache) = &settings.irradiance {
+        eprintln!("Irradiance cache holds {} records", cache.records());
+    }
+    let mut img = acc.resolve();
+    let mut aov_images = aovs.map_or(Vec::new(), |images| images.resolve());
+    if settings.checkerboard {
+        let guides = checkerboard::guides(world, cam, settings);
+        checkerboard::reconstruct(&mut img, &guides, |x, y| settings.traced(x, y));
+        for (_, img) in aov_images.iter_mut() {
+            checkerboard::reconstruct(img, &guides, |x, y| settings.traced(x, y));
+        }
+    }
+
+    let output = args.output.as_deref().map(&path);
+    write_image(&img, acc.mean_samples(), args, output.as_deref())
+        .map_err(|e| format!("failed to write image: {}", e))?;
+    if let (Some(deep), Some(base)) = (&deep, &args.deep) {
+        File::create(path(base)).and_then(|f| deep.write_exr(&mut BufWriter::new(f)))
+            .map_err(|e| format!("failed to write deep image: {}", e))?;
+    }
+    for (aov, img) in aov_images.iter() {
+        let output = args.output.as_deref().map(|base| with_suffix(&path(base), aov.name()));
+        write_image(img, acc.mean_samples(), args, output.as_deref())
+            .map_err(|e| format!("failed to write {} image: {}", aov.name(), e))?;
+    }
+    write_stats(&img, args, &path).map_err(|e| format!("failed to write statistics: {}", e))?;
+    Ok((img, acc.mean_samples()))
 }
@@ -0,0 +1,282 @@
+use std::ops;
+
+use crate::{degrees_to_radians, Float};
+use crate::vec::{Vec3, Point3, cross, dot, unit_vector};
+
+// A 4x4 matrix as a list of rows, transforming points and directions
+// as columns, so that `a * b` applies `b` first.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [[Float; 4]; 4]);
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4([[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]);
+
+    // The matrix taking the axes to x, y and z and the origin to origin.
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3, origin: Point3) -> Self {
+        Mat4([[x.x, y.x, z.x, origin.x], [x.y, y.y, z.y, origin.y], [x.z, y.z, z.z, origin.z], [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::from_columns(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), offset)
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::from_columns(Vec3::new(factors.x, 0.0, 0.0), Vec3::new(0.0, factors.y, 0.0), Vec3::new(0.0, 0.0, factors.z), Point3::default())
+    }
+
+    pub fn rotation(q: Quat) -> Self {
+        let axis = |x, y, z| q.rotate(Vec3::new(x, y, z));
+        Self::from_columns(axis(1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0), axis(0.0, 0.0, 1.0), Point3::default())
+    }
+
+    // The camera-to-world matrix of a camera at `from` looking at `at`,
+    // down its -z axis with its y axis towards `up`, as in Blender and
+    // OpenGL. Its inverse is the view matrix.
+    pub fn look_at(from: Point3, at: Point3, up: Vec3) -> Self {
+        let w = unit_vector(from - at);
+        let u = unit_vector(cross(&up, &w));
+        let v = cross(&w, &u);
+        Self::from_columns(u, v, w, from)
+    }
+
+    // OpenGL's projection of a camera looking down -z, with a vertical
+    // field of view in degrees, into clip space, where the near and far
+    // planes end up at a depth of -1 and 1.
+    pub fn perspective(vfov: Float, aspect_ratio: Float, near: Float, far: Float) -> Self {
+        let f = 1.0 / (degrees_to_radians(vfov) / 2.0).tan();
+        let depth = near - far;
+        Mat4([
+            [f / aspect_ratio, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, (far + near) / depth, 2.0 * far * near / depth],
+            [0.0, 0.0, -1.0, 0.0],
+        ])
+    }
+
+    // The first three entries of a column, e.g. the position of the
+    // origin for the last.
+    pub fn column(&self, j: usize) -> Vec3 {
+        Vec3::new(self.0[0][j], self.0[1][j], self.0[2][j])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut t = Mat4::default();
+        for i in 0..4 {
+            for j in 0..4 {
+                t.0[i][j] = self.0[j][i];
+            }
+        }
+        t
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None when the
+    // matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let (mut a, mut inv) = (self.0, Mat4::IDENTITY.0);
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for i in (0..4).filter(|&i| i != col) {
+                let factor = a[i][col];
+                for j in 0..4 {
+                    a[i][j] -= factor * a[col][j];
+                    inv[i][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Mat4(inv))
+    }
+
+    // The inverse of a matrix that only rotates and translates, such as a
+    // view matrix, which is the inverse of a camera-to-world one.
+    pub fn rigid_inverse(&self) -> Self {
+        let mut inverse = Mat4::IDENTITY;
+        for i in 0..3 {
+            for j in 0..3 {
+                inverse.0[i][j] = self.0[j][i];
+            }
+            inverse.0[i][3] = -(0..3).map(|j| self.0[j][i] * self.0[j][3]).sum::<Float>();
+        }
+        inverse
+    }
+
+    // Divides by the fourth coordinate, which only projections change.
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let row = |i: usize| self.0[i][0] * p.x + self.0[i][1] * p.y + self.0[i][2] * p.z + self.0[i][3];
+        let w = row(3);
+        Point3::new(row(0), row(1), row(2)) / if w != 0.0 { w } else { 1.0 }
+    }
+
+    // Directions are not translated.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let row = |i: usize| self.0[i][0] * v.x + self.0[i][1] * v.y + self.0[i][2] * v.z;
+        Vec3::new(row(0), row(1), row(2))
+    }
+}
+
+impl ops::Index<usize> for Mat4 {
+    type Output = [Float; 4];
+
+    fn index(&self, i: usize) -> &[Float; 4] {
+        &self.0[i]
+    }
+}
+
+impl ops::IndexMut<usize> for Mat4 {
+    fn index_mut(&mut self, i: usize) -> &mut [Float; 4] {
+        &mut self.0[i]
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, o: Mat4) -> Mat4 {
+        let mut m = Mat4::default();
+        for i in 0..4 {
+            for j in 0..4 {
+                m.0[i][j] = (0..4).map(|k| self.0[i][k] * o.0[k][j]).sum();
+            }
+        }
+        m
+    }
+}
+
+// A rotation as a unit quaternion, which unlike Euler angles or
+// matrices can be blended between two orientations while staying a
+// rotation, turning at an even rate about a single axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub w: Float,
+    pub v: Vec3,
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self { w: 1.0, v: Vec3::default() }
+    }
+}
+
+impl Quat {
+    // A turn by the angle in degrees about the axis, counterclockwise
+    // looking down the axis towards the origin.
+    pub fn axis_angle(axis: Vec3, degrees: Float) -> Self {
+        let (sin, cos) = (degrees_to_radians(degrees) / 2.0).sin_cos();
+        Self { w: cos, v: sin * unit_vector(axis) }
+    }
+
+    // The rotation of Euler angles in degrees, applied about the X, then
+    // Y, then Z axis, as in a `RigidTransform`.
+    pub fn from_euler(degrees: Vec3) -> Self {
+        let about = |x, y, z, degrees| Quat::axis_angle(Vec3::new(x, y, z), degrees);
+        about(0.0, 0.0, 1.0, degrees.z) * about(0.0, 1.0, 0.0, degrees.y) * about(1.0, 0.0, 0.0, degrees.x)
+    }
+
+    // The inverse rotation.
+    pub fn conjugate(self) -> Self {
+        Self { w: self.w, v: -self.v }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let t = 2.0 * cross(&self.v, &v);
+        v + self.w * t + cross(&self.v, &t)
+    }
+
+    // Angle of the rotation in radians, from 0 to pi.
+    pub fn angle(&self) -> Float {
+        2.0 * self.v.length().atan2(self.w.abs())
+    }
+
+    // Spherical interpolation, the shorter way around.
+    pub fn slerp(&self, other: &Quat, t: Float) -> Quat {
+        let mut cos = self.w * other.w + dot(&self.v, &other.v);
+        let mut other = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = Quat { w: -other.w, v: -other.v };
+        }
+        let (a, b) = if cos > 0.9995 {
+            // Nearly the same, where the angle is too small to divide by.
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        let (w, v) = (a * self.w + b * other.w, a * self.v + b * other.v);
+        let norm = (w * w + v.length_sqrd()).sqrt();
+        Quat { w: w / norm, v: v / norm }
+    }
+}
+
+// Rotates by the right-hand side first.
+impl ops::Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, o: Quat) -> Quat {
+        Quat { w: self.w * o.w - dot(&self.v, &o.v), v: self.w * o.v + o.w * self.v + cross(&self.v, &o.v) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn test_transforms() {
+        let q = Quat::axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        assert!(approx_eq(Vec3::new(0.0, 0.0, -1.0), q.rotate(Vec3::new(1.0, 0.0, 0.0))));
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0)) * Mat4::rotation(q) * Mat4::scaling(Vec3::new(2.0, 2.0, 2.0));
+        assert!(approx_eq(Point3::new(1.0, 2.0, 1.0), m.transform_point(Point3::new(1.0, 0.0, 0.0))));
+        assert!(approx_eq(Vec3::new(0.0, 0.0, -2.0), m.transform_vector(Vec3::new(1.0, 0.0, 0.0))));
+
+        // Inverses undo the matrix, and singular matrices have none.
+        let p = Point3::new(0.5, -2.0, 4.0);
+        assert!(approx_eq(p, m.inverse().unwrap().transform_point(m.transform_point(p))));
+        let rigid = Mat4::translation(Vec3::new(1.0, 2.0, 3.0)) * Mat4::rotation(Quat::from_euler(Vec3::new(30.0, 45.0, 60.0)));
+        assert!(approx_eq(p, rigid.rigid_inverse().transform_point(rigid.transform_point(p))));
+        assert_eq!(None, Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse());
+        assert_eq!(rigid.transpose().transpose(), rigid);
+    }
+
+    #[test]
+    fn test_look_at_and_perspective() {
+        let m = Mat4::look_at(Point3::new(0.0, 0.0, 5.0), Point3::default(), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(Mat4::translation(Vec3::new(0.0, 0.0, 5.0)), m);
+
+        // The near and far planes end up at depths of -1 and 1, and the
+        // top of the field of view at the top of clip space.
+        let proj = Mat4::perspective(90.0, 2.0, 1.0, 10.0);
+        assert!(approx_eq(Point3::new(0.0, 1.0, -1.0), proj.transform_point(Point3::new(0.0, 1.0, -1.0))));
+        assert!(approx_eq(Point3::new(0.5, 0.0, 1.0), proj.transform_point(Point3::new(10.0, 0.0, -10.0))));
+    }
+
+    #[test]
+    fn test_quaternions() {
+        // Euler angles turn about X, then Y, then Z.
+        let q = Quat::from_euler(Vec3::new(90.0, 90.0, 0.0));
+        assert!(approx_eq(Vec3::new(1.0, 0.0, 0.0), q.rotate(Vec3::new(0.0, 1.0, 0.0))));
+        assert!(approx_eq(Vec3::new(0.5, -2.0, 4.0), q.conjugate().rotate(q.rotate(Vec3::new(0.5, -2.0, 4.0)))));
+
+        // Halfway between two orientations the rotation has turned as far
+        // from the first as it has left to the second.
+        let (start, end) = (Quat::default(), Quat::from_euler(Vec3::new(30.0, 120.0, 45.0)));
+        let mid = start.slerp(&end, 0.5);
+        let angle = |a: &Quat, b: &Quat| (a.conjugate() * *b).angle();
+        assert!((angle(&start, &mid) - angle(&mid, &end)).abs() < 1e-4);
+        assert!((2.0 * angle(&start, &mid) - angle(&start, &end)).abs() < 1e-4);
+    }
+}
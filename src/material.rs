@@ -1,7 +1,13 @@
-use crate::random_f32;
+use std::sync::Arc;
+
+use crate::{random_float, PI, Float};
+use crate::texture::{Texture, SolidColor};
 use crate::ray::Ray;
 use crate::hittable::HitRecord;
+use crate::onb::Onb;
+use crate::pdf::{Pdf, CosinePdf};
 use crate::vec::{
+    Vec3,
     Color, 
     random_unit_vector, 
     reflect, 
@@ -9,108 +15,759 @@ use crate::vec::{
     unit_vector, 
     dot, 
     random_in_unit_sphere,
+    cross,
 };
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool;
-    fn clone(&self) -> Box<dyn Material>;
+    // Light given off by the surface itself, none for most materials.
+    fn emitted(&self) -> Color {
+        Color::default()
+    }
+
+    // Whether the scattered direction is (nearly) determined by the
+    // incoming one, as for mirrors and glass. Paths through such
+    // surfaces are worth splitting; see `Settings::split`.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    // The albedo of an ideal diffuse surface at the hit, for lighting
+    // it from the irradiance cache. None for other materials.
+    fn diffuse_albedo(&self, _rec: &HitRecord) -> Option<Color> {
+        None
+    }
+
+    // The fraction of the light arriving at the hit along `dir`, cosine
+    // included, that leaves it back along the incoming ray, and the
+    // density per unit solid angle with which `scatter` picks `dir`.
+    // Lights can be sampled for materials that have these, to weigh
+    // the two ways of finding them against each other. None for those
+    // scattering into single directions, such as mirrors and glass.
+    fn scattering(&self, _r_in: &Ray, _rec: &HitRecord, _dir: &Vec3) -> Option<(Color, Float)> {
+        None
+    }
+
+    // The chance that a ray stops at the hit instead of passing through
+    // as if nothing were there, 1 for all but masked materials.
+    fn opacity(&self, _rec: &HitRecord) -> Float {
+        1.0
+    }
+
+    // Whether `opacity` may be below 1, for objects to be wrapped in a
+    // `Cutout` that tests their hits against it.
+    fn is_masked(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        let scatter_dir = rec.normal + random_unit_vector();
-        *scattered = Ray::new(&rec.p, &scatter_dir);
-        *attenuation = self.albedo;
+    // Directions are drawn in the frame of the normal, so unlike the sum
+    // of the normal and a random unit vector they never come out near zero.
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+        let scatter_dir = CosinePdf::new(&rec.normal).generate();
+        *scattered = Ray::new(&rec.p, &scatter_dir, r_in.time);
+        *attenuation = self.albedo.value_at(rec);
         true
     }
 
-    fn clone(&self) -> Box<dyn Material> {
-        Box::new(Self::new(self.albedo))
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Option<Color> {
+        Some(self.albedo.value_at(rec))
+    }
+
+    // The scattered directions are distributed as the cosine.
+    fn scattering(&self, _r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> Option<(Color, Float)> {
+        let pdf = CosinePdf::new(&rec.normal).value(dir);
+        Some((pdf * self.albedo.value_at(rec), pdf))
     }
 }
 
 pub struct Metal {
-    albedo: Color,
-    roughness: f32,
+    albedo: Arc<dyn Texture>,
+    roughness: Float,
 }
 
 impl Metal {
-    pub fn new(albedo: Color, r: f32) -> Self {
-        Self { albedo, roughness: f32::min(r, 1.0) }
+    pub fn new(albedo: Color, r: Float) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)), r)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, r: Float) -> Self {
+        Self { albedo, roughness: Float::min(r, 1.0) }
     }
 }
 
 impl Material for Metal {
+    // The mirror direction is fuzzed by a random point within a sphere of
+    // the roughness's radius. Fuzzed directions ending up below the
+    // surface are mirrored back above it rather than absorbed, which
+    // would darken rough metals, the more so at grazing angles.
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
         let u = unit_vector(r_in.dir);
-        let reflected = reflect(&u, &rec.normal) + self.roughness * random_in_unit_sphere();
-        *scattered = Ray::new(&rec.p, &reflected);
-        *attenuation = self.albedo;
-        dot(&scattered.dir, &rec.normal) > 0.0
+        let mut reflected = reflect(&u, &rec.normal) + self.roughness * random_in_unit_sphere();
+        if dot(&reflected, &rec.normal) < 0.0 {
+            reflected = reflect(&reflected, &rec.normal);
+        }
+        *scattered = Ray::new(&rec.p, &reflected, r_in.time);
+        *attenuation = self.albedo.value_at(rec);
+        true
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// A conductor whose surface is made of tiny mirrors, or microfacets,
+// facing away from the normal as in the GGX distribution, which shadow
+// and mask each other as in Smith's model. Unlike the fuzz of `Metal`
+// this gives the stretched highlights of real metals. The albedo is the
+// color seen head on, which turns white at grazing angles.
+pub struct MicrofacetMetal {
+    albedo: Arc<dyn Texture>,
+    roughness: Float,
+    sampling: Sampling,
+}
+
+// How microfacets are drawn to scatter off. Both give the same image on
+// average, but drawing only those the incoming ray can see wastes none
+// on facets facing away from it, so surfaces seen at grazing angles are
+// far less noisy. Drawing from the whole distribution is kept to compare
+// against.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sampling {
+    // Heitz's sampling of the distribution of visible normals.
+    #[default]
+    Visible,
+    // The GGX distribution of all normals times their cosine.
+    Distribution,
+}
+
+impl MicrofacetMetal {
+    pub fn new(albedo: Color, roughness: Float) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)), roughness)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, roughness: Float) -> Self {
+        Self { albedo, roughness: roughness.clamp(0.0, 1.0), sampling: Sampling::default() }
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+}
+
+impl Material for MicrofacetMetal {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+        let alpha = self.roughness * self.roughness;
+        let v = -unit_vector(r_in.dir);
+        let h = match self.sampling.facet(&rec.normal, &v, alpha) {
+            Some(h) => h,
+            None => return false,
+        };
+        let dir = reflect(&-v, &h);
+        let (cos_v, cos_o, cos_h, cos_vh) = (dot(&v, &rec.normal), dot(&dir, &rec.normal), dot(&h, &rec.normal), dot(&v, &h));
+        if cos_o <= 0.0 || cos_vh <= 0.0 {
+            return false
+        }
+
+        let f0 = self.albedo.value_at(rec);
+        let fresnel = f0 + (1.0 - cos_vh).powi(5) * (Color::new(1.0, 1.0, 1.0) - f0);
+        *scattered = Ray::new(&rec.p, &dir, r_in.time);
+        *attenuation = self.sampling.weight(alpha, cos_v, cos_o, cos_h, cos_vh) * fresnel;
+        true
+    }
+
+    fn is_specular(&self) -> bool {
+        true
     }
 
-    fn clone(&self) -> Box<dyn Material> {
-        Box::new(Self::new(self.albedo, self.roughness))
+    // The microfacet reflecting the incoming ray into `dir` is halfway
+    // between the two, and is drawn with the density `Sampling` gives
+    // it, over the stretch of reflecting off it.
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> Option<(Color, Float)> {
+        let alpha = self.roughness * self.roughness;
+        if alpha == 0.0 {
+            return None
+        }
+        let v = -unit_vector(r_in.dir);
+        let o = unit_vector(*dir);
+        let (cos_v, cos_o) = (dot(&v, &rec.normal), dot(&o, &rec.normal));
+        if cos_o <= 0.0 || cos_v <= 0.0 {
+            return Some((Color::default(), 0.0))
+        }
+        let h = unit_vector(v + o);
+        let (cos_h, cos_vh) = (dot(&h, &rec.normal), dot(&v, &h));
+        let d = ggx(cos_h, alpha);
+        let f0 = self.albedo.value_at(rec);
+        let fresnel = f0 + (1.0 - cos_vh).powi(5) * (Color::new(1.0, 1.0, 1.0) - f0);
+        let g = smith_g1(cos_v, alpha) * smith_g1(cos_o, alpha);
+        let pdf = match self.sampling {
+            Sampling::Visible => smith_g1(cos_v, alpha) * d / (4.0 * cos_v),
+            Sampling::Distribution => d * cos_h / (4.0 * cos_vh),
+        };
+        Some((d * g / (4.0 * cos_v) * fresnel, pdf))
     }
 }
 
+impl Sampling {
+    // A microfacet normal around the surface normal to scatter the light
+    // arriving from `v`, seen from whose side it is, if any.
+    fn facet(self, normal: &Vec3, v: &Vec3, alpha: Float) -> Option<Vec3> {
+        match self {
+            Sampling::Visible => sample_visible_ggx(normal, v, alpha),
+            Sampling::Distribution => Some(sample_ggx(normal, alpha)),
+        }
+    }
+
+    // The weight of light scattered off a microfacet drawn by `facet`,
+    // leaving the Fresnel term aside. Visible facets are drawn in
+    // proportion to the masking towards `v`, leaving only the shadowing
+    // towards the way out.
+    fn weight(self, alpha: Float, cos_v: Float, cos_o: Float, cos_h: Float, cos_vh: Float) -> Float {
+        match self {
+            Sampling::Visible => smith_g1(cos_o, alpha),
+            Sampling::Distribution => ggx_weight(alpha, cos_v, cos_o, cos_h, cos_vh),
+        }
+    }
+}
+
+// Density of microfacets facing at the given cosine to the normal in
+// the GGX distribution, per unit solid angle of their normals.
+fn ggx(cos_h: Float, alpha: Float) -> Float {
+    let a2 = alpha * alpha;
+    let denom = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * denom * denom)
+}
+
+// A microfacet normal around the surface normal, drawn with a probability
+// of the GGX distribution with the given width, times its cosine.
+fn sample_ggx(normal: &Vec3, alpha: Float) -> Vec3 {
+    let (a, b) = (random_float(), random_float());
+    let theta = (alpha * (a / (1.0 - a)).sqrt()).atan();
+    let phi = 2.0 * PI * b;
+    let local = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+    Onb::from_w(normal).local(&local)
+}
+
+// A microfacet normal drawn with the density of those seen from `v`: the
+// GGX distribution times the cosine to `v`, over the cosine of `v` to
+// the normal and times the masking. Following Heitz, "Sampling the GGX
+// Distribution of Visible Normals" (2018), the view is stretched to that
+// of a hemisphere, whose visible normals are a disk squashed on the side
+// facing away. None from below the surface, where nothing is visible.
+fn sample_visible_ggx(normal: &Vec3, v: &Vec3, alpha: Float) -> Option<Vec3> {
+    let onb = Onb::from_w(normal);
+    let local = Vec3::new(dot(v, &onb.u), dot(v, &onb.v), dot(v, &onb.w));
+    if local.z <= 0.0 {
+        return None
+    }
+    let stretched = unit_vector(Vec3::new(alpha * local.x, alpha * local.y, local.z));
+    let across = stretched.x * stretched.x + stretched.y * stretched.y;
+    let t1 = if across > 0.0 { Vec3::new(-stretched.y, stretched.x, 0.0) / across.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+    let t2 = cross(&stretched, &t1);
+
+    let (a, b) = (random_float(), random_float());
+    let (r, phi) = (a.sqrt(), 2.0 * PI * b);
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + stretched.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let up = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    let h = p1 * t1 + p2 * t2 + up * stretched;
+    Some(onb.local(&unit_vector(Vec3::new(alpha * h.x, alpha * h.y, h.z.max(1e-6)))))
+}
+
+// Smith's fraction of the microfacets seen from a direction at the
+// given cosine to the normal that are not hidden behind others.
+fn smith_g1(cos: Float, alpha: Float) -> Float {
+    let cos = cos.abs();
+    2.0 * cos / (cos + (alpha * alpha + (1.0 - alpha * alpha) * cos * cos).sqrt())
+}
+
+// The weight of light scattered off a microfacet drawn by `sample_ggx`,
+// leaving the Fresnel term aside: the masking and shadowing over the
+// probability of the direction, times the cosine of the way out.
+fn ggx_weight(alpha: Float, cos_v: Float, cos_o: Float, cos_h: Float, cos_vh: Float) -> Float {
+    smith_g1(cos_v, alpha) * smith_g1(cos_o, alpha) * cos_vh.abs() / (cos_v.abs() * cos_h)
+}
+
 pub struct Dielectric {
-    ref_idx: f32,
+    ref_idx: Float,
+    // Fraction of the light absorbed per unit of distance travelled
+    // inside, per channel, following the Beer-Lambert law.
+    absorption: Color,
+    // Width of the GGX distribution of microfacets of frosted glass,
+    // which is smooth at 0.
+    roughness: Float,
+    sampling: Sampling,
+    // Indices of refraction of red, green and blue light in dispersive
+    // glass, which splits white light into its colors.
+    dispersion: Option<[Float; 3]>,
 }
 
+// Wavelengths in nanometers of the Fraunhofer lines glass is measured at,
+// the d line of the index of refraction and the F and C ones of the Abbe
+// number, and those taken for red, green and blue light.
+const LINE_D: Float = 587.6;
+const LINE_F: Float = 486.1;
+const LINE_C: Float = 656.3;
+const CHANNEL_WAVELENGTHS: [Float; 3] = [650.0, 550.0, 450.0];
+
 impl Dielectric {
-    pub fn new(ref_idx: f32) -> Self {
-        Self { ref_idx }
+    pub fn new(ref_idx: Float) -> Self {
+        Self { ref_idx, absorption: Color::default(), roughness: 0.0, sampling: Sampling::default(), dispersion: None }
+    }
+
+    // Dispersive glass of the given Abbe number, taking the index of
+    // refraction as that of the d line. The lower the number the wider
+    // the colors spread: about 60 for crown glass, 30 for flint glass and
+    // 55 for diamond. Follows Cauchy's equation, n = a + b / wavelength^2.
+    pub fn with_dispersion(mut self, abbe: Float) -> Self {
+        let b = (self.ref_idx - 1.0) / (abbe * (1.0 / (LINE_F * LINE_F) - 1.0 / (LINE_C * LINE_C)));
+        let a = self.ref_idx - b / (LINE_D * LINE_D);
+        let ior = |wavelength: Float| a + b / (wavelength * wavelength);
+        self.dispersion = Some([ior(CHANNEL_WAVELENGTHS[0]), ior(CHANNEL_WAVELENGTHS[1]), ior(CHANNEL_WAVELENGTHS[2])]);
+        self
+    }
+
+    // Frosted glass, reflecting and refracting off microfacets as
+    // `MicrofacetMetal` reflects off them.
+    pub fn with_roughness(mut self, roughness: Float) -> Self {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    // Picks a microfacet and reflects or refracts off it, with the
+    // probability of reflection Fresnel gives. Returns the direction
+    // and its weight, or None when it lands on the wrong side.
+    fn scatter_rough(&self, unit_dir: Vec3, normal: &Vec3, etai_over_etat: Float) -> Option<(Vec3, Float)> {
+        let alpha = self.roughness * self.roughness;
+        let v = -unit_dir;
+        let h = self.sampling.facet(normal, &v, alpha)?;
+        let cos_vh = dot(&v, &h);
+        if cos_vh <= 0.0 {
+            return None
+        }
+        let sin_vh = (1.0 - cos_vh * cos_vh).sqrt();
+        let reflect_prob = if etai_over_etat * sin_vh > 1.0 { 1.0 } else { schlick(cos_vh, etai_over_etat) };
+        let reflected = random_float() < reflect_prob;
+        let dir = if reflected { reflect(&unit_dir, &h) } else { refract(&unit_dir, &h, etai_over_etat) };
+
+        let (cos_v, cos_o, cos_h) = (dot(&v, normal), dot(&dir, normal), dot(&h, normal));
+        if reflected != (cos_o > 0.0) {
+            return None
+        }
+        Some((dir, self.sampling.weight(alpha, cos_v, cos_o, cos_h, cos_vh)))
+    }
+
+    // Colored glass, passing on the given color of the light going
+    // through a unit of distance of it at a density of 1. Thicker
+    // parts and denser glass are darker and more saturated.
+    pub fn with_absorption(mut self, color: Color, density: Float) -> Self {
+        let coefficient = |c: Float| -c.ln() * density;
+        self.absorption = Color::new(coefficient(color.x), coefficient(color.y), coefficient(color.z));
+        self
     }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        // Leaving through a back face, the ray has crossed the inside.
+        *attenuation = if rec.front_face {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            let distance = rec.t * r_in.dir.length();
+            let a = self.absorption;
+            Color::new((-a.x * distance).exp(), (-a.y * distance).exp(), (-a.z * distance).exp())
+        };
 
-        let etai_over_etat = if rec.front_face { 1.0 / self.ref_idx } else { self.ref_idx };
+        // Dispersive glass bends each channel its own way, so the ray
+        // carries on with one of them picked at random, weighted by 3 for
+        // the odds of picking it, and keeps to it from then on.
+        let (ref_idx, channel) = match (self.dispersion, r_in.channel) {
+            (Some(iors), Some(channel)) => (iors[channel], Some(channel)),
+            (Some(iors), None) => {
+                let channel = ((random_float() * 3.0) as usize).min(2);
+                *attenuation = 3.0 * *attenuation;
+                (iors[channel], Some(channel))
+            }
+            (None, channel) => (self.ref_idx, channel),
+        };
+        if let Some(channel) = channel {
+            let mut only = Color::default();
+            only[channel] = attenuation[channel];
+            *attenuation = only;
+        }
+        let etai_over_etat = if rec.front_face { 1.0 / ref_idx } else { ref_idx };
 
         let unit_dir = unit_vector(r_in.dir);
+        if self.roughness > 0.0 {
+            return match self.scatter_rough(unit_dir, &rec.normal, etai_over_etat) {
+                Some((dir, weight)) => {
+                    *attenuation = weight * *attenuation;
+                    *scattered = Ray { channel, ..Ray::new(&rec.p, &dir, r_in.time) };
+                    true
+                }
+                None => false,
+            }
+        }
 
         let dotted = dot(&-unit_dir, &rec.normal);
-        let cos_theta = f32::min(dotted, 1.0);
+        let cos_theta = Float::min(dotted, 1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
         if (etai_over_etat * sin_theta) > 1.0 {
             let reflected = reflect(&unit_dir, &rec.normal);
-            *scattered = Ray::new(&rec.p, &reflected);
+            *scattered = Ray { channel, ..Ray::new(&rec.p, &reflected, r_in.time) };
             return true
         }
 
         let reflect_prob = schlick(cos_theta, etai_over_etat);
-        if random_f32() < reflect_prob {
+        if random_float() < reflect_prob {
             let reflected = reflect(&unit_dir, &rec.normal);
-            *scattered = Ray::new(&rec.p, &reflected);
+            *scattered = Ray { channel, ..Ray::new(&rec.p, &reflected, r_in.time) };
             return true
         }
 
         let refracted = refract(&unit_dir, &rec.normal, etai_over_etat);
-        *scattered = Ray::new(&rec.p, &refracted);
+        *scattered = Ray { channel, ..Ray::new(&rec.p, &refracted, r_in.time) };
 
         true
     }
 
-    fn clone(&self) -> Box<dyn Material> {
-        Box::new(Self::new(self.ref_idx))
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// A surface that only emits light, such as a light panel.
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _attenuation: &mut Color, _scattered: &mut Ray) -> bool {
+        false
+    }
+
+    fn emitted(&self) -> Color {
+        self.emit
     }
 }
 
-pub fn schlick(cosine: f32, ref_idx: f32) -> f32 {
+// Scatters light equally in every direction, the phase function
+// of participating media such as smoke and fog.
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+        *scattered = Ray::new(&rec.p, &random_unit_vector(), r_in.time);
+        *attenuation = self.albedo.value_at(rec);
+        true
+    }
+
+    fn scattering(&self, _r_in: &Ray, rec: &HitRecord, _dir: &Vec3) -> Option<(Color, Float)> {
+        let pdf = 1.0 / (4.0 * PI);
+        Some((pdf * self.albedo.value_at(rec), pdf))
+    }
+}
+
+// Any material cut out by an opacity map, e.g. the outline of a leaf on
+// a single quad or the holes of a fence. Texels of an opacity below the
+// cutoff let every ray through, and those above stop them with their
+// opacity as the chance, the mean of the map's channels. Rays are let
+// through by the objects in a `Cutout` rather than scattered, so they
+// cost no bounce and shadow rays pass too.
+pub struct Masked {
+    mat: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+    cutoff: Float,
+}
+
+impl Masked {
+    pub fn new(mat: Arc<dyn Material>, opacity: Arc<dyn Texture>, cutoff: Float) -> Self {
+        Self { mat, opacity, cutoff }
+    }
+}
+
+impl Material for Masked {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+        self.mat.scatter(r_in, rec, attenuation, scattered)
+    }
+
+    fn emitted(&self) -> Color {
+        self.mat.emitted()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.mat.is_specular()
+    }
+
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Option<Color> {
+        self.mat.diffuse_albedo(rec)
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> Option<(Color, Float)> {
+        self.mat.scattering(r_in, rec, dir)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> Float {
+        let c = self.opacity.value_at(rec);
+        let opacity = (c.x + c.y + c.z) / 3.0;
+        if opacity < self.cutoff {
+            0.0
+        } else {
+            opacity.min(1.0)
+        }
+    }
+
+    fn is_masked(&self) -> bool {
+        true
+    }
+}
+
+pub fn schlick(cosine: Float, ref_idx: Float) -> Float {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0.powi(2);
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::{Point3, Vec3};
+
+    #[test]
+    fn test_dielectric_absorption() {
+        let glass = Dielectric::new(1.0).with_absorption(Color::new(0.5, 1.0, 0.25), 1.0);
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let mut rec = HitRecord {
+            p: r.at(1.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat: &glass,
+        };
+        let attenuation = |rec: &HitRecord| {
+            let (mut attenuation, mut scattered) = (Color::default(), r);
+            assert!(glass.scatter(&r, rec, &mut attenuation, &mut scattered));
+            attenuation
+        };
+
+        // Entering glass is free, leaving it after two units of
+        // distance passes on the color twice over.
+        assert_eq!(Color::new(1.0, 1.0, 1.0), attenuation(&rec));
+        rec.front_face = false;
+        let leaving = attenuation(&rec);
+        assert!((leaving - Color::new(0.25, 1.0, 0.0625)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // Blue bends most, and the index is the one given at the d line,
+        // which lies between red and green.
+        let glass = Dielectric::new(1.5).with_dispersion(30.0);
+        let [red, green, blue] = glass.dispersion.unwrap();
+        assert!(red < 1.5 && 1.5 < green && green < blue, "{} {} {}", red, green, blue);
+        // The Abbe number is the spread between the F and C lines.
+        let b = (blue - green) / (1.0 / ((450.0 as Float) * 450.0) - 1.0 / (550.0 * 550.0));
+        let abbe = 0.5 / (b * (1.0 / (LINE_F * LINE_F) - 1.0 / (LINE_C * LINE_C)));
+        assert!((abbe - 30.0).abs() < 0.1, "{}", abbe);
+
+        // Every ray carries on in a single channel, the one it already
+        // carries if any.
+        let r = Ray::new(&Point3::new(0.0, 1.0, 1.0), &Vec3::new(0.0, -1.0, -1.0), 0.0);
+        let rec = HitRecord {
+            p: Point3::default(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat: &glass,
+        };
+        for _ in 0..20 {
+            let (mut attenuation, mut scattered) = (Color::default(), r);
+            assert!(glass.scatter(&r, &rec, &mut attenuation, &mut scattered));
+            let channels = [attenuation.x, attenuation.y, attenuation.z];
+            assert_eq!(1, channels.iter().filter(|&&c| c == 3.0).count(), "{:?}", attenuation);
+            assert_eq!(2, channels.iter().filter(|&&c| c == 0.0).count(), "{:?}", attenuation);
+            assert!(scattered.channel.is_some());
+        }
+        let blue = Ray { channel: Some(2), ..r };
+        let (mut attenuation, mut scattered) = (Color::default(), r);
+        assert!(glass.scatter(&blue, &rec, &mut attenuation, &mut scattered));
+        assert_eq!((Color::new(0.0, 0.0, 1.0), Some(2)), (attenuation, scattered.channel));
+    }
+
+    // Reflectance of a white furnace: the average weight of light
+    // scattered off the material towards a fixed direction.
+    fn albedo(mat: &dyn Material, cos: Float) -> Float {
+        let weights = weights(mat, cos);
+        weights.iter().sum::<Float>() / weights.len() as Float
+    }
+
+    // The weights of light scattered off the material towards a fixed
+    // direction, 0 for none.
+    fn weights(mat: &dyn Material, cos: Float) -> Vec<Float> {
+        let dir = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, -cos);
+        let r = Ray::new(&Point3::new(0.0, 0.0, 1.0), &dir, 0.0);
+        let rec = HitRecord {
+            p: Point3::default(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat,
+        };
+        (0..20000)
+            .map(|_| {
+                let (mut attenuation, mut scattered) = (Color::default(), r);
+                if mat.scatter(&r, &rec, &mut attenuation, &mut scattered) { attenuation.x } else { 0.0 }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fuzzy_metal() {
+        crate::rng::seed(4);
+
+        // However rough, and however grazing the light, a white metal
+        // reflects all of it, and only above the surface.
+        let white = Color::new(1.0, 1.0, 1.0);
+        for &(roughness, cos) in [(0.5, 0.95), (1.0, 0.95), (1.0, 0.1)].iter() {
+            assert_eq!(1.0, albedo(&Metal::new(white, roughness), cos));
+        }
+        let r = Ray::new(&Point3::new(0.0, 0.0, 1.0), &Vec3::new(1.0, 0.0, -0.1), 0.0);
+        let mat = Metal::new(white, 1.0);
+        let rec = HitRecord { p: Point3::default(), normal: Vec3::new(0.0, 0.0, 1.0), t: 1.0, u: 0.0, v: 0.0, front_face: true, mat: &mat };
+        for _ in 0..1000 {
+            let (mut attenuation, mut scattered) = (Color::default(), r);
+            assert!(mat.scatter(&r, &rec, &mut attenuation, &mut scattered));
+            assert!(scattered.dir.z >= 0.0, "{:?}", scattered.dir);
+        }
+    }
+
+    #[test]
+    fn test_microfacets() {
+        crate::rng::seed(5);
+
+        // A smooth white metal is a mirror, and rougher ones lose
+        // little energy head on, and more at grazing angles.
+        assert_eq!(1.0, albedo(&MicrofacetMetal::new(Color::new(1.0, 1.0, 1.0), 0.0), 1.0));
+        let rough = MicrofacetMetal::new(Color::new(1.0, 1.0, 1.0), 0.5);
+        let (head_on, grazing) = (albedo(&rough, 0.95), albedo(&rough, 0.2));
+        assert!(head_on > 0.9 && head_on <= 1.0, "{}", head_on);
+        assert!(grazing < head_on, "{} {}", grazing, head_on);
+
+        // Frosted glass lets most light through, and so keeps about
+        // as much energy as smooth glass.
+        let smooth = albedo(&Dielectric::new(1.5), 0.95);
+        let frosted = albedo(&Dielectric::new(1.5).with_roughness(0.3), 0.95);
+        assert!((smooth - 1.0).abs() < 1e-6);
+        assert!(frosted > 0.9 && frosted < 1.05, "{}", frosted);
+    }
+
+    #[test]
+    fn test_visible_normals() {
+        crate::rng::seed(9);
+        let variance = |weights: &[Float]| {
+            let mean = weights.iter().sum::<Float>() / weights.len() as Float;
+            (mean, weights.iter().map(|w| (w - mean) * (w - mean)).sum::<Float>() / weights.len() as Float)
+        };
+
+        // Both ways of drawing facets agree on average, but drawing
+        // visible ones is far less noisy at grazing angles.
+        let white = Color::new(1.0, 1.0, 1.0);
+        let visible = MicrofacetMetal::new(white, 0.6);
+        let all = MicrofacetMetal::new(white, 0.6).with_sampling(Sampling::Distribution);
+        let (visible, all) = (variance(&weights(&visible, 0.15)), variance(&weights(&all, 0.15)));
+        assert!((visible.0 - all.0).abs() < 0.03, "{:?} {:?}", visible, all);
+        assert!(visible.1 < 0.1 * all.1, "{:?} {:?}", visible, all);
+
+        let frosted = |sampling| Dielectric::new(1.5).with_roughness(0.5).with_sampling(sampling);
+        let (visible, all) = (albedo(&frosted(Sampling::Visible), 0.3), albedo(&frosted(Sampling::Distribution), 0.3));
+        assert!((visible - all).abs() < 0.03, "{} {}", visible, all);
+    }
+
+    #[test]
+    fn test_scattering() {
+        crate::rng::seed(3);
+        let r = Ray::new(&Point3::new(1.0, 0.0, 1.0), &Vec3::new(-1.0, 0.0, -1.0), 0.0);
+        let rec = |mat| HitRecord {
+            p: Point3::default(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat,
+        };
+
+        // What a material scatters towards a direction over the density
+        // of picking it is the weight `scatter` gives it.
+        let materials: [&dyn Material; 4] = [
+            &Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+            &MicrofacetMetal::new(Color::new(0.9, 0.6, 0.3), 0.4),
+            &Isotropic::new(Color::new(0.5, 0.5, 0.5)),
+            &MicrofacetMetal::new(Color::new(0.9, 0.6, 0.3), 0.4).with_sampling(Sampling::Distribution),
+        ];
+        for &mat in materials.iter() {
+            let rec = rec(mat);
+            for _ in 0..100 {
+                let (mut attenuation, mut scattered) = (Color::default(), r);
+                if !mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
+                    continue
+                }
+                let (value, pdf) = mat.scattering(&r, &rec, &scattered.dir).unwrap();
+                assert!((value / pdf - attenuation).length() < 1e-3, "{:?} vs {:?}", value / pdf, attenuation);
+            }
+        }
+
+        // Below the surface nothing is scattered, and mirrors have no density.
+        let metal = rec(materials[1]);
+        assert_eq!(Some((Color::default(), 0.0)), materials[1].scattering(&r, &metal, &Vec3::new(1.0, 0.0, -1.0)));
+        let mirror = MicrofacetMetal::new(Color::new(1.0, 1.0, 1.0), 0.0);
+        assert_eq!(None, mirror.scattering(&r, &rec(&mirror), &Vec3::new(-1.0, 0.0, 1.0)));
+    }
+}
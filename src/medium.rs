@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use crate::{random_float, INF, Float};
+use crate::ray::Ray;
+use crate::vec::{Vec3, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Material, Isotropic};
+use crate::aabb::Aabb;
+
+// Hits past the boundary's entry point closer than this are taken
+// to be the entry point again rather than the exit.
+const EXIT_EPSILON: Float = 1e-4;
+
+// A volume of uniform density filling a closed boundary object, such as
+// smoke in a box or fog in a sphere. Rays passing through are scattered
+// at a random distance, exponentially distributed with the density, in
+// a random direction; the rest pass through untouched.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    phase_function: Arc<dyn Material>,
+    neg_inv_density: Float,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, density: Float, albedo: Color) -> Self {
+        Self {
+            boundary,
+            phase_function: Arc::new(Isotropic::new(albedo)),
+            neg_inv_density: -1.0 / density,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    // The boundary is hit along the whole line of the ray, so that rays
+    // starting inside the volume, e.g. after scattering in it, find it
+    // too. This assumes the boundary is convex.
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let enter = self.boundary.hit(r, -INF, INF)?;
+        let exit = self.boundary.hit(r, enter.t + EXIT_EPSILON, INF)?;
+
+        let t0 = enter.t.max(t_min).max(0.0);
+        let t1 = exit.t.min(t_max);
+        if t0 >= t1 {
+            return None
+        }
+
+        let ray_length = r.dir.length();
+        let distance_inside = (t1 - t0) * ray_length;
+        let hit_distance = self.neg_inv_density * random_float().ln();
+        if hit_distance > distance_inside {
+            return None
+        }
+
+        // The normal and face are arbitrary, since the phase
+        // function scatters the same way in every direction.
+        let t = t0 + hit_distance / ray_length;
+        Some(HitRecord {
+            p: r.at(t),
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            t,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat: self.phase_function.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Point3;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+
+    fn fog(density: Float) -> ConstantMedium {
+        let boundary = Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::default())));
+        ConstantMedium::new(Box::new(boundary), density, Color::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn test_transmittance() {
+        // Across a unit sphere a ray travels two units through the
+        // medium, and passes through with probability e^(-2 density).
+        let medium = fog(0.5);
+        let r = Ray::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let trials = 10000;
+        let mut passed = 0;
+        for _ in 0..trials {
+            match medium.hit(&r, 0.001, INF) {
+                Some(rec) => assert!(rec.p.z >= -1.0 && rec.p.z <= 1.0, "{:?}", rec.p),
+                None => passed += 1,
+            }
+        }
+        let expected = (-(1.0 as Float)).exp();
+        assert!((passed as Float / trials as Float - expected).abs() < 0.03, "{}", passed);
+    }
+
+    #[test]
+    fn test_ray_inside() {
+        // Rays leaving a scattering point inside scatter again before
+        // the boundary, and never behind their origin.
+        let medium = fog(100.0);
+        let r = Ray::new(&Point3::default(), &Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let rec = medium.hit(&r, 0.001, INF).unwrap();
+        assert!(rec.t > 0.001 && rec.t < 1.0);
+
+        let away = Ray::new(&Point3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(medium.hit(&away, 0.001, INF).is_none());
+    }
+}
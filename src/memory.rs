@@ -0,0 +1,140 @@
+use std::mem::size_of;
+
+use crate::hittable::Hittable;
+use crate::vec::Color;
+use crate::exr::DeepSample;
+use crate::checkerboard::Guide;
+use crate::filter::Filter;
+use crate::{bvh, Float};
+use crate::render::{PixelSample, Settings};
+
+// Deep pixels usually hold only a few distinct depths, since
+// samples within a small tolerance of each other are merged.
+const DEEP_LAYERS: usize = 4;
+
+// Rough peak memory of rendering one camera, so that settings
+// far too large for the machine are refused up front instead of
+// the process being killed part way through the render.
+pub struct Estimate {
+    pub framebuffer: usize,
+    pub deep: usize,
+    pub scene: usize,
+}
+
+impl Estimate {
+    pub fn new(settings: &Settings, deep: bool, aovs: usize, objects: &[Box<dyn Hittable>]) -> Self {
+        let pixels = settings.width * settings.height;
+
+        // The accumulated sums with their counts, and their weights for
+        // filters that splat, one pass worth of tile samples, the
+        // resolved image and its 8-bit or float encoding on output. Half
+        // floats take less space for the sums and encoding.
+        let color_size = if settings.half_float { size_of::<[u16; 3]>() } else { size_of::<Color>() };
+        let accumulated = color_size + size_of::<u32>();
+        let weights = if settings.filter == Filter::Box { 0 } else { size_of::<Float>() };
+        let mut per_pixel = accumulated + weights
+            + size_of::<PixelSample>()
+            + size_of::<Color>() + color_size;
+        if settings.checkerboard {
+            per_pixel += size_of::<Guide>();
+        }
+        // Every AOV is accumulated, held in the tile samples and resolved
+        // like the light.
+        per_pixel += aovs * (accumulated + size_of::<Color>() + size_of::<Color>());
+
+        // Sample lists, their counts and the encoded file, which
+        // holds five floats per sample and an offset per pixel.
+        let deep = if deep {
+            pixels * (size_of::<Vec<DeepSample>>() + size_of::<usize>() + 4
+                + DEEP_LAYERS * (size_of::<DeepSample>() + 5 * 4))
+        } else {
+            0
+        };
+
+        Self {
+            framebuffer: pixels * per_pixel,
+            deep,
+            scene: bvh::memory_estimate(objects),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.framebuffer + self.deep + self.scene
+    }
+}
+
+// Formats a byte count with a binary unit, e.g. `1.5 GiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::render::{Background, Integrator};
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!("512 B", format_bytes(512));
+        assert_eq!("1.5 KiB", format_bytes(1536));
+        assert_eq!("4.0 GiB", format_bytes(4 << 30));
+    }
+
+    #[test]
+    fn test_estimate_scales_with_pixels() {
+        let settings = |width, height, half_float| Settings {
+            width,
+            height,
+            max_depth: 1,
+            min_throughput: 0.0,
+            background: Background::Sky,
+            ray_bias: 0.001,
+            split: 1,
+            irradiance: None,
+            threads: 1,
+            pin_threads: false,
+            checkerboard: false,
+            exposure: 1.0,
+            half_float,
+            integrator: Integrator::Path,
+            clamp: Default::default(),
+            filter: Filter::Box,
+            lights: Arc::default(),
+            ambient: None,
+            sun: None,
+            punctual_lights: Vec::new(),
+            focus: None,
+            seed: 0,
+            frame: 0,
+        };
+        let small = Estimate::new(&settings(100, 100, false), true, 0, &[]);
+        let large = Estimate::new(&settings(1000, 1000, false), true, 0, &[]);
+        assert_eq!(100 * small.framebuffer, large.framebuffer);
+        assert_eq!(100 * small.deep, large.deep);
+        assert!(Estimate::new(&settings(16384, 9216, false), false, 0, &[]).total() > 4 << 30);
+
+        let half = Estimate::new(&settings(100, 100, true), true, 0, &[]);
+        let saved = size_of::<Color>() - size_of::<[u16; 3]>();
+        assert_eq!(small.framebuffer - 100 * 100 * 2 * saved, half.framebuffer);
+        let with_aovs = Estimate::new(&settings(100, 100, false), true, 3, &[]);
+        let per_aov = 3 * size_of::<Color>() + size_of::<u32>();
+        assert_eq!(small.framebuffer + 100 * 100 * 3 * per_aov, with_aovs.framebuffer);
+
+        // Only filters that splat keep weights.
+        let tent = Estimate::new(&Settings { filter: Filter::Tent(1.0), ..settings(100, 100, false) }, true, 0, &[]);
+        assert_eq!(small.framebuffer + 100 * 100 * size_of::<Float>(), tent.framebuffer);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::sync::Arc;
+use std::io::{self, BufRead, BufReader};
+
+use crate::Float;
+use crate::vec::{Vec3, Point3};
+use crate::hittable::Hittable;
+use crate::material::Material;
+use crate::triangle::{Triangle, Culling};
+
+// Loads the triangles of a Wavefront OBJ file, all sharing the given
+// material. Only vertex positions, texture coordinates, normals and
+// faces are read; polygons with more than three vertices are split
+// into a fan of triangles. Groups and materials are ignored.
+pub fn load_obj<R: BufRead>(input: R, mat: &Arc<dyn Material>) -> io::Result<Vec<Triangle>> {
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(Float, Float)> = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (idx, line) in input.lines().enumerate() {
+        let line = line?;
+        let err = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", idx + 1, msg));
+
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("v") => positions.push(parse_vec3(fields).ok_or_else(|| err("invalid vertex"))?),
+            Some("vn") => normals.push(parse_vec3(fields).ok_or_else(|| err("invalid normal"))?),
+            Some("vt") => {
+                let mut next = || fields.next()?.parse::<Float>().ok();
+                let uv = next().zip(next()).ok_or_else(|| err("invalid texture coordinate"))?;
+                uvs.push(uv);
+            }
+            Some("f") => {
+                let corners = fields
+                    .map(|f| parse_corner(f, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| err("invalid face"))?;
+                if corners.len() < 3 {
+                    return Err(err("face with fewer than three vertices"))
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                    let vertices = [positions[a.0], positions[b.0], positions[c.0]];
+                    let mut tri = Triangle::new(vertices, mat.clone());
+                    if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+                        tri = tri.with_uvs([uvs[ta], uvs[tb], uvs[tc]]);
+                    }
+                    if let (Some(na), Some(nb), Some(nc)) = (a.2, b.2, c.2) {
+                        tri = tri.with_normals([normals[na], normals[nb], normals[nc]]);
+                    }
+                    triangles.push(tri);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+// The triangles of a file, and the problems found with them.
+type Checked = (Vec<Box<dyn Hittable>>, Vec<String>);
+
+pub fn load_obj_file(path: &str, mat: &Arc<dyn Material>, culling: Culling) -> io::Result<Checked> {
+    let triangles = load_obj(BufReader::new(File::open(path)?), mat)?;
+    let problems = check(&triangles);
+    let triangles = triangles.into_iter()
+        .map(|t| Box::new(t.with_culling(culling)) as Box<dyn Hittable>)
+        .collect();
+    Ok((triangles, problems))
+}
+
+// Likely mistakes in a mesh that still loads: triangles no ray can hit,
+// and missing normals, without which it is shaded flat.
+pub fn check(triangles: &[Triangle]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if triangles.is_empty() {
+        problems.push("mesh has no triangles".to_string());
+    }
+    let degenerate = triangles.iter().filter(|t| t.is_degenerate()).count();
+    if degenerate > 0 {
+        problems.push(format!("{} of {} triangles are degenerate", degenerate, triangles.len()));
+    }
+    match triangles.iter().filter(|t| t.normals.is_none()).count() {
+        0 => {}
+        n if n == triangles.len() => problems.push("mesh has no normals and is shaded flat".to_string()),
+        n => problems.push(format!("{} of {} triangles have no normals and are shaded flat", n, triangles.len())),
+    }
+    problems
+}
+
+fn parse_vec3<'a, I: Iterator<Item = &'a str>>(mut fields: I) -> Option<Vec3> {
+    let mut next = || fields.next()?.parse::<Float>().ok();
+    Some(Vec3::new(next()?, next()?, next()?))
+}
+
+// A face corner as zero-based indices of its position and,
+// when given, its texture coordinates and normal.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Resolves a face corner such as `3`, `3/1`, `3//2` or `3/1/2`.
+// Indices are one-based, or relative to the end of the list so
+// far when negative.
+fn parse_corner(s: &str, positions: usize, uvs: usize, normals: usize) -> Option<Corner> {
+    let mut parts = s.split('/');
+    let position = resolve(parts.next()?, positions)?;
+    let mut optional = |count| match parts.next() {
+        Some(n) if !n.is_empty() => resolve(n, count).map(Some),
+        _ => Some(None),
+    };
+    let uv = optional(uvs)?;
+    let normal = optional(normals)?;
+    Some((position, uv, normal))
+}
+
+fn resolve(s: &str, count: usize) -> Option<usize> {
+    let idx: isize = s.parse().ok()?;
+    let resolved = if idx < 0 { count as isize + idx } else { idx - 1 };
+    if resolved >= 0 && (resolved as usize) < count {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+    use crate::material::Lambertian;
+
+    fn load(s: &str) -> io::Result<Vec<Triangle>> {
+        load_obj(s.as_bytes(), &(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))) as Arc<dyn Material>))
+    }
+
+    #[test]
+    fn test_load_quad() {
+        let obj = "# a unit square\n\
+            v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+            vt 0 0\nvn 0 0 1\n\
+            f 1/1/1 2/1/1 3/1/1 4/1/1\n";
+        let triangles = load(obj).unwrap();
+        assert_eq!(2, triangles.len());
+        assert_eq!(Point3::new(1.0, 1.0, 0.0), triangles[1].vertices[1]);
+        assert_eq!(Some([Vec3::new(0.0, 0.0, 1.0); 3]), triangles[0].normals);
+        assert_eq!(Some([(0.0, 0.0); 3]), triangles[0].uvs);
+    }
+
+    #[test]
+    fn test_relative_indices() {
+        let triangles = load("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").unwrap();
+        assert_eq!(1, triangles.len());
+        assert_eq!(Point3::new(0.0, 0.0, 0.0), triangles[0].vertices[0]);
+        assert_eq!(None, triangles[0].normals);
+    }
+
+    #[test]
+    fn test_check() {
+        let quad = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\n";
+        assert!(check(&load(quad).unwrap()).is_empty());
+        let problems = check(&load("v 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4\n").unwrap());
+        assert_eq!(vec!["1 of 2 triangles are degenerate", "mesh has no normals and is shaded flat"], problems);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(load("v 0 0 0\nv 1 0 0\nf 1 2 3\n").is_err());
+        assert!(load("v 0 0\n").is_err());
+        assert!(load("v 0 0 0\nv 1 0 0\nf 1 2\n").is_err());
+    }
+}
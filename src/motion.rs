@@ -0,0 +1,239 @@
+use crate::Float;
+use crate::vec::{Vec3, Point3};
+use crate::mat::{Mat4, Quat};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+
+// A rigid placement of an object: a rotation given as Euler angles
+// in degrees, applied about the X, then Y, then Z axis, followed by
+// a translation.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RigidTransform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+}
+
+impl RigidTransform {
+    pub fn new(translation: Vec3, rotation: Vec3) -> Self {
+        Self { translation, rotation }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        Quat::from_euler(self.rotation).rotate(v)
+    }
+
+    pub fn unrotate(&self, v: Vec3) -> Vec3 {
+        Quat::from_euler(self.rotation).conjugate().rotate(v)
+    }
+
+    // The object-to-world matrix of the placement.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.translation) * Mat4::rotation(Quat::from_euler(self.rotation))
+    }
+
+    // Object space to world space.
+    pub fn apply(&self, p: Point3) -> Point3 {
+        self.rotate(p) + self.translation
+    }
+
+    // World space to object space.
+    pub fn invert(&self, p: Point3) -> Point3 {
+        self.unrotate(p - self.translation)
+    }
+
+}
+
+// A rigid placement with its rotation as a quaternion, worked out once
+// rather than from the Euler angles at every ray, which objects are
+// intersected through. Moving objects blend between two of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+impl Pose {
+    pub fn new(xf: &RigidTransform) -> Self {
+        Self { translation: xf.translation, rotation: Quat::from_euler(xf.rotation) }
+    }
+
+    // Translation moves in a straight line and rotation turns about a
+    // fixed axis at an even rate, so that the object keeps its shape
+    // all along.
+    fn interpolate(&self, other: &Pose, t: Float) -> Pose {
+        Pose {
+            translation: (1.0 - t) * self.translation + t * other.translation,
+            rotation: self.rotation.slerp(&other.rotation, t),
+        }
+    }
+
+    // Object space to world space.
+    pub fn apply(&self, p: Point3) -> Point3 {
+        self.rotation.rotate(p) + self.translation
+    }
+
+    // World space to object space.
+    pub fn invert(&self, p: Point3) -> Point3 {
+        self.rotation.conjugate().rotate(p - self.translation)
+    }
+
+    // Intersects an object placed with this pose, by moving the ray
+    // into its space and the hit back out.
+    pub fn hit<'a>(&self, object: &'a dyn Hittable, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'a>> {
+        let unrotate = self.rotation.conjugate();
+        let local = Ray { orig: unrotate.rotate(r.orig - self.translation), dir: unrotate.rotate(r.dir), ..*r };
+        let mut rec = object.hit(&local, t_min, t_max)?;
+        rec.p = self.apply(rec.p);
+        rec.normal = self.rotation.rotate(rec.normal);
+        Some(rec)
+    }
+
+    // Box around a transformed box.
+    pub fn apply_box(&self, bbox: &Aabb) -> Aabb {
+        let corners: Vec<Point3> = bbox.corners().iter().map(|&c| self.apply(c)).collect();
+        Aabb::from_points(&corners)
+    }
+}
+
+// Wraps any object so that it moves from one placement to another
+// while the shutter is open. Rays are transformed into the object's
+// own space at their time, so rotating objects blur correctly too.
+// The rotation between the placements takes the shorter way around, so
+// objects turn by at most half a turn while the shutter is open.
+pub struct Moving {
+    object: Box<dyn Hittable>,
+    start: Pose,
+    end: Pose,
+    time0: Float,
+    time1: Float,
+}
+
+impl Moving {
+    pub fn new(
+        object: Box<dyn Hittable>,
+        start: RigidTransform,
+        end: RigidTransform,
+        time0: Float,
+        time1: Float,
+    ) -> Self {
+        Self { object, start: Pose::new(&start), end: Pose::new(&end), time0, time1 }
+    }
+
+    // Placement at the given time, held still outside [time0, time1].
+    fn at(&self, time: Float) -> Pose {
+        if self.time1 <= self.time0 {
+            return self.start
+        }
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.start.interpolate(&self.end, t)
+    }
+}
+
+impl Hittable for Moving {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        self.at(r.time).hit(self.object.as_ref(), r, t_min, t_max)
+    }
+
+    // Without rotation the object sweeps linearly between its two end
+    // placements, so the union of those boxes is exact. Otherwise the
+    // swept volume is bounded conservatively: every point of the object
+    // stays within its bounding sphere, whose center keeps a fixed
+    // distance from the (linearly moving) translation.
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        let local = self.object.bounding_box(time0, time1)?;
+        let (a, b) = (self.at(time0), self.at(time1));
+
+        if a.rotation == b.rotation {
+            return Some(a.apply_box(&local).surrounding(&b.apply_box(&local)))
+        }
+
+        let reach = local.centroid().length() + 0.5 * local.extent().length();
+        let pad = Vec3::new(reach, reach, reach);
+        let path = Aabb::from_points(&[a.translation, b.translation]);
+        Some(Aabb::new(path.min - pad, path.max + pad))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::vec::Color;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+
+    fn approx_eq(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    fn unit_sphere_at(center: Point3) -> Box<dyn Hittable> {
+        let mat = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        Box::new(Sphere::new(center, 1.0, Arc::new(mat)))
+    }
+
+    #[test]
+    fn test_rotate_round_trip() {
+        let xf = RigidTransform::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(30.0, 45.0, 60.0));
+        let p = Point3::new(0.5, -2.0, 4.0);
+        assert!(approx_eq(p, xf.invert(xf.apply(p))));
+        assert!(approx_eq(Vec3::new(0.0, 0.0, -1.0), RigidTransform::new(Vec3::default(), Vec3::new(0.0, 90.0, 0.0)).rotate(Vec3::new(1.0, 0.0, 0.0))));
+    }
+
+    #[test]
+    fn test_quaternions() {
+        let xf = RigidTransform::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(30.0, 45.0, 60.0));
+        let p = Point3::new(0.5, -2.0, 4.0);
+        assert!(approx_eq(xf.apply(p), Pose::new(&xf).apply(p)));
+        assert!(approx_eq(xf.apply(p), xf.matrix().transform_point(p)));
+
+        // Halfway between two orientations the object has turned as far
+        // from the first as it has left to the second, and keeps its shape.
+        let start = Pose::new(&RigidTransform::default());
+        let end = Pose::new(&RigidTransform::new(Vec3::default(), Vec3::new(30.0, 120.0, 45.0)));
+        let mid = start.interpolate(&end, 0.5);
+        let angle = |a: &Pose, b: &Pose| (a.rotation.conjugate() * b.rotation).angle();
+        assert!((angle(&start, &mid) - angle(&mid, &end)).abs() < 1e-4);
+        assert!((2.0 * angle(&start, &mid) - angle(&start, &end)).abs() < 1e-4);
+        let (a, b) = (Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 1.0));
+        assert!(((mid.apply(a) - mid.apply(b)).length() - (a - b).length()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_hit_follows_time() {
+        let start = RigidTransform::default();
+        let end = RigidTransform::new(Vec3::new(10.0, 0.0, 0.0), Vec3::default());
+        let moving = Moving::new(unit_sphere_at(Point3::default()), start, end, 0.0, 1.0);
+
+        let dir = Vec3::new(0.0, 0.0, 1.0);
+        let early = Ray::new(&Point3::new(0.0, 0.0, -5.0), &dir, 0.0);
+        let late = Ray::new(&Point3::new(10.0, 0.0, -5.0), &dir, 1.0);
+        assert!(moving.hit(&early, 0.001, Float::INFINITY).is_some());
+        assert!(moving.hit(&Ray { time: 1.0, ..early }, 0.001, Float::INFINITY).is_none());
+
+        let rec = moving.hit(&late, 0.001, Float::INFINITY).unwrap();
+        assert!(approx_eq(Point3::new(10.0, 0.0, -1.0), rec.p));
+        assert!(approx_eq(Vec3::new(0.0, 0.0, -1.0), rec.normal));
+    }
+
+    #[test]
+    fn test_swept_bounds() {
+        let start = RigidTransform::default();
+        let end = RigidTransform::new(Vec3::new(10.0, 0.0, 0.0), Vec3::default());
+        let moving = Moving::new(unit_sphere_at(Point3::default()), start, end, 0.0, 1.0);
+        let bbox = moving.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(Point3::new(-1.0, -1.0, -1.0), bbox.min);
+        assert_eq!(Point3::new(11.0, 1.0, 1.0), bbox.max);
+
+        // An off-center object rotating about the Y axis.
+        let end = RigidTransform::new(Vec3::default(), Vec3::new(0.0, 180.0, 0.0));
+        let spinning = Moving::new(unit_sphere_at(Point3::new(3.0, 0.0, 0.0)), start, end, 0.0, 1.0);
+        let bbox = spinning.bounding_box(0.0, 1.0).unwrap();
+        for i in 0..=10 {
+            let xf = spinning.at(i as Float / 10.0);
+            let c = xf.apply(Point3::new(3.0, 0.0, 0.0));
+            assert!(c.x - 1.0 >= bbox.min.x && c.x + 1.0 <= bbox.max.x);
+            assert!(c.z - 1.0 >= bbox.min.z && c.z + 1.0 <= bbox.max.z);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::vec::{Vec3, cross, unit_vector};
+
+// An orthonormal basis around a direction `w`, for turning directions
+// sampled around the z axis into directions around `w`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: &Vec3) -> Self {
+        let w = unit_vector(*n);
+        // Any axis not too close to w will do for the cross product.
+        let a = if w.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = unit_vector(cross(&w, &a));
+        let u = cross(&w, &v);
+        Self { u, v, w }
+    }
+
+    // The vector with the given coordinates in this basis.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::dot;
+
+    #[test]
+    fn test_orthonormal() {
+        for n in [Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 3.0, 0.5)].iter() {
+            let onb = Onb::from_w(n);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)].iter() {
+                assert!(dot(a, b).abs() < 1e-6);
+                assert!((a.length() - 1.0).abs() < 1e-6);
+            }
+            assert!((onb.local(&Vec3::new(0.0, 0.0, 1.0)) - unit_vector(*n)).length() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{PI, random_float, Float};
+use crate::vec::{Vec3, Point3, dot, unit_vector};
+use crate::hittable::Hittable;
+use crate::onb::Onb;
+
+// A distribution of directions to send rays in, for importance sampling.
+// `value` is the probability density of a direction per unit solid
+// angle, and `generate` draws a direction following it.
+pub trait Pdf {
+    fn value(&self, dir: &Vec3) -> Float;
+    fn generate(&self) -> Vec3;
+}
+
+// Directions over the hemisphere around a normal, denser towards the
+// normal as the cosine, the way light leaves an ideal diffuse surface.
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(normal: &Vec3) -> Self {
+        Self { uvw: Onb::from_w(normal) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, dir: &Vec3) -> Float {
+        let cosine = dot(&unit_vector(*dir), &self.uvw.w);
+        cosine.max(0.0) / PI
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.local(&random_cosine_direction())
+    }
+}
+
+// Directions from a point towards an object, usually a light.
+pub struct HittablePdf<'a> {
+    origin: Point3,
+    object: &'a dyn Hittable,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(object: &'a dyn Hittable, origin: Point3) -> Self {
+        Self { origin, object }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, dir: &Vec3) -> Float {
+        self.object.pdf_value(&self.origin, dir)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.object.random(&self.origin)
+    }
+}
+
+// A unit vector around +z with a density of cos(theta) / pi.
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_float();
+    let r2 = random_float();
+    let phi = 2.0 * PI * r1;
+    let z = (1.0 - r2).sqrt();
+    Vec3::new(phi.cos() * r2.sqrt(), phi.sin() * r2.sqrt(), z)
+}
+
+// A unit vector around +z towards a sphere of the given radius at the
+// given squared distance, uniform over the cone the sphere fills.
+pub fn random_to_sphere(radius: Float, distance_squared: Float) -> Vec3 {
+    let r1 = random_float();
+    let r2 = random_float();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+    let phi = 2.0 * PI * r1;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::aarect::{AaRect, Plane};
+    use crate::sphere::Sphere;
+    use crate::material::DiffuseLight;
+    use crate::vec::{Color, random_unit_vector};
+    use crate::rng;
+
+    const SAMPLES: usize = 100_000;
+
+    fn light() -> Arc<DiffuseLight> {
+        Arc::new(DiffuseLight::new(Color::new(1.0, 1.0, 1.0)))
+    }
+
+    // The solid angle an object fills seen from the origin, estimated
+    // once from the pdf of directions sampled towards it, and once from
+    // the fraction of uniformly distributed directions that hit it.
+    fn solid_angles(object: &dyn Hittable) -> (Float, Float) {
+        let pdf = HittablePdf::new(object, Point3::default());
+        let importance = (0..SAMPLES).map(|_| 1.0 / pdf.value(&pdf.generate())).sum::<Float>() / SAMPLES as Float;
+        let hits = (0..SAMPLES).filter(|_| pdf.value(&random_unit_vector()) > 0.0).count();
+        (importance, 4.0 * PI * hits as Float / SAMPLES as Float)
+    }
+
+    #[test]
+    fn test_cosine_pdf() {
+        rng::seed(1);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let pdf = CosinePdf::new(&normal);
+        assert_eq!(0.0, pdf.value(&Vec3::new(0.0, -1.0, 0.0)));
+        assert!((pdf.value(&Vec3::new(0.0, 2.0, 0.0)) - 1.0 / PI).abs() < 1e-6);
+
+        // Weighting cosine over pi by its own density gives exactly one.
+        for _ in 0..1000 {
+            let dir = pdf.generate();
+            assert!(dot(&dir, &normal) >= 0.0);
+            let weight = dot(&unit_vector(dir), &normal) / PI / pdf.value(&dir);
+            assert!((weight - 1.0).abs() < 1e-3, "{}", weight);
+        }
+    }
+
+    #[test]
+    fn test_hittable_pdf() {
+        rng::seed(1);
+        let rect = AaRect::new(Plane::Xz, (-1.0, 2.0), (-0.5, 1.0), 3.0, light());
+        let (importance, uniform) = solid_angles(&rect);
+        assert!((importance - uniform).abs() < 0.02 * uniform, "{} vs {}", importance, uniform);
+
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, -3.0), 1.5, light());
+        let (importance, uniform) = solid_angles(&sphere);
+        assert!((importance - uniform).abs() < 0.02 * uniform, "{} vs {}", importance, uniform);
+
+        // Inside a sphere every direction hits it.
+        let around = Sphere::new(Point3::new(0.1, 0.0, 0.0), 2.0, light());
+        let (importance, uniform) = solid_angles(&around);
+        assert!((importance - 4.0 * PI).abs() < 1e-2 && uniform == 4.0 * PI, "{} {}", importance, uniform);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{random_float, Float};
+use crate::vec::{Vec3, Point3, dot, unit_vector};
+
+// Perlin noise, as in The Next Week: smooth random values in space,
+// from which textures such as marble and turbulence are made.
+
+const PERLIN_POINTS: usize = 256;
+
+// Gradient noise over a lattice of random unit vectors, with
+// shuffled permutations hashing lattice points to vectors.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| unit_vector(Vec3::random_bounded(-1.0, 1.0)))
+            .collect();
+        Self { gradients, perm: [permutation(), permutation(), permutation()] }
+    }
+
+    // Smooth noise in roughly [-1, 1].
+    pub fn noise(&self, p: &Point3) -> Float {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite smoothing hides the lattice.
+        let smooth = |t: Float| t * t * (3.0 - 2.0 * t);
+        let (uu, vv, ww) = (smooth(u), smooth(v), smooth(w));
+
+        let wrap = |n: i64| (n & (PERLIN_POINTS as i64 - 1)) as usize;
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let hash = self.perm[0][wrap(i + di)] ^ self.perm[1][wrap(j + dj)] ^ self.perm[2][wrap(k + dk)];
+                    let weight = Vec3::new(u - di as Float, v - dj as Float, w - dk as Float);
+                    let (a, b, c) = (di as Float, dj as Float, dk as Float);
+                    sum += (a * uu + (1.0 - a) * (1.0 - uu))
+                        * (b * vv + (1.0 - b) * (1.0 - vv))
+                        * (c * ww + (1.0 - c) * (1.0 - ww))
+                        * dot(&self.gradients[hash], &weight);
+                }
+            }
+        }
+        sum
+    }
+
+    // Sum of noise at increasing frequencies and decreasing amplitudes.
+    pub fn turbulence(&self, p: &Point3, depth: usize) -> Float {
+        let mut sum = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+        sum.abs()
+    }
+}
+
+fn permutation() -> Vec<usize> {
+    let mut perm: Vec<usize> = (0..PERLIN_POINTS).collect();
+    for i in (1..perm.len()).rev() {
+        let target = (random_float() * (i + 1) as Float) as usize;
+        perm.swap(i, target.min(i));
+    }
+    perm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perlin() {
+        let perlin = Perlin::new();
+        // Noise vanishes on the lattice and is continuous between points.
+        assert_eq!(0.0, perlin.noise(&Point3::new(3.0, 4.0, 5.0)));
+        let a = perlin.noise(&Point3::new(0.5, 0.5, 0.5));
+        let b = perlin.noise(&Point3::new(0.5001, 0.5, 0.5));
+        assert!((a - b).abs() < 1e-3);
+        for i in 0..100 {
+            let n = perlin.noise(&Point3::new(i as Float * 0.37, i as Float * 0.11, 0.3));
+            assert!(n.abs() <= 1.5);
+        }
+    }
+
+    #[test]
+    fn test_turbulence() {
+        let perlin = Perlin::new();
+        // Every octave adds detail at half the size and half the
+        // amplitude, so the sum stays bounded.
+        for i in 0..100 {
+            let p = Point3::new(i as Float * 0.37, i as Float * 0.11, 0.3);
+            let (one, seven) = (perlin.turbulence(&p, 1), perlin.turbulence(&p, 7));
+            assert_eq!(perlin.noise(&p).abs(), one);
+            assert!((0.0..=3.0).contains(&seven), "{}", seven);
+        }
+        assert_eq!(0.0, perlin.turbulence(&Point3::new(1.0, 2.0, 3.0), 7));
+    }
+}
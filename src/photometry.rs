@@ -0,0 +1,148 @@
+use crate::Float;
+use crate::consts::PI;
+
+use crate::vec::Color;
+use crate::image::Image;
+use crate::stats::luminance;
+
+// Lights given in physical units are converted to radiance whose
+// luminance is in candela per square meter (nits), so that their
+// values can be copied from lighting datasheets. Such scenes are far
+// too bright to show as they are and need a camera `Exposure`.
+
+// Luminous efficacy assumed for lights given in watts, in lumens per
+// watt. Roughly that of white LEDs; incandescent bulbs give about 15.
+pub const DEFAULT_EFFICACY: Float = 100.0;
+
+// Luminance of a surface giving off the given flux in lumens evenly
+// over its area, as a diffuse emitter lit from one side does.
+pub fn luminance_from_flux(lumens: Float, area: Float) -> Float {
+    lumens / (PI * area)
+}
+
+// The color scaled to have the given luminance in nits.
+pub fn with_luminance(color: Color, nits: Float) -> Color {
+    let y = luminance(color);
+    if y > 0.0 { color * (nits / y) } else { color }
+}
+
+// Settings of a physical camera: the f-number of the lens, the shutter
+// time in seconds and the ISO sensitivity of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exposure {
+    pub f_number: Float,
+    pub shutter: Float,
+    pub iso: Float,
+}
+
+impl Exposure {
+    // The exposure value at ISO 100. Every step halves the light let in.
+    pub fn ev100(&self) -> Float {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    // Factor turning luminance in nits into the renderer's display range,
+    // where 1 is white. The luminance that saturates the sensor follows
+    // from the saturation based ISO speed, 1.2 * 2^EV100.
+    pub fn scale(&self) -> Float {
+        1.0 / (1.2 * (2 as Float).powf(self.ev100()))
+    }
+}
+
+// Luminance automatic exposure brings the metered luminance of an
+// image to, the middle gray of a light meter.
+pub const MIDDLE_GRAY: Float = 0.18;
+
+// Luminance added before taking logarithms, so that black pixels
+// don't send the average to zero.
+const METERING_DELTA: Float = 1e-4;
+
+// How automatic exposure meters an image. Either way the luminance
+// averaged is the geometric mean, on which a few bright lights in view
+// have far less pull than on the plain mean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metering {
+    // Every pixel counts the same.
+    Average,
+    // Pixels count less the farther they are from the center, down to
+    // a tenth or so in the corners.
+    CenterWeighted,
+}
+
+impl Metering {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "average" => Some(Metering::Average),
+            "center" => Some(Metering::CenterWeighted),
+            _ => None,
+        }
+    }
+
+    // Factor bringing the image's metered luminance to middle gray,
+    // 1 for images without any light.
+    pub fn exposure(self, img: &Image) -> Float {
+        let (cx, cy) = (img.width as Float / 2.0, img.height as Float / 2.0);
+        let radius = cx.hypot(cy).max(1.0);
+        let mut log_sum = 0.0;
+        let mut weights = 0.0;
+        for (idx, &p) in img.pixels().iter().enumerate() {
+            let weight = match self {
+                Metering::Average => 1.0,
+                Metering::CenterWeighted => {
+                    let x = (idx % img.width) as Float + 0.5 - cx;
+                    let y = (idx / img.width) as Float + 0.5 - cy;
+                    (-2.3 * (x * x + y * y) / (radius * radius)).exp()
+                }
+            };
+            log_sum += weight * (METERING_DELTA + luminance(p).max(0.0)).ln();
+            weights += weight;
+        }
+        if weights == 0.0 {
+            return 1.0
+        }
+        // Black images, whose key is the delta up to rounding, are left be.
+        let key = (log_sum / weights).exp();
+        if key > 1.001 * METERING_DELTA { MIDDLE_GRAY / key } else { 1.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_units() {
+        // A 60 W equivalent bulb, 800 lm, as a 10 cm wide panel.
+        assert!((luminance_from_flux(800.0, 0.01) - 25465.0).abs() < 1.0);
+        let light = with_luminance(Color::new(1.0, 0.5, 0.25), 1000.0);
+        assert!((luminance(light) - 1000.0).abs() < 0.01);
+        assert_eq!(0.25 * light.x, light.z);
+    }
+
+    #[test]
+    fn test_metering() {
+        // A uniform image is brought to middle gray either way.
+        let mut img = Image::new(4, 4);
+        img.pixels_mut().iter_mut().for_each(|p| *p = Color::new(2.0, 2.0, 2.0));
+        for &metering in [Metering::Average, Metering::CenterWeighted].iter() {
+            assert!((2.0 * metering.exposure(&img) - MIDDLE_GRAY).abs() < 1e-3, "{:?}", metering);
+        }
+
+        // A bright corner darkens the average more than the center weighted.
+        img.set(0, 0, Color::new(1000.0, 1000.0, 1000.0));
+        assert!(Metering::Average.exposure(&img) < Metering::CenterWeighted.exposure(&img));
+        assert_eq!(1.0, Metering::Average.exposure(&Image::new(2, 2)));
+    }
+
+    #[test]
+    fn test_exposure() {
+        // Sunny 16: at f/16, 1/100 s and ISO 100 white paper in full
+        // sun, around 30000 nits, comes out close to white.
+        let sunny = Exposure { f_number: 16.0, shutter: 0.01, iso: 100.0 };
+        assert!((sunny.ev100() - 14.64).abs() < 0.01);
+        assert!((30000.0 * sunny.scale() - 1.0).abs() < 0.05);
+        // Doubling the ISO doubles the image brightness.
+        let faster = Exposure { iso: 200.0, ..sunny };
+        assert!((faster.scale() / sunny.scale() - 2.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,261 @@
+use std::io::{self, Write};
+
+use crate::inflate::zlib_decompress;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// Deflate stored blocks can hold at most this many bytes each.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// Encodes an 8-bit RGB raster (rows top to bottom) as a PNG.
+// The image data is written with uncompressed deflate blocks,
+// which keeps the encoder tiny at the cost of file size.
+pub fn encode_rgb8(width: usize, height: usize, data: &[u8]) -> Vec<u8> {
+    assert_eq!(data.len(), width * height * 3, "raster size does not match dimensions");
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SIGNATURE);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 2 (truecolor), default compression,
+    // filter method and no interlacing.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    // Every scanline is prefixed with its filter type, which is always none.
+    let stride = width * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height);
+    for row in data.chunks(stride.max(1)).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+pub fn write_rgb8<W: Write>(out: &mut W, width: usize, height: usize, data: &[u8]) -> io::Result<()> {
+    out.write_all(&encode_rgb8(width, height, data))
+}
+
+// Decodes a non-interlaced PNG of any color type and bit depth into
+// 8-bit RGBA, rows top to bottom. Images without an alpha channel
+// (and without transparency chunks, which are ignored) are opaque.
+pub fn decode_rgba8(data: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err(invalid("not a PNG file"))
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: Vec<u8> = Vec::new();
+    let mut idat = Vec::new();
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("truncated PNG chunk"))?;
+        match kind {
+            b"IHDR" if len == 13 => header = Some(body.to_vec()),
+            b"PLTE" => palette = body.to_vec(),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or_else(|| invalid("missing PNG header"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(invalid("interlaced PNGs are not supported"))
+    }
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("invalid PNG color type")),
+    };
+    if ![1, 2, 4, 8, 16].contains(&depth) {
+        return Err(invalid("invalid PNG bit depth"))
+    }
+
+    let raw = zlib_decompress(&idat)?;
+    let bits_per_pixel = channels * depth;
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let bpp = bits_per_pixel.div_ceil(8);
+    if raw.len() < (stride + 1) * height {
+        return Err(invalid("truncated PNG image data"))
+    }
+
+    let mut prev = vec![0u8; stride];
+    let mut out = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let line = &raw[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let mut cur = line[1..].to_vec();
+        unfilter(line[0], &mut cur, &prev, bpp)?;
+
+        for x in 0..width {
+            // Samples scaled to 8 bits.
+            let sample = |c: usize| -> u8 {
+                let idx = x * channels + c;
+                match depth {
+                    16 => cur[idx * 2],
+                    8 => cur[idx],
+                    _ => {
+                        let bit = idx * depth;
+                        let v = (cur[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+                        if color_type == 3 { v } else { (v as usize * 255 / ((1 << depth) - 1)) as u8 }
+                    }
+                }
+            };
+            let rgba = match color_type {
+                0 => [sample(0), sample(0), sample(0), 255],
+                2 => [sample(0), sample(1), sample(2), 255],
+                3 => {
+                    let i = sample(0) as usize * 3;
+                    let entry = palette.get(i..i + 3).ok_or_else(|| invalid("palette index out of range"))?;
+                    [entry[0], entry[1], entry[2], 255]
+                }
+                4 => [sample(0), sample(0), sample(0), sample(1)],
+                _ => [sample(0), sample(1), sample(2), sample(3)],
+            };
+            out.extend_from_slice(&rgba);
+        }
+        prev = cur;
+    }
+
+    Ok((width, height, out))
+}
+
+fn unfilter(filter: u8, cur: &mut [u8], prev: &[u8], bpp: usize) -> io::Result<()> {
+    for i in 0..cur.len() {
+        let a = if i >= bpp { cur[i - bpp] as i16 } else { 0 };
+        let b = prev[i] as i16;
+        let c = if i >= bpp { prev[i - bpp] as i16 } else { 0 };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => (a + b) / 2,
+            4 => {
+                let p = a + b - c;
+                let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+            }
+            _ => return Err(invalid("invalid PNG filter type")),
+        };
+        cur[i] = cur[i].wrapping_add(predictor as u8);
+    }
+    Ok(())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED_BLOCK + 1;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // CMF/FLG: deflate with a 32K window, fastest compression level.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(0x11e6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rgb = [255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30, 40, 50, 60, 70, 80, 90];
+        let (w, h, rgba) = decode_rgba8(&encode_rgb8(3, 2, &rgb)).unwrap();
+        assert_eq!((3, 2), (w, h));
+        let back: Vec<u8> = rgba.chunks(4).flat_map(|p| p[..3].to_vec()).collect();
+        assert_eq!(rgb.to_vec(), back);
+    }
+
+    #[test]
+    fn test_unfilter_paeth() {
+        let prev = [10, 20, 30];
+        let mut cur = [1, 2, 3];
+        unfilter(4, &mut cur, &prev, 1).unwrap();
+        assert_eq!([11, 22, 33], cur);
+    }
+
+    #[test]
+    fn test_encode_header() {
+        let png = encode_rgb8(2, 1, &[255, 0, 0, 0, 255, 0]);
+        assert_eq!(SIGNATURE, png[..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!(2, u32::from_be_bytes([png[16], png[17], png[18], png[19]]));
+        assert_eq!(1, u32::from_be_bytes([png[20], png[21], png[22], png[23]]));
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+    }
+}
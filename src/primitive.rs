@@ -0,0 +1,95 @@
+use crate::Float;
+use crate::vec::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::sphere::{Sphere, SpherePacket};
+use crate::aarect::AaRect;
+use crate::triangle::Triangle;
+
+// The objects hierarchies are mostly made of, kept by value, so that
+// testing a ray against one is a match the traversal loop can inline
+// rather than a call through a vtable. Any other object is kept boxed,
+// so scenes are still built of anything `Hittable`. Triangles make most
+// of the variants in a mesh, so they are not boxed despite their size.
+#[allow(clippy::large_enum_variant)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Packet(Box<SpherePacket>),
+    Rect(AaRect),
+    Triangle(Triangle),
+    Other(Box<dyn Hittable>),
+}
+
+impl Primitive {
+    pub fn new(obj: Box<dyn Hittable>) -> Self {
+        match obj.primitive() {
+            Some(primitive) => primitive,
+            None => Primitive::Other(obj),
+        }
+    }
+
+    fn object(&self) -> &dyn Hittable {
+        match self {
+            Primitive::Sphere(sphere) => sphere,
+            Primitive::Packet(packet) => packet.as_ref(),
+            Primitive::Rect(rect) => rect,
+            Primitive::Triangle(triangle) => triangle,
+            Primitive::Other(obj) => obj.as_ref(),
+        }
+    }
+}
+
+impl Hittable for Primitive {
+    #[inline]
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        match self {
+            Primitive::Sphere(sphere) => sphere.hit(r, t_min, t_max),
+            Primitive::Packet(packet) => packet.hit(r, t_min, t_max),
+            Primitive::Rect(rect) => rect.hit(r, t_min, t_max),
+            Primitive::Triangle(triangle) => triangle.hit(r, t_min, t_max),
+            Primitive::Other(obj) => obj.hit(r, t_min, t_max),
+        }
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        self.object().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        self.object().pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.object().random(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::INF;
+    use crate::vec::Color;
+    use crate::material::Lambertian;
+    use crate::hittable::HittableList;
+
+    #[test]
+    fn test_new() {
+        let gray = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let ball = Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, gray.clone());
+        let primitive = Primitive::new(Box::new(ball.clone()));
+        assert!(matches!(primitive, Primitive::Sphere(_)));
+        let mut list = HittableList::default();
+        list.add(Box::new(ball));
+        let other = Primitive::new(Box::new(list));
+        assert!(matches!(other, Primitive::Other(_)));
+
+        // Either way the ball is hit the same, and keeps its material.
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let (a, b) = (primitive.hit(&r, 0.001, INF).unwrap(), other.hit(&r, 0.001, INF).unwrap());
+        assert_eq!(1.5, a.t);
+        assert_eq!((a.t, a.p, a.normal), (b.t, b.p, b.normal));
+        assert!(std::ptr::addr_eq(a.mat, gray.as_ref()));
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::Cell;
+use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Float;
+use crate::vec::{Point3, Vec3};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+
+// Progress of a render, shown on stderr as a bar with the percentage
+// done, the time left and the rays traced per second. Render threads
+// report every tile they finish, and whichever one finds the line out
+// of date redraws it.
+pub struct Progress {
+    goal: Goal,
+    start: Instant,
+    tiles: AtomicUsize,
+    rays: AtomicU64,
+    // When the line was last drawn.
+    drawn: Mutex<Option<Instant>>,
+}
+
+// What a render is done at: a number of tiles, over all of its passes,
+// or a time budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Goal {
+    Tiles(usize),
+    Time(Duration),
+}
+
+// The line is redrawn at most this often, in milliseconds.
+const REFRESH: u64 = 100;
+const BAR_WIDTH: usize = 30;
+
+impl Progress {
+    pub fn new(goal: Goal) -> Self {
+        Self { goal, start: Instant::now(), tiles: AtomicUsize::new(0), rays: AtomicU64::new(0), drawn: Mutex::new(None) }
+    }
+
+    // Counts a finished tile and the rays traced for it, redrawing the
+    // line when it is due, unless another thread is already at it.
+    pub fn tile_done(&self, rays: u64) {
+        self.tiles.fetch_add(1, Ordering::Relaxed);
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        let mut drawn = match self.drawn.try_lock() {
+            Ok(drawn) => drawn,
+            Err(_) => return,
+        };
+        let now = Instant::now();
+        if drawn.is_some_and(|at| now.duration_since(at) < Duration::from_millis(REFRESH)) {
+            return
+        }
+        *drawn = Some(now);
+        self.draw(false);
+    }
+
+    // Draws the line a last time and ends it.
+    pub fn finish(&self) {
+        let _lock = self.drawn.lock();
+        self.draw(true);
+        eprintln!();
+    }
+
+    fn draw(&self, finished: bool) {
+        let elapsed = self.start.elapsed();
+        let done = match self.goal {
+            Goal::Tiles(total) => self.tiles.load(Ordering::Relaxed) as f32 / total.max(1) as f32,
+            Goal::Time(budget) => elapsed.as_secs_f32() / budget.as_secs_f32(),
+        };
+        let done = if finished { 1.0 } else { done.min(1.0) };
+        eprint!("\r{}", line(done, elapsed, self.rays.load(Ordering::Relaxed)));
+        let _ = stderr().flush();
+    }
+}
+
+// The progress line for the fraction done after the elapsed time, e.g.
+// `[#######.......]  50% 0:10 left, 2.5M rays/s`. The time left assumes
+// the rest goes as fast as what is done.
+fn line(done: f32, elapsed: Duration, rays: u64) -> String {
+    let filled = (done * BAR_WIDTH as f32) as usize;
+    let bar = format!("{}{}", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled));
+    let left = if done > 0.0 {
+        duration(elapsed.as_secs_f32() * (1.0 - done) / done)
+    } else {
+        "-:--".to_string()
+    };
+    let rate = rays as f32 / elapsed.as_secs_f32().max(1e-3);
+    format!("[{}] {:3.0}% {} left, {} rays/s  ", bar, 100.0 * done, left, si(rate))
+}
+
+// Seconds as hours, minutes and seconds, e.g. 1:02:03 or 2:03.
+fn duration(secs: f32) -> String {
+    let secs = secs.round() as u64;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 { format!("{}:{:02}:{:02}", h, m, s) } else { format!("{}:{:02}", m, s) }
+}
+
+fn si(x: f32) -> String {
+    match x {
+        x if x >= 1e9 => format!("{:.1}G", x / 1e9),
+        x if x >= 1e6 => format!("{:.1}M", x / 1e6),
+        x if x >= 1e3 => format!("{:.1}k", x / 1e3),
+        x => format!("{:.0}", x),
+    }
+}
+
+thread_local! {
+    // Rays the thread traced through a `Counted` world.
+    static RAYS: Cell<u64> = const { Cell::new(0) };
+}
+
+// The rays the thread traced since the last call.
+pub fn take_rays() -> u64 {
+    RAYS.with(|rays| rays.replace(0))
+}
+
+// A world counting every ray traced through it, camera, scattered and
+// shadow rays alike, in a counter of the thread's own.
+pub struct Counted<'a, T: Hittable>(pub &'a T);
+
+impl<T: Hittable> Hittable for Counted<'_, T> {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        RAYS.with(|rays| rays.set(rays.get() + 1));
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        self.0.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        self.0.pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.0.random(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+    use crate::vec::Color;
+
+    #[test]
+    fn test_line() {
+        let line = line(0.5, Duration::from_secs(10), 25_000_000);
+        assert_eq!("[###############...............]  50% 0:10 left, 2.5M rays/s  ", line);
+        assert_eq!("-:--", &self::line(0.0, Duration::from_secs(1), 0)[38..42]);
+        assert_eq!("1:01:05", duration(3665.0));
+        assert_eq!("950", si(950.0));
+    }
+
+    #[test]
+    fn test_counted() {
+        let ball = Sphere::new(Point3::new(0.0, 0.0, -2.0), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let counted = Counted(&ball);
+        take_rays();
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(counted.hit(&r, 0.001, Float::INFINITY).is_some());
+        assert!(counted.hit(&Ray::new(&Point3::default(), &Vec3::new(0.0, 1.0, 0.0), 0.0), 0.001, Float::INFINITY).is_none());
+        assert_eq!(2, take_rays());
+        assert_eq!(0, take_rays());
+    }
+}
@@ -1,17 +1,28 @@
+use crate::Float;
 use crate::vec::{Point3, Vec3};
 
 #[derive(Default, Clone, Copy)]
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    // The moment within the camera shutter the ray was sent at.
+    pub time: Float,
+    // Sent from the camera rather than scattered off a surface.
+    pub primary: bool,
+    // Looking for a light, or asking whether anything lies in the way of
+    // one or of a point for `Scene::occluded`.
+    pub shadow: bool,
+    // The only color channel the ray still carries, once dispersive
+    // glass has split the light into its colors.
+    pub channel: Option<usize>,
 }
 
 impl Ray {
-    pub fn new(orig: &Point3, dir: &Vec3) -> Self {
-        Self { orig: *orig, dir: *dir }
+    pub fn new(orig: &Point3, dir: &Vec3, time: Float) -> Self {
+        Self { orig: *orig, dir: *dir, time, primary: false, shadow: false, channel: None }
     }
 
-    pub fn at(&self, t: f32) -> Point3 {
+    pub fn at(&self, t: Float) -> Point3 {
         self.orig + self.dir * t
     }
 }
\ No newline at end of file
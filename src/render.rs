@@ -0,0 +1,1122 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{random_float, to_f32, INF, Float};
+use crate::vec::{Vec3, Point3, Color, dot, unit_vector, random_unit_vector};
+use crate::ray::Ray;
+use crate::pdf::{Pdf, HittablePdf};
+use crate::hittable::{HitRecord, Hittable};
+use crate::camera::Camera;
+use crate::light::{self, Light};
+use crate::image::{Accumulator, Image};
+use crate::exr::{DeepImage, SKY_DEPTH};
+use crate::affinity;
+use crate::rng;
+use crate::irradiance::IrradianceCache;
+use crate::texture::{Texture, ImageTexture};
+use crate::sphere::sphere_uv;
+use crate::sun::Sun;
+use crate::aov::{Aov, AovImages};
+use crate::filter::Filter;
+use crate::scene::Scene;
+use crate::checkerboard;
+use crate::progress::{self, Counted, Goal, Progress};
+use crate::lighttree::LightTree;
+
+const TILE_SIZE: usize = 16;
+
+// Ray bias used when the scene has no finite bounds to scale it by.
+pub const DEFAULT_RAY_BIAS: Float = 0.001;
+
+// Paths traced over the hemisphere of every irradiance cache record.
+const IRRADIANCE_SAMPLES: usize = 256;
+
+// The automatic ray bias relative to the largest coordinate in the
+// scene, a few dozen times the precision of floats at that magnitude.
+const RAY_BIAS_SCALE: Float = 1e-6;
+
+// Colors of the sky at the horizon and straight up.
+pub const SKY_HORIZON: Color = Color::new(1.0, 1.0, 1.0);
+pub const SKY_ZENITH: Color = Color::new(0.5, 0.7, 1.0);
+
+// Light arriving along rays that escape the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    // A blue-white gradient from the horizon up.
+    Sky,
+    Solid(Color),
+    // Blends from one color straight down to the other straight up.
+    Gradient { bottom: Color, top: Color },
+    // An environment map in equirectangular projection, wrapped around
+    // the scene the way textures wrap around spheres: the top row is
+    // straight up and the middle of the image looks along +x. Usually
+    // a high dynamic range photograph which then lights the scene,
+    // scaled by the intensity.
+    Environment { map: Arc<ImageTexture>, intensity: Float },
+    // Backgrounds added together, e.g. an environment map seen in
+    // reflections with a dimmed sky.
+    Combined(Vec<Background>),
+}
+
+impl Background {
+    pub fn color(&self, r: &Ray) -> Color {
+        let unit_dir = unit_vector(r.dir);
+        let gradient = |bottom: Color, top: Color| {
+            let t = 0.5 * (unit_dir.y + 1.0);
+            (1.0 - t) * bottom + t * top
+        };
+        match self {
+            Background::Sky => gradient(SKY_HORIZON, SKY_ZENITH),
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => gradient(*bottom, *top),
+            Background::Environment { map, intensity } => {
+                let (u, v) = sphere_uv(&unit_dir);
+                *intensity * map.value(u, v, &unit_dir)
+            }
+            Background::Combined(backgrounds) => backgrounds.iter().map(|bg| bg.color(r)).fold(Color::default(), |a, b| a + b),
+        }
+    }
+}
+
+// Light added at every diffuse hit whatever the scene, as if the surface
+// gave it off, for stylized renders or to brighten drafts traced with few
+// bounces. It blends from the ground color for surfaces facing straight
+// down to the sky color for those facing straight up; a constant ambient
+// term has both the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ambient {
+    pub sky: Color,
+    pub ground: Color,
+}
+
+impl Ambient {
+    pub fn color(&self, normal: &Vec3) -> Color {
+        let t = 0.5 * (unit_vector(*normal).y + 1.0);
+        (1.0 - t) * self.ground + t * self.sky
+    }
+}
+
+// Limits on the light a single path adds to a pixel, which scale down
+// brighter light to cut off fireflies, at the cost of some energy. Light
+// reaching the camera after bouncing off at most one surface is direct,
+// and limited apart from the indirect rest, so that lights and their
+// highlights can stay crisp while spikes of indirect noise are cut off.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Clamp {
+    pub direct: Option<Float>,
+    pub indirect: Option<Float>,
+}
+
+impl Clamp {
+    // The light found after the given number of bounces, with its
+    // largest channel brought down to the limit, keeping its hue.
+    pub fn apply(&self, light: Color, bounces: usize) -> Color {
+        let limit = if bounces <= 1 { self.direct } else { self.indirect };
+        let peak = light.x.max(light.y).max(light.z);
+        match limit {
+            Some(max) if peak > max => max / peak * light,
+            _ => light,
+        }
+    }
+}
+
+// A ray bias suited to the size of the world over the given time
+// interval. Rounding errors in hit points grow with their distance
+// from the origin, so a bias that works for a ring a few millimeters
+// across lets rays leaving a kilometer-wide terrain hit it again.
+pub fn auto_ray_bias<T: Hittable>(world: &T, time0: Float, time1: Float) -> Float {
+    match scene_scale(world, time0, time1) {
+        Some(scale) => (scale * RAY_BIAS_SCALE).max(Float::MIN_POSITIVE),
+        None => DEFAULT_RAY_BIAS,
+    }
+}
+
+// The largest coordinate of the world's bounds, if they are finite.
+pub fn scene_scale<T: Hittable>(world: &T, time0: Float, time1: Float) -> Option<Float> {
+    let bbox = world.bounding_box(time0, time1)?;
+    let extent = (0..3)
+        .map(|axis| bbox.min[axis].abs().max(bbox.max[axis].abs()))
+        .fold(0.0, Float::max);
+    if extent.is_finite() && extent > 0.0 { Some(extent) } else { None }
+}
+
+// Hits closer than the ray bias along the ray are ignored, so that rays
+// leaving a surface do not hit it again because of rounding errors.
+// The first specular surface along the path continues it in `split`
+// directions instead of one, averaging them; later bounces don't split.
+pub fn ray_color<T: Hittable>(r: &Ray, world: &T, settings: &Settings, depth: usize, split: usize) -> Color {
+    // We have exceeded the ray bounce limit, no more light is gathered.
+    if depth == 0 {
+        return Color::new(0.0, 0.0, 0.0)
+    }
+
+    match world.hit(r, settings.ray_bias, INF) {
+        Some(rec) => shade(r, rec, world, settings, depth, split),
+        None => settings.clamp.apply(settings.environment(r), settings.max_depth.saturating_sub(depth)),
+    }
+}
+
+// Light leaving a hit along the ray that found it. The path is followed
+// bounce by bounce in a loop rather than by recursion, carrying the
+// product of the attenuations so far as its throughput, which scales
+// the light found at every later bounce. Only splitting recurses, once.
+fn shade<'a, T: Hittable>(r: &Ray, rec: HitRecord<'a>, world: &'a T, settings: &Settings, depth: usize, split: usize) -> Color {
+    let (mut ray, mut rec, mut depth) = (*r, rec, depth);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut radiance = Color::default();
+    // Where the last bounce left from and the density of the direction
+    // it picked, if the lights were sampled from there as well.
+    let mut sampled_from: Option<(Point3, Float)> = None;
+    loop {
+        // Surfaces light given off here bounces off on its way to the
+        // camera; light reflected here bounces off one more.
+        let bounces = settings.max_depth.saturating_sub(depth);
+        let clamped = |light: Color, bounces: usize| settings.clamp.apply(light, bounces);
+
+        let mut emitted = rec.mat.emitted();
+        if let Some((origin, pdf)) = sampled_from {
+            emitted = power_heuristic(pdf, settings.lights.pdf_value(&origin, &ray.dir)) * emitted;
+        }
+        let mut reflected = Color::default();
+        if let (Some(ambient), Some(albedo)) = (settings.ambient, rec.mat.diffuse_albedo(&rec)) {
+            reflected += albedo * ambient.color(&rec.normal);
+        }
+        if let (false, Some(albedo)) = (settings.punctual_lights.is_empty(), rec.mat.diffuse_albedo(&rec)) {
+            reflected += albedo * light::direct(&settings.punctual_lights, world, &rec, ray.time, settings.ray_bias);
+        }
+        radiance += clamped(throughput * emitted, bounces) + clamped(throughput * reflected, bounces + 1);
+
+        // Diffuse surfaces seen directly are lit from the cache.
+        if depth == settings.max_depth {
+            if let (Some(cache), Some(albedo)) = (&settings.irradiance, rec.mat.diffuse_albedo(&rec)) {
+                return radiance + throughput * albedo * cached_irradiance(cache, &ray, &rec, world, settings, depth)
+            }
+        }
+
+        // Every continuation carries an equal share of the path's weight,
+        // so absorbed ones still count towards the average.
+        if split > 1 && rec.mat.is_specular() {
+            let mut sum = Color::default();
+            for _ in 0..split {
+                let mut scattered = Ray::default();
+                let mut attenuation = Color::default();
+                if rec.mat.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                    scattered.channel = scattered.channel.or(ray.channel);
+                    sum += attenuation * ray_color(&scattered, world, settings, depth-1, 1);
+                }
+            }
+            return radiance + throughput * sum / split as Float
+        }
+
+        // Lights found by the next bounce are only worth sampling when
+        // the bounce after it still counts.
+        let lit = if depth > 1 { sample_lights(&ray, &rec, world, settings) } else { None };
+        if let Some(lit) = lit {
+            radiance += clamped(throughput * lit, bounces + 1);
+        }
+
+        let mut scattered = Ray::default();
+        let mut weight = Color::default();
+        if !rec.mat.scatter(&ray, &rec, &mut weight, &mut scattered) {
+            return radiance
+        }
+        scattered.channel = scattered.channel.or(ray.channel);
+        // The bounce weighed against the lights sampled here looks for
+        // them as those shadow rays do, and the path goes on from what
+        // it finds.
+        scattered.shadow = lit.is_some();
+        sampled_from = match lit {
+            Some(_) => rec.mat.scattering(&ray, &rec, &scattered.dir).map(|(_, pdf)| (rec.p, pdf)),
+            None => None,
+        };
+        throughput *= weight;
+        depth -= 1;
+        if depth == 0 || throughput.x.max(throughput.y).max(throughput.z) < settings.min_throughput {
+            return radiance
+        }
+        match world.hit(&scattered, settings.ray_bias, INF) {
+            Some(hit) => rec = hit,
+            None => return radiance + clamped(throughput * settings.environment(&scattered), bounces + 1),
+        }
+        ray = scattered;
+    }
+}
+
+// Light reaching the hit straight from a direction drawn towards the
+// lights and scattered back along the ray, or None when the material
+// can't be sampled that way or there are no lights. Both this and the
+// direction the material scatters in may find a light; each counts by
+// the power heuristic of the densities the two had of picking it, so
+// small lights are found by sampling them, and lights seen in glossy
+// highlights by scattering, without fireflies either way. Both are
+// shadow rays, so they agree on what stands in the way of the light.
+fn sample_lights<T: Hittable>(r: &Ray, rec: &HitRecord, world: &T, settings: &Settings) -> Option<Color> {
+    if settings.lights.is_empty() {
+        return None
+    }
+    let to_lights = HittablePdf::new(settings.lights.as_ref(), rec.p);
+    let dir = to_lights.generate();
+    let (value, scattering_pdf) = rec.mat.scattering(r, rec, &dir)?;
+    let light_pdf = to_lights.value(&dir);
+    if light_pdf <= 0.0 || scattering_pdf <= 0.0 {
+        return Some(Color::default())
+    }
+    let shadow = Ray { shadow: true, ..Ray::new(&rec.p, &dir, r.time) };
+    let emitted = match world.hit(&shadow, settings.ray_bias, INF) {
+        Some(hit) => hit.mat.emitted(),
+        None => return Some(Color::default()),
+    };
+    Some(power_heuristic(light_pdf, scattering_pdf) / light_pdf * value * emitted)
+}
+
+// The weight of a sample drawn with one density when another could have
+// drawn it too, favoring whichever is larger more than in proportion.
+fn power_heuristic(pdf: Float, other: Float) -> Float {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+fn uv_color<T: Hittable>(r: &Ray, world: &T, settings: &Settings) -> Color {
+    world.hit(r, settings.ray_bias, INF).map_or(Color::default(), |rec| Color::new(rec.u, rec.v, 0.0))
+}
+
+fn focus_color<T: Hittable>(r: &Ray, world: &T, cam: &Camera, settings: &Settings) -> Color {
+    let rec = match world.hit(r, settings.ray_bias, INF) {
+        Some(rec) => rec,
+        None => return Color::default(),
+    };
+    let facing = dot(&rec.normal, &-unit_vector(r.dir)).abs();
+    let gray = 0.2 + 0.6 * facing;
+    let blur = cam.defocus(rec.p) / cam.pixel_angle(settings.height);
+    let amount = ((blur.abs() - 1.0) / (FOCUS_TINT_BLUR - 1.0)).clamp(0.0, 1.0);
+    let tint = if blur < 0.0 { Color::new(0.2, 0.4, 1.0) } else { Color::new(1.0, 0.3, 0.2) };
+    gray * ((1.0 - amount) * Color::new(1.0, 1.0, 1.0) + amount * tint)
+}
+
+// The value of an AOV for a camera ray, given its first hit if any.
+fn aov_value(aov: Aov, r: &Ray, rec: Option<&HitRecord>, settings: &Settings) -> Color {
+    match (aov, rec) {
+        (Aov::Normal, Some(rec)) => rec.normal,
+        (Aov::Depth, Some(rec)) => Color::new(1.0, 1.0, 1.0) * (rec.t * r.dir.length()),
+        (Aov::Albedo, Some(rec)) => rec.mat.diffuse_albedo(rec).unwrap_or(Color::new(1.0, 1.0, 1.0)),
+        (Aov::Albedo, None) => settings.environment(r),
+        (_, None) => Color::default(),
+    }
+}
+
+// Incoming light at a diffuse hit, averaged over its hemisphere with
+// the same cosine weighting diffuse surfaces scatter with. Taken from
+// the cache when possible, otherwise sampled and added to it.
+fn cached_irradiance<T: Hittable>(
+    cache: &IrradianceCache,
+    r: &Ray,
+    rec: &HitRecord,
+    world: &T,
+    settings: &Settings,
+    depth: usize,
+) -> Color {
+    if let Some(irradiance) = cache.lookup(&rec.p, &rec.normal) {
+        return irradiance
+    }
+
+    let mut sum = Color::default();
+    let mut inverse_distance = 0.0;
+    for _ in 0..IRRADIANCE_SAMPLES {
+        // A unit vector opposite the normal would cancel it out.
+        let mut dir = rec.normal + random_unit_vector();
+        if dir.near_zero() {
+            dir = rec.normal;
+        }
+        let ray = Ray::new(&rec.p, &dir, r.time);
+        match world.hit(&ray, settings.ray_bias, INF) {
+            Some(hit) => {
+                inverse_distance += 1.0 / (hit.t * ray.dir.length());
+                if depth > 1 {
+                    sum += shade(&ray, hit, world, settings, depth-1, settings.split);
+                }
+            }
+            None if depth > 1 => sum += settings.environment(&ray),
+            None => {}
+        }
+    }
+
+    let irradiance = sum / IRRADIANCE_SAMPLES as Float;
+    let depth = rec.t * r.dir.length();
+    cache.insert(rec.p, rec.normal, irradiance, IRRADIANCE_SAMPLES as Float / inverse_distance, depth);
+    irradiance
+}
+
+// Image-wide settings shared by every camera of a render.
+pub struct Settings {
+    pub width: usize,
+    pub height: usize,
+    // Maximum number of bounces of every path.
+    pub max_depth: usize,
+    // Paths end early once the product of their attenuations has fallen
+    // below this in every channel, as whatever light they still find adds
+    // next to nothing, e.g. deep inside stacks of glass. 0 for never.
+    pub min_throughput: Float,
+    pub background: Background,
+    // Distance along every ray before which hits are ignored.
+    pub ray_bias: Float,
+    // Number of continuations traced from the first specular bounce
+    // of every path, 1 for none. Splitting reduces noise in pixels
+    // seen through glass or mirrors, where a single continuation
+    // picks either reflection or refraction, at the cost of more rays.
+    pub split: usize,
+    // Cache of indirect lighting on diffuse surfaces seen directly,
+    // interpolated instead of traced for every sample when present.
+    // It holds for a single camera and frame.
+    pub irradiance: Option<IrradianceCache>,
+    // Number of worker threads, and whether each is pinned to its own core.
+    pub threads: usize,
+    pub pin_threads: bool,
+    // Only trace every other pixel, in a checkerboard pattern, and
+    // reconstruct the rest from their neighbors afterwards.
+    pub checkerboard: bool,
+    // Factor every sample is scaled by, e.g. by a physical camera's
+    // exposure when lights are given in physical units.
+    pub exposure: Float,
+    // Accumulate samples in half floats, halving the memory of the
+    // accumulated image with the box filter and saving a little less
+    // with the others, and write EXR output with half channels.
+    pub half_float: bool,
+    pub integrator: Integrator,
+    // Limits on the light of every path sample.
+    pub clamp: Clamp,
+    // How samples are weighted into the pixels around them.
+    pub filter: Filter,
+    // Objects standing in for the scene's lights, sampled directly from
+    // diffuse and glossy surfaces, the near and bright ones more often.
+    // When empty, paths only find lights by chance.
+    pub lights: Arc<LightTree>,
+    pub ambient: Option<Ambient>,
+    // Seen by rays escaping the scene towards it, on top of the background.
+    pub sun: Option<Sun>,
+    // Lights of no size, which diffuse hits cast a shadow ray towards
+    // each of.
+    pub punctual_lights: Vec<Light>,
+    // Part of the image being examined, whose tiles are rendered first
+    // in every pass so that it converges first under a time budget.
+    pub focus: Option<Focus>,
+    // Seed of the random numbers of every sample. Renders with the same
+    // seed and settings give the same image, except with an irradiance
+    // cache, which depends on the order its records are added in.
+    pub seed: u64,
+    // Frame of an animation being rendered, which the seed of every
+    // sample also depends on, so that frames don't share their noise.
+    pub frame: usize,
+}
+
+// What every camera sample computes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    // The light arriving along the ray, traced along random paths.
+    #[default]
+    Path,
+    // Texture coordinates of the first hit as red and green, to check
+    // the mapping of textures onto objects. Misses are black.
+    Uv,
+    // A quick gray shading of the first hit, tinted blue in front of the
+    // plane in focus and red behind it the blurrier it will render, to
+    // set the focus distance and aperture before rendering. Misses are black.
+    Focus,
+}
+
+// Blur, in pixels across, at which the tint of the focus integrator is full.
+// Up to a pixel of blur stays gray.
+pub const FOCUS_TINT_BLUR: Float = 8.0;
+
+impl Settings {
+    // The same settings at a fraction of the resolution, for previews.
+    pub fn downscaled(&self, factor: usize) -> Settings {
+        Settings {
+            width: (self.width / factor).max(2),
+            height: (self.height / factor).max(2),
+            irradiance: None,
+            checkerboard: false,
+            background: self.background.clone(),
+            lights: self.lights.clone(),
+            punctual_lights: self.punctual_lights.clone(),
+            ..*self
+        }
+    }
+
+    // Light arriving along a ray that escapes the scene.
+    pub fn environment(&self, r: &Ray) -> Color {
+        let sun = self.sun.map_or(Color::default(), |sun| sun.radiance(&r.dir));
+        self.background.color(r) + sun
+    }
+
+    // Whether the pixel is traced at all.
+    pub fn traced(&self, x: usize, y: usize) -> bool {
+        !self.checkerboard || (x + y).is_multiple_of(2)
+    }
+}
+
+// Which samples of every pixel a render should take.
+pub enum Schedule {
+    // A fixed slice of sample indices, one pass per sample.
+    Samples(Range<usize>),
+    // As many passes as fit in the given wall-clock time.
+    Budget(Duration),
+}
+
+impl Schedule {
+    // The schedule of the next of `renders` renders left to share this
+    // one, after `elapsed` of it has gone: the same samples, or an even
+    // share of the time left, so that time one render leaves over goes
+    // to the others.
+    pub fn share(&self, elapsed: Duration, renders: usize) -> Schedule {
+        match self {
+            Schedule::Samples(range) => Schedule::Samples(range.clone()),
+            Schedule::Budget(budget) => Schedule::Budget(budget.saturating_sub(elapsed) / renders.max(1) as u32),
+        }
+    }
+}
+
+// A rectangle of the image, from its top left corner, in fractions of
+// the image's width and height so that it holds at every resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Focus {
+    pub x: Float,
+    pub y: Float,
+    pub width: Float,
+    pub height: Float,
+}
+
+impl Focus {
+    // Distance in pixels from the rectangle, 0 within it.
+    fn distance(&self, x: Float, y: Float, width: usize, height: usize) -> Float {
+        let (w, h) = (width as Float, height as Float);
+        let dx = (self.x * w - x).max(x - (self.x + self.width) * w).max(0.0);
+        let dy = (self.y * h - y).max(y - (self.y + self.height) * h).max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+// A rectangle of pixels, in image coordinates with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+// Tiles in rows from the top, or from the focus outwards if there is one.
+fn tiles(width: usize, height: usize, focus: Option<Focus>) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    if let Some(focus) = focus {
+        let distance = |t: &Tile| {
+            focus.distance(t.x as Float + t.width as Float / 2.0, t.y as Float + t.height as Float / 2.0, width, height)
+        };
+        tiles.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+    }
+    tiles
+}
+
+// A camera sample of a pixel, along with the distance to the first
+// surface hit when depth was requested, and the requested AOVs.
+pub struct PixelSample {
+    x: usize,
+    y: usize,
+    // Where in the image the sample was taken, in pixels from the top left.
+    position: (Float, Float),
+    color: Color,
+    depth: Float,
+    aovs: Vec<Color>,
+}
+
+// One sample for every traced pixel of a tile.
+type TileSamples = Vec<PixelSample>;
+
+fn render_tile<T: Hittable>(
+    world: &T,
+    cam: &Camera,
+    tile: Tile,
+    settings: &Settings,
+    sample: usize,
+    with_depth: bool,
+    aovs: &[Aov],
+) -> TileSamples {
+    match cam.clipped(world) {
+        Some(clipped) => trace_tile(&Counted(&clipped), cam, tile, settings, sample, with_depth, aovs),
+        None => trace_tile(&Counted(world), cam, tile, settings, sample, with_depth, aovs),
+    }
+}
+
+fn trace_tile<T: Hittable>(
+    world: &T,
+    cam: &Camera,
+    tile: Tile,
+    settings: &Settings,
+    sample: usize,
+    with_depth: bool,
+    aovs: &[Aov],
+) -> TileSamples {
+    let (width, height) = (settings.width, settings.height);
+    let mut samples = Vec::with_capacity(tile.width * tile.height);
+    for y in tile.y..tile.y + tile.height {
+        let j = height - 1 - y;
+        for i in tile.x..tile.x + tile.width {
+            if !settings.traced(i, y) {
+                continue
+            }
+
+            rng::seed(rng::sample_seed(settings.seed, settings.frame, i, y, sample));
+            let (du, dv) = (random_float(), random_float());
+            let u = (i as Float + du) / (width - 1) as Float;
+            let v = (j as Float + dv) / (height - 1) as Float;
+            let r = cam.get_ray(u, v);
+            let color = match settings.integrator {
+                Integrator::Path => settings.exposure * ray_color(&r, world, settings, settings.max_depth, settings.split),
+                Integrator::Uv => uv_color(&r, world, settings),
+                Integrator::Focus => focus_color(&r, world, cam, settings),
+            };
+            // NaNs would turn into black specks once written out.
+            debug_assert!(!color.has_nan(), "NaN sample in pixel ({}, {})", i, y);
+
+            let (depth, aovs) = if with_depth || !aovs.is_empty() {
+                let rec = world.hit(&r, settings.ray_bias, INF);
+                let depth = rec.as_ref().map_or(SKY_DEPTH as Float, |rec| rec.t * r.dir.length());
+                (depth, aovs.iter().map(|&aov| aov_value(aov, &r, rec.as_ref(), settings)).collect())
+            } else {
+                (0.0, Vec::new())
+            };
+            let position = (i as Float + du, (y + 1) as Float - dv);
+            samples.push(PixelSample { x: i, y, position, color, depth, aovs });
+        }
+    }
+    samples
+}
+
+// Renders the world from a single camera. The image is split into
+// tiles which worker threads pull from a shared queue, and every pass
+// takes one sample per pixel. With a time budget, passes continue
+// until the budget runs out; tiles not started by then are skipped,
+// so pixels may end up with different sample counts, which the
+// accumulator normalizes individually. When a deep image is given,
+// every camera sample is also recorded in it at the distance of the
+// first surface the primary ray hits, and AOV images given receive
+// the first hit's AOVs. In checkerboard mode only half of the pixels
+// receive samples; see `checkerboard::reconstruct`.
+pub fn render<T: Hittable>(
+    world: &T,
+    cam: &Camera,
+    settings: &Settings,
+    schedule: &Schedule,
+    mut deep: Option<&mut DeepImage>,
+    mut aov_images: Option<&mut AovImages>,
+) -> Accumulator {
+    let start = Instant::now();
+    let mut acc = if settings.half_float {
+        Accumulator::half_float(settings.width, settings.height)
+    } else {
+        Accumulator::new(settings.width, settings.height)
+    };
+    let tiles = tiles(settings.width, settings.height, settings.focus);
+    let with_depth = deep.is_some();
+    let aovs = aov_images.as_ref().map_or(Vec::new(), |images| images.aovs().to_vec());
+    let aovs = &aovs;
+    let progress = Progress::new(match schedule {
+        Schedule::Samples(range) => Goal::Tiles(range.len() * tiles.len()),
+        Schedule::Budget(budget) => Goal::Time(*budget),
+    });
+    let progress = &progress;
+
+    let mut pass = 0;
+    loop {
+        if matches!(schedule, Schedule::Samples(range) if pass == range.len()) {
+            break
+        }
+
+        // The first pass always completes so that no pixel is left empty.
+        let out_of_time = || match schedule {
+            Schedule::Budget(budget) => pass > 0 && start.elapsed() >= *budget,
+            Schedule::Samples(_) => false,
+        };
+
+        // Slices of the samples of a pixel get seeds of their own.
+        let sample = match schedule {
+            Schedule::Samples(range) => range.start + pass,
+            Schedule::Budget(_) => pass,
+        };
+        let next = AtomicUsize::new(0);
+        let (tiles, next, out_of_time) = (&tiles, &next, &out_of_time);
+        let results: Vec<TileSamples> = thread::scope(|s| {
+            let workers: Vec<_> = (0..settings.threads)
+                .map(|idx| s.spawn(move || {
+                    if settings.pin_threads {
+                        affinity::pin_to_core(idx);
+                    }
+                    let mut done = Vec::new();
+                    while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if out_of_time() {
+                            break
+                        }
+                        done.push(render_tile(world, cam, tile, settings, sample, with_depth, aovs));
+                        progress.tile_done(progress::take_rays());
+                    }
+                    done
+                }))
+                .collect();
+            workers.into_iter()
+                .flat_map(|w| w.join().expect("render thread panicked"))
+                .collect()
+        });
+
+        let complete = results.len() == tiles.len();
+        for samples in results {
+            for PixelSample { x, y, position: (px, py), color, depth, aovs } in samples {
+                match settings.filter {
+                    Filter::Box => acc.add(x, y, color),
+                    filter => {
+                        acc.count(x, y);
+                        for (sx, sy, weight) in filter.footprint(px, py, settings.width, settings.height) {
+                            acc.splat(sx, sy, color, weight);
+                        }
+                    }
+                }
+                if let Some(deep) = deep.as_mut() {
+                    deep.add(x, y, to_f32(depth), color);
+                }
+                if let Some(images) = aov_images.as_mut() {
+                    images.add(x, y, &aovs);
+                }
+            }
+        }
+
+        pass += 1;
+        if !complete {
+            break
+        }
+    }
+    progress.finish();
+
+    acc
+}
+
+// Renders scenes into images for programs using the renderer as a
+// library. Every render is taken from one of the scene's cameras, the
+// first by default, and the scene's objects are traced as they are, so
+// scenes of many objects render much faster with them in a `Bvh`.
+pub struct Renderer {
+    schedule: Schedule,
+    camera: usize,
+}
+
+impl Renderer {
+    pub fn new(schedule: Schedule) -> Self {
+        Self { schedule, camera: 0 }
+    }
+
+    pub fn with_camera(mut self, index: usize) -> Self {
+        self.camera = index;
+        self
+    }
+
+    // The image is left black when the scene has no such camera.
+    pub fn render(&self, scene: &Scene, settings: &Settings) -> Image {
+        let cam = match scene.cameras.get(self.camera) {
+            Some(cam) => cam,
+            None => return Image::new(settings.width, settings.height),
+        };
+        let mut img = render(&scene.world, cam, settings, &self.schedule, None, None).resolve();
+        if settings.checkerboard {
+            let guides = checkerboard::guides(&scene.world, cam, settings);
+            checkerboard::reconstruct(&mut img, &guides, |x, y| settings.traced(x, y));
+        }
+        img
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::vec::{Vec3, Point3, reflect};
+    use crate::sphere::Sphere;
+    use crate::hittable::{HitRecord, HittableList};
+    use crate::material::Material;
+    use crate::image::Image;
+    use crate::material::Lambertian;
+    use crate::PI;
+
+    #[test]
+    fn test_tiles_cover_image() {
+        let tiles = tiles(40, 20, None);
+        assert_eq!(6, tiles.len());
+        assert_eq!(Tile { x: 32, y: 16, width: 8, height: 4 }, tiles[5]);
+
+        let area: usize = tiles.iter().map(|t| t.width * t.height).sum();
+        assert_eq!(40 * 20, area);
+    }
+
+    #[test]
+    fn test_focus_tiles() {
+        // Tiles around the bottom right corner come first, the farthest last.
+        let corner = Focus { x: 0.9, y: 0.9, width: 0.0, height: 0.0 };
+        let ordered = tiles(40, 20, Some(corner));
+        assert_eq!(Tile { x: 32, y: 16, width: 8, height: 4 }, ordered[0]);
+        assert_eq!(Tile { x: 0, y: 0, width: 16, height: 16 }, ordered[5]);
+
+        // All tiles overlapping the focus rank the same, in rows.
+        let middle = Focus { x: 0.0, y: 0.0, width: 1.0, height: 0.1 };
+        let top: Vec<usize> = tiles(40, 20, Some(middle)).iter().take(3).map(|t| t.x).collect();
+        assert_eq!(vec![0, 16, 32], top);
+    }
+
+    // A mirror that counts how often it scatters.
+    struct CountingMirror(Arc<AtomicUsize>);
+
+    impl Material for CountingMirror {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            *attenuation = Color::new(0.5, 0.5, 0.5);
+            *scattered = Ray::new(&rec.p, &reflect(&r_in.dir, &rec.normal), r_in.time);
+            true
+        }
+
+        fn is_specular(&self) -> bool {
+            true
+        }
+    }
+
+    fn settings(split: usize) -> Settings {
+        Settings {
+            width: 1,
+            height: 1,
+            max_depth: 10,
+            min_throughput: 0.0,
+            background: Background::Solid(Color::new(1.0, 1.0, 1.0)),
+            ray_bias: 0.001,
+            split,
+            irradiance: None,
+            threads: 1,
+            pin_threads: false,
+            checkerboard: false,
+            exposure: 1.0,
+            half_float: false,
+            integrator: Integrator::Path,
+            clamp: Clamp::default(),
+            filter: Filter::Box,
+            lights: Arc::default(),
+            ambient: None,
+            sun: None,
+            punctual_lights: Vec::new(),
+            focus: None,
+            seed: 0,
+            frame: 0,
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(CountingMirror(count.clone())));
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        // Only the first bounce splits, and the average keeps the path's weight.
+        assert_eq!(Color::new(0.5, 0.5, 0.5), ray_color(&r, &world, &settings(1), 10, 1));
+        assert_eq!(1, count.swap(0, Ordering::SeqCst));
+        assert_eq!(Color::new(0.5, 0.5, 0.5), ray_color(&r, &world, &settings(4), 10, 4));
+        assert_eq!(4, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_min_throughput() {
+        // Inside a mirror ball, every bounce halves the path's throughput.
+        let count = Arc::new(AtomicUsize::new(0));
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(CountingMirror(count.clone())));
+        let r = Ray::new(&Point3::default(), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        ray_color(&r, &world, &settings(1), 10, 1);
+        assert_eq!(10, count.swap(0, Ordering::SeqCst));
+        ray_color(&r, &world, &Settings { min_throughput: 0.1, ..settings(1) }, 10, 1);
+        assert_eq!(4, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_backgrounds() {
+        let ray = |x, y, z| Ray::new(&Point3::default(), &Vec3::new(x, y, z), 0.0);
+        let gradient = Background::Gradient { bottom: Color::new(1.0, 0.0, 0.0), top: Color::new(0.0, 0.0, 1.0) };
+        assert_eq!(Color::new(0.0, 0.0, 1.0), gradient.color(&ray(0.0, 2.0, 0.0)));
+        assert_eq!(Color::new(0.5, 0.0, 0.5), gradient.color(&ray(1.0, 0.0, 0.0)));
+
+        // One column of the map for every quarter turn about the y axis.
+        let mut img = Image::new(4, 1);
+        for x in 0..4 {
+            img.set(x, 0, Color::new(x as Float, 0.0, 0.0));
+        }
+        let env = Background::Environment { map: Arc::new(ImageTexture::from_image(&img)), intensity: 1.0 };
+        assert_eq!(0.0, env.color(&ray(-1.0, 0.0, 1.0)).x);
+        assert_eq!(1.0, env.color(&ray(1.0, 0.0, 1.0)).x);
+        assert_eq!(2.0, env.color(&ray(1.0, 0.0, -1.0)).x);
+        assert_eq!(3.0, env.color(&ray(-1.0, 0.0, -1.0)).x);
+
+        let dimmed = match env.clone() {
+            Background::Environment { map, .. } => Background::Environment { map, intensity: 0.5 },
+            _ => unreachable!(),
+        };
+        let combined = Background::Combined(vec![dimmed, Background::Solid(Color::new(0.0, 1.0, 0.0))]);
+        assert_eq!(Color::new(1.5, 1.0, 0.0), combined.color(&ray(-1.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn test_seeded_render() {
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let cam = Camera::new(40.0, 1.0, 0.1, 5.0, Point3::new(0.0, 0.0, 5.0), Point3::default(), Vec3::new(0.0, 1.0, 0.0));
+        let image = |seed, frame, threads, samples| {
+            let settings = Settings { width: 8, height: 8, threads, seed, frame, ..settings(1) };
+            render(&world, &cam, &settings, &Schedule::Samples(samples), None, None).resolve()
+        };
+        let first = image(7, 0, 2, 0..2);
+        assert!(first.pixels() == image(7, 0, 2, 0..2).pixels());
+        assert!(first.pixels() != image(8, 0, 2, 0..2).pixels());
+        // Slices of the samples, and frames, differ from each other.
+        assert!(image(7, 0, 2, 0..1).pixels() != image(7, 0, 2, 1..2).pixels());
+        assert!(first.pixels() != image(7, 1, 2, 0..2).pixels());
+        // However many threads take the tiles, in whatever order.
+        assert!(first.pixels() == image(7, 0, 1, 0..2).pixels());
+        assert!(first.pixels() == image(7, 0, 3, 0..2).pixels());
+    }
+
+    #[test]
+    fn test_aovs() {
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.6))));
+        let cam = Camera::new(1.0, 1.0, 0.0, 5.0, Point3::new(0.0, 0.0, 5.0), Point3::default(), Vec3::new(0.0, 1.0, 0.0));
+        let settings = Settings { width: 3, height: 3, background: Background::Solid(Color::new(1.0, 0.0, 0.0)), ..settings(1) };
+        let mut images = AovImages::new(&[Aov::Depth, Aov::Normal, Aov::Albedo], 3, 3, false);
+        render(&world, &cam, &settings, &Schedule::Samples(0..1), None, Some(&mut images));
+
+        // The center pixel looks straight at the front of the sphere.
+        let center = |img: &Image| img.pixels()[4];
+        let resolved = images.resolve();
+        assert!((center(&resolved[0].1).x - 4.0).abs() < 1e-2);
+        assert!((center(&resolved[1].1) - Vec3::new(0.0, 0.0, 1.0)).length() < 5e-2);
+        assert_eq!(Color::new(0.2, 0.4, 0.6), center(&resolved[2].1));
+
+        // Rays escaping the scene see the background.
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert_eq!(Color::new(1.0, 0.0, 0.0), aov_value(Aov::Albedo, &r, None, &settings));
+        assert_eq!(Color::default(), aov_value(Aov::Depth, &r, None, &settings));
+    }
+
+    #[test]
+    fn test_focus_color() {
+        // Spheres in front of, at and behind the plane in focus.
+        let gray = || Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let cam = Camera::new(40.0, 1.0, 0.5, 5.0, Point3::new(0.0, 0.0, 5.0), Point3::default(), Vec3::new(0.0, 1.0, 0.0));
+        let settings = Settings { width: 100, height: 100, ..settings(1) };
+        let color = |z: Float| {
+            let world = Sphere::new(Point3::new(0.0, 0.0, z), 0.1, gray());
+            let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            focus_color(&r, &world, &cam, &settings)
+        };
+        let front = color(3.0);
+        assert!(front.z > front.x);
+        assert!((color(0.1) - Color::new(0.8, 0.8, 0.8)).length() < 1e-4);
+        let behind = color(-10.0);
+        assert!(behind.x > behind.z);
+    }
+
+    #[test]
+    fn test_filtered_render() {
+        // Splatting keeps a uniform image uniform, edges included, since
+        // every pixel is normalized by the weights it received.
+        let world = HittableList::default();
+        let cam = Camera::new(40.0, 2.0, 0.0, 1.0, Point3::default(), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+        let gray = Color::new(0.5, 0.5, 0.5);
+        for &filter in [Filter::Tent(1.0), Filter::Gaussian(2.0)].iter() {
+            let settings = Settings { width: 6, height: 3, background: Background::Solid(gray), filter, ..settings(1) };
+            let img = render(&world, &cam, &settings, &Schedule::Samples(0..2), None, None).resolve();
+            assert!(img.pixels().iter().all(|&p| (p - gray).length() < 1e-6), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_renderer() {
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))));
+        let cam = Camera::new(40.0, 1.0, 0.0, 5.0, Point3::new(0.0, 0.0, 5.0), Point3::default(), Vec3::new(0.0, 1.0, 0.0));
+        let scene = Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() };
+        let settings = Settings { width: 8, height: 8, seed: 3, ..settings(1) };
+
+        let renderer = Renderer::new(Schedule::Samples(0..2));
+        let img = renderer.render(&scene, &settings);
+        assert!(img.pixels() == render(&scene.world, &cam, &settings, &Schedule::Samples(0..2), None, None).resolve().pixels());
+        assert!(img.pixels().iter().all(|p| p.x > 0.0));
+        assert!(renderer.with_camera(1).render(&scene, &settings).pixels().iter().all(|&p| p == Color::default()));
+    }
+
+    #[test]
+    fn test_light_sampling() {
+        use crate::aarect::{AaRect, Plane};
+        use crate::material::DiffuseLight;
+
+        // A floor lit only by a small panel straight above it, with
+        // no bounces after the first so only direct light counts.
+        rng::seed(3);
+        let panel = || Box::new(AaRect::new(Plane::Xz, (-0.1, 0.1), (-0.1, 0.1), 1.0, Arc::new(DiffuseLight::new(Color::new(10.0, 10.0, 10.0)))));
+        let floor = AaRect::new(Plane::Xz, (-5.0, 5.0), (-5.0, 5.0), 0.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let world = HittableList { objects: vec![Box::new(floor), panel()] };
+        let r = Ray::new(&Point3::new(1.0, 1.0, 0.0), &Vec3::new(-1.0, -1.0, 0.0), 0.0);
+        let estimate = |settings: &Settings| {
+            let samples: Vec<Float> = (0..20000).map(|_| ray_color(&r, &world, settings, 2, 1).x).collect();
+            let mean = samples.iter().sum::<Float>() / samples.len() as Float;
+            let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<Float>() / samples.len() as Float;
+            (mean, variance)
+        };
+
+        let dark = Settings { background: Background::Solid(Color::default()), ..settings(1) };
+        let (mean, variance) = estimate(&dark);
+        let lights = Arc::new(LightTree::new(HittableList { objects: vec![panel()] }));
+        let (sampled_mean, sampled_variance) = estimate(&Settings { lights, ..dark });
+
+        // Albedo over pi times the panel's radiance times its projected
+        // solid angle, a little less than its area at this distance.
+        let expected = 0.5 / PI * 10.0 * 0.039_48;
+        assert!((sampled_mean - expected).abs() < 0.03 * expected, "{} vs {}", sampled_mean, expected);
+        assert!((mean - expected).abs() < 0.2 * expected, "{} vs {}", mean, expected);
+        assert!(sampled_variance < 0.1 * variance, "{} vs {}", sampled_variance, variance);
+    }
+
+    #[test]
+    fn test_glossy_light_sampling() {
+        use crate::aarect::{AaRect, Plane};
+        use crate::material::{DiffuseLight, MicrofacetMetal};
+
+        // A rough metal floor reflecting a small panel in a highlight,
+        // which the floor is seen at the mirror angle of.
+        rng::seed(4);
+        let panel = || Box::new(AaRect::new(Plane::Xz, (-0.6, -0.4), (-0.1, 0.1), 1.0, Arc::new(DiffuseLight::new(Color::new(10.0, 10.0, 10.0)))));
+        let floor = AaRect::new(Plane::Xz, (-5.0, 5.0), (-5.0, 5.0), 0.0, Arc::new(MicrofacetMetal::new(Color::new(0.9, 0.9, 0.9), 0.3)));
+        let world = HittableList { objects: vec![Box::new(floor), panel()] };
+        let r = Ray::new(&Point3::new(0.5, 1.0, 0.0), &Vec3::new(-1.0, -2.0, 0.0), 0.0);
+        let estimate = |settings: &Settings| {
+            let samples: Vec<Float> = (0..40000).map(|_| ray_color(&r, &world, settings, 2, 1).x).collect();
+            let mean = samples.iter().sum::<Float>() / samples.len() as Float;
+            let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<Float>() / samples.len() as Float;
+            (mean, variance)
+        };
+
+        // Sampling the panel as well as the floor's reflection finds the
+        // same light with less noise.
+        let dark = Settings { background: Background::Solid(Color::default()), ..settings(1) };
+        let (mean, variance) = estimate(&dark);
+        let lights = Arc::new(LightTree::new(HittableList { objects: vec![panel()] }));
+        let (sampled_mean, sampled_variance) = estimate(&Settings { lights, ..dark });
+        assert!((sampled_mean - mean).abs() < 0.05 * mean, "{} vs {}", sampled_mean, mean);
+        assert!(sampled_variance < 0.1 * variance, "{} vs {}", sampled_variance, variance);
+    }
+
+    #[test]
+    fn test_shadow_culling() {
+        use crate::aarect::{AaRect, Plane};
+        use crate::material::DiffuseLight;
+        use crate::triangle::{Triangle, Culling};
+
+        // A floor under a panel, with a triangle in between that faces
+        // the panel, seen from below the triangle.
+        let panel = || Box::new(AaRect::new(Plane::Xz, (-0.1, 0.1), (-0.1, 0.1), 1.0, Arc::new(DiffuseLight::new(Color::new(10.0, 10.0, 10.0)))));
+        let floor = || Box::new(AaRect::new(Plane::Xz, (-5.0, 5.0), (-5.0, 5.0), 0.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))));
+        let roof = |shadow| {
+            let vertices = [Point3::new(-3.0, 0.5, -3.0), Point3::new(-3.0, 0.5, 3.0), Point3::new(3.0, 0.5, 0.0)];
+            let triangle = Triangle::new(vertices, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+            Box::new(triangle.with_culling(Culling { camera: false, shadow }))
+        };
+        let r = Ray::new(&Point3::new(1.0, 0.25, 0.0), &Vec3::new(-1.0, -0.25, 0.0), 0.0);
+        let lights = Arc::new(LightTree::new(HittableList { objects: vec![panel()] }));
+        let settings = Settings { background: Background::Solid(Color::default()), lights, ..settings(1) };
+        let estimate = |world: &HittableList| {
+            rng::seed(6);
+            (0..2000).map(|_| ray_color(&r, world, &settings, 2, 1).x).sum::<Float>()
+        };
+
+        // Both the sampled light and the bounce weighed against it pass
+        // through the back of a culled triangle, as if it weren't there.
+        let open = estimate(&HittableList { objects: vec![floor(), panel()] });
+        assert!(open > 0.0);
+        assert_eq!(open, estimate(&HittableList { objects: vec![floor(), panel(), roof(true)] }));
+        assert_eq!(0.0, estimate(&HittableList { objects: vec![floor(), panel(), roof(false)] }));
+    }
+
+    #[test]
+    fn test_share_schedule() {
+        let budget = Schedule::Budget(Duration::from_secs(60));
+        assert!(matches!(budget.share(Duration::ZERO, 3), Schedule::Budget(d) if d == Duration::from_secs(20)));
+        assert!(matches!(budget.share(Duration::from_secs(30), 2), Schedule::Budget(d) if d == Duration::from_secs(15)));
+        assert!(matches!(budget.share(Duration::from_secs(90), 1), Schedule::Budget(d) if d == Duration::ZERO));
+        assert!(matches!(Schedule::Samples(2..5).share(Duration::from_secs(30), 2), Schedule::Samples(r) if r == (2..5)));
+    }
+
+    #[test]
+    fn test_clamp() {
+        let clamp = Clamp { direct: None, indirect: Some(2.0) };
+        let bright = Color::new(8.0, 4.0, 1.0);
+        assert_eq!(bright, clamp.apply(bright, 1));
+        assert_eq!(Color::new(2.0, 1.0, 0.25), clamp.apply(bright, 2));
+
+        // A light seen directly keeps its brightness unless direct
+        // light is clamped too.
+        use crate::material::DiffuseLight;
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(DiffuseLight::new(bright)));
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert_eq!(bright, ray_color(&r, &world, &Settings { clamp, ..settings(1) }, 10, 1));
+        let clamp = Clamp { direct: Some(4.0), ..clamp };
+        assert_eq!(Color::new(4.0, 2.0, 0.5), ray_color(&r, &world, &Settings { clamp, ..settings(1) }, 10, 1));
+    }
+
+    #[test]
+    fn test_ambient() {
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let ambient = Ambient { sky: Color::new(1.0, 1.0, 1.0), ground: Color::new(0.0, 0.0, 1.0) };
+        let lit = Settings { ambient: Some(ambient), ..settings(1) };
+
+        // A single bounce gathers nothing but the ambient light.
+        let top = Ray::new(&Point3::new(0.0, 5.0, 0.0), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert_eq!(Color::new(0.5, 0.5, 0.5), ray_color(&top, &world, &lit, 1, 1));
+        let side = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert_eq!(Color::new(0.25, 0.25, 0.5), ray_color(&side, &world, &lit, 1, 1));
+        assert_eq!(Color::default(), ray_color(&side, &world, &settings(1), 1, 1));
+    }
+
+    #[test]
+    fn test_uv_integrator() {
+        let world = Sphere::new(Point3::default(), 1.0, Arc::new(CountingMirror(Arc::default())));
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let uv = uv_color(&r, &world, &settings(1));
+        assert!((uv - Color::new(0.25, 0.5, 0.0)).length() < 1e-5, "{:?}", uv);
+        let miss = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(Color::default(), uv_color(&miss, &world, &settings(1)));
+    }
+
+    #[test]
+    fn test_auto_ray_bias() {
+        let sphere = |center, radius| -> Box<dyn Hittable> {
+            Box::new(Sphere::new(center, radius, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))))
+        };
+        let ring = HittableList { objects: vec![sphere(Point3::default(), 0.01)] };
+        let terrain = HittableList { objects: vec![sphere(Point3::new(0.0, -5000.0, 0.0), 5000.0)] };
+        let ring_bias = auto_ray_bias(&ring, 0.0, 0.0);
+        let terrain_bias = auto_ray_bias(&terrain, 0.0, 0.0);
+        assert!(ring_bias < 1e-6);
+        assert!((terrain_bias - 0.01).abs() < 1e-6);
+
+        let empty = HittableList { objects: Vec::new() };
+        assert_eq!(DEFAULT_RAY_BIAS, auto_ray_bias(&empty, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,91 @@
+use std::cell::Cell;
+
+use crate::Float;
+
+// Every thread draws its random numbers from a SplitMix64 generator of
+// its own. Renders reseed it for every sample with a hash of the render
+// seed, the frame, and the sample's pixel and index, so that every pixel
+// has streams of its own: the image doesn't depend on the number of
+// threads or the order tiles are taken in, the same seed gives the same
+// image, and any slice of the samples of any frame can be rendered again
+// on its own, as by another machine or when resuming a render.
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(rand::random());
+}
+
+pub fn seed(seed: u64) {
+    STATE.with(|s| s.set(seed));
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn next_u64() -> u64 {
+    STATE.with(|s| {
+        let state = s.get().wrapping_add(GOLDEN_GAMMA);
+        s.set(state);
+        mix(state)
+    })
+}
+
+// Uniform in [0, 1), from the top 24 bits, or 53 with the `f64`
+// feature, all a float holds.
+pub fn next_float() -> Float {
+    let bits = Float::MANTISSA_DIGITS;
+    (next_u64() >> (64 - bits)) as Float / (1u64 << bits) as Float
+}
+
+// The PCG hash: a step of PCG's 64-bit LCG followed by its RXS-M-XS
+// output permutation, which spreads every bit of the input over all of
+// the output. Both steps are one-to-one, so distinct inputs never collide.
+fn pcg(v: u64) -> u64 {
+    let state = v.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+    let word = ((state >> ((state >> 59) + 5)) ^ state).wrapping_mul(12_605_985_483_623_352_807);
+    (word >> 43) ^ word
+}
+
+// Seed of a single sample, with the values hashed one after another
+// so that nearby pixels, frames and samples get unrelated sequences.
+pub fn sample_seed(seed: u64, frame: usize, x: usize, y: usize, sample: usize) -> u64 {
+    [frame, x, y, sample].iter().fold(pcg(seed), |h, &v| pcg(h ^ v as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequence() {
+        seed(42);
+        let a: Vec<u64> = (0..4).map(|_| next_u64()).collect();
+        seed(42);
+        let b: Vec<u64> = (0..4).map(|_| next_u64()).collect();
+        assert_eq!(a, b);
+        seed(43);
+        assert_ne!(a[0], next_u64());
+
+        let mean = (0..10000).map(|_| next_float()).sum::<Float>() / 10000.0;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+        assert!((0..10000).map(|_| next_float()).all(|x| (0.0..1.0).contains(&x)));
+    }
+
+    #[test]
+    fn test_sample_seed() {
+        let s = sample_seed(7, 0, 10, 20, 3);
+        assert_eq!(s, sample_seed(7, 0, 10, 20, 3));
+        assert_ne!(s, sample_seed(8, 0, 10, 20, 3));
+        assert_ne!(s, sample_seed(7, 1, 10, 20, 3));
+        assert_ne!(s, sample_seed(7, 0, 20, 10, 3));
+        assert_ne!(s, sample_seed(7, 0, 10, 20, 4));
+
+        // Neighboring pixels' seeds share no more bits than chance would.
+        let differing = (0..1000).map(|x| (sample_seed(7, 0, x, 0, 0) ^ sample_seed(7, 0, x + 1, 0, 0)).count_ones()).sum::<u32>();
+        assert!((differing as Float / 1000.0 - 32.0).abs() < 1.0, "{}", differing);
+    }
+}
@@ -0,0 +1,154 @@
+// Helpers for placing objects over a rectangular region of the ground,
+// spanning `x` and `z`. Points are given as (x, z) pairs.
+
+use crate::consts::{PI, SQRT_2};
+
+use crate::{random_float, Float};
+
+// New points tried around every point of a Poisson disk
+// distribution before giving up on its neighborhood.
+const POISSON_ATTEMPTS: usize = 30;
+
+// One point in every square cell of the given size, moved away from
+// the cell's corner by up to `jitter` times the size in each direction.
+// Without jitter the points form a regular grid; with full jitter they
+// may still nearly touch across cell borders.
+pub fn jittered_grid(x: (Float, Float), z: (Float, Float), spacing: Float, jitter: Float) -> Vec<(Float, Float)> {
+    let cols = ((x.1 - x.0) / spacing).floor() as usize;
+    let rows = ((z.1 - z.0) / spacing).floor() as usize;
+    let mut points = Vec::with_capacity(cols * rows);
+    for i in 0..cols {
+        for j in 0..rows {
+            points.push((
+                x.0 + spacing * (i as Float + jitter * random_float()),
+                z.0 + spacing * (j as Float + jitter * random_float()),
+            ));
+        }
+    }
+    points
+}
+
+// Random points no closer to each other than `min_distance`, filling the
+// region evenly without clumps or gaps, using Bridson's algorithm: new
+// points are tried in the ring around existing ones, and a background
+// grid holding at most one point per cell finds the neighbors to check.
+pub fn poisson_disk(x: (Float, Float), z: (Float, Float), min_distance: Float) -> Vec<(Float, Float)> {
+    let cell = min_distance / SQRT_2;
+    let cols = ((x.1 - x.0) / cell).ceil().max(1.0) as usize;
+    let rows = ((z.1 - z.0) / cell).ceil().max(1.0) as usize;
+    let cell_of = |p: (Float, Float)| {
+        let i = (((p.0 - x.0) / cell) as usize).min(cols - 1);
+        let j = (((p.1 - z.0) / cell) as usize).min(rows - 1);
+        (i, j)
+    };
+
+    let mut grid: Vec<Option<usize>> = vec![None; cols * rows];
+    let mut points = Vec::new();
+    let mut active = Vec::new();
+    let mut next = Some((x.0 + random_float() * (x.1 - x.0), z.0 + random_float() * (z.1 - z.0)));
+    loop {
+        if let Some(p) = next.take() {
+            let (i, j) = cell_of(p);
+            grid[j * cols + i] = Some(points.len());
+            active.push(points.len());
+            points.push(p);
+        }
+        if active.is_empty() {
+            break
+        }
+
+        let slot = ((random_float() * active.len() as Float) as usize).min(active.len() - 1);
+        let center = points[active[slot]];
+
+        let mut found = None;
+        for _ in 0..POISSON_ATTEMPTS {
+            let angle = 2.0 * PI * random_float();
+            let distance = min_distance * (1.0 + random_float());
+            let p = (center.0 + distance * angle.cos(), center.1 + distance * angle.sin());
+            if p.0 < x.0 || p.0 >= x.1 || p.1 < z.0 || p.1 >= z.1 {
+                continue
+            }
+
+            // Points closer than the minimum distance are at most two cells away.
+            let (i, j) = cell_of(p);
+            let clear = (i.saturating_sub(2)..(i + 3).min(cols))
+                .flat_map(|i| (j.saturating_sub(2)..(j + 3).min(rows)).map(move |j| (i, j)))
+                .filter_map(|(i, j)| grid[j * cols + i])
+                .all(|k| {
+                    let (dx, dz) = (points[k].0 - p.0, points[k].1 - p.1);
+                    dx * dx + dz * dz >= min_distance * min_distance
+                });
+            if clear {
+                found = Some(p);
+                break
+            }
+        }
+
+        match found {
+            Some(p) => next = Some(p),
+            None => {
+                active.swap_remove(slot);
+            }
+        }
+    }
+    points
+}
+
+// Keeps every point with the probability given by the density map at
+// it, clamped to [0, 1], so objects thin out where the density is low.
+pub fn thinned<F>(points: Vec<(Float, Float)>, density: F) -> Vec<(Float, Float)>
+where
+    F: Fn(Float, Float) -> Float,
+{
+    points.into_iter()
+        .filter(|&(x, z)| random_float() < density(x, z).clamp(0.0, 1.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jittered_grid() {
+        let points = jittered_grid((0.0, 4.0), (-1.0, 1.0), 1.0, 0.5);
+        assert_eq!(8, points.len());
+        for (i, &(x, z)) in points.iter().enumerate() {
+            let (col, row) = ((i / 2) as Float, (i % 2) as Float - 1.0);
+            assert!(x >= col && x <= col + 0.5, "{}", x);
+            assert!(z >= row && z <= row + 0.5, "{}", z);
+        }
+    }
+
+    #[test]
+    fn test_poisson_disk() {
+        let points = poisson_disk((-5.0, 5.0), (0.0, 10.0), 1.0);
+        for (i, a) in points.iter().enumerate() {
+            assert!(a.0 >= -5.0 && a.0 < 5.0 && a.1 >= 0.0 && a.1 < 10.0);
+            for b in points[i + 1..].iter() {
+                let d = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+                assert!(d >= 1.0, "{:?} and {:?} are {} apart", a, b, d);
+            }
+        }
+        // The region is filled: no spot is farther than two
+        // minimum distances away from the nearest point.
+        assert!(points.len() > 40, "{}", points.len());
+        for &spot in jittered_grid((-5.0, 5.0), (0.0, 10.0), 1.0, 1.0).iter() {
+            let nearest = points.iter()
+                .map(|p| ((p.0 - spot.0).powi(2) + (p.1 - spot.1).powi(2)).sqrt())
+                .fold(Float::INFINITY, Float::min);
+            assert!(nearest < 2.0, "{:?} is {} from the nearest point", spot, nearest);
+        }
+    }
+
+    #[test]
+    fn test_thinned() {
+        let points = jittered_grid((0.0, 100.0), (0.0, 100.0), 1.0, 1.0);
+        assert!(thinned(points.clone(), |_, _| 0.0).is_empty());
+        assert_eq!(points.len(), thinned(points.clone(), |_, _| 2.0).len());
+        // Only the left half, where the density is one.
+        let half = thinned(points, |x, _| if x < 50.0 { 1.0 } else { 0.0 });
+        assert_eq!(5000, half.len());
+        assert!(half.iter().all(|p| p.0 < 50.0));
+    }
+}
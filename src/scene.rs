@@ -0,0 +1,727 @@
+use std::sync::Arc;
+
+use crate::{random_float, INF, PI, Float};
+use crate::vec::{Vec3, Point3, Color, dot, unit_vector};
+use crate::sphere::{self, Sphere};
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::aarect::{AaRect, Plane, cuboid};
+use crate::camera::Camera;
+use crate::material::{Metal, Lambertian, Dielectric, DiffuseLight, Material};
+use crate::motion::{Moving, RigidTransform};
+use crate::instance::Instance;
+use crate::medium::ConstantMedium;
+use crate::scatter;
+use crate::bvh::Bvh;
+use crate::sun::Sun;
+use crate::light::Light;
+use crate::render::{Background, DEFAULT_RAY_BIAS};
+use crate::texture::{CheckerTexture, MarbleTexture, ImageTexture};
+use crate::image::Image;
+
+// Everything needed to render: the geometry, which is built
+// once, and every viewpoint it should be rendered from.
+pub struct Scene {
+    pub world: HittableList,
+    pub cameras: Vec<Camera>,
+    pub background: Background,
+    // Copies of the lights worth sampling directly, which may be empty.
+    pub lights: HittableList,
+    pub sun: Option<Sun>,
+    // Lights of no size, only found by shadow rays.
+    pub punctual_lights: Vec<Light>,
+}
+
+impl Scene {
+    // The closest hit along the ray, for queries such as picking objects
+    // without rendering. Hits closer than the default ray bias are
+    // ignored, and the objects are visited without a hierarchy.
+    pub fn raycast(&self, r: &Ray) -> Option<HitRecord<'_>> {
+        self.world.hit(r, DEFAULT_RAY_BIAS, INF)
+    }
+
+    // The closest hit along the ray as `raycast` finds it, with the
+    // index of the object hit among those of the world.
+    pub fn pick(&self, r: &Ray) -> Option<(usize, HitRecord<'_>)> {
+        let mut closest = INF;
+        let mut picked = None;
+        for (i, obj) in self.world.objects.iter().enumerate() {
+            if let Some(rec) = obj.hit(r, DEFAULT_RAY_BIAS, closest) {
+                closest = rec.t;
+                picked = Some((i, rec));
+            }
+        }
+        picked
+    }
+
+    // Whether anything lies between the two points, ignoring hits within
+    // the default ray bias of either, so that points on surfaces can be
+    // tested at the start of the first shutter.
+    pub fn occluded(&self, a: Point3, b: Point3) -> bool {
+        let r = Ray { shadow: true, ..Ray::new(&a, &(b - a), 0.0) };
+        let bias = DEFAULT_RAY_BIAS / r.dir.length();
+        bias < 0.5 && self.world.hit(&r, bias, 1.0 - bias).is_some()
+    }
+}
+
+// The names of the built-in scenes, those of this renderer first and
+// then those from the books.
+pub const NAMES: [&str; 12] = [
+    "random", "random-jittered", "motion", "spin", "lanterns", "cornell", "cornell-smoke", "textures",
+    "three-spheres", "checkered-spheres", "simple-light", "book2-final",
+];
+
+// Looks up one of the built-in scenes by name.
+pub fn by_name(name: &str, aspect_ratio: Float) -> Option<Scene> {
+    match name {
+        "random" => Some(random_scene(aspect_ratio, true)),
+        "random-jittered" => Some(random_scene(aspect_ratio, false)),
+        "motion" => Some(motion_scene(aspect_ratio)),
+        "spin" => Some(spin_scene(aspect_ratio)),
+        "lanterns" => Some(lanterns_scene(aspect_ratio)),
+        "cornell" => Some(cornell_box(aspect_ratio)),
+        "cornell-smoke" => Some(cornell_smoke(aspect_ratio)),
+        "textures" => Some(textures_scene(aspect_ratio)),
+        "three-spheres" => Some(three_spheres(aspect_ratio)),
+        "checkered-spheres" => Some(checkered_spheres(aspect_ratio)),
+        "simple-light" => Some(simple_light(aspect_ratio)),
+        "book2-final" => Some(book2_final(aspect_ratio)),
+        _ => None,
+    }
+}
+
+// The small spheres of the first book's final scene, on the ground
+// around the three large ones.
+fn small_spheres(separated: bool) -> Vec<Sphere> {
+    let large = [Point3::new(0.0, 1.0, 0.0), Point3::new(-4.0, 1.0, 0.0), Point3::new(4.0, 1.0, 0.0)];
+    let points = if separated {
+        scatter::poisson_disk((-11.0, 11.0), (-11.0, 11.0), 0.8)
+    } else {
+        scatter::jittered_grid((-11.0, 11.0), (-11.0, 11.0), 1.0, 0.9)
+    };
+    // The glass spheres all share one material.
+    let glass: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+    let mut small = Vec::new();
+    for (x, z) in points {
+        let center = Point3::new(x, 0.2, z);
+        let clear = if separated {
+            large.iter().all(|&c| (center - c).length() >= 1.2)
+        } else {
+            (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9
+        };
+        if !clear {
+            continue
+        }
+
+        let choose_mat = random_float();
+        if choose_mat < 0.8 {
+            let albedo = Color::random() * Color::random();
+            let sphere_mat = Lambertian::new(albedo);
+            small.push(Sphere::new(center, 0.2, Arc::new(sphere_mat)));
+        } else if choose_mat > 0.95 {
+            let albedo = Color::random_bounded(0.5, 1.0);
+            let fuzz = 0.5 * random_float();
+            let sphere_mat = Metal::new(albedo, fuzz);
+            small.push(Sphere::new(center, 0.2, Arc::new(sphere_mat)));
+        } else {
+            small.push(Sphere::new(center, 0.2, glass.clone()));
+        }
+    }
+    small
+}
+
+// The final scene of the first book: three large spheres among
+// hundreds of small ones with random materials. When `separated`, the
+// small spheres are spread evenly with a Poisson disk distribution and
+// never touch each other or the large ones. Otherwise they are placed
+// on a jittered grid as in the book, where neighbors may interpenetrate.
+pub fn random_scene(aspect_ratio: Float, separated: bool) -> Scene {
+    let mut world = HittableList::default();
+
+    // let ground_material = Material::Lambertian(Color::new(0.5, 0.5, 0.5));
+    let ground_material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_material.clone())));
+    world.objects.extend(sphere::packets(small_spheres(separated)));
+
+    let mat1 = Dielectric::new(1.5);
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Arc::new(mat1))));
+
+    let mat2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
+    world.add(Box::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Arc::new(mat2))));
+
+    let mat3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    world.add(Box::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Arc::new(mat3))));
+
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.1;
+
+    let cam = Camera::new(
+        20.0, 
+        aspect_ratio, 
+        aperture,
+        dist_to_focus,
+        look_from, 
+        look_at, 
+        vup,
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// The final scene of the first book at night, lit only by a third of
+// its small spheres glowing in warm colors, for judging how well a few
+// hundred lights are sampled: each spot of ground should be lit by the
+// spheres around it with little noise.
+pub fn lanterns_scene(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let ground_material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(ground_material))));
+
+    let large = [Point3::new(0.0, 1.0, 0.0), Point3::new(-4.0, 1.0, 0.0), Point3::new(4.0, 1.0, 0.0)];
+    let mut small = Vec::new();
+    for (x, z) in scatter::poisson_disk((-11.0, 11.0), (-11.0, 11.0), 0.8) {
+        let center = Point3::new(x, 0.2, z);
+        if large.iter().any(|&c| (center - c).length() < 1.2) {
+            continue
+        }
+
+        if random_float() < 1.0 / 3.0 {
+            let glow = 4.0 * Color::new(1.0, 0.4 + 0.4 * random_float(), 0.1 + 0.2 * random_float());
+            let lantern = || Sphere::new(center, 0.2, Arc::new(DiffuseLight::new(glow)));
+            small.push(lantern());
+            lights.add(Box::new(lantern()));
+        } else {
+            let albedo = Color::random() * Color::random();
+            small.push(Sphere::new(center, 0.2, Arc::new(Lambertian::new(albedo))));
+        }
+    }
+    world.objects.extend(sphere::packets(small));
+
+    world.add(Box::new(Sphere::new(large[0], 1.0, Arc::new(Dielectric::new(1.5)))));
+    world.add(Box::new(Sphere::new(large[1], 1.0, Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))))));
+    world.add(Box::new(Sphere::new(large[2], 1.0, Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0)))));
+
+    let cam = Camera::new(
+        20.0,
+        aspect_ratio,
+        0.1,
+        10.0,
+        Point3::new(13.0, 2.0, 3.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Solid(Color::default()), lights, sun: None, punctual_lights: Vec::new() }
+}
+
+// A checkered cube tumbling about a tilted axis while the shutter is
+// open, for judging rotational motion blur: the squares of its faces
+// should smear along arcs without the cube shrinking or shearing.
+pub fn spin_scene(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let ground = CheckerTexture::from_colors(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9), 1.0);
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::textured(Arc::new(ground))))));
+
+    // Checkers in texture coordinates, which turn with the faces.
+    let mut squares = Image::new(8, 8);
+    for y in 0..8 {
+        for x in 0..8 {
+            let color = if (x + y) % 2 == 0 { Color::new(0.9, 0.9, 0.9) } else { Color::new(0.1, 0.2, 0.6) };
+            squares.set(x, y, color);
+        }
+    }
+    let squares = Lambertian::textured(Arc::new(ImageTexture::from_image(&squares)));
+    let cube = cuboid(Point3::new(-0.7, -0.7, -0.7), Point3::new(0.7, 0.7, 0.7), Arc::new(squares));
+    let start = RigidTransform::new(Vec3::new(0.0, 1.3, 0.0), Vec3::new(20.0, 10.0, 0.0));
+    let end = RigidTransform::new(Vec3::new(0.0, 1.3, 0.0), Vec3::new(35.0, 35.0, 15.0));
+    world.add(Box::new(Moving::new(Box::new(cube), start, end, 0.0, 1.0)));
+
+    let cam = Camera::new(
+        30.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(0.0, 2.5, 7.0),
+        Point3::new(0.0, 1.1, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ).with_shutter(0.0, 1.0);
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// Objects translating and spinning while the shutter is open.
+pub fn motion_scene(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(ground))));
+
+    // A sphere sliding sideways.
+    let red = Lambertian::new(Color::new(0.7, 0.1, 0.1));
+    let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.7, Arc::new(red));
+    let start = RigidTransform::new(Vec3::new(-3.0, 0.7, 0.0), Vec3::default());
+    let end = RigidTransform::new(Vec3::new(-2.0, 0.7, 0.0), Vec3::default());
+    world.add(Box::new(Moving::new(Box::new(sphere), start, end, 0.0, 1.0)));
+
+    // A dumbbell of two spheres spinning about its center.
+    let mut dumbbell = HittableList::default();
+    let metal = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 0.1));
+    dumbbell.add(Box::new(Sphere::new(Point3::new(-1.0, 0.0, 0.0), 0.5, metal.clone())));
+    dumbbell.add(Box::new(Sphere::new(Point3::new(1.0, 0.0, 0.0), 0.5, metal.clone())));
+    dumbbell.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.2, metal)));
+    let start = RigidTransform::new(Vec3::new(1.5, 1.0, 0.0), Vec3::default());
+    let end = RigidTransform::new(Vec3::new(1.5, 1.0, 0.0), Vec3::new(0.0, 0.0, 45.0));
+    world.add(Box::new(Moving::new(Box::new(dumbbell), start, end, 0.0, 1.0)));
+
+    let glass = Dielectric::new(1.5);
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 0.5, 2.0), 0.5, Arc::new(glass))));
+
+    let cam = Camera::new(
+        30.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(0.0, 2.0, 10.0),
+        Point3::new(0.0, 0.7, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ).with_shutter(0.0, 1.0);
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// The classic Cornell box: a closed room lit only by a panel in
+// the ceiling, with two boxes standing on the floor.
+pub fn cornell_box(aspect_ratio: Float) -> Scene {
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let mut contents = HittableList::default();
+    for (size, placement) in cornell_boxes() {
+        let cube = cuboid(Point3::default(), size, white.clone());
+        contents.add(Box::new(Instance::new(Arc::new(cube), placement)));
+    }
+    cornell_with(contents, aspect_ratio)
+}
+
+// The room of the Cornell box, lit by its ceiling panel and seen from
+// its camera, around other contents than the two boxes, to see how
+// anything fares in the classic test of global illumination. The room
+// spans 0 to 555 along every axis, with the floor at y = 0 and the
+// open front towards -z.
+pub fn cornell_with(contents: HittableList, aspect_ratio: Float) -> Scene {
+    let mut world = cornell_room();
+
+    let light = Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0)));
+    let panel = || AaRect::new(Plane::Xz, (213.0, 343.0), (227.0, 332.0), 554.0, light.clone());
+    world.add(Box::new(panel()));
+    let mut lights = HittableList::default();
+    lights.add(Box::new(panel()));
+    world.objects.extend(contents.objects);
+
+    Scene { world, cameras: vec![cornell_camera(aspect_ratio)], background: Background::Solid(Color::default()), lights, sun: None, punctual_lights: Vec::new() }
+}
+
+// The Cornell box with blocks of dark and light smoke instead of
+// solid boxes, under a larger and dimmer light.
+pub fn cornell_smoke(aspect_ratio: Float) -> Scene {
+    let mut world = cornell_room();
+
+    let light = Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0)));
+    let panel = || AaRect::new(Plane::Xz, (113.0, 443.0), (127.0, 432.0), 554.0, light.clone());
+    world.add(Box::new(panel()));
+    let mut lights = HittableList::default();
+    lights.add(Box::new(panel()));
+
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let smoke = [Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)];
+    for ((size, placement), &color) in cornell_boxes().into_iter().zip(smoke.iter()) {
+        let cube = cuboid(Point3::default(), size, white.clone());
+        let boundary = Instance::new(Arc::new(cube), placement);
+        world.add(Box::new(ConstantMedium::new(Box::new(boundary), 0.01, color)));
+    }
+
+    Scene { world, cameras: vec![cornell_camera(aspect_ratio)], background: Background::Solid(Color::default()), lights, sun: None, punctual_lights: Vec::new() }
+}
+
+// The walls, floor and ceiling of the Cornell box, without a light.
+fn cornell_room() -> HittableList {
+    let mut world = HittableList::default();
+
+    let red = Lambertian::new(Color::new(0.65, 0.05, 0.05));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(Color::new(0.12, 0.45, 0.15));
+
+    world.add(Box::new(AaRect::new(Plane::Yz, (0.0, 555.0), (0.0, 555.0), 555.0, Arc::new(green))));
+    world.add(Box::new(AaRect::new(Plane::Yz, (0.0, 555.0), (0.0, 555.0), 0.0, Arc::new(red))));
+    world.add(Box::new(AaRect::new(Plane::Xz, (0.0, 555.0), (0.0, 555.0), 0.0, white.clone())));
+    world.add(Box::new(AaRect::new(Plane::Xz, (0.0, 555.0), (0.0, 555.0), 555.0, white.clone())));
+    world.add(Box::new(AaRect::new(Plane::Xy, (0.0, 555.0), (0.0, 555.0), 555.0, white)));
+    world
+}
+
+// Sizes and placements of the tall and the short box on the floor.
+// The boxes are built at the origin, then turned and moved into place.
+fn cornell_boxes() -> Vec<(Point3, RigidTransform)> {
+    vec![
+        (Point3::new(165.0, 330.0, 165.0), RigidTransform::new(Vec3::new(265.0, 0.0, 295.0), Vec3::new(0.0, 15.0, 0.0))),
+        (Point3::new(165.0, 165.0, 165.0), RigidTransform::new(Vec3::new(130.0, 0.0, 65.0), Vec3::new(0.0, -18.0, 0.0))),
+    ]
+}
+
+fn cornell_camera(aspect_ratio: Float) -> Camera {
+    Camera::new(
+        40.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(278.0, 278.0, -800.0),
+        Point3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    )
+}
+
+// A marble sphere and a checkered metal one on a checkered floor.
+pub fn textures_scene(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let checker = CheckerTexture::from_colors(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9), 1.0);
+    let ground = Lambertian::textured(Arc::new(checker));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(ground))));
+
+    let marble = Lambertian::textured(Arc::new(MarbleTexture::new(4.0)));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 2.0, 0.0), 2.0, Arc::new(marble))));
+
+    let tiles = CheckerTexture::from_colors(Color::new(0.8, 0.6, 0.2), Color::new(0.7, 0.7, 0.7), 4.0);
+    let metal = Metal::textured(Arc::new(tiles), 0.05);
+    world.add(Box::new(Sphere::new(Point3::new(3.0, 1.0, 3.0), 1.0, Arc::new(metal))));
+
+    let cam = Camera::new(
+        20.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(13.0, 2.0, 3.0),
+        Point3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// The scene of the first book's chapter on positioning the camera:
+// a diffuse, a hollow glass and a metal sphere side by side.
+pub fn three_spheres(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let ground = Lambertian::new(Color::new(0.8, 0.8, 0.0));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, Arc::new(ground))));
+    let center = Lambertian::new(Color::new(0.1, 0.2, 0.5));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, Arc::new(center))));
+    // A negative radius turns the normals inwards, making a bubble
+    // inside the glass sphere that leaves a thin shell.
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Box::new(Sphere::new(Point3::new(-1.0, 0.0, -1.0), 0.5, glass.clone())));
+    world.add(Box::new(Sphere::new(Point3::new(-1.0, 0.0, -1.0), -0.45, glass)));
+    let metal = Metal::new(Color::new(0.8, 0.6, 0.2), 0.0);
+    world.add(Box::new(Sphere::new(Point3::new(1.0, 0.0, -1.0), 0.5, Arc::new(metal))));
+
+    let cam = Camera::new(
+        20.0,
+        aspect_ratio,
+        0.0,
+        1.0,
+        Point3::new(-2.0, 2.0, 1.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// The two checkered spheres of the second book's chapter on textures.
+pub fn checkered_spheres(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let checker = CheckerTexture::from_colors(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9), 1.0);
+    let checkered = Arc::new(Lambertian::textured(Arc::new(checker)));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -10.0, 0.0), 10.0, checkered.clone())));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 10.0, 0.0), 10.0, checkered)));
+
+    let cam = Camera::new(
+        20.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(13.0, 2.0, 3.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Sky, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// The second book's first scene lit by its objects alone: marble
+// spheres under a glowing sphere and beside a glowing panel.
+pub fn simple_light(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let marble = Arc::new(Lambertian::textured(Arc::new(MarbleTexture::new(4.0))));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, marble.clone())));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 2.0, 0.0), 2.0, marble)));
+
+    let light = Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
+    let panel = || AaRect::new(Plane::Xy, (3.0, 5.0), (1.0, 3.0), -2.0, light.clone());
+    let bulb = || Sphere::new(Point3::new(0.0, 7.0, 0.0), 2.0, light.clone());
+    world.add(Box::new(panel()));
+    world.add(Box::new(bulb()));
+    let mut lights = HittableList::default();
+    lights.add(Box::new(panel()));
+    lights.add(Box::new(bulb()));
+
+    let cam = Camera::new(
+        20.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(26.0, 3.0, 6.0),
+        Point3::new(0.0, 2.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    Scene { world, cameras: vec![cam], background: Background::Solid(Color::default()), lights, sun: None, punctual_lights: Vec::new() }
+}
+
+// The final scene of the second book, showing off everything it adds:
+// a floor of boxes of random heights, a moving sphere, glass, metal,
+// a glass sphere filled with blue fog, a thin mist over everything,
+// a marble sphere and a rotated cube of small spheres. The book's
+// sphere mapped with a photo of the earth is checkered here instead.
+pub fn book2_final(aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+
+    let mut floor: Vec<Box<dyn Hittable>> = Vec::new();
+    let ground = Arc::new(Lambertian::new(Color::new(0.48, 0.83, 0.53)));
+    for i in 0..20 {
+        for j in 0..20 {
+            let w = 100.0;
+            let (x0, z0) = (-1000.0 + i as Float * w, -1000.0 + j as Float * w);
+            let y1 = 1.0 + 100.0 * random_float();
+            floor.push(Box::new(cuboid(Point3::new(x0, 0.0, z0), Point3::new(x0 + w, y1, z0 + w), ground.clone())));
+        }
+    }
+    world.add(Box::new(Bvh::new(floor, 0.0, 1.0)));
+
+    let light = Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0)));
+    let panel = || AaRect::new(Plane::Xz, (123.0, 423.0), (147.0, 412.0), 554.0, light.clone());
+    world.add(Box::new(panel()));
+    let mut lights = HittableList::default();
+    lights.add(Box::new(panel()));
+
+    let orange = Lambertian::new(Color::new(0.7, 0.3, 0.1));
+    let sphere = Sphere::new(Point3::default(), 50.0, Arc::new(orange));
+    let start = RigidTransform::new(Vec3::new(400.0, 400.0, 200.0), Vec3::default());
+    let end = RigidTransform::new(Vec3::new(430.0, 400.0, 200.0), Vec3::default());
+    world.add(Box::new(Moving::new(Box::new(sphere), start, end, 0.0, 1.0)));
+
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Box::new(Sphere::new(Point3::new(260.0, 150.0, 45.0), 50.0, glass.clone())));
+    let metal = Metal::new(Color::new(0.8, 0.8, 0.9), 1.0);
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 150.0, 145.0), 50.0, Arc::new(metal))));
+
+    let bubble = || Sphere::new(Point3::new(360.0, 150.0, 145.0), 70.0, glass.clone());
+    world.add(Box::new(bubble()));
+    world.add(Box::new(ConstantMedium::new(Box::new(bubble()), 0.2, Color::new(0.2, 0.4, 0.9))));
+    let mist = Sphere::new(Point3::default(), 5000.0, glass.clone());
+    world.add(Box::new(ConstantMedium::new(Box::new(mist), 0.0001, Color::new(1.0, 1.0, 1.0))));
+
+    let globe = CheckerTexture::from_colors(Color::new(0.1, 0.3, 0.7), Color::new(0.3, 0.6, 0.2), 0.05);
+    world.add(Box::new(Sphere::new(Point3::new(400.0, 200.0, 400.0), 100.0, Arc::new(Lambertian::textured(Arc::new(globe))))));
+    let marble = Lambertian::textured(Arc::new(MarbleTexture::new(0.1)));
+    world.add(Box::new(Sphere::new(Point3::new(220.0, 280.0, 300.0), 80.0, Arc::new(marble))));
+
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let spheres: Vec<Box<dyn Hittable>> = (0..1000)
+        .map(|_| {
+            let center = Point3::new(165.0 * random_float(), 165.0 * random_float(), 165.0 * random_float());
+            Box::new(Sphere::new(center, 10.0, white.clone())) as Box<dyn Hittable>
+        })
+        .collect();
+    let cube = Bvh::new(spheres, 0.0, 1.0);
+    let placement = RigidTransform::new(Vec3::new(-100.0, 270.0, 395.0), Vec3::new(0.0, 15.0, 0.0));
+    world.add(Box::new(Instance::new(Arc::new(cube), placement)));
+
+    let cam = Camera::new(
+        40.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(478.0, 278.0, -600.0),
+        Point3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ).with_shutter(0.0, 1.0);
+
+    Scene { world, cameras: vec![cam], background: Background::Solid(Color::default()), lights, sun: None, punctual_lights: Vec::new() }
+}
+
+// The standard setup for judging a material on its own: a ball of it
+// resting on a checkered floor, seen from a fixed camera and lit by a
+// studio environment.
+pub fn material_preview(mat: Arc<dyn Material>, aspect_ratio: Float) -> Scene {
+    let mut world = HittableList::default();
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat)));
+
+    let checker = CheckerTexture::from_colors(Color::new(0.15, 0.15, 0.15), Color::new(0.6, 0.6, 0.6), 2.0);
+    let floor = Lambertian::textured(Arc::new(checker));
+    world.add(Box::new(AaRect::new(Plane::Xz, (-50.0, 50.0), (-50.0, 50.0), 0.0, Arc::new(floor))));
+
+    let cam = Camera::new(
+        35.0,
+        aspect_ratio,
+        0.0,
+        10.0,
+        Point3::new(0.0, 2.5, 6.0),
+        Point3::new(0.0, 0.8, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+
+    let map = ImageTexture::from_image(&studio_environment(512, 256));
+    let background = Background::Environment { map: Arc::new(map), intensity: 1.0 };
+    Scene { world, cameras: vec![cam], background, lights: HittableList::default(), sun: None, punctual_lights: Vec::new() }
+}
+
+// An environment map of a photo studio, in the layout of environment
+// backgrounds: a dim backdrop darker towards the floor, under a large
+// key light to the front left, a dimmer fill to the right and a rim
+// light behind. Lights are round, with a soft edge.
+fn studio_environment(width: usize, height: usize) -> Image {
+    let softboxes = [
+        (Vec3::new(-1.0, 1.0, 1.0), 25.0, Color::new(6.0, 5.8, 5.5)),
+        (Vec3::new(1.5, 0.5, 1.0), 20.0, Color::new(1.5, 1.6, 1.8)),
+        (Vec3::new(0.0, 1.0, -1.5), 15.0, Color::new(4.0, 4.0, 4.0)),
+    ];
+    let mut img = Image::new(width, height);
+    for y in 0..height {
+        // The inverse of the mapping of directions to the environment.
+        let theta = PI * (1.0 - (y as Float + 0.5) / height as Float);
+        for x in 0..width {
+            let phi = 2.0 * PI * (x as Float + 0.5) / width as Float - PI;
+            let dir = Vec3::new(theta.sin() * phi.cos(), -theta.cos(), -theta.sin() * phi.sin());
+
+            let mut color = (0.05 + 0.1 * (0.5 + 0.5 * dir.y)) * Color::new(1.0, 1.0, 1.0);
+            for &(center, radius, light) in softboxes.iter() {
+                let angle = dot(&dir, &unit_vector(center)).clamp(-1.0, 1.0).acos().to_degrees();
+                let edge = ((radius - angle) / (0.2 * radius)).clamp(0.0, 1.0);
+                color += edge * light;
+            }
+            img.set(x, y, color);
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_by_name() {
+        // Every scene's first camera looks at something.
+        for name in NAMES.iter() {
+            let scene = by_name(name, 1.5).unwrap();
+            assert!(scene.raycast(&scene.cameras[0].get_ray(0.5, 0.5)).is_some(), "{}", name);
+        }
+        assert!(by_name("book3", 1.5).is_none());
+    }
+
+    // A unit ball at the origin, the only object of the scene.
+    fn ball() -> Scene {
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))));
+        Scene {
+            world,
+            cameras: Vec::new(),
+            background: Background::Solid(Color::default()),
+            lights: HittableList::default(),
+            sun: None,
+            punctual_lights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_raycast() {
+        let scene = ball();
+        let rec = scene.raycast(&Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0)).unwrap();
+        assert_eq!(4.0, rec.t);
+        assert_eq!(Point3::new(0.0, 0.0, 1.0), rec.p);
+        assert!(scene.raycast(&Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0)).is_none());
+        assert!(scene.raycast(&Ray::new(&Point3::new(0.0, 2.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_occluded() {
+        let scene = ball();
+        assert!(scene.occluded(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, -5.0)));
+        assert!(!scene.occluded(Point3::new(0.0, 2.0, 5.0), Point3::new(0.0, 2.0, -5.0)));
+        // Ending short of the ball, and starting on its surface.
+        assert!(!scene.occluded(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 2.0)));
+        assert!(!scene.occluded(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 5.0)));
+        // A point is never in its own way.
+        assert!(!scene.occluded(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 5.0)));
+
+        // The bias leaves nothing of a segment shorter than twice it,
+        // even across the surface, while a longer one finds it.
+        assert!(!scene.occluded(Point3::new(0.0, 0.0, 1.0004), Point3::new(0.0, 0.0, 0.9998)));
+        assert!(scene.occluded(Point3::new(0.0, 0.0, 1.01), Point3::new(0.0, 0.0, 0.99)));
+    }
+
+    #[test]
+    fn test_material_preview() {
+        let scene = material_preview(Arc::new(Lambertian::new(Color::new(1.0, 0.0, 0.0))), 1.0);
+        let rec = scene.raycast(&scene.cameras[0].get_ray(0.5, 0.5)).unwrap();
+        assert!((rec.p - Point3::new(0.0, 1.0, 0.0)).length() < 1.0 + 1e-3);
+
+        // The key light is found where the environment maps its direction.
+        let lit = |dir: Vec3| scene.background.color(&Ray::new(&Point3::default(), &dir, 0.0));
+        assert!(lit(Vec3::new(-1.0, 1.0, 1.0)).x > 5.0);
+        assert!(lit(Vec3::new(0.0, -1.0, 0.0)).x < 0.1);
+    }
+
+    #[test]
+    fn test_cornell_with() {
+        let glass = Arc::new(Dielectric::new(1.5));
+        let mut contents = HittableList::default();
+        contents.add(Box::new(Sphere::new(Point3::new(278.0, 100.0, 278.0), 100.0, glass)));
+        let scene = cornell_with(contents, 1.0);
+
+        // The ball stands in the middle of the floor, under the light.
+        let rec = scene.raycast(&scene.cameras[0].get_ray(0.5, 0.3)).unwrap();
+        assert!((rec.p - Point3::new(278.0, 100.0, 278.0)).length() < 100.0 + 1e-2);
+        assert_eq!(1, scene.lights.objects.len());
+        assert!(!scene.occluded(Point3::new(278.0, 554.0, 279.0), Point3::new(278.0, 200.0, 278.0)));
+    }
+
+    #[test]
+    fn test_random_scene_separated() {
+        // The small spheres along with the large ones.
+        let spheres = |separated: bool| -> Vec<(Point3, Float)> {
+            let large = [(Point3::new(0.0, 1.0, 0.0), 1.0), (Point3::new(-4.0, 1.0, 0.0), 1.0), (Point3::new(4.0, 1.0, 0.0), 1.0)];
+            small_spheres(separated).iter().map(|s| (s.center, s.radius)).chain(large).collect()
+        };
+        let overlaps = |spheres: &[(Point3, Float)]| {
+            spheres.iter().enumerate().any(|(i, &(a, ra))| {
+                spheres[i + 1..].iter().any(|&(b, rb)| (a - b).length() < ra + rb - 1e-4)
+            })
+        };
+
+        let separated = spheres(true);
+        assert!(separated.len() > 300, "{}", separated.len());
+        assert!(!overlaps(&separated));
+        // Among hundreds of spheres on the book's jittered grid,
+        // some neighbors all but certainly interpenetrate.
+        assert!(overlaps(&spheres(false)));
+    }
+}
@@ -0,0 +1,1623 @@
+use std::cell::RefCell;
+use crate::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{to_f64, INF, Float};
+use crate::json::{self, Json};
+use crate::expr;
+use crate::vec::{Vec3, Point3, Color, cross};
+use crate::hittable::{Hittable, HittableList};
+use crate::aabb::Aabb;
+use crate::sphere::Sphere;
+use crate::triangle::{Triangle, Culling};
+use crate::aarect::{AaRect, Plane, cuboid};
+use crate::backdrop::{Backdrop, BackdropTexture, Pattern};
+use crate::camera::{Camera, Projection, View, look_from_matrix};
+use crate::mat::Mat4;
+use crate::clip::Clip;
+use crate::material::{Material, Lambertian, Metal, MicrofacetMetal, Dielectric, DiffuseLight, Masked, Sampling};
+use crate::texture::{Texture, SolidColor, CheckerTexture, MarbleTexture, TurbulenceTexture, GradientTexture, WoodTexture, ImageTexture};
+use crate::texture::{ColorRamp, Interpolation, LayeredTexture, Blend, TriplanarTexture};
+use crate::motion::{Moving, RigidTransform};
+use crate::instance::Instance;
+use crate::transform::{Transform, TransformedHittable};
+use crate::cutout::Cutout;
+use crate::render::{Background, SKY_HORIZON, SKY_ZENITH};
+use crate::scene::Scene;
+use crate::bvh::Bvh;
+use crate::mesh;
+use crate::photometry::{self, Metering};
+use crate::scatter;
+use crate::sun::{Sun, SUN_DIAMETER, SUN_LIMB_DARKENING};
+use crate::light::Light;
+
+// Render settings a scene file may give. Those given on
+// the command line take precedence.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FileSettings {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub min_throughput: Option<Float>,
+    // How 8-bit output is metered for automatic exposure, if it is.
+    pub auto_exposure: Option<Metering>,
+}
+
+// A single material, given as a JSON object in the format of scene files
+// or the path of a file holding one, for previews. Materials given by
+// their flux are spread over a unit sphere.
+pub fn load_material(def: &str) -> Result<Arc<dyn Material>, String> {
+    let (spec, dir) = read_definition(def)?;
+    let loader = Loader { dir, materials: Vec::new(), flux_lights: Vec::new(), geometry: Vec::new(), warnings: RefCell::default() };
+    loader.material(&spec, Some(4.0 * PI))
+}
+
+// A single texture, given as a JSON object in the format of scene files
+// or the path of a file holding one, for baking into images.
+pub fn load_texture(def: &str) -> Result<Arc<dyn Texture>, String> {
+    let (spec, dir) = read_definition(def)?;
+    let loader = Loader { dir, materials: Vec::new(), flux_lights: Vec::new(), geometry: Vec::new(), warnings: RefCell::default() };
+    loader.texture(&spec)
+}
+
+// The JSON of a definition given inline or in a file, and the directory
+// its paths are relative to.
+fn read_definition(def: &str) -> Result<(Json, &Path), String> {
+    let (text, dir) = if def.trim_start().starts_with('{') {
+        (def.to_string(), Path::new(""))
+    } else {
+        let text = fs::read_to_string(def).map_err(|e| format!("'{}': {}", def, e))?;
+        (text, Path::new(def).parent().unwrap_or_else(|| Path::new("")))
+    };
+    Ok((json::parse(&text)?, dir))
+}
+
+// A change to a field of a named material, made to a scene file after
+// it is read and before anything is built from it, given on the command
+// line as e.g. `material.gold.roughness=0.3`. Values are JSON, so strings
+// need quotes and colors are arrays.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    material: String,
+    field: String,
+    value: Json,
+}
+
+impl Override {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (target, value) = s.split_once('=').ok_or_else(|| format!("invalid override '{}', expected target=value", s))?;
+        let value = json::parse(value).map_err(|e| format!("invalid value in '{}': {}", s, e))?;
+        Self::new(target, value)
+    }
+
+    // One override for each of a comma separated list of values, as in
+    // `material.gold.roughness=0,0.5,1`.
+    pub fn parse_sweep(s: &str) -> Result<Vec<Self>, String> {
+        let (target, values) = s.split_once('=').ok_or_else(|| format!("invalid sweep '{}', expected target=values", s))?;
+        let values = json::parse(&format!("[{}]", values)).map_err(|e| format!("invalid values in '{}': {}", s, e))?;
+        values.as_array().unwrap_or_default().iter()
+            .map(|value| Self::new(target, value.clone()))
+            .collect()
+    }
+
+    // Targets are `material.<name>.<field>`, where only the
+    // name may contain dots.
+    fn new(target: &str, value: Json) -> Result<Self, String> {
+        let invalid = || format!("invalid override target '{}', expected material.<name>.<field>", target);
+        let path = target.strip_prefix("material.").ok_or_else(invalid)?;
+        match path.rsplit_once('.') {
+            Some((material, field)) if !material.is_empty() && !field.is_empty() => {
+                Ok(Self { material: material.to_string(), field: field.to_string(), value })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn apply(&self, root: &mut Json) -> Result<(), String> {
+        let spec = root.get_mut("materials")
+            .and_then(|materials| materials.get_mut(&self.material))
+            .ok_or_else(|| format!("no material '{}' to override", self.material))?;
+        match spec {
+            Json::Object(fields) => match fields.iter_mut().find(|(k, _)| *k == self.field) {
+                Some((_, value)) => *value = self.value.clone(),
+                None => fields.push((self.field.clone(), self.value.clone())),
+            },
+            _ => return Err(format!("material '{}' is not an object", self.material)),
+        }
+        Ok(())
+    }
+}
+
+// A camera's view, with its own shutter interval and clip if it has them.
+type FileCamera = (View, Option<(Float, Float)>, Option<Clip>);
+
+// A scene described in a JSON file, e.g.
+//
+//   {
+//     "settings": { "width": 640, "samples": 200 },
+//     "background": "sky",
+//     "cameras": [{ "from": [13, 2, 3], "at": [0, 0, 0], "vfov": 20 }],
+//     "materials": {
+//       "ground": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }
+//     },
+//     "objects": [
+//       { "type": "sphere", "center": [0, -1000, 0], "radius": 1000, "material": "ground" },
+//       { "type": "sphere", "center": [0, 1, 0], "radius": 1,
+//         "material": { "type": "dielectric", "ior": 1.5 } }
+//     ]
+//   }
+//
+// See `examples/scene.json` for every supported field. Cameras are
+// kept as views until the image dimensions are known.
+//
+// Any number may be given as a string of an expression after `=`
+// instead, evaluated when the file is loaded, and scenes may be
+// animated through the scene time `t` in them, e.g. "=45 * t" as an
+// angle of rotation; see `expr::evaluate`.
+pub struct SceneFile {
+    pub settings: FileSettings,
+    // Whether any expression depends on the scene time.
+    animated: bool,
+    world: HittableList,
+    cameras: Vec<FileCamera>,
+    background: Background,
+    lights: HittableList,
+    sun: Option<Sun>,
+    punctual_lights: Vec<Light>,
+    // Likely mistakes that don't keep the scene from rendering.
+    warnings: Vec<String>,
+    names: Names,
+}
+
+// What the objects and materials of a scene file are called, to tell
+// which entries of the file hits in its scene are on.
+#[derive(Clone, Default)]
+pub struct Names {
+    // Where each object of the world is in the file, with its type and
+    // the name it may be given, e.g. `objects[2] (sphere "teapot")`.
+    objects: Vec<String>,
+    materials: Vec<(String, Arc<dyn Material>)>,
+}
+
+impl Names {
+    // The object at the index among those of the world.
+    pub fn object(&self, index: usize) -> Option<&str> {
+        self.objects.get(index).map(String::as_str)
+    }
+
+    // The name of a material, if it is one of those named in the file
+    // and not given inline or by its flux.
+    pub fn material(&self, mat: &dyn Material) -> Option<&str> {
+        self.materials.iter()
+            .find(|(_, m)| std::ptr::addr_eq(Arc::as_ptr(m), mat as *const dyn Material))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+// How an object is shown by `Names`.
+fn object_label(index: usize, spec: &Json) -> String {
+    let kind = spec.get("type").and_then(Json::as_str).unwrap_or("object");
+    match spec.get("name").and_then(Json::as_str) {
+        Some(name) => format!("objects[{}] ({} \"{}\")", index, kind, name),
+        None => format!("objects[{}] ({})", index, kind),
+    }
+}
+
+// Objects reaching farther than this from the origin are reported, as
+// rounding errors in their hits grow with the distance.
+const FAR: Float = 1e6;
+
+impl SceneFile {
+    pub fn load(path: &str, overrides: &[Override], time: Float) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Self::parse_with(&text, dir, overrides, time)
+    }
+
+    // Relative paths of meshes and images are taken
+    // relative to the given directory.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        Self::parse_with(text, dir, &[], 0.0)
+    }
+
+    // The scene with the overrides made, and at the given scene time.
+    pub fn parse_with(text: &str, dir: &Path, overrides: &[Override], time: Float) -> Result<Self, String> {
+        let mut root = json::parse(text)?;
+        if root.as_object().is_none() {
+            return Err("expected an object at the top level".to_string())
+        }
+        for o in overrides.iter() {
+            o.apply(&mut root)?;
+        }
+        let animated = evaluate_expressions(&mut root, time)?;
+        let keyframed = list(&root, "cameras")?.iter().any(|c| c.get("keyframes").is_some());
+
+        let settings = match root.get("settings") {
+            Some(s) => file_settings(s).map_err(|e| format!("settings: {}", e))?,
+            None => FileSettings::default(),
+        };
+
+        let cameras = list(&root, "cameras")?.iter()
+            .enumerate()
+            .map(|(i, c)| camera(c, time).map_err(|e| format!("cameras[{}]: {}", i, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut loader = Loader { dir, materials: Vec::new(), flux_lights: Vec::new(), geometry: Vec::new(), warnings: RefCell::default() };
+        let background = match root.get("background") {
+            Some(bg) => loader.background(bg).map_err(|e| format!("background: {}", e))?,
+            None => Background::Sky,
+        };
+        let sun = optional(&root, "sun", sun)?;
+        let punctual_lights = list(&root, "lights")?.iter()
+            .enumerate()
+            .map(|(i, l)| light(l).map_err(|e| format!("lights[{}]: {}", i, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(materials) = root.get("materials") {
+            let fields = materials.as_object().ok_or("materials: expected an object")?;
+            for (name, spec) in fields.iter() {
+                // Checked with a made up area, to report errors here.
+                let flux = spec.get("lumens").is_some() || spec.get("watts").is_some();
+                let area = if flux { Some(1.0) } else { None };
+                let mat = loader.material(spec, area).map_err(|e| format!("materials.{}: {}", name, e))?;
+                if flux {
+                    loader.flux_lights.push((name.clone(), spec.clone()));
+                } else {
+                    loader.materials.push((name.clone(), mat));
+                }
+            }
+        }
+
+        if let Some(geometry) = root.get("geometry") {
+            let fields = geometry.as_object().ok_or("geometry: expected an object")?;
+            for (name, spec) in fields.iter() {
+                let since = loader.warnings.borrow().len();
+                let obj = loader.geometry(spec).map_err(|e| format!("geometry.{}: {}", name, e))?;
+                loader.prefix_warnings(since, &format!("geometry.{}", name));
+                loader.geometry.push((name.clone(), obj));
+            }
+        }
+
+        let mut world = HittableList::default();
+        let mut lights = HittableList::default();
+        let mut objects = Vec::new();
+        for (i, spec) in list(&root, "objects")?.iter().enumerate() {
+            let since = loader.warnings.borrow().len();
+            let obj = loader.object(spec).map_err(|e| format!("objects[{}]: {}", i, e))?;
+            match obj.bounding_box(0.0, 1.0).map(|bbox| reach(&bbox)) {
+                Some(reach) if reach > FAR => loader.warn(format!("reaches {:.0e} from the origin, too far for precise hits", reach)),
+                _ => {}
+            }
+            loader.prefix_warnings(since, &format!("objects[{}]", i));
+            world.add(obj);
+            objects.push(object_label(i, spec));
+            if loader.is_sampled_light(spec) {
+                let since = loader.warnings.borrow().len();
+                lights.add(loader.object(spec)?);
+                loader.warnings.borrow_mut().truncate(since);
+            }
+        }
+        let mut warnings = loader.warnings.into_inner();
+        warnings.extend(unused_materials(&root).into_iter().map(|name| format!("materials.{}: never used", name)));
+        let names = Names { objects, materials: loader.materials };
+
+        Ok(Self { settings, animated: animated || keyframed, world, cameras, background, lights, sun, punctual_lights, warnings, names })
+    }
+
+    // Likely mistakes found in the scene, such as degenerate triangles,
+    // spheres of no size or materials no object uses.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // What the objects and materials are called, which the scene the
+    // file is turned into no longer knows.
+    pub fn names(&self) -> &Names {
+        &self.names
+    }
+
+    // Animated scenes change from frame to frame, and
+    // are to be loaded again at every frame's time.
+    pub fn animated(&self) -> bool {
+        self.animated
+    }
+
+    pub fn into_scene(self, aspect_ratio: Float) -> Scene {
+        let cameras = self.cameras.iter()
+            .map(|(view, shutter, clip)| {
+                let mut cam = view.camera(aspect_ratio);
+                if let Some((open, close)) = shutter {
+                    cam = cam.with_shutter(*open, *close);
+                }
+                match clip {
+                    Some(clip) => cam.with_clip(*clip),
+                    None => cam,
+                }
+            })
+            .collect::<Vec<Camera>>();
+        let (lights, sun, punctual_lights) = (self.lights, self.sun, self.punctual_lights);
+        Scene { world: self.world, cameras, background: self.background, lights, sun, punctual_lights }
+    }
+}
+
+struct Loader<'a> {
+    dir: &'a Path,
+    // Named materials, shared by every object using them.
+    materials: Vec<(String, Arc<dyn Material>)>,
+    // Lights given by their flux, which are only built once the
+    // area of the object they are applied to is known.
+    flux_lights: Vec<(String, Json)>,
+    // Named objects, built once and shared by every instance of them.
+    geometry: Vec<(String, Arc<dyn Hittable>)>,
+    warnings: RefCell<Vec<String>>,
+}
+
+impl<'a> Loader<'a> {
+    fn warn(&self, warning: String) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    // Prefixes the warnings given since there were `since` of them with
+    // where they were found, as errors are.
+    fn prefix_warnings(&self, since: usize, context: &str) {
+        for warning in self.warnings.borrow_mut()[since..].iter_mut() {
+            *warning = format!("{}: {}", context, warning);
+        }
+    }
+
+    fn path(&self, json: &Json, key: &str) -> Result<String, String> {
+        let path = string(json, key)?;
+        Ok(self.dir.join(path).to_string_lossy().into_owned())
+    }
+
+    // Either "sky", a solid color, a "gradient" from a "bottom" to a "top"
+    // color, or an "environment" map loaded from the image at "path". The
+    // sky and environment maps may be scaled by an "intensity". A list of
+    // backgrounds adds them up, e.g. to light a scene with both an
+    // environment map and the sky.
+    fn background(&self, json: &Json) -> Result<Background, String> {
+        match json {
+            Json::String(s) if s == "sky" => return Ok(Background::Sky),
+            Json::Array(items) if items.iter().all(|item| item.as_f64().is_some()) => {
+                return Ok(Background::Solid(vec3(json)?))
+            }
+            Json::Array(items) => {
+                return items.iter()
+                    .enumerate()
+                    .map(|(i, item)| self.background(item).map_err(|e| format!("[{}]: {}", i, e)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Background::Combined)
+            }
+            _ => {}
+        }
+        match string(json, "type")? {
+            "sky" => {
+                let intensity = optional(json, "intensity", number)?.unwrap_or(1.0);
+                Ok(Background::Gradient { bottom: intensity * SKY_HORIZON, top: intensity * SKY_ZENITH })
+            }
+            "gradient" => Ok(Background::Gradient {
+                bottom: vec3(field(json, "bottom")?).map_err(|e| format!("bottom: {}", e))?,
+                top: vec3(field(json, "top")?).map_err(|e| format!("top: {}", e))?,
+            }),
+            "environment" => {
+                let path = self.path(json, "path")?;
+                let map = ImageTexture::load(&path).map_err(|e| format!("failed to load '{}': {}", path, e))?;
+                let intensity = optional(json, "intensity", number)?.unwrap_or(1.0);
+                Ok(Background::Environment { map: Arc::new(map), intensity })
+            }
+            other => Err(format!("unknown background type '{}'", other)),
+        }
+    }
+
+    // A material is either the name of one defined under "materials"
+    // or a material object of its own. `area` is the surface area of
+    // the object it is applied to, when known.
+    fn material(&self, json: &Json, area: Option<Float>) -> Result<Arc<dyn Material>, String> {
+        if let Json::String(name) = json {
+            if let Some((_, spec)) = self.flux_lights.iter().find(|(n, _)| n == name) {
+                return self.material(spec, area)
+            }
+            return self.materials.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, mat)| mat.clone())
+                .ok_or_else(|| format!("unknown material '{}'", name))
+        }
+
+        let mat: Arc<dyn Material> = match string(json, "type")? {
+            "lambertian" => Arc::new(Lambertian::textured(self.texture(field(json, "albedo")?)?)),
+            // A "roughness" makes a metal of microfacets, while the
+            // books' metal is blurred by its "fuzz" instead.
+            "metal" => {
+                let albedo = self.texture(field(json, "albedo")?)?;
+                match optional(json, "roughness", number)? {
+                    Some(roughness) => Arc::new(MicrofacetMetal::textured(albedo, roughness).with_sampling(sampling(json)?)),
+                    None => Arc::new(Metal::textured(albedo, optional(json, "fuzz", number)?.unwrap_or(0.0))),
+                }
+            }
+            "dielectric" => {
+                let roughness = optional(json, "roughness", number)?.unwrap_or(0.0);
+                let mut glass = Dielectric::new(number(field(json, "ior")?)?).with_roughness(roughness).with_sampling(sampling(json)?);
+                if let Some(abbe) = optional(json, "abbe", number)? {
+                    if abbe <= 0.0 {
+                        return Err("abbe: expected a positive number".to_string())
+                    }
+                    glass = glass.with_dispersion(abbe);
+                }
+                match optional(json, "absorption", vec3)? {
+                    Some(color) => {
+                        if [color.x, color.y, color.z].iter().any(|&c| !(c > 0.0 && c <= 1.0)) {
+                            return Err("absorption: expected a color within (0, 1]".to_string())
+                        }
+                        let density = optional(json, "density", number)?.unwrap_or(1.0);
+                        Arc::new(glass.with_absorption(color, density))
+                    }
+                    None => Arc::new(glass),
+                }
+            }
+            "light" => Arc::new(DiffuseLight::new(emission(json, area)?)),
+            other => return Err(format!("unknown material type '{}'", other)),
+        };
+
+        // Any material may be cut out by an "opacity" map, letting rays
+        // through texels below the "cutoff" and the rest by chance.
+        match optional(json, "opacity", |o| self.opacity(o))? {
+            Some(opacity) => {
+                let cutoff = optional(json, "cutoff", number)?.unwrap_or(0.0);
+                if !(0.0..=1.0).contains(&cutoff) {
+                    return Err("cutoff: expected a number from 0 to 1".to_string())
+                }
+                Ok(Arc::new(Masked::new(mat, opacity, cutoff)))
+            }
+            None => Ok(mat),
+        }
+    }
+
+    // Opacity is a texture, or a single number for the whole surface.
+    fn opacity(&self, json: &Json) -> Result<Arc<dyn Texture>, String> {
+        match json {
+            Json::Number(_) => {
+                let opacity = number(json)?;
+                Ok(Arc::new(SolidColor::new(Color::new(opacity, opacity, opacity))))
+            }
+            _ => self.texture(json),
+        }
+    }
+
+    // Whether the material, named or given inline, has an opacity map,
+    // which objects of it then have their hits tested against.
+    fn is_masked(&self, json: &Json) -> bool {
+        match json {
+            Json::String(name) => {
+                let flux_light = self.flux_lights.iter().find(|(n, _)| n == name);
+                let named = self.materials.iter().find(|(n, _)| n == name);
+                match (flux_light, named) {
+                    (Some((_, spec)), _) => spec.get("opacity").is_some(),
+                    (None, Some((_, mat))) => mat.is_masked(),
+                    (None, None) => false,
+                }
+            }
+            _ => json.get("opacity").is_some(),
+        }
+    }
+
+    // A texture is either a plain color or a texture object. "marble",
+    // also called "noise", and "turbulence" are Perlin noise. Layered
+    // textures combine texture "a" with "b" as "multiply", "add",
+    // "overlay", or "mix" going from a to b by a "mask" texture.
+    // "triplanar" projects a "texture" along the axes instead of using
+    // texture coordinates, repeating every 1 / "scale", and blending the
+    // projections by the normal with a "sharpness", 4 by default.
+    fn texture(&self, json: &Json) -> Result<Arc<dyn Texture>, String> {
+        if json.as_array().is_some() {
+            return Ok(Arc::new(SolidColor::new(vec3(json)?)))
+        }
+
+        let tex: Arc<dyn Texture> = match string(json, "type")? {
+            "checker" => Arc::new(CheckerTexture::new(
+                self.texture(field(json, "even")?)?,
+                self.texture(field(json, "odd")?)?,
+                optional(json, "scale", number)?.unwrap_or(1.0),
+            )),
+            "marble" | "noise" => {
+                let scale = optional(json, "scale", number)?.unwrap_or(1.0);
+                match optional(json, "ramp", ramp)? {
+                    Some(ramp) => Arc::new(MarbleTexture::with_ramp(scale, ramp)),
+                    None => Arc::new(MarbleTexture::new(scale)),
+                }
+            }
+            "turbulence" => {
+                let scale = optional(json, "scale", number)?.unwrap_or(1.0);
+                match optional(json, "ramp", ramp)? {
+                    Some(ramp) => Arc::new(TurbulenceTexture::with_ramp(scale, ramp)),
+                    None => Arc::new(TurbulenceTexture::new(scale)),
+                }
+            }
+            "gradient" => Arc::new(GradientTexture::new(
+                vec3(field(json, "from")?).map_err(|e| format!("from: {}", e))?,
+                vec3(field(json, "to")?).map_err(|e| format!("to: {}", e))?,
+                ramp(field(json, "ramp")?).map_err(|e| format!("ramp: {}", e))?,
+            )),
+            "wood" => {
+                let scale = optional(json, "scale", number)?.unwrap_or(1.0);
+                match optional(json, "ramp", ramp)? {
+                    Some(ramp) => Arc::new(WoodTexture::with_ramp(scale, ramp)),
+                    None => Arc::new(WoodTexture::new(scale)),
+                }
+            }
+            name @ ("mix" | "multiply" | "add" | "overlay") => {
+                let blend = match name {
+                    "mix" => Blend::Mix(self.texture(field(json, "mask")?).map_err(|e| format!("mask: {}", e))?),
+                    "multiply" => Blend::Multiply,
+                    "add" => Blend::Add,
+                    _ => Blend::Overlay,
+                };
+                let a = self.texture(field(json, "a")?).map_err(|e| format!("a: {}", e))?;
+                let b = self.texture(field(json, "b")?).map_err(|e| format!("b: {}", e))?;
+                Arc::new(LayeredTexture::new(a, b, blend))
+            }
+            "triplanar" => Arc::new(TriplanarTexture::new(
+                self.texture(field(json, "texture")?).map_err(|e| format!("texture: {}", e))?,
+                optional(json, "scale", number)?.unwrap_or(1.0),
+                optional(json, "sharpness", number)?.unwrap_or(4.0),
+            )),
+            "image" => {
+                let path = self.path(json, "path")?;
+                let image = ImageTexture::load(&path).map_err(|e| format!("failed to load '{}': {}", path, e))?;
+                Arc::new(image)
+            }
+            other => return Err(format!("unknown texture type '{}'", other)),
+        };
+        Ok(tex)
+    }
+
+    // Every object may be placed with "translate" and "rotate" (in
+    // degrees about each axis), and move to a second placement given
+    // under "motion" over the shutter interval [0, 1]. Before that it
+    // may be shaped in its own space, see `shape`.
+    fn object(&self, json: &Json) -> Result<Box<dyn Hittable>, String> {
+        let mat = |area| self.material(field(json, "material")?, Some(area));
+        let obj: Box<dyn Hittable> = match string(json, "type")? {
+            "sphere" => {
+                let radius = number(field(json, "radius")?)?;
+                if radius == 0.0 {
+                    self.warn("sphere of zero radius".to_string());
+                }
+                Box::new(Sphere::new(vec3(field(json, "center")?)?, radius, mat(4.0 * PI * radius * radius)?))
+            }
+            "rect" => {
+                let plane = match string(json, "plane")? {
+                    "xy" => Plane::Xy,
+                    "xz" => Plane::Xz,
+                    "yz" => Plane::Yz,
+                    other => return Err(format!("unknown plane '{}', expected xy, xz or yz", other)),
+                };
+                let (a, b) = (pair(field(json, "a")?)?, pair(field(json, "b")?)?);
+                let area = ((a.1 - a.0) * (b.1 - b.0)).abs();
+                let rect = AaRect::new(plane, a, b, number(field(json, "k")?)?, mat(area)?);
+                match optional(json, "flipped", boolean)? {
+                    Some(true) => Box::new(rect.flipped()),
+                    _ => Box::new(rect),
+                }
+            }
+            "box" => {
+                let (min, max) = (vec3(field(json, "min")?)?, vec3(field(json, "max")?)?);
+                let size = max - min;
+                let area = 2.0 * (size.x * size.y + size.y * size.z + size.z * size.x).abs();
+                Box::new(cuboid(min, max, mat(area)?))
+            }
+            "triangle" => {
+                let vertices = field(json, "vertices")?.as_array()
+                    .filter(|v| v.len() == 3)
+                    .ok_or("vertices: expected three points")?;
+                let vertices = [vec3(&vertices[0])?, vec3(&vertices[1])?, vec3(&vertices[2])?];
+                let area = 0.5 * cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).length();
+                let triangle = Triangle::new(vertices, mat(area)?).with_culling(culling(json)?);
+                if triangle.is_degenerate() {
+                    self.warn("degenerate triangle".to_string());
+                }
+                Box::new(triangle)
+            }
+            "mesh" => {
+                let path = self.path(json, "path")?;
+                let mat = self.material(field(json, "material")?, None)?;
+                let (triangles, problems) = mesh::load_obj_file(&path, &mat, culling(json)?)
+                    .map_err(|e| format!("failed to load '{}': {}", path, e))?;
+                for problem in problems {
+                    self.warn(format!("'{}': {}", path, problem));
+                }
+                Box::new(Bvh::new(triangles, 0.0, 0.0))
+            }
+            "scatter" => self.scatter(json)?,
+            "instance" => return self.placed(self.named_geometry(string(json, "geometry")?)?, json),
+            "backdrop" => Box::new(Backdrop::new(Arc::new(Lambertian::textured(Arc::new(backdrop_texture(json)?))))),
+            other => return Err(format!("unknown object type '{}'", other)),
+        };
+        let obj: Box<dyn Hittable> = match json.get("material") {
+            Some(mat) if self.is_masked(mat) => Box::new(Cutout::new(obj)),
+            _ => obj,
+        };
+        let obj: Box<dyn Hittable> = match shape(json)? {
+            Some(transform) => Box::new(TransformedHittable::new(Arc::from(obj), transform)),
+            None => obj,
+        };
+
+        let start = placement(json)?;
+        match json.get("motion") {
+            Some(motion) => {
+                let end = placement(motion).map_err(|e| format!("motion: {}", e))?;
+                Ok(Box::new(Moving::new(obj, start, end, 0.0, 1.0)))
+            }
+            None if start == RigidTransform::default() => Ok(obj),
+            None => Ok(Box::new(Instance::new(Arc::from(obj), start))),
+        }
+    }
+
+    // Named geometry is a single object or a list of them, which then
+    // get a hierarchy of their own, shared by every instance.
+    fn geometry(&self, json: &Json) -> Result<Arc<dyn Hittable>, String> {
+        match json.as_array() {
+            Some(specs) => {
+                let objects = specs.iter()
+                    .enumerate()
+                    .map(|(i, spec)| self.object(spec).map_err(|e| format!("[{}]: {}", i, e)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Arc::new(Bvh::new(objects, 0.0, 1.0)))
+            }
+            None => Ok(Arc::from(self.object(json)?)),
+        }
+    }
+
+    fn named_geometry(&self, name: &str) -> Result<Arc<dyn Hittable>, String> {
+        self.geometry.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, obj)| obj.clone())
+            .ok_or_else(|| format!("unknown geometry '{}'", name))
+    }
+
+    // Instances of named geometry are always placed through a transform
+    // of their own, so that the geometry is shared rather than copied.
+    fn placed(&self, obj: Arc<dyn Hittable>, json: &Json) -> Result<Box<dyn Hittable>, String> {
+        let start = placement(json)?;
+        let shape = shape(json)?;
+        if let Some(motion) = json.get("motion") {
+            let end = placement(motion).map_err(|e| format!("motion: {}", e))?;
+            let shaped: Box<dyn Hittable> = match shape {
+                Some(shape) => Box::new(TransformedHittable::new(obj, shape)),
+                None => Box::new(Instance::new(obj, RigidTransform::default())),
+            };
+            return Ok(Box::new(Moving::new(shaped, start, end, 0.0, 1.0)))
+        }
+        match shape {
+            Some(shape) => {
+                let transform = Transform::new(start.matrix() * shape.matrix())
+                    .ok_or("matrix: expected a matrix that can be inverted")?;
+                Ok(Box::new(TransformedHittable::new(obj, transform)))
+            }
+            None => Ok(Box::new(Instance::new(obj, start))),
+        }
+    }
+
+    // Whether the object is a sphere or rectangle giving off light, a
+    // copy of which is then sampled directly. Moving, reshaped or cut out
+    // lights and other shapes are only found by chance, as sampling their
+    // whole shape would aim at holes no light comes from.
+    fn is_sampled_light(&self, json: &Json) -> bool {
+        let shape = matches!(json.get("type"), Some(Json::String(t)) if t == "sphere" || t == "rect");
+        let reshaped = json.get("scale").is_some() || json.get("matrix").is_some();
+        let masked = json.get("material").is_some_and(|m| self.is_masked(m));
+        shape && !reshaped && !masked && json.get("motion").is_none() && field(json, "material")
+            .and_then(|m| self.material(m, Some(1.0)))
+            .is_ok_and(|m| m.emitted() != Color::default())
+    }
+
+    // Copies of an object spread over a region of the ground, at points
+    // at least "spacing" apart ("poisson") or one in every cell of a grid
+    // ("grid", moved by up to "jitter" cells). A "density" texture, with
+    // u along x and v along z, thins the copies out where it is dark.
+    // The object may also be the name of a geometry.
+    fn scatter(&self, json: &Json) -> Result<Box<dyn Hittable>, String> {
+        let since = self.warnings.borrow().len();
+        let object = match field(json, "object")? {
+            Json::String(name) => self.named_geometry(name).map_err(|e| format!("object: {}", e))?,
+            spec => Arc::from(self.object(spec).map_err(|e| format!("object: {}", e))?),
+        };
+        self.prefix_warnings(since, "object");
+        let (x, z) = (pair(field(json, "x")?)?, pair(field(json, "z")?)?);
+        let spacing = number(field(json, "spacing")?)?;
+        if spacing <= 0.0 {
+            return Err("spacing: expected a positive number".to_string())
+        }
+
+        let pattern = match json.get("pattern") {
+            Some(_) => Some(string(json, "pattern")?),
+            None => None,
+        };
+        let points = match pattern {
+            None | Some("poisson") => scatter::poisson_disk(x, z, spacing),
+            Some("grid") => scatter::jittered_grid(x, z, spacing, optional(json, "jitter", number)?.unwrap_or(1.0)),
+            Some(other) => return Err(format!("unknown pattern '{}', expected poisson or grid", other)),
+        };
+        let points = match json.get("density") {
+            Some(density) => {
+                let density = self.texture(density).map_err(|e| format!("density: {}", e))?;
+                scatter::thinned(points, |px, pz| {
+                    let (u, v) = ((px - x.0) / (x.1 - x.0), (pz - z.0) / (z.1 - z.0));
+                    let c = density.value(u, v, &Vec3::new(px, 0.0, pz));
+                    (c.x + c.y + c.z) / 3.0
+                })
+            }
+            None => points,
+        };
+
+        let copies = points.into_iter()
+            .map(|(px, pz)| {
+                let placement = RigidTransform::new(Vec3::new(px, 0.0, pz), Vec3::default());
+                Box::new(Instance::new(object.clone(), placement)) as Box<dyn Hittable>
+            })
+            .collect();
+        Ok(Box::new(Bvh::new(copies, 0.0, 1.0)))
+    }
+}
+
+// The largest coordinate of the box.
+fn reach(bbox: &Aabb) -> Float {
+    (0..3).map(|axis| bbox.min[axis].abs().max(bbox.max[axis].abs())).fold(0.0, Float::max)
+}
+
+// The names of the materials that no object refers to, in the order
+// they are defined in.
+fn unused_materials(root: &Json) -> Vec<String> {
+    fn used<'a>(json: &'a Json, names: &mut Vec<&'a str>) {
+        match json {
+            Json::Object(fields) => for (key, value) in fields.iter() {
+                match value {
+                    Json::String(name) if key == "material" => names.push(name),
+                    _ => used(value, names),
+                }
+            },
+            Json::Array(items) => items.iter().for_each(|item| used(item, names)),
+            _ => {}
+        }
+    }
+    let mut names = Vec::new();
+    for key in ["objects", "geometry"] {
+        if let Some(objects) = root.get(key) {
+            used(objects, &mut names);
+        }
+    }
+    match root.get("materials") {
+        Some(Json::Object(fields)) => fields.iter()
+            .map(|(name, _)| name)
+            .filter(|name| !names.contains(&name.as_str()))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Light is given either as radiance, "emit", or in physical units: as
+// luminance in nits, "luminance", or as the flux of the whole object
+// it is applied to, "lumens" or "watts" at "efficacy" lumens per watt.
+// Physical units may be tinted by a "color".
+fn emission(json: &Json, area: Option<Float>) -> Result<Color, String> {
+    if let Some(emit) = optional(json, "emit", vec3)? {
+        return Ok(emit)
+    }
+
+    let nits = match optional(json, "luminance", number)? {
+        Some(nits) => nits,
+        None => {
+            let lumens = match (optional(json, "lumens", number)?, optional(json, "watts", number)?) {
+                (Some(lumens), _) => lumens,
+                (None, Some(watts)) => watts * optional(json, "efficacy", number)?.unwrap_or(photometry::DEFAULT_EFFICACY),
+                (None, None) => return Err("expected one of emit, luminance, lumens or watts".to_string()),
+            };
+            let area = area.ok_or("lumens and watts need the area of a sphere, rect, box or triangle")?;
+            photometry::luminance_from_flux(lumens, area)
+        }
+    };
+    let color = optional(json, "color", vec3)?.unwrap_or_else(|| Color::new(1.0, 1.0, 1.0));
+    Ok(photometry::with_luminance(color, nits))
+}
+
+fn file_settings(json: &Json) -> Result<FileSettings, String> {
+    Ok(FileSettings {
+        width: optional(json, "width", |v| count(v, 2))?,
+        height: optional(json, "height", |v| count(v, 2))?,
+        samples: optional(json, "samples", |v| count(v, 1))?,
+        max_depth: optional(json, "max_depth", |v| count(v, 1))?,
+        min_throughput: optional(json, "min_throughput", fraction)?,
+        auto_exposure: optional(json, "auto_exposure", metering)?,
+    })
+}
+
+fn metering(json: &Json) -> Result<Metering, String> {
+    let name = json.as_str().ok_or_else(|| "expected a string".to_string())?;
+    Metering::parse(name).ok_or_else(|| format!("unknown metering '{}', expected average or center", name))
+}
+
+// Triangles and meshes may let camera rays through their back faces,
+// with "cull_backfaces", and shadow rays, with "cull_shadow_backfaces".
+fn culling(json: &Json) -> Result<Culling, String> {
+    Ok(Culling {
+        camera: optional(json, "cull_backfaces", boolean)?.unwrap_or(false),
+        shadow: optional(json, "cull_shadow_backfaces", boolean)?.unwrap_or(false),
+    })
+}
+
+// The floor of a backdrop, a "grid" of lines by default, or "checker" or
+// "plain", in the "lines" color over its own "color", every "spacing"
+// apart. The pattern fades out by the "fade" distance from the origin,
+// and the floor may blend into a "horizon" color by "horizon_distance".
+fn backdrop_texture(json: &Json) -> Result<BackdropTexture, String> {
+    let defaults = BackdropTexture::default();
+    let pattern = match json.get("pattern").map(|p| p.as_str().and_then(Pattern::parse)) {
+        None => defaults.pattern,
+        Some(Some(pattern)) => pattern,
+        Some(None) => return Err("pattern: expected grid, checker or plain".to_string()),
+    };
+    let positive = |key: &str, default: Float| match optional(json, key, number)? {
+        Some(x) if x <= 0.0 => Err(format!("{}: expected a positive number", key)),
+        x => Ok(x.unwrap_or(default)),
+    };
+    let horizon = match optional(json, "horizon", vec3)? {
+        Some(color) => Some((color, positive("horizon_distance", 100.0)?)),
+        None => None,
+    };
+    Ok(BackdropTexture {
+        pattern,
+        color: optional(json, "color", vec3)?.unwrap_or(defaults.color),
+        lines: optional(json, "lines", vec3)?.unwrap_or(defaults.lines),
+        spacing: positive("spacing", defaults.spacing)?,
+        fade: positive("fade", defaults.fade)?,
+        horizon,
+    })
+}
+
+// Objects may be stretched by a "scale", one number for every axis or
+// one for each, and then by a 4x4 "matrix" given as a list of rows,
+// which may also shear them, e.g. an ellipsoid from a unit sphere.
+fn shape(json: &Json) -> Result<Option<Transform>, String> {
+    let scale = optional(json, "scale", |s| match number(s) {
+        Ok(k) => Ok(Vec3::new(k, k, k)),
+        Err(_) => vec3(s).map_err(|_| "expected a number or three numbers".to_string()),
+    })?;
+    let m = optional(json, "matrix", matrix)?;
+    if scale.is_none() && m.is_none() {
+        return Ok(None)
+    }
+    if scale.is_some_and(|s| s.x * s.y * s.z == 0.0) {
+        return Err("scale: expected factors other than zero".to_string())
+    }
+    let scaling = Mat4::scaling(scale.unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0)));
+    Transform::new(m.unwrap_or(Mat4::IDENTITY) * scaling)
+        .map(Some)
+        .ok_or_else(|| "matrix: expected a matrix that can be inverted".to_string())
+}
+
+fn placement(json: &Json) -> Result<RigidTransform, String> {
+    let translation = optional(json, "translate", vec3)?.unwrap_or_default();
+    let rotation = optional(json, "rotate", vec3)?.unwrap_or_default();
+    Ok(RigidTransform::new(translation, rotation))
+}
+
+// Cameras are placed either by where they look "from" and "at", with an
+// optional "up" direction, or by a "matrix" from camera to world or a
+// "view_matrix" from world to camera, e.g. of Blender; see
+// `camera::look_from_matrix`. Cameras placed by a matrix are focused
+// one unit ahead unless given a "focus_dist". Animated cameras are
+// placed by "keyframes" instead, see `keyframes`.
+fn camera(json: &Json, time: Float) -> Result<FileCamera, String> {
+    let up = || Ok::<_, String>(optional(json, "up", vec3)?.unwrap_or(Vec3::new(0.0, 1.0, 0.0)));
+    let placements = ["matrix", "view_matrix", "keyframes"].iter().filter(|&&key| json.get(key).is_some()).count();
+    if placements > 1 {
+        return Err("expected one of a matrix, a view_matrix or keyframes".to_string())
+    }
+    let (look_from, look_at, up) = match (optional(json, "matrix", matrix)?, optional(json, "view_matrix", matrix)?) {
+        (Some(m), _) => look_from_matrix(&m).map_err(|e| format!("matrix: {}", e))?,
+        (None, Some(m)) => look_from_matrix(&m.rigid_inverse()).map_err(|e| format!("view_matrix: {}", e))?,
+        (None, None) => match optional(json, "keyframes", |keys| keyframes(keys, time))? {
+            Some((from, at)) => (from, at, up()?),
+            None => (vec3(field(json, "from")?)?, vec3(field(json, "at")?)?, up()?),
+        },
+    };
+    let view = View {
+        look_from,
+        look_at,
+        vfov: optional(json, "vfov", number)?.unwrap_or(20.0),
+        up,
+        aperture: optional(json, "aperture", number)?.unwrap_or(0.0),
+        focus_dist: optional(json, "focus_dist", number)?,
+        projection: optional(json, "projection", projection)?.unwrap_or_default(),
+    };
+    Ok((view, optional(json, "shutter", pair)?, optional(json, "clip", clip)?))
+}
+
+// Where a camera looks from and at by the scene time, given by a list
+// of keyframes, each of a time "t" and of where the camera looks "from"
+// and "at" then, in order of time. The camera glides through them along
+// a smooth curve, and holds still before the first and after the last.
+fn keyframes(json: &Json, time: Float) -> Result<(Point3, Point3), String> {
+    let keys = json.as_array().ok_or("expected an array")?.iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let parsed = (|| Ok::<_, String>((number(field(key, "t")?)?, vec3(field(key, "from")?)?, vec3(field(key, "at")?)?)))();
+            parsed.map_err(|e| format!("[{}]: {}", i, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("expected at least one keyframe".to_string())
+    }
+    if keys.windows(2).any(|pair| pair[1].0 <= pair[0].0) {
+        return Err("expected keyframes in increasing order of t".to_string())
+    }
+    let times: Vec<Float> = keys.iter().map(|key| key.0).collect();
+    let from: Vec<Point3> = keys.iter().map(|key| key.1).collect();
+    let at: Vec<Point3> = keys.iter().map(|key| key.2).collect();
+    Ok((spline(&times, &from, time), spline(&times, &at, time)))
+}
+
+// The point at the time along the Catmull-Rom spline through the points
+// at the given times, which passes through every point with the slope
+// of the line between its neighbors.
+fn spline(times: &[Float], points: &[Point3], time: Float) -> Point3 {
+    let last = points.len() - 1;
+    if time <= times[0] {
+        return points[0]
+    }
+    if time >= times[last] {
+        return points[last]
+    }
+    let i = times.iter().rposition(|&t| t <= time).unwrap_or(0);
+    let slope = |k: usize| {
+        let (a, b) = (k.saturating_sub(1), (k + 1).min(last));
+        (points[b] - points[a]) / (times[b] - times[a])
+    };
+    let dt = times[i + 1] - times[i];
+    let s = (time - times[i]) / dt;
+    let (s2, s3) = (s * s, s * s * s);
+    (2.0 * s3 - 3.0 * s2 + 1.0) * points[i]
+        + (s3 - 2.0 * s2 + s) * dt * slope(i)
+        + (-2.0 * s3 + 3.0 * s2) * points[i + 1]
+        + (s3 - s2) * dt * slope(i + 1)
+}
+
+fn projection(json: &Json) -> Result<Projection, String> {
+    let name = json.as_str().ok_or("expected a string")?;
+    Projection::parse(name).ok_or_else(|| format!("unknown projection '{}'", name))
+}
+
+// Nothing is cut away beyond a clip without a far distance.
+fn clip(json: &Json) -> Result<Clip, String> {
+    let near = optional(json, "near", number)?.unwrap_or(0.0);
+    let far = optional(json, "far", number)?.unwrap_or(INF);
+    if !(near >= 0.0 && far > near) {
+        return Err("expected 0 <= near < far".to_string())
+    }
+    Ok(Clip::new(near, far, optional(json, "cap", boolean)?.unwrap_or(false)))
+}
+
+// Replaces every string starting with `=` by the value of the expression
+// after it, returning whether any of them depends on the time.
+fn evaluate_expressions(json: &mut Json, time: Float) -> Result<bool, String> {
+    let value = match json {
+        Json::String(s) => match s.strip_prefix('=') {
+            Some(text) => expr::evaluate(text, to_f64(time))?,
+            None => return Ok(false),
+        },
+        Json::Array(items) => return items.iter_mut().try_fold(false, |any, item| Ok(evaluate_expressions(item, time)? || any)),
+        Json::Object(fields) => return fields.iter_mut().try_fold(false, |any, (_, item)| Ok(evaluate_expressions(item, time)? || any)),
+        _ => return Ok(false),
+    };
+    *json = Json::Number(value.0);
+    Ok(value.1)
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.get(key).ok_or_else(|| format!("missing field '{}'", key))
+}
+
+// A field that may be absent, converted when present.
+fn optional<T, F>(json: &Json, key: &str, convert: F) -> Result<Option<T>, String>
+where
+    F: Fn(&Json) -> Result<T, String>,
+{
+    match json.get(key) {
+        Some(v) => convert(v).map(Some).map_err(|e| format!("{}: {}", key, e)),
+        None => Ok(None),
+    }
+}
+
+// An array that may be absent, in which case it is empty.
+fn list<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    match json.get(key) {
+        Some(v) => v.as_array().ok_or_else(|| format!("{}: expected an array", key)),
+        None => Ok(&[]),
+    }
+}
+
+fn string<'a>(json: &'a Json, key: &str) -> Result<&'a str, String> {
+    field(json, key)?.as_str().ok_or_else(|| format!("{}: expected a string", key))
+}
+
+fn number(json: &Json) -> Result<Float, String> {
+    json.as_f64().map(|n| n as Float).ok_or_else(|| "expected a number".to_string())
+}
+
+// How the microfacets of a rough material are drawn, as "visible" (the
+// default) or "distribution" for comparing against.
+fn sampling(json: &Json) -> Result<Sampling, String> {
+    match json.get("sampling").map(|s| s.as_str()) {
+        None | Some(Some("visible")) => Ok(Sampling::Visible),
+        Some(Some("distribution")) => Ok(Sampling::Distribution),
+        _ => Err("sampling: expected visible or distribution".to_string()),
+    }
+}
+
+// A list of "stops", each a position and a color, with an optional
+// "interpolation": "constant", "linear" (the default) or "smooth".
+fn ramp(json: &Json) -> Result<ColorRamp, String> {
+    let stops = field(json, "stops")?.as_array().ok_or("stops: expected an array")?.iter()
+        .enumerate()
+        .map(|(i, stop)| match stop.as_array() {
+            Some([position, color]) => Ok((number(position)?, vec3(color)?)),
+            _ => Err("expected a position and a color".to_string()),
+        }.map_err(|e| format!("stops[{}]: {}", i, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let interpolation = match json.get("interpolation").map(|i| i.as_str()) {
+        None | Some(Some("linear")) => Interpolation::Linear,
+        Some(Some("constant")) => Interpolation::Constant,
+        Some(Some("smooth")) => Interpolation::Smooth,
+        _ => return Err("interpolation: expected constant, linear or smooth".to_string()),
+    };
+    Ok(ColorRamp::new(stops, interpolation))
+}
+
+fn boolean(json: &Json) -> Result<bool, String> {
+    json.as_bool().ok_or_else(|| "expected true or false".to_string())
+}
+
+fn fraction(json: &Json) -> Result<Float, String> {
+    match number(json)? {
+        x if x > 0.0 && x <= 1.0 => Ok(x),
+        _ => Err("expected a number in (0, 1]".to_string()),
+    }
+}
+
+fn count(json: &Json, min: usize) -> Result<usize, String> {
+    match json.as_f64() {
+        Some(n) if n.fract() == 0.0 && n >= min as f64 => Ok(n as usize),
+        _ => Err(format!("expected a whole number of at least {}", min)),
+    }
+}
+
+// A sun towards "direction", whose light falling straight onto a surface
+// has the "irradiance" given as a color, scaled by an "intensity". Its
+// "diameter" in degrees and "limb_darkening" are those of the real sun
+// by default.
+fn sun(json: &Json) -> Result<Sun, String> {
+    let direction = vec3(field(json, "direction")?).map_err(|e| format!("direction: {}", e))?;
+    if direction.length() == 0.0 {
+        return Err("direction: expected a non-zero vector".to_string())
+    }
+    let irradiance = vec3(field(json, "irradiance")?).map_err(|e| format!("irradiance: {}", e))?;
+    let diameter = optional(json, "diameter", number)?.unwrap_or(SUN_DIAMETER);
+    if !(diameter > 0.0 && diameter < 180.0) {
+        return Err("diameter: expected degrees between 0 and 180".to_string())
+    }
+    let limb_darkening = optional(json, "limb_darkening", number)?.unwrap_or(SUN_LIMB_DARKENING);
+    if !(0.0..=1.0).contains(&limb_darkening) {
+        return Err("limb_darkening: expected a number from 0 to 1".to_string())
+    }
+    let intensity = optional(json, "intensity", number)?.unwrap_or(1.0);
+    Ok(Sun::new(direction, diameter, intensity * irradiance, limb_darkening))
+}
+
+// A light of no size, of "type" point at a "position", directional towards
+// a "direction", or spot at a "position" pointing in a "direction". Point
+// and spot lights give an "intensity", the irradiance at a distance of 1,
+// and directional ones an "irradiance", both as colors. Spots shine into
+// a cone of half "angle" degrees, 30 by default, fading out from
+// "inner_angle", 80% of the angle by default.
+fn light(json: &Json) -> Result<Light, String> {
+    let direction = |json: &Json| -> Result<Vec3, String> {
+        let direction = vec3(field(json, "direction")?).map_err(|e| format!("direction: {}", e))?;
+        if direction.length() == 0.0 {
+            return Err("direction: expected a non-zero vector".to_string())
+        }
+        Ok(direction)
+    };
+    let position = |json: &Json| vec3(field(json, "position")?).map_err(|e| format!("position: {}", e));
+    let color = |key: &str| vec3(field(json, key)?).map_err(|e| format!("{}: {}", key, e));
+    match string(json, "type")? {
+        "point" => Ok(Light::point(position(json)?, color("intensity")?)),
+        "directional" => Ok(Light::directional(direction(json)?, color("irradiance")?)),
+        "spot" => {
+            let angle = optional(json, "angle", number)?.unwrap_or(30.0);
+            if !(angle > 0.0 && angle < 90.0) {
+                return Err("angle: expected degrees between 0 and 90".to_string())
+            }
+            let inner_angle = optional(json, "inner_angle", number)?.unwrap_or(0.8 * angle);
+            if !(0.0..=angle).contains(&inner_angle) {
+                return Err("inner_angle: expected degrees from 0 to the angle".to_string())
+            }
+            Ok(Light::spot(position(json)?, direction(json)?, color("intensity")?, inner_angle, angle))
+        }
+        other => Err(format!("unknown light type '{}'", other)),
+    }
+}
+
+fn pair(json: &Json) -> Result<(Float, Float), String> {
+    match json.as_array() {
+        Some([a, b]) => Ok((number(a)?, number(b)?)),
+        _ => Err("expected two numbers".to_string()),
+    }
+}
+
+fn matrix(json: &Json) -> Result<Mat4, String> {
+    let rows = json.as_array().filter(|rows| rows.len() == 4).ok_or("expected four rows of four numbers")?;
+    let mut m = Mat4::default();
+    for (i, row) in rows.iter().enumerate() {
+        match row.as_array() {
+            Some([a, b, c, d]) => m[i] = [number(a)?, number(b)?, number(c)?, number(d)?],
+            _ => return Err("expected four rows of four numbers".to_string()),
+        }
+    }
+    Ok(m)
+}
+
+fn vec3(json: &Json) -> Result<Vec3, String> {
+    match json.as_array() {
+        Some([x, y, z]) => Ok(Vec3::new(number(x)?, number(y)?, number(z)?)),
+        _ => Err("expected three numbers".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::vec::Color;
+
+    const SCENE: &str = r#"{
+        "settings": { "width": 320, "samples": 16, "min_throughput": 0.01, "auto_exposure": "center" },
+        "background": [0, 0, 0],
+        "cameras": [{ "from": [0, 0, 5], "at": [0, 0, 0], "vfov": 40, "shutter": [0, 0.5] }],
+        "materials": {
+            "gray": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] },
+            "floor": { "type": "metal", "fuzz": 0.1,
+                       "albedo": { "type": "checker", "even": [1, 1, 1], "odd": [0, 0, 0] } }
+        },
+        "objects": [
+            { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gray" },
+            { "type": "rect", "plane": "xz", "a": [-5, 5], "b": [-5, 5], "k": -1, "material": "floor" },
+            { "type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "translate": [3, 0, 0],
+              "material": { "type": "light", "emit": [4, 4, 4] } },
+            { "type": "triangle", "vertices": [[0, 0, -3], [1, 0, -3], [0, 1, -3]], "material": "gray",
+              "cull_shadow_backfaces": true, "motion": { "translate": [0, 2, 0] } },
+            { "type": "scatter", "x": [10, 20], "z": [0, 4], "spacing": 2, "pattern": "grid", "jitter": 0,
+              "translate": [0, -10, 0], "density": { "type": "checker", "even": [1, 1, 1], "odd": [0, 0, 0], "scale": 0.5 },
+              "object": { "type": "sphere", "center": [0, 0, 0], "radius": 0.5, "material": "gray" } }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_scene() {
+        let file = SceneFile::parse(SCENE, Path::new("")).unwrap();
+        let settings = FileSettings {
+            width: Some(320),
+            samples: Some(16),
+            min_throughput: Some(0.01),
+            auto_exposure: Some(Metering::CenterWeighted),
+            ..Default::default()
+        };
+        assert_eq!(settings, file.settings);
+        assert_eq!(5, file.world.objects.len());
+
+        let scene = file.into_scene(2.0);
+        assert_eq!(Background::Solid(Color::default()), scene.background);
+        assert_eq!(1, scene.cameras.len());
+        assert_eq!((0.0, 0.5), scene.cameras[0].shutter());
+
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert_eq!(Some(4.0), scene.world.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t));
+        // The sphere and the triangle behind it share their material.
+        let sphere = scene.world.hit(&r, 0.001, Float::INFINITY).unwrap();
+        let back = Ray::new(&Point3::new(0.2, 0.2, -5.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let triangle = scene.world.hit(&back, 0.001, Float::INFINITY).unwrap();
+        assert_eq!(2.0, triangle.t);
+        assert!(std::ptr::addr_eq(sphere.mat, triangle.mat));
+        // Shadow rays pass through the back of the triangle to the sphere.
+        let shadow = Ray { shadow: true, ..back };
+        assert!(scene.world.hit(&shadow, 0.001, Float::INFINITY).unwrap().t > 3.0);
+        let r = Ray::new(&Point3::new(3.5, 0.5, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert_eq!(Some(4.0), scene.world.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t));
+
+        // Scattered spheres are kept on the white squares of the density
+        // map and dropped on the black ones.
+        let down = |x, z| Ray::new(&Point3::new(x, 0.0, z), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert_eq!(Some(9.5), scene.world.hit(&down(12.0, 0.0), 0.001, Float::INFINITY).map(|rec| rec.t));
+        assert!(scene.world.hit(&down(10.0, 0.0), 0.001, Float::INFINITY).is_none());
+        assert!(scene.world.hit(&down(12.0, 2.0), 0.001, Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_light_units() {
+        let file = SceneFile::parse(r#"{
+            "materials": { "panel": { "type": "light", "lumens": 3141.59 } },
+            "objects": [
+                { "type": "rect", "plane": "xy", "a": [0, 1], "b": [0, 1], "k": 0, "material": "panel" },
+                { "type": "rect", "plane": "xy", "a": [0, 2], "b": [0, 2], "k": -1, "material": "panel" },
+                { "type": "sphere", "center": [5, 0, 0], "radius": 1,
+                  "material": { "type": "light", "luminance": 500, "color": [1, 0, 0] } },
+                { "type": "sphere", "center": [10, 0, 0], "radius": 1,
+                  "material": { "type": "light", "watts": 10, "efficacy": 15 } }
+            ]
+        }"#, Path::new("")).unwrap();
+        let emitted = |x: Float| {
+            let r = Ray::new(&Point3::new(x, 0.5, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            file.world.hit(&r, 0.001, Float::INFINITY).unwrap().mat.emitted()
+        };
+
+        // The same flux spread over four times the area is a quarter as bright.
+        assert!((emitted(0.5).x - 1000.0).abs() < 0.01);
+        assert!((emitted(1.5).x - 250.0).abs() < 0.01);
+        assert!((emitted(5.0).x - 500.0 / 0.2126).abs() < 0.01);
+        assert_eq!(0.0, emitted(5.0).y);
+        assert!((emitted(10.0).x - 150.0 / (4.0 * PI * PI)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_gradient_background() {
+        let scene = SceneFile::parse(r#"{"background": {"type": "gradient", "bottom": [1, 0, 0], "top": [0, 0, 1]}}"#, Path::new("")).unwrap();
+        let gradient = Background::Gradient { bottom: Color::new(1.0, 0.0, 0.0), top: Color::new(0.0, 0.0, 1.0) };
+        assert_eq!(gradient, scene.background);
+
+        let scene = SceneFile::parse(r#"{"background": [{"type": "sky", "intensity": 0.5}, [0, 0, 1]]}"#, Path::new("")).unwrap();
+        let dimmed = Background::Gradient { bottom: 0.5 * SKY_HORIZON, top: 0.5 * SKY_ZENITH };
+        assert_eq!(Background::Combined(vec![dimmed, Background::Solid(Color::new(0.0, 0.0, 1.0))]), scene.background);
+    }
+
+    #[test]
+    fn test_sun() {
+        let scene = SceneFile::parse(r#"{"sun": {"direction": [1, 1, 0], "irradiance": [1.5, 1.5, 1.5], "intensity": 2}}"#, Path::new("")).unwrap();
+        let sun = Sun::new(Vec3::new(1.0, 1.0, 0.0), SUN_DIAMETER, Color::new(3.0, 3.0, 3.0), SUN_LIMB_DARKENING);
+        assert_eq!(Some(sun), scene.sun);
+        assert_eq!(None, SceneFile::parse("{}", Path::new("")).unwrap().sun);
+    }
+
+    #[test]
+    fn test_camera_matrices() {
+        // The same camera, rolled to the side, placed in every way.
+        let scene = SceneFile::parse(r#"{"cameras": [
+            {"from": [0, 0, 5], "at": [0, 0, 0], "up": [1, 1, 0]},
+            {"matrix": [[0.7071068, 0.7071068, 0, 0], [-0.7071068, 0.7071068, 0, 0], [0, 0, 1, 5], [0, 0, 0, 1]]},
+            {"view_matrix": [[0.7071068, -0.7071068, 0, 0], [0.7071068, 0.7071068, 0, 0], [0, 0, 1, -5], [0, 0, 0, 1]]}
+        ]}"#, Path::new("")).unwrap();
+        let matrices: Vec<Mat4> = scene.into_scene(1.0).cameras.iter().map(Camera::matrix).collect();
+        for m in matrices[1..].iter() {
+            for (a, b) in m.0.iter().flatten().zip(matrices[0].0.iter().flatten()) {
+                assert!((a - b).abs() < 1e-6, "{:?} vs {:?}", m, matrices[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let text = r#"{"cameras": [{"keyframes": [
+            {"t": 0, "from": [0, 1, 10], "at": [0, 0, 0]},
+            {"t": 1, "from": [10, 1, 0], "at": [0, 0, 0]},
+            {"t": 2, "from": [0, 1, -10], "at": [0, 1, 0]}
+        ]}]}"#;
+        let at = |time| {
+            let file = SceneFile::parse_with(text, Path::new(""), &[], time).unwrap();
+            assert!(file.animated());
+            let view = file.cameras[0].0;
+            (view.look_from, view.look_at)
+        };
+        // Through every keyframe, held still outside of them.
+        assert_eq!((Point3::new(10.0, 1.0, 0.0), Point3::default()), at(1.0));
+        assert_eq!((Point3::new(0.0, 1.0, -10.0), Point3::new(0.0, 1.0, 0.0)), at(3.0));
+        assert_eq!(at(0.0), at(-1.0));
+        // Curving around rather than cutting the corner.
+        let (from, _) = at(0.5);
+        assert!(from.x + from.z > 10.5, "{:?}", from);
+    }
+
+    #[test]
+    fn test_lights() {
+        let scene = SceneFile::parse(r#"{"lights": [
+            {"type": "point", "position": [0, 2, 0], "intensity": [4, 4, 4]},
+            {"type": "directional", "direction": [0, 1, 1], "irradiance": [1, 1, 1]},
+            {"type": "spot", "position": [0, 3, 0], "direction": [0, -1, 0], "intensity": [9, 9, 9], "angle": 20}
+        ]}"#, Path::new("")).unwrap();
+        let white = Color::new(1.0, 1.0, 1.0);
+        assert_eq!(vec![
+            Light::point(Point3::new(0.0, 2.0, 0.0), 4.0 * white),
+            Light::directional(Vec3::new(0.0, 1.0, 1.0), white),
+            Light::spot(Point3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 9.0 * white, 16.0, 20.0),
+        ], scene.punctual_lights);
+    }
+
+    #[test]
+    fn test_clip() {
+        let file = SceneFile::parse(r#"{
+            "cameras": [{ "from": [0, 0, 5], "at": [0, 0, 0], "clip": { "near": 5, "cap": true } }],
+            "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": { "type": "lambertian", "albedo": [1, 1, 1] } }]
+        }"#, Path::new("")).unwrap();
+        let scene = file.into_scene(1.0);
+        let world = scene.cameras[0].clipped(&scene.world).unwrap();
+        let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert_eq!(Some(5.0), world.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.t));
+    }
+
+    #[test]
+    fn test_load_material() {
+        let mat = load_material(r#"{ "type": "light", "lumens": 1000 }"#).unwrap();
+        assert!((mat.emitted().x - 1000.0 / (4.0 * PI * PI)).abs() < 0.01);
+        assert_eq!("unknown material type 'glass'", load_material(r#"{ "type": "glass" }"#).err().unwrap());
+        assert!(load_material(r#"{ "type": "metal", "albedo": [1, 1, 1], "roughness": 0.3, "sampling": "distribution" }"#).is_ok());
+        let naive = r#"{ "type": "dielectric", "ior": 1.5, "roughness": 0.3, "sampling": "naive" }"#;
+        assert_eq!("sampling: expected visible or distribution", load_material(naive).err().unwrap());
+        assert!(load_material("missing.json").is_err());
+    }
+
+    #[test]
+    fn test_load_texture() {
+        let checker = load_texture(r#"{ "type": "checker", "even": [1, 1, 1], "odd": [0, 0, 0] }"#).unwrap();
+        assert_eq!(Color::new(1.0, 1.0, 1.0), checker.value(0.0, 0.0, &Point3::new(0.5, 0.5, 0.5)));
+        assert!(load_texture(r#"{ "type": "plaid" }"#).is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let text = r#"{
+            "materials": { "gold": { "type": "metal", "albedo": [0.8, 0.6, 0.2] } },
+            "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gold" }]
+        }"#;
+        let albedo = |overrides: &[Override]| {
+            let file = SceneFile::parse_with(text, Path::new(""), overrides, 0.0).unwrap();
+            let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let rec = file.world.hit(&r, 0.001, Float::INFINITY).unwrap();
+            let (mut attenuation, mut scattered) = (Color::default(), r);
+            rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered).then_some(attenuation)
+        };
+        assert_eq!(Some(Color::new(0.8, 0.6, 0.2)), albedo(&[]));
+
+        let sweep = Override::parse_sweep("material.gold.albedo=[1, 0, 0], [0, 0, 1]").unwrap();
+        assert_eq!(Some(Color::new(0.0, 0.0, 1.0)), albedo(&sweep[1..]));
+        let smooth = Override::parse("material.gold.roughness=0").unwrap();
+        assert_eq!(Some(Color::new(1.0, 0.0, 0.0)), albedo(&[sweep[0].clone(), smooth]));
+
+        let err = |s: &str| SceneFile::parse_with(text, Path::new(""), &[Override::parse(s).unwrap()], 0.0).err().unwrap();
+        assert_eq!("no material 'silver' to override", err("material.silver.roughness=0"));
+        assert_eq!("materials.gold: unknown material type 'glass'", err(r#"material.gold.type="glass""#));
+    }
+
+    #[test]
+    fn test_backdrop() {
+        let file = SceneFile::parse(r#"{"objects": [{ "type": "backdrop", "pattern": "checker", "color": [1, 1, 1],
+            "lines": [0, 0, 0], "horizon": [0, 0, 1], "translate": [0, -1, 0] }]}"#, Path::new("")).unwrap();
+        let down = |x: Float| Ray::new(&Point3::new(x, 1.0, 0.5), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let albedo = |x: Float| {
+            let rec = file.world.hit(&down(x), 0.001, Float::INFINITY).unwrap();
+            assert_eq!(2.0, rec.t);
+            rec.mat.diffuse_albedo(&rec).unwrap()
+        };
+        assert_eq!(Color::new(1.0, 1.0, 1.0), albedo(0.5));
+        assert_eq!(Color::new(0.0, 0.0, 0.0), albedo(1.5));
+        assert_eq!(Color::new(0.0, 0.0, 1.0), albedo(1000.5));
+    }
+
+    #[test]
+    fn test_reshaped_objects() {
+        // An ellipsoid twice as wide as it is tall, on its side and moved
+        // away, and a sheared box leaning over towards x.
+        let file = SceneFile::parse(r#"{"objects": [
+            { "type": "sphere", "center": [0, 0, 0], "radius": 1, "scale": [2, 1, 1], "rotate": [0, 90, 0],
+              "translate": [0, 0, -10], "material": { "type": "light", "emit": [1, 1, 1] } },
+            { "type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "translate": [5, 0, 0],
+              "matrix": [[1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]], "material": { "type": "lambertian", "albedo": [1, 1, 1] } }
+        ]}"#, Path::new("")).unwrap();
+        let hit = |orig: Point3, dir: Vec3| file.world.hit(&Ray::new(&orig, &dir, 0.0), 0.001, Float::INFINITY).map(|rec| rec.p);
+        let p = hit(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((p.z + 8.0).abs() < 1e-4, "{:?}", p);
+        let p = hit(Point3::new(0.0, 5.0, -11.5), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((p.y - (1.0 - 0.75 * 0.75 as Float).sqrt()).abs() < 1e-4, "{:?}", p);
+        let p = hit(Point3::new(10.0, 0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((p.x - 6.5).abs() < 1e-4, "{:?}", p);
+        // Reshaped lights aren't sampled.
+        assert!(file.lights.objects.is_empty());
+    }
+
+    #[test]
+    fn test_instances() {
+        // A pebble of two spheres, placed twice by hand and then four
+        // times over a grid, all sharing the one hierarchy.
+        let file = SceneFile::parse(r#"{
+            "materials": { "stone": { "type": "lambertian", "albedo": [0.4, 0.4, 0.4] } },
+            "geometry": { "pebble": [
+                { "type": "sphere", "center": [0, 0, 0], "radius": 0.5, "material": "stone" },
+                { "type": "sphere", "center": [0, 0.5, 0], "radius": 0.25, "material": "stone" }
+            ] },
+            "objects": [
+                { "type": "instance", "geometry": "pebble", "translate": [10, 0, 0] },
+                { "type": "instance", "geometry": "pebble", "translate": [-10, 0, 0], "rotate": [0, 0, 90], "scale": 2 },
+                { "type": "scatter", "x": [0, 4], "z": [0, 4], "spacing": 2, "pattern": "grid", "jitter": 0,
+                  "translate": [0, -10, 0], "object": "pebble" }
+            ]
+        }"#, Path::new("")).unwrap();
+        assert!(file.warnings().is_empty(), "{:?}", file.warnings());
+        let hit = |orig: Point3| {
+            let r = Ray::new(&orig, &Vec3::new(0.0, -1.0, 0.0), 0.0);
+            file.world.hit(&r, 0.001, Float::INFINITY).map(|rec| rec.p.y)
+        };
+        assert_eq!(Some(0.75), hit(Point3::new(10.0, 5.0, 0.0)));
+        // Turned onto its side and doubled, the small sphere sticks out to -x.
+        assert!((hit(Point3::new(-10.0, 5.0, 0.0)).unwrap() - 1.0).abs() < 1e-4);
+        assert!((hit(Point3::new(-11.0, 5.0, 0.0)).unwrap() - 0.5).abs() < 1e-4);
+        assert_eq!(Some(-9.25), hit(Point3::new(2.0, 5.0, 2.0)));
+
+        let err = SceneFile::parse(r#"{"objects": [{"type": "instance", "geometry": "boulder"}]}"#, Path::new("")).err();
+        assert_eq!(Some("objects[0]: unknown geometry 'boulder'".to_string()), err);
+    }
+
+    #[test]
+    fn test_cutouts() {
+        // A fence of squares with every other one cut out, in front of a wall.
+        let file = SceneFile::parse(r#"{
+            "materials": {
+                "fence": { "type": "lambertian", "albedo": [0.5, 0.3, 0.1],
+                           "opacity": { "type": "checker", "even": [1, 1, 1], "odd": [0, 0, 0] }, "cutoff": 0.5 },
+                "wall": { "type": "lambertian", "albedo": [0.8, 0.8, 0.8] }
+            },
+            "objects": [
+                { "type": "rect", "plane": "xy", "a": [-4, 4], "b": [0, 1], "k": 0, "material": "fence" },
+                { "type": "rect", "plane": "xy", "a": [-4, 4], "b": [0, 1], "k": -2, "material": "wall" }
+            ]
+        }"#, Path::new("")).unwrap();
+        let scene = file.into_scene(1.0);
+        let hit = |x: Float| scene.raycast(&Ray::new(&Point3::new(x, 0.5, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0)).map(|rec| rec.p.z);
+        assert_eq!((Some(0.0), Some(-2.0)), (hit(0.5), hit(1.5)));
+        // Light gets through the holes to the wall too.
+        assert!(scene.occluded(Point3::new(0.5, 0.5, 5.0), Point3::new(0.5, 0.5, -2.0)));
+        assert!(!scene.occluded(Point3::new(1.5, 0.5, 5.0), Point3::new(1.5, 0.5, -2.0)));
+
+        // Lights cut out are not sampled, unlike those without holes.
+        let file = SceneFile::parse(r#"{
+            "materials": { "grille": { "type": "light", "emit": [4, 4, 4], "opacity": 0.5 } },
+            "objects": [
+                { "type": "rect", "plane": "xz", "a": [-1, 1], "b": [-1, 1], "k": 2, "material": "grille" },
+                { "type": "rect", "plane": "xz", "a": [-1, 1], "b": [-1, 1], "k": 3, "material": { "type": "light", "emit": [4, 4, 4] } }
+            ]
+        }"#, Path::new("")).unwrap();
+        assert_eq!(1, file.lights.objects.len());
+        let below = Point3::new(0.0, 0.0, 0.0);
+        assert!(file.lights.objects[0].pdf_value(&below, &Vec3::new(0.0, 1.0, 0.0)) > 0.0);
+
+        let err = SceneFile::parse(r#"{"materials": {"leaf": {"type": "lambertian", "albedo": [0, 1, 0], "opacity": 0.5, "cutoff": 2}}}"#, Path::new("")).err();
+        assert_eq!(Some("materials.leaf: cutoff: expected a number from 0 to 1".to_string()), err);
+        let err = SceneFile::parse(r#"{"materials": {"leaf": {"type": "lambertian", "albedo": [0, 1, 0], "opacity": "leaf.png"}}}"#, Path::new("")).err();
+        assert!(err.unwrap().starts_with("materials.leaf: opacity: "));
+    }
+
+    #[test]
+    fn test_pick() {
+        let file = SceneFile::parse(r#"{
+            "materials": { "gold": { "type": "metal", "albedo": [0.8, 0.6, 0.2] } },
+            "objects": [
+                { "type": "sphere", "center": [0, 0, -10], "radius": 1, "material": "gold", "name": "teapot" },
+                { "type": "sphere", "center": [0, 0, -5], "radius": 1, "material": { "type": "dielectric", "ior": 1.5 } }
+            ]
+        }"#, Path::new("")).unwrap();
+        let names = file.names().clone();
+        let scene = file.into_scene(1.0);
+        let pick = |orig: Point3| scene.pick(&Ray::new(&orig, &Vec3::new(0.0, 0.0, -1.0), 0.0));
+
+        let (i, rec) = pick(Point3::new(0.0, 0.0, 0.0)).unwrap();
+        assert_eq!((1, 4.0), (i, rec.t));
+        assert_eq!((Some("objects[1] (sphere)"), None), (names.object(i), names.material(rec.mat)));
+        let (i, rec) = pick(Point3::new(0.0, 0.0, -7.0)).unwrap();
+        assert_eq!((0, 2.0), (i, rec.t));
+        assert_eq!((Some("objects[0] (sphere \"teapot\")"), Some("gold")), (names.object(i), names.material(rec.mat)));
+        assert!(pick(Point3::new(5.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_expressions() {
+        let text = r#"{
+            "objects": [{ "type": "sphere", "center": [0, 0, 0], "radius": "=0.5 + t",
+                          "material": { "type": "light", "emit": ["=2 * 2", 0, 0] } }]
+        }"#;
+        let at = |time| SceneFile::parse_with(text, Path::new(""), &[], time).unwrap();
+        let hit = |file: &SceneFile| {
+            let r = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+            file.world.hit(&r, 0.001, Float::INFINITY).map(|rec| (rec.t, rec.mat.emitted()))
+        };
+        assert_eq!(Some((4.5, Color::new(4.0, 0.0, 0.0))), hit(&at(0.0)));
+        assert_eq!(Some((4.0, Color::new(4.0, 0.0, 0.0))), hit(&at(0.5)));
+        assert!(at(0.0).animated());
+        assert!(!SceneFile::parse(r#"{"settings": {"samples": "=4 * 4"}}"#, Path::new("")).unwrap().animated());
+
+        let err = SceneFile::parse(r#"{"settings": {"samples": "=4 *"}}"#, Path::new("")).err().unwrap();
+        assert!(err.contains("unexpected end of expression"));
+    }
+
+    #[test]
+    fn test_scene_errors() {
+        let err = |text: &str| SceneFile::parse(text, Path::new("")).err().unwrap();
+        assert_eq!("objects[0]: unknown material 'gold'", err(r#"{"objects": [
+            {"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gold"}]}"#));
+        assert_eq!("objects[0]: missing field 'radius'", err(r#"{"objects": [
+            {"type": "sphere", "center": [0, 0, 0], "material": {"type": "dielectric", "ior": 1.5}}]}"#));
+        assert_eq!("cameras[0]: vfov: expected a number", err(r#"{"cameras": [
+            {"from": [0, 0, 1], "at": [0, 0, 0], "vfov": "wide"}]}"#));
+        assert_eq!("settings: width: expected a whole number of at least 2", err(r#"{"settings": {"width": 1.5}}"#));
+        assert_eq!("cameras[0]: matrix: expected four rows of four numbers", err(r#"{"cameras": [{"matrix": [[1, 0, 0, 0]]}]}"#));
+        assert_eq!("cameras[0]: matrix: expected a rotation and translation, without scale, shear or mirroring", err(r#"{"cameras": [
+            {"matrix": [[2, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]}]}"#));
+        assert_eq!("lights[0]: inner_angle: expected degrees from 0 to the angle", err(r#"{"lights": [{"type": "spot",
+            "position": [0, 1, 0], "direction": [0, -1, 0], "intensity": [1, 1, 1], "angle": 10, "inner_angle": 15}]}"#));
+        assert_eq!("objects[0]: object: missing field 'radius'", err(r#"{"objects": [{"type": "scatter",
+            "x": [0, 1], "z": [0, 1], "spacing": 0.5, "object": {"type": "sphere", "center": [0, 0, 0]}}]}"#));
+        assert_eq!("objects[0]: lumens and watts need the area of a sphere, rect, box or triangle", err(r#"{"objects": [
+            {"type": "mesh", "path": "a.obj", "material": {"type": "light", "lumens": 100}}]}"#));
+        assert_eq!("objects[0]: pattern: expected grid, checker or plain", err(r#"{"objects": [{"type": "backdrop", "pattern": "dots"}]}"#));
+        assert_eq!("objects[0]: fade: expected a positive number", err(r#"{"objects": [{"type": "backdrop", "fade": 0}]}"#));
+        assert_eq!("objects[0]: scale: expected factors other than zero", err(r#"{"objects": [
+            {"type": "sphere", "center": [0, 0, 0], "radius": 1, "scale": [1, 0, 1], "material": "gray"}],
+            "materials": {"gray": {"type": "lambertian", "albedo": [1, 1, 1]}}}"#));
+        assert_eq!("objects[0]: matrix: expected a matrix that can be inverted", err(r#"{"objects": [
+            {"type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "matrix": [[1, 0, 0, 0], [1, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]],
+             "material": {"type": "lambertian", "albedo": [1, 1, 1]}}]}"#));
+        assert_eq!("background: unknown background type 'hdri'", err(r#"{"background": {"type": "hdri"}}"#));
+        assert_eq!("background: [1]: unknown background type 'hdri'", err(r#"{"background": ["sky", {"type": "hdri"}]}"#));
+        assert_eq!("objects[0]: missing field 'mask'", err(r#"{"objects": [{"type": "sphere", "center": [0, 0, 0], "radius": 1,
+            "material": {"type": "lambertian", "albedo": {"type": "mix", "a": [1, 1, 1], "b": [0, 0, 0]}}}]}"#));
+        assert_eq!("cameras[0]: clip: expected 0 <= near < far", err(r#"{"cameras": [
+            {"from": [0, 0, 1], "at": [0, 0, 0], "clip": {"near": 2, "far": 1}}]}"#));
+        assert_eq!("sun: diameter: expected degrees between 0 and 180", err(r#"{"sun": {
+            "direction": [0, 1, 0], "irradiance": [1, 1, 1], "diameter": 0}}"#));
+        assert!(err(r#"{"background": {"type": "environment", "path": "missing.hdr"}}"#).contains("failed to load"));
+        assert_eq!("materials.tint: absorption: expected a color within (0, 1]", err(r#"{"materials": {
+            "tint": {"type": "dielectric", "ior": 1.5, "absorption": [0, 0.5, 1]}}}"#));
+        assert_eq!("cameras[0]: keyframes: expected keyframes in increasing order of t", err(r#"{"cameras": [{"keyframes": [
+            {"t": 1, "from": [0, 0, 1], "at": [0, 0, 0]}, {"t": 0, "from": [0, 0, 2], "at": [0, 0, 0]}]}]}"#));
+        assert_eq!("cameras[0]: keyframes: [0]: missing field 'at'", err(r#"{"cameras": [{"keyframes": [{"t": 0, "from": [0, 0, 1]}]}]}"#));
+        assert_eq!("materials.prism: abbe: expected a positive number", err(r#"{"materials": {
+            "prism": {"type": "dielectric", "ior": 1.5, "abbe": 0}}}"#));
+        assert_eq!("objects[0]: ramp: stops[1]: expected a position and a color", err(r#"{"objects": [
+            {"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": {"type": "lambertian",
+             "albedo": {"type": "wood", "ramp": {"stops": [[0, [1, 1, 1]], [1]]}}}}]}"#));
+        assert!(err("[]").contains("top level"));
+        assert!(err("{").contains("line 1"));
+    }
+
+    #[test]
+    fn test_warnings() {
+        assert!(SceneFile::parse(SCENE, Path::new("")).unwrap().warnings().is_empty());
+        let file = SceneFile::parse(r#"{
+            "materials": {
+                "gray": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] },
+                "spare": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }
+            },
+            "objects": [
+                { "type": "sphere", "center": [0, 0, 0], "radius": 0, "material": "gray" },
+                { "type": "triangle", "vertices": [[0, 0, 0], [1, 0, 0], [2, 0, 0]], "material": "gray" },
+                { "type": "sphere", "center": [0, 0, 0], "radius": 1, "translate": [3e6, 0, 0], "material": "gray" },
+                { "type": "scatter", "x": [0, 1], "z": [0, 1], "spacing": 2,
+                  "object": { "type": "sphere", "center": [0, 0, 0], "radius": 0, "material": "gray" } }
+            ]
+        }"#, Path::new("")).unwrap();
+        let warnings = [
+            "objects[0]: sphere of zero radius",
+            "objects[1]: degenerate triangle",
+            "objects[2]: reaches 3e6 from the origin, too far for precise hits",
+            "objects[3]: object: sphere of zero radius",
+            "materials.spare: never used",
+        ];
+        assert_eq!(warnings, file.warnings());
+    }
+}
@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{random_float, INF, Float};
+use crate::vec::{Vec3, Point3, Color, dot, unit_vector, random_unit_vector, random_in_unit_disk};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HittableList};
+use crate::sphere::Sphere;
+use crate::bvh::Bvh;
+use crate::material::{Material, Lambertian, Metal, Dielectric};
+use crate::image::Image;
+use crate::{exr, kernels, png, scene};
+
+const EPSILON: Float = 1e-3;
+
+// Chi-square critical value for 9 degrees of freedom at p = 0.001,
+// so a correct sampler fails the test about once in a thousand runs.
+const CHI_SQUARE_9DOF: Float = 27.877;
+
+type Check = fn() -> Result<(), String>;
+type Sampler = fn() -> Float;
+
+const CHECKS: [(&str, Check); 9] = [
+    ("sphere intersection invariants", sphere_invariants),
+    ("bvh agrees with a linear scan", bvh_matches_list),
+    ("intersection kernels match scalar", kernels::match_scalar),
+    ("cornell box queries", cornell_queries),
+    ("white furnace", furnace),
+    ("sampler uniformity (chi-square)", sampler_uniformity),
+    ("ppm round-trip", ppm_round_trip),
+    ("png round-trip", png_round_trip),
+    ("exr round-trip", exr_round_trip),
+];
+
+// Runs every check, reporting each on stderr.
+// Returns whether all of them passed.
+pub fn run() -> bool {
+    eprintln!("Intersection kernels: {}", kernels::isa_name());
+    let mut failures = 0;
+    for (name, check) in CHECKS.iter() {
+        match check() {
+            Ok(()) => eprintln!("[PASS] {}", name),
+            Err(e) => {
+                eprintln!("[FAIL] {}: {}", name, e);
+                failures += 1;
+            }
+        }
+    }
+
+    eprintln!("{} of {} checks passed.", CHECKS.len() - failures, CHECKS.len());
+    failures == 0
+}
+
+fn gray() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+}
+
+fn random_sphere() -> Sphere {
+    let center = Point3::random_bounded(-5.0, 5.0);
+    Sphere::new(center, 0.1 + 2.0 * random_float(), gray())
+}
+
+// A ray from a random point around the sphere aimed somewhere
+// within its silhouette, so it is guaranteed to hit.
+fn ray_toward(center: Point3, radius: Float, distance: Float) -> Ray {
+    let orig = center + distance * random_unit_vector();
+    let w = unit_vector(center - orig);
+    let target = center + 0.9 * radius * (random_unit_vector() - dot(&random_unit_vector(), &w) * w) / 2.0;
+    Ray::new(&orig, &(target - orig), 0.0)
+}
+
+fn sphere_invariants() -> Result<(), String> {
+    for _ in 0..1000 {
+        let sphere = random_sphere();
+        let r = ray_toward(sphere.center, sphere.radius, sphere.radius * (1.5 + 5.0 * random_float()));
+
+        let rec = sphere.hit(&r, EPSILON, INF).ok_or("ray aimed at a sphere missed it")?;
+        if ((rec.p - sphere.center).length() - sphere.radius).abs() > EPSILON * sphere.radius.max(1.0) {
+            return Err(format!("hit point {:?} is not on the sphere", rec.p))
+        }
+        if (rec.p - r.at(rec.t)).length() > EPSILON {
+            return Err("hit point does not match ray parameter".to_string())
+        }
+        if (rec.normal.length() - 1.0).abs() > EPSILON {
+            return Err(format!("normal {:?} is not unit length", rec.normal))
+        }
+        if !rec.front_face || dot(&rec.normal, &r.dir) > 0.0 {
+            return Err("outside hit is not front facing".to_string())
+        }
+        if sphere.hit(&r, EPSILON, rec.t * 0.5).is_some() {
+            return Err("hit reported beyond t_max".to_string())
+        }
+
+        // Continuing from just past the entry point the ray exits
+        // through the back face, with the normal facing it.
+        let exit = sphere.hit(&r, rec.t + EPSILON, INF).ok_or("ray entering a sphere never left")?;
+        if exit.front_face || dot(&exit.normal, &r.dir) > 0.0 {
+            return Err("inside hit is not back facing".to_string())
+        }
+    }
+    Ok(())
+}
+
+fn bvh_matches_list() -> Result<(), String> {
+    let spheres: Vec<Sphere> = (0..200).map(|_| random_sphere()).collect();
+    let boxed = || -> Vec<Box<dyn Hittable>> {
+        spheres.iter()
+            .map(|s| Box::new(Sphere::new(s.center, s.radius, gray())) as Box<dyn Hittable>)
+            .collect()
+    };
+    let list = HittableList { objects: boxed() };
+    let bvh = Bvh::new(boxed(), 0.0, 0.0);
+
+    for _ in 0..2000 {
+        let orig = Point3::random_bounded(-10.0, 10.0);
+        let r = Ray::new(&orig, &random_unit_vector(), 0.0);
+        let a = list.hit(&r, EPSILON, INF).map(|rec| rec.t);
+        let b = bvh.hit(&r, EPSILON, INF).map(|rec| rec.t);
+        let agree = match (a, b) {
+            (Some(a), Some(b)) => (a - b).abs() <= EPSILON * a.max(1.0),
+            (None, None) => true,
+            _ => false,
+        };
+        if !agree {
+            return Err(format!("list hit {:?} but bvh hit {:?}", a, b))
+        }
+    }
+    Ok(())
+}
+
+// Raycasts and visibility queries in the Cornell box, whose floor lies
+// at y = 0 and whose front is open towards -z.
+fn cornell_queries() -> Result<(), String> {
+    let scene = scene::cornell_box(1.0);
+    let down = Ray::new(&Point3::new(50.0, 100.0, 50.0), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+    match scene.raycast(&down) {
+        Some(rec) if (rec.t - 100.0).abs() < EPSILON => {}
+        Some(rec) => return Err(format!("downward ray hit at t = {}, expected the floor at 100", rec.t)),
+        None => return Err("downward ray missed the floor".to_string()),
+    }
+    let out = Ray::new(&Point3::new(278.0, 278.0, 100.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+    if scene.raycast(&out).is_some() {
+        return Err("ray leaving through the open front hit something".to_string())
+    }
+
+    // The floor in a back corner sees the light, but under the middle
+    // of the short box it doesn't.
+    let light = Point3::new(278.0, 554.0, 279.0);
+    if scene.occluded(light, Point3::new(50.0, 0.0, 500.0)) {
+        return Err("back corner of the floor is occluded from the light".to_string())
+    }
+    if !scene.occluded(light, Point3::new(183.0, 0.0, 169.0)) {
+        return Err("floor under the short box is visible from the light".to_string())
+    }
+    Ok(())
+}
+
+// Traces paths through a sphere lit by a uniform white environment of
+// radiance 1, so the result is the fraction of energy the material keeps.
+fn furnace_radiance(mat: Arc<dyn Material>, samples: usize) -> Float {
+    let sphere = Sphere::new(Point3::default(), 1.0, mat);
+    let mut total = 0.0;
+    for _ in 0..samples {
+        let mut r = ray_toward(sphere.center, sphere.radius, 5.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        for _ in 0..50 {
+            match sphere.hit(&r, EPSILON, INF) {
+                Some(rec) => {
+                    let mut scattered = Ray::default();
+                    let mut attenuation = Color::default();
+                    if !rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
+                        throughput = Color::default();
+                        break
+                    }
+                    throughput *= attenuation;
+                    r = scattered;
+                }
+                None => break,
+            }
+        }
+        total += throughput.y;
+    }
+    total / samples as Float
+}
+
+fn furnace() -> Result<(), String> {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let cases: Vec<(&str, Arc<dyn Material>, Float)> = vec![
+        ("lambertian", Arc::new(Lambertian::new(white)), 1.0),
+        ("half gray lambertian", Arc::new(Lambertian::new(0.5 * white)), 0.5),
+        ("mirror", Arc::new(Metal::new(white, 0.0)), 1.0),
+        ("fuzzy metal", Arc::new(Metal::new(white, 0.5)), 1.0),
+        ("rough metal", Arc::new(Metal::new(white, 1.0)), 1.0),
+        ("glass", Arc::new(Dielectric::new(1.5)), 1.0),
+    ];
+
+    for (name, mat, expected) in cases {
+        let radiance = furnace_radiance(mat, 2000);
+        if (radiance - expected).abs() > 0.02 {
+            return Err(format!("{} returned {:.4}, expected {}", name, radiance, expected))
+        }
+    }
+    Ok(())
+}
+
+fn chi_square(bins: &[usize], samples: usize) -> Float {
+    let expected = samples as Float / bins.len() as Float;
+    bins.iter().map(|&n| (n as Float - expected).powi(2) / expected).sum()
+}
+
+fn sampler_uniformity() -> Result<(), String> {
+    const SAMPLES: usize = 100_000;
+    let tests: [(&str, Sampler); 3] = [
+        ("random_float", random_float),
+        // By Archimedes' theorem z is uniform on the unit sphere.
+        ("random_unit_vector", || (random_unit_vector().z + 1.0) / 2.0),
+        // The polar angle of a uniform point on the unit disk is uniform.
+        ("random_in_unit_disk", || {
+            let p = random_in_unit_disk();
+            (p.y.atan2(p.x) + crate::consts::PI) / (2.0 * crate::consts::PI)
+        }),
+    ];
+
+    for (name, sample) in tests.iter() {
+        let mut bins = [0usize; 10];
+        for _ in 0..SAMPLES {
+            let v = sample();
+            if !(0.0..=1.0).contains(&v) {
+                return Err(format!("{} produced {} outside [0, 1]", name, v))
+            }
+            bins[((v * 10.0) as usize).min(9)] += 1;
+        }
+
+        let chi2 = chi_square(&bins, SAMPLES);
+        if chi2 > CHI_SQUARE_9DOF {
+            return Err(format!("{} is not uniform: chi-square {:.2} > {}", name, chi2, CHI_SQUARE_9DOF))
+        }
+    }
+    Ok(())
+}
+
+// Components are f32 values, which EXR files hold exactly.
+fn test_image() -> Image {
+    let mut img = Image::new(17, 9);
+    let mut rng = rand::thread_rng();
+    let exact = |v: f32| v as Float;
+    for y in 0..img.height {
+        for x in 0..img.width {
+            img.set(x, y, Color::new(exact(rng.gen_range(0.0, 1.0)), exact(rng.gen_range(0.0, 4.0)), exact(1e-3 * x as f32)));
+        }
+    }
+    img
+}
+
+fn ppm_round_trip() -> Result<(), String> {
+    let img = test_image();
+    let mut buf = Vec::new();
+    img.write_ppm(None, &mut buf).map_err(|e| e.to_string())?;
+
+    let text = String::from_utf8(buf).map_err(|e| e.to_string())?;
+    let mut tokens = text.split_whitespace();
+    if tokens.next() != Some("P3") {
+        return Err("missing P3 magic".to_string())
+    }
+    let values = tokens.map(|t| t.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|e| e.to_string())?;
+    if values[..3] != [img.width, img.height, 255] {
+        return Err(format!("unexpected header {:?}", &values[..3]))
+    }
+    let rgb: Vec<u8> = values[3..].iter().map(|&v| v as u8).collect();
+    if rgb != img.to_rgb8(None) {
+        return Err("decoded pixels differ".to_string())
+    }
+    Ok(())
+}
+
+fn png_round_trip() -> Result<(), String> {
+    let img = test_image();
+    let mut buf = Vec::new();
+    img.write_png(None, &mut buf).map_err(|e| e.to_string())?;
+
+    let (width, height, rgba) = png::decode_rgba8(&buf).map_err(|e| e.to_string())?;
+    if (width, height) != (img.width, img.height) {
+        return Err(format!("decoded {}x{}, expected {}x{}", width, height, img.width, img.height))
+    }
+    let rgb: Vec<u8> = rgba.chunks(4).flat_map(|p| p[..3].to_vec()).collect();
+    if rgb != img.to_rgb8(None) {
+        return Err("decoded pixels differ".to_string())
+    }
+    Ok(())
+}
+
+fn exr_round_trip() -> Result<(), String> {
+    let img = test_image();
+    let mut buf = Vec::new();
+    exr::write_exr(&img, Some(7.0), false, None, &mut buf).map_err(|e| e.to_string())?;
+
+    let (read, samples) = exr::read_exr(&mut buf.as_slice()).map_err(|e| e.to_string())?;
+    if samples != Some(7.0) {
+        return Err(format!("samples attribute read back as {:?}", samples))
+    }
+    if read.pixels() != img.pixels() {
+        return Err("decoded pixels differ".to_string())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_pass() {
+        for (name, check) in CHECKS.iter() {
+            assert_eq!(Ok(()), check(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_chi_square() {
+        assert_eq!(0.0, chi_square(&[10, 10, 10, 10], 40));
+        assert_eq!(20.0, chi_square(&[20, 0], 20));
+    }
+}
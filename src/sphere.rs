@@ -1,52 +1,194 @@
-use crate::vec::{Vec3, Point3, dot};
-use crate::hittable::{HitRecord, Hittable};
+use std::sync::Arc;
+
+use crate::{PI, INF, Float};
+use crate::vec::{Vec3, Point3, random_unit_vector};
+use crate::hittable::{HitRecord, Hittable, PDF_EPSILON};
+use crate::onb::Onb;
+use crate::pdf::random_to_sphere;
 use crate::ray::Ray;
 use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::primitive::Primitive;
+use crate::kernels::{self, SphereLanes};
 
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Point3,
-    pub radius: f32,
-    pub mat: Box<dyn Material>,
+    pub radius: Float,
+    pub mat: Arc<dyn Material>,
 }
 
 impl Sphere{
-    pub fn new(center: Point3, radius: f32, mat: Box<dyn Material>) -> Self {
+    pub fn new(center: Point3, radius: Float, mat: Arc<dyn Material>) -> Self {
         Self { center, radius, mat }
     }
+
+    // The record of the ray meeting the sphere at t.
+    fn record(&self, r: &Ray, t: Float) -> HitRecord<'_> {
+        let p = r.at(t);
+        let outward_normal = (p - self.center) / self.radius;
+        let (u, v) = sphere_uv(&outward_normal);
+        let mut rec = HitRecord{ p, t, u, v, normal: Vec3::default(), front_face: false, mat: self.mat.as_ref() };
+        rec.set_face_normal(r, &outward_normal);
+        rec
+    }
+}
+
+// Texture coordinates of a point on the unit sphere: u is the angle
+// around the y axis starting from -x, and v the angle from -y to +y,
+// both scaled to [0, 1].
+pub fn sphere_uv(p: &Point3) -> (Float, Float) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
 }
 
 impl Hittable for Sphere {
     // Define how to determine if a ray
     // has hit a sphere
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = r.orig - self.center;
-        let a = r.dir.length_sqrd();
-        let half_b = dot(&oc, &r.dir);
-        let c = oc.length_sqrd() - self.radius.powi(2);
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let [a, half_b, oc_sqrd] = kernels::sphere(&self.center, &r.orig, &r.dir);
+        let c = oc_sqrd - self.radius.powi(2);
         let discriminant = half_b.powi(2) - a * c;
 
         if discriminant > 0.0 {
             let root = discriminant.sqrt();
             let mut temp = (-half_b - root) / a;
             if temp < t_max && temp > t_min {
-                let t = temp;
-                let p = r.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-                let mut rec = HitRecord{ p, t, normal: Vec3::default(), front_face: false, mat: self.mat.clone() };
-                rec.set_face_normal(r, &outward_normal);
-                return Some(rec)
+                return Some(self.record(r, temp))
             }
             temp = (-half_b + root) / a;
             if temp < t_max && temp > t_min {
-                let t = temp;
-                let p = r.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-                let mut rec = HitRecord{ p, t, normal: Vec3::default(), front_face: false, mat: self.mat.clone() };
-                rec.set_face_normal(r, &outward_normal);
-                return Some(rec)
+                return Some(self.record(r, temp))
             }
         }
 
         None
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        Some(sphere_box(self))
+    }
+
+    fn primitive(&self) -> Option<Primitive> {
+        Some(Primitive::Sphere(self.clone()))
+    }
+
+    // Directions are sampled uniformly over the cone the sphere fills,
+    // or over all directions from inside it.
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> Float {
+        if self.hit(&Ray::new(origin, dir, 0.0), PDF_EPSILON, INF).is_none() {
+            return 0.0
+        }
+        let distance_squared = (self.center - *origin).length_sqrd();
+        if distance_squared <= self.radius.powi(2) {
+            return 1.0 / (4.0 * PI)
+        }
+        let cos_theta_max = (1.0 - self.radius.powi(2) / distance_squared).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_sqrd();
+        if distance_squared <= self.radius.powi(2) {
+            return random_unit_vector()
+        }
+        Onb::from_w(&direction).local(&random_to_sphere(self.radius, distance_squared))
+    }
+}
+
+// Up to `kernels::LANES` spheres tested against a ray all at once, which
+// takes about as long as testing one. Hierarchies of scenes with many
+// small spheres have a packet in every leaf instead of a sphere, and
+// need fewer boxes tested to find a hit.
+#[derive(Clone)]
+pub struct SpherePacket {
+    lanes: SphereLanes,
+    spheres: Vec<Sphere>,
+    bbox: Aabb,
+}
+
+impl SpherePacket {
+    fn new(spheres: Vec<Sphere>) -> Self {
+        let placed: Vec<(Point3, Float)> = spheres.iter().map(|s| (s.center, s.radius)).collect();
+        let bbox = spheres[1..].iter().fold(sphere_box(&spheres[0]), |acc, s| acc.surrounding(&sphere_box(s)));
+        Self { lanes: SphereLanes::new(&placed), spheres, bbox }
+    }
+}
+
+fn sphere_box(sphere: &Sphere) -> Aabb {
+    let r = Vec3::new(sphere.radius.abs(), sphere.radius.abs(), sphere.radius.abs());
+    Aabb::new(sphere.center - r, sphere.center + r)
+}
+
+// The spheres in packets of neighbors, split in half along the longest
+// axis of their centers until each half fits in a packet.
+pub fn packets(mut spheres: Vec<Sphere>) -> Vec<Box<dyn Hittable>> {
+    if spheres.len() <= kernels::LANES {
+        return if spheres.is_empty() { Vec::new() } else { vec![Box::new(SpherePacket::new(spheres))] }
+    }
+    let centers: Vec<Point3> = spheres.iter().map(|s| s.center).collect();
+    let axis = Aabb::from_points(&centers).longest_axis();
+    spheres.sort_by(|a, b| a.center[axis].total_cmp(&b.center[axis]));
+    // Halves of whole packets, so that only the last one is partly empty.
+    let packets_in_half = spheres.len().div_ceil(kernels::LANES) / 2;
+    let far = spheres.split_off(packets_in_half * kernels::LANES);
+    let mut near = packets(spheres);
+    near.extend(packets(far));
+    near
+}
+
+impl Hittable for SpherePacket {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let ts = kernels::spheres(&self.lanes, &r.orig, &r.dir, t_min, t_max);
+        let (i, t) = ts.iter().enumerate().min_by(|a, b| a.1.total_cmp(b.1))?;
+        if *t < t_max { Some(self.spheres[i].record(r, *t)) } else { None }
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+
+    fn primitive(&self) -> Option<Primitive> {
+        Some(Primitive::Packet(Box::new(self.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_uv() {
+        let close = |(u, v): (Float, Float), (eu, ev): (Float, Float)| (u - eu).abs() < 1e-6 && (v - ev).abs() < 1e-6;
+        assert!(close(sphere_uv(&Point3::new(1.0, 0.0, 0.0)), (0.5, 0.5)));
+        assert!(close(sphere_uv(&Point3::new(0.0, 1.0, 0.0)), (0.5, 1.0)));
+        assert!(close(sphere_uv(&Point3::new(0.0, 0.0, 1.0)), (0.25, 0.5)));
+        assert!(close(sphere_uv(&Point3::new(0.0, -1.0, 0.0)), (0.5, 0.0)));
+    }
+
+    #[test]
+    fn test_packets() {
+        use crate::material::Lambertian;
+        use crate::hittable::HittableList;
+        use crate::vec::Color;
+
+        // Packets find the same hits as the spheres one by one.
+        crate::rng::seed(5);
+        let gray = || Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let spheres = || (0..20).map(|i| Sphere::new(Vec3::random_bounded(-4.0, 4.0), 0.2 + 0.05 * i as Float, gray()));
+        let packed = HittableList { objects: packets(spheres().collect()) };
+        assert_eq!(3, packed.objects.len());
+        crate::rng::seed(5);
+        let single = HittableList { objects: spheres().map(|s| Box::new(s) as Box<dyn Hittable>).collect() };
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let r = Ray::new(&Vec3::random_bounded(-5.0, 5.0), &Vec3::random_bounded(-1.0, 1.0), 0.0);
+            let (a, b) = (packed.hit(&r, 0.001, INF), single.hit(&r, 0.001, INF));
+            assert_eq!(a.as_ref().map(|rec| (rec.t, rec.p, rec.normal)), b.as_ref().map(|rec| (rec.t, rec.p, rec.normal)));
+            hits += a.is_some() as usize;
+        }
+        assert!(hits > 100, "{}", hits);
+    }
+}
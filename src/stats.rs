@@ -0,0 +1,190 @@
+use std::fmt::Write;
+
+use crate::{to_f64, Float};
+use crate::image::{Image, to_display};
+use crate::vec::Color;
+
+const BINS: usize = 256;
+const PERCENTILES: [Float; 7] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0];
+const CHANNEL_NAMES: [&str; 4] = ["r", "g", "b", "luminance"];
+
+const PLOT_HEIGHT: usize = 128;
+
+// Rec. 709 relative luminance of a linear color.
+pub fn luminance(c: Color) -> Float {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Summary of a single channel's linear (pre-gamma) values.
+pub struct ChannelStats {
+    pub min: Float,
+    pub max: Float,
+    pub mean: Float,
+    pub percentiles: Vec<(Float, Float)>,
+    // Fraction of pixels that saturate the 8-bit output.
+    pub clipped: Float,
+}
+
+// Histograms are taken over the gamma-corrected 8-bit output
+// values while the percentile statistics use the linear radiance,
+// which is what exposure decisions should be based on.
+pub struct ImageStats {
+    pub pixel_count: usize,
+    pub histograms: [[u32; BINS]; 4],
+    pub channels: [ChannelStats; 4],
+}
+
+impl ImageStats {
+    pub fn compute(img: &Image) -> Self {
+        let mut histograms = [[0u32; BINS]; 4];
+        let mut values: [Vec<Float>; 4] = Default::default();
+
+        for &p in img.pixels() {
+            let display = to_display(p);
+            let luma = luminance(p);
+            let display_luma = to_display(Color::new(luma, luma, luma))[0];
+
+            for (c, &v) in display.iter().chain(&[display_luma]).enumerate() {
+                histograms[c][v as usize] += 1;
+            }
+            for (c, &v) in [p.x, p.y, p.z, luma].iter().enumerate() {
+                values[c].push(v);
+            }
+        }
+
+        let [r, g, b, l] = values;
+        let channels = [
+            channel_stats(r, &histograms[0]),
+            channel_stats(g, &histograms[1]),
+            channel_stats(b, &histograms[2]),
+            channel_stats(l, &histograms[3]),
+        ];
+
+        Self { pixel_count: img.pixels().len(), histograms, channels }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "{{");
+        let _ = writeln!(s, "  \"pixels\": {},", self.pixel_count);
+        let _ = writeln!(s, "  \"channels\": {{");
+        for (c, name) in CHANNEL_NAMES.iter().enumerate() {
+            let stats = &self.channels[c];
+            let _ = writeln!(s, "    \"{}\": {{", name);
+            let _ = writeln!(s, "      \"min\": {},", json_f32(stats.min));
+            let _ = writeln!(s, "      \"max\": {},", json_f32(stats.max));
+            let _ = writeln!(s, "      \"mean\": {},", json_f32(stats.mean));
+            let _ = writeln!(s, "      \"clipped\": {},", json_f32(stats.clipped));
+            let percentiles: Vec<String> = stats.percentiles.iter()
+                .map(|(p, v)| format!("\"p{}\": {}", p, json_f32(*v)))
+                .collect();
+            let _ = writeln!(s, "      \"percentiles\": {{ {} }},", percentiles.join(", "));
+            let bins: Vec<String> = self.histograms[c].iter().map(|n| n.to_string()).collect();
+            let _ = writeln!(s, "      \"histogram\": [{}]", bins.join(", "));
+            let sep = if c + 1 < CHANNEL_NAMES.len() { "," } else { "" };
+            let _ = writeln!(s, "    }}{}", sep);
+        }
+        let _ = writeln!(s, "  }}");
+        let _ = writeln!(s, "}}");
+        s
+    }
+
+    // Draws the red, green and blue histograms as additive bars with
+    // the luminance histogram outlined in white on top. Returns an
+    // 8-bit RGB raster of BINS x PLOT_HEIGHT pixels.
+    pub fn plot(&self) -> Image {
+        let peak = self.histograms.iter()
+            .flat_map(|h| h.iter())
+            .cloned()
+            .max()
+            .unwrap_or(0)
+            .max(1) as Float;
+        let bar = |count: u32| ((count as Float / peak) * PLOT_HEIGHT as Float).ceil() as usize;
+
+        let mut img = Image::new(BINS, PLOT_HEIGHT);
+        for x in 0..BINS {
+            let heights = [
+                bar(self.histograms[0][x]),
+                bar(self.histograms[1][x]),
+                bar(self.histograms[2][x]),
+            ];
+            let luma = bar(self.histograms[3][x]);
+            for y in 0..PLOT_HEIGHT {
+                let level = PLOT_HEIGHT - y;
+                let fill = |h: usize| if level <= h { 0.6 } else { 0.02 };
+                let mut c = Color::new(fill(heights[0]), fill(heights[1]), fill(heights[2]));
+                if luma > 0 && level == luma {
+                    c = Color::new(1.0, 1.0, 1.0);
+                }
+                img.set(x, y, c);
+            }
+        }
+        img
+    }
+}
+
+fn channel_stats(mut values: Vec<Float>, histogram: &[u32; BINS]) -> ChannelStats {
+    // NaNs would poison the ordering, so they are treated as black.
+    for v in values.iter_mut() {
+        if v.is_nan() { *v = 0.0 };
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let n = values.len();
+    if n == 0 {
+        return ChannelStats { min: 0.0, max: 0.0, mean: 0.0, percentiles: Vec::new(), clipped: 0.0 };
+    }
+
+    let mean = values.iter().map(|&v| to_f64(v)).sum::<f64>() / n as f64;
+    let percentiles = PERCENTILES.iter()
+        .map(|&p| {
+            let idx = ((p / 100.0) * (n - 1) as Float).round() as usize;
+            (p, values[idx])
+        })
+        .collect();
+
+    ChannelStats {
+        min: values[0],
+        max: values[n - 1],
+        mean: mean as Float,
+        percentiles,
+        clipped: histogram[BINS - 1] as Float / n as Float,
+    }
+}
+
+fn json_f32(v: Float) -> String {
+    if v.is_finite() { format!("{}", v) } else { "null".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_counts() {
+        let mut img = Image::new(2, 2);
+        img.set(0, 0, Color::new(1.0, 1.0, 1.0));
+        img.set(1, 0, Color::new(1.0, 0.0, 0.0));
+
+        let stats = ImageStats::compute(&img);
+        assert_eq!(2, stats.histograms[0][255]);
+        assert_eq!(2, stats.histograms[0][0]);
+        assert_eq!(3, stats.histograms[1][0]);
+        assert_eq!(0.5, stats.channels[0].clipped);
+        assert_eq!(1.0, stats.channels[0].max);
+        assert_eq!(0.5, stats.channels[0].mean);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut img = Image::new(101, 1);
+        for x in 0..101 {
+            let v = x as Float / 100.0;
+            img.set(x, 0, Color::new(v, v, v));
+        }
+
+        let stats = ImageStats::compute(&img);
+        let median = stats.channels[1].percentiles.iter().find(|(p, _)| *p == 50.0).unwrap().1;
+        assert_eq!(0.5, median);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{PI, degrees_to_radians, random_float, Float};
+use crate::vec::{Vec3, Point3, Color, unit_vector};
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::onb::Onb;
+
+// The angular diameter of the real sun, in degrees.
+pub const SUN_DIAMETER: Float = 0.53;
+
+// The linear limb darkening coefficient of the sun in visible light.
+pub const SUN_LIMB_DARKENING: Float = 0.6;
+
+// A distant disk lighting the scene from a fixed direction, seen by rays
+// escaping towards it. Its size sets how soft shadows are: the real sun
+// is about half a degree across, and a point light would be zero. The
+// disk is darker towards its rim, its limb, by `1 - u (1 - mu)` where mu
+// is the cosine between the line of sight and the sun's surface normal.
+//
+// It is never hit, being infinitely far, but as a `Hittable` it can be
+// added to the lights, which samples directions uniformly over its disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sun {
+    // Unit vector towards the center of the disk.
+    direction: Vec3,
+    // 1 - cos of the angular radius, computed so that it stays precise
+    // for small disks, and the solid angle the disk fills.
+    one_minus_cos: Float,
+    solid_angle: Float,
+    limb_darkening: Float,
+    // Radiance at the center of the disk.
+    radiance: Color,
+}
+
+impl Sun {
+    // A sun of the given angular diameter in degrees, whose light falling
+    // straight onto a surface has the given irradiance, whatever its size.
+    pub fn new(direction: Vec3, diameter: Float, irradiance: Color, limb_darkening: Float) -> Self {
+        let half_angle = degrees_to_radians(diameter) / 4.0;
+        let one_minus_cos = 2.0 * half_angle.sin().powi(2);
+        let solid_angle = 2.0 * PI * one_minus_cos;
+        // Over the disk mu averages 2/3, which darkening scales down.
+        let radiance = irradiance / (solid_angle * (1.0 - limb_darkening / 3.0));
+        Self { direction: unit_vector(direction), one_minus_cos, solid_angle, limb_darkening, radiance }
+    }
+
+    // 1 - cos of the angle between the direction and the sun's center.
+    fn offset(&self, dir: &Vec3) -> Float {
+        (unit_vector(*dir) - self.direction).length_sqrd() / 2.0
+    }
+
+    // Light arriving from the direction, none outside the disk.
+    pub fn radiance(&self, dir: &Vec3) -> Color {
+        let offset = self.offset(dir);
+        if offset >= self.one_minus_cos {
+            return Color::default()
+        }
+        // For small angles 1 - cos grows with the square of the angle,
+        // so their ratio is the squared distance from the center of the
+        // disk, relative to its radius.
+        let mu = (1.0 - offset / self.one_minus_cos).sqrt();
+        (1.0 - self.limb_darkening * (1.0 - mu)) * self.radiance
+    }
+}
+
+impl Hittable for Sun {
+    fn hit(&self, _r: &Ray, _t_min: Float, _t_max: Float) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        None
+    }
+
+    fn pdf_value(&self, _origin: &Point3, dir: &Vec3) -> Float {
+        if self.offset(dir) < self.one_minus_cos { 1.0 / self.solid_angle } else { 0.0 }
+    }
+
+    // Uniform over the cone, in which 1 - cos is uniform.
+    fn random(&self, _origin: &Point3) -> Vec3 {
+        let phi = 2.0 * PI * random_float();
+        let offset = random_float() * self.one_minus_cos;
+        let z = 1.0 - offset;
+        let sin_theta = (offset * (2.0 - offset)).sqrt();
+        Onb::from_w(&self.direction).local(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng;
+    use crate::vec::dot;
+
+    #[test]
+    fn test_disk() {
+        let sun = Sun::new(Vec3::new(0.0, 1.0, 1.0), SUN_DIAMETER, Color::new(1.0, 1.0, 1.0), SUN_LIMB_DARKENING);
+        let radius = degrees_to_radians(SUN_DIAMETER / 2.0);
+        let at = |angle: Float| Vec3::new(0.0, (PI / 4.0 + angle).sin(), (PI / 4.0 + angle).cos());
+
+        // Full brightness at the center, darkened to 1 - u at the limb.
+        let center = sun.radiance(&Vec3::new(0.0, 2.0, 2.0)).x;
+        let near_limb = sun.radiance(&at(0.999 * radius)).x;
+        assert!((near_limb / center - (1.0 - SUN_LIMB_DARKENING)).abs() < 0.05, "{} {}", near_limb, center);
+        assert_eq!(Color::default(), sun.radiance(&at(1.01 * radius)));
+        assert!(sun.pdf_value(&Point3::default(), &at(0.9 * radius)) > 0.0);
+        assert_eq!(0.0, sun.pdf_value(&Point3::default(), &at(1.1 * radius)));
+    }
+
+    #[test]
+    fn test_irradiance() {
+        // Integrating the disk by sampling it gives back the irradiance,
+        // for a pinpoint and for a large sun alike.
+        rng::seed(5);
+        for &diameter in [SUN_DIAMETER, 10.0].iter() {
+            let sun = Sun::new(Vec3::new(1.0, 1.0, 0.0), diameter, Color::new(2.0, 2.0, 2.0), SUN_LIMB_DARKENING);
+            let n = 20000;
+            let irradiance = (0..n).map(|_| {
+                let dir = sun.random(&Point3::default());
+                sun.radiance(&dir).x * dot(&unit_vector(dir), &sun.direction) * sun.solid_angle
+            }).sum::<Float>() / n as Float;
+            assert!((irradiance - 2.0).abs() < 0.02, "{} at {} degrees", irradiance, diameter);
+        }
+    }
+}
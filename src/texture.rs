@@ -0,0 +1,557 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use crate::clamp;
+use crate::vec::{Vec3, Point3, Color, dot};
+use crate::{png, jpeg, hdr, exr, Float};
+use crate::image::Image;
+use crate::hittable::HitRecord;
+use crate::perlin::Perlin;
+
+// Maps a surface point, given both by its texture coordinates and
+// its position in space, to a color. Textures can get large, so
+// materials share them by reference rather than copying them.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: Float, v: Float, p: &Point3) -> Color;
+
+    // The color at a hit, which textures projected along the surface
+    // normal need. Others only look at the coordinates and position.
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: Float, _v: Float, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+// Alternates between two textures in a 3D checker pattern, with
+// `scale` cells per unit length. Being defined in space rather
+// than on the surface it needs no texture coordinates.
+pub struct CheckerTexture {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: Float,
+}
+
+impl CheckerTexture {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: Float) -> Self {
+        Self { even, odd, scale }
+    }
+
+    pub fn from_colors(even: Color, odd: Color, scale: Float) -> Self {
+        Self::new(Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)), scale)
+    }
+}
+
+impl CheckerTexture {
+    fn cell(&self, p: &Point3) -> &dyn Texture {
+        let cell = |x: Float| (self.scale * x).floor() as i64;
+        if (cell(p.x) + cell(p.y) + cell(p.z)).rem_euclid(2) == 0 {
+            self.even.as_ref()
+        } else {
+            self.odd.as_ref()
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: Float, v: Float, p: &Point3) -> Color {
+        self.cell(p).value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.cell(&rec.p).value_at(rec)
+    }
+}
+
+// How a color ramp blends between neighboring stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    // Holds the color of the stop below, giving hard bands.
+    Constant,
+    Linear,
+    // Eases in and out of every stop, hiding the kinks at the stops.
+    Smooth,
+}
+
+// Maps a value to a color along a list of stops, each a position and a
+// color. Values outside the stops take the color of the nearest end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<(Float, Color)>,
+    interpolation: Interpolation,
+}
+
+impl ColorRamp {
+    // Stops may be given in any order. Without stops the ramp is black.
+    pub fn new(mut stops: Vec<(Float, Color)>, interpolation: Interpolation) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops, interpolation }
+    }
+
+    // A linear blend from one color at 0 to another at 1.
+    pub fn between(a: Color, b: Color) -> Self {
+        Self::new(vec![(0.0, a), (1.0, b)], Interpolation::Linear)
+    }
+
+    pub fn value(&self, t: Float) -> Color {
+        let above = self.stops.iter().position(|s| s.0 > t);
+        let (lo, hi) = match above {
+            None => return self.stops.last().map_or(Color::default(), |s| s.1),
+            Some(0) => return self.stops[0].1,
+            Some(i) => (self.stops[i - 1], self.stops[i]),
+        };
+        let f = (t - lo.0) / (hi.0 - lo.0);
+        let f = match self.interpolation {
+            Interpolation::Constant => 0.0,
+            Interpolation::Linear => f,
+            Interpolation::Smooth => f * f * (3.0 - 2.0 * f),
+        };
+        (1.0 - f) * lo.1 + f * hi.1
+    }
+}
+
+// Marble-like veins: a sine wave along z whose phase is disturbed by
+// turbulence, colored by a ramp over [0, 1], black to white by default.
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: Float,
+    ramp: ColorRamp,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: Float) -> Self {
+        Self::with_ramp(scale, ColorRamp::between(Color::default(), Color::new(1.0, 1.0, 1.0)))
+    }
+
+    pub fn with_ramp(scale: Float, ramp: ColorRamp) -> Self {
+        Self { noise: Perlin::new(), scale, ramp }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        let phase = self.scale * p.z + 10.0 * self.noise.turbulence(p, TURBULENCE_DEPTH);
+        self.ramp.value(0.5 * (1.0 + phase.sin()))
+    }
+}
+
+// Octaves of noise summed by the turbulence of textures.
+const TURBULENCE_DEPTH: usize = 7;
+
+// The turbulence of noise `scale` times finer than the lattice, like
+// the book's camouflage netting, colored by a ramp over [0, 1], black
+// to white by default.
+pub struct TurbulenceTexture {
+    noise: Perlin,
+    scale: Float,
+    ramp: ColorRamp,
+}
+
+impl TurbulenceTexture {
+    pub fn new(scale: Float) -> Self {
+        Self::with_ramp(scale, ColorRamp::between(Color::default(), Color::new(1.0, 1.0, 1.0)))
+    }
+
+    pub fn with_ramp(scale: Float, ramp: ColorRamp) -> Self {
+        Self { noise: Perlin::new(), scale, ramp }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        self.ramp.value(self.noise.turbulence(&(self.scale * *p), TURBULENCE_DEPTH))
+    }
+}
+
+// A ramp laid out in space, from 0 at `from` to 1 at `to`, and
+// constant across planes perpendicular to the line between them.
+pub struct GradientTexture {
+    from: Point3,
+    to: Point3,
+    ramp: ColorRamp,
+}
+
+impl GradientTexture {
+    pub fn new(from: Point3, to: Point3, ramp: ColorRamp) -> Self {
+        Self { from, to, ramp }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        let axis = self.to - self.from;
+        self.ramp.value(dot(&(*p - self.from), &axis) / axis.length_sqrd())
+    }
+}
+
+// Growth rings around the y axis, `scale` per unit length, wobbled
+// by noise. The ramp colors every ring from its inside, at 0, to its
+// outside, at 1, light early wood to dark late wood by default.
+pub struct WoodTexture {
+    noise: Perlin,
+    scale: Float,
+    ramp: ColorRamp,
+}
+
+impl WoodTexture {
+    pub fn new(scale: Float) -> Self {
+        let ramp = ColorRamp::new(vec![
+            (0.0, Color::new(0.75, 0.55, 0.35)),
+            (0.7, Color::new(0.65, 0.45, 0.25)),
+            (1.0, Color::new(0.35, 0.2, 0.1)),
+        ], Interpolation::Smooth);
+        Self::with_ramp(scale, ramp)
+    }
+
+    pub fn with_ramp(scale: Float, ramp: ColorRamp) -> Self {
+        Self { noise: Perlin::new(), scale, ramp }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        let radius = (p.x * p.x + p.z * p.z).sqrt();
+        let rings = self.scale * radius + 0.3 * self.noise.noise(&(2.0 * *p));
+        self.ramp.value(rings - rings.floor())
+    }
+}
+
+// How the layer of a `LayeredTexture` combines with its base.
+pub enum Blend {
+    // From the base where the mask is black to the layer where it is
+    // white, channel by channel.
+    Mix(Arc<dyn Texture>),
+    Multiply,
+    Add,
+    // Multiplies where the base is dark and screens where it is light,
+    // raising the contrast of the base by the layer, as in image editors.
+    Overlay,
+}
+
+// Two textures combined into one, to compose surfaces from simpler
+// textures, e.g. dirt mixed onto wood through a noise mask.
+pub struct LayeredTexture {
+    base: Arc<dyn Texture>,
+    layer: Arc<dyn Texture>,
+    blend: Blend,
+}
+
+impl LayeredTexture {
+    pub fn new(base: Arc<dyn Texture>, layer: Arc<dyn Texture>, blend: Blend) -> Self {
+        Self { base, layer, blend }
+    }
+}
+
+impl LayeredTexture {
+    // Combines the colors of the base and the layer, looking up the
+    // mask of a mix with the given function.
+    fn combine<F: Fn(&dyn Texture) -> Color>(&self, a: Color, b: Color, mask: F) -> Color {
+        match &self.blend {
+            Blend::Mix(m) => {
+                let m = mask(m.as_ref());
+                let lerp = |a: Float, b: Float, t: Float| a + t.clamp(0.0, 1.0) * (b - a);
+                Color::new(lerp(a.x, b.x, m.x), lerp(a.y, b.y, m.y), lerp(a.z, b.z, m.z))
+            }
+            Blend::Multiply => a * b,
+            Blend::Add => a + b,
+            Blend::Overlay => {
+                let overlay = |a: Float, b: Float| if a < 0.5 { 2.0 * a * b } else { 1.0 - 2.0 * (1.0 - a) * (1.0 - b) };
+                Color::new(overlay(a.x, b.x), overlay(a.y, b.y), overlay(a.z, b.z))
+            }
+        }
+    }
+}
+
+impl Texture for LayeredTexture {
+    fn value(&self, u: Float, v: Float, p: &Point3) -> Color {
+        self.combine(self.base.value(u, v, p), self.layer.value(u, v, p), |m| m.value(u, v, p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.combine(self.base.value_at(rec), self.layer.value_at(rec), |m| m.value_at(rec))
+    }
+}
+
+// A texture projected onto the surface from the three axes, blended by
+// how much the surface faces each of them, for objects without texture
+// coordinates such as meshes from STL files. The texture repeats every
+// 1 / `scale` along each axis. Higher `sharpness` narrows the seams
+// where projections blend, and keeps each projection from smearing
+// onto faces turned away from its axis.
+pub struct TriplanarTexture {
+    texture: Arc<dyn Texture>,
+    scale: Float,
+    sharpness: Float,
+}
+
+impl TriplanarTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: Float, sharpness: Float) -> Self {
+        Self { texture, scale, sharpness }
+    }
+
+    fn project(&self, a: Float, b: Float, p: &Point3) -> Color {
+        let wrap = |x: Float| (self.scale * x).rem_euclid(1.0);
+        self.texture.value(wrap(a), wrap(b), p)
+    }
+}
+
+impl Texture for TriplanarTexture {
+    // Without a normal only the projection from above is used.
+    fn value(&self, _u: Float, _v: Float, p: &Point3) -> Color {
+        self.project(p.x, p.z, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let p = &rec.p;
+        let n = rec.normal;
+        let weights = Vec3::new(n.x.abs(), n.y.abs(), n.z.abs());
+        let weights = Vec3::new(weights.x.powf(self.sharpness), weights.y.powf(self.sharpness), weights.z.powf(self.sharpness));
+        let total = weights.x + weights.y + weights.z;
+        if total <= 0.0 {
+            return self.value(rec.u, rec.v, p)
+        }
+        (weights.x * self.project(p.z, p.y, p) + weights.y * self.project(p.x, p.z, p) + weights.z * self.project(p.x, p.y, p)) / total
+    }
+}
+
+// An image wrapped onto the surface through its texture coordinates,
+// with (0, 0) the bottom left corner of the image.
+#[derive(PartialEq)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Linear colors, rows top to bottom.
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    // Decodes 8-bit RGBA pixels. They are taken to be gamma encoded
+    // the same way rendered images are written, i.e. with gamma 2.
+    pub fn from_rgba8(width: usize, height: usize, data: &[u8]) -> Self {
+        let linear = |c: u8| (c as Float / 255.0).powi(2);
+        let pixels = data.chunks(4)
+            .map(|p| Color::new(linear(p[0]), linear(p[1]), linear(p[2])))
+            .collect();
+        Self { width, height, pixels }
+    }
+
+    // Takes linear colors as they are, e.g. from high dynamic range files.
+    pub fn from_image(img: &Image) -> Self {
+        Self { width: img.width, height: img.height, pixels: img.pixels().to_vec() }
+    }
+
+    // Loads a PNG, baseline JPEG, Radiance HDR or OpenEXR file, told
+    // apart by their contents.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        if data.starts_with(b"#?") {
+            return Ok(Self::from_image(&hdr::read_hdr(&data)?))
+        }
+        if data.starts_with(&exr::MAGIC) {
+            return Ok(Self::from_image(&exr::read_exr(&mut data.as_slice())?.0))
+        }
+        let (width, height, rgba) = if data.starts_with(&[0xff, 0xd8]) {
+            jpeg::decode_rgba8(&data)?
+        } else {
+            png::decode_rgba8(&data)?
+        };
+        Ok(Self::from_rgba8(width, height, &rgba))
+    }
+}
+
+// The texture over the unit square of texture coordinates, taken at the
+// center of every pixel, as an image `ImageTexture` maps back onto the
+// same coordinates. Textures of points in space are taken on the plane
+// z = 0, at (u, v, 0), so their own scale zooms in and out of them.
+pub fn bake(texture: &dyn Texture, width: usize, height: usize) -> Image {
+    let mut img = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as Float + 0.5) / width as Float;
+            let v = 1.0 - (y as Float + 0.5) / height as Float;
+            img.set(x, y, texture.value(u, v, &Point3::new(u, v, 0.0)));
+        }
+    }
+    img
+}
+
+// Only the size, the pixels would flood the output.
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ImageTexture({}x{})", self.width, self.height)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: Float, v: Float, _p: &Point3) -> Color {
+        if self.pixels.is_empty() {
+            // Cyan makes missing texture data easy to spot.
+            return Color::new(0.0, 1.0, 1.0)
+        }
+
+        let u = clamp(u, 0.0, 1.0);
+        let v = 1.0 - clamp(v, 0.0, 1.0);
+        let x = ((u * self.width as Float) as usize).min(self.width - 1);
+        let y = ((v * self.height as Float) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::unit_vector;
+
+    #[test]
+    fn test_checker() {
+        let checker = CheckerTexture::from_colors(Color::new(1.0, 1.0, 1.0), Color::default(), 1.0);
+        let at = |x, y, z| checker.value(0.0, 0.0, &Point3::new(x, y, z));
+        assert_eq!(Color::new(1.0, 1.0, 1.0), at(0.5, 0.5, 0.5));
+        assert_eq!(Color::default(), at(1.5, 0.5, 0.5));
+        assert_eq!(Color::default(), at(-0.5, 0.5, 0.5));
+        assert_eq!(Color::new(1.0, 1.0, 1.0), at(-0.5, -0.5, 0.5));
+    }
+
+    #[test]
+    fn test_color_ramp() {
+        let (red, green, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0));
+        let stops = vec![(1.0, blue), (0.0, red), (0.5, green)];
+        let linear = ColorRamp::new(stops.clone(), Interpolation::Linear);
+        assert_eq!(red, linear.value(-1.0));
+        assert_eq!(blue, linear.value(2.0));
+        assert_eq!(green, linear.value(0.5));
+        assert_eq!(Color::new(0.5, 0.5, 0.0), linear.value(0.25));
+
+        assert_eq!(red, ColorRamp::new(stops.clone(), Interpolation::Constant).value(0.4));
+        let smooth = ColorRamp::new(stops, Interpolation::Smooth);
+        assert!(smooth.value(0.05).x > linear.value(0.05).x);
+        assert_eq!(Color::new(0.5, 0.5, 0.0), smooth.value(0.25));
+        assert_eq!(Color::default(), ColorRamp::new(Vec::new(), Interpolation::Linear).value(0.5));
+    }
+
+    #[test]
+    fn test_gradient_and_wood() {
+        let ramp = ColorRamp::between(Color::default(), Color::new(1.0, 1.0, 1.0));
+        let gradient = GradientTexture::new(Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 3.0, 0.0), ramp.clone());
+        assert_eq!(Color::new(0.5, 0.5, 0.5), gradient.value(0.0, 0.0, &Point3::new(5.0, 2.0, -5.0)));
+
+        // Every ring runs through the whole ramp.
+        let wood = WoodTexture::with_ramp(4.0, ramp);
+        for i in 0..100 {
+            let c = wood.value(0.0, 0.0, &Point3::new(i as Float * 0.03, 0.5, 0.2));
+            assert!(c.x >= 0.0 && c.x < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_marble_and_turbulence() {
+        let ramp = ColorRamp::between(Color::default(), Color::new(1.0, 1.0, 1.0));
+        let marble = MarbleTexture::with_ramp(4.0, ramp.clone());
+        let turbulence = TurbulenceTexture::with_ramp(4.0, ramp);
+        let mut veins = Vec::new();
+        for i in 0..200 {
+            let p = Point3::new(i as Float * 0.13, 0.5, i as Float * 0.07);
+            let (c, t) = (marble.value(0.0, 0.0, &p), turbulence.value(0.0, 0.0, &p));
+            assert!(c.x >= 0.0 && c.x <= 1.0 && t.x >= 0.0 && t.x <= 1.0);
+            veins.push(c.x);
+        }
+        // The veins run through dark and light.
+        assert!(veins.iter().any(|&x| x < 0.1) && veins.iter().any(|&x| x > 0.9));
+        assert_eq!(Color::default(), turbulence.value(0.0, 0.0, &Point3::new(0.25, 0.5, 0.75)));
+    }
+
+    #[test]
+    fn test_layered() {
+        let solid = |r, g, b| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Color::new(r, g, b))) };
+        let p = Point3::default();
+        let layered = |blend| LayeredTexture::new(solid(0.2, 0.5, 0.8), solid(0.5, 1.0, 0.5), blend).value(0.0, 0.0, &p);
+        assert_eq!(Color::new(0.1, 0.5, 0.4), layered(Blend::Multiply));
+        assert_eq!(Color::new(0.7, 1.5, 1.3), layered(Blend::Add));
+        let mix = layered(Blend::Mix(solid(0.5, 0.5, 0.5)));
+        assert!((mix - Color::new(0.35, 0.75, 0.65)).length() < 1e-6, "{:?}", mix);
+        assert_eq!(Color::new(0.2, 1.0, 0.8), layered(Blend::Mix(solid(0.0, 2.0, -1.0))));
+        // A gray layer leaves the base as it is.
+        let overlay = LayeredTexture::new(solid(0.2, 0.5, 0.8), solid(0.5, 0.5, 0.5), Blend::Overlay).value(0.0, 0.0, &p);
+        assert!((overlay - Color::new(0.2, 0.5, 0.8)).length() < 1e-6, "{:?}", overlay);
+        assert_eq!(Color::new(0.2, 1.0, 0.8), layered(Blend::Overlay));
+    }
+
+    // Shows the texture coordinates it is looked up with.
+    struct Coordinates;
+
+    impl Texture for Coordinates {
+        fn value(&self, u: Float, v: Float, _p: &Point3) -> Color {
+            Color::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_triplanar() {
+        use crate::material::Lambertian;
+
+        let mat = Lambertian::new(Color::default());
+        let tex = TriplanarTexture::new(Arc::new(Coordinates), 0.5, 4.0);
+        let p = Point3::new(0.5, 1.0, 3.5);
+        let at = |normal| tex.value_at(&HitRecord { p, normal, t: 1.0, u: 0.0, v: 0.0, front_face: true, mat: &mat });
+
+        // Faces along an axis only see its projection, wrapped to [0, 1).
+        assert_eq!(Color::new(0.25, 0.75, 0.0), at(Vec3::new(0.0, -1.0, 0.0)));
+        assert_eq!(Color::new(0.75, 0.5, 0.0), at(Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!(Color::new(0.25, 0.5, 0.0), at(Vec3::new(0.0, 0.0, 1.0)));
+        // Halfway between two axes both projections count equally.
+        let diagonal = at(unit_vector(Vec3::new(1.0, 0.0, 1.0)));
+        assert!((diagonal - Color::new(0.5, 0.5, 0.0)).length() < 1e-6, "{:?}", diagonal);
+        // Sharper blending favors the axis the face is closest to.
+        let slanted = unit_vector(Vec3::new(1.0, 0.0, 0.5));
+        let sharp = TriplanarTexture::new(Arc::new(Coordinates), 0.5, 16.0);
+        let hit = HitRecord { p, normal: slanted, t: 1.0, u: 0.0, v: 0.0, front_face: true, mat: &mat };
+        assert!(sharp.value_at(&hit).x > tex.value_at(&hit).x);
+    }
+
+    #[test]
+    fn test_image_lookup() {
+        // Red top left, green top right, blue bottom left, white bottom right.
+        let data = [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255];
+        let tex = ImageTexture::from_rgba8(2, 2, &data);
+        let p = Point3::default();
+        assert_eq!(Color::new(1.0, 0.0, 0.0), tex.value(0.25, 0.75, &p));
+        assert_eq!(Color::new(0.0, 1.0, 0.0), tex.value(0.75, 0.75, &p));
+        assert_eq!(Color::new(0.0, 0.0, 1.0), tex.value(0.0, 0.0, &p));
+        assert_eq!(Color::new(1.0, 1.0, 1.0), tex.value(1.0, 0.0, &p));
+    }
+
+    #[test]
+    fn test_bake() {
+        // Mapped back from a PNG, a baked texture has the colors it had
+        // at the centers of the pixels, up to 8 bits.
+        let marble = MarbleTexture::new(4.0);
+        let img = bake(&marble, 64, 32);
+        let mut png = Vec::new();
+        img.write_png(None, &mut png).unwrap();
+        let (width, height, rgba) = png::decode_rgba8(&png).unwrap();
+        let baked = ImageTexture::from_rgba8(width, height, &rgba);
+        for &(u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)].iter() {
+            let center = |x: Float, n: Float| ((x * n).floor() + 0.5) / n;
+            let (cu, cv) = (center(u, 64.0), 1.0 - center(1.0 - v, 32.0));
+            let expected = marble.value(cu, cv, &Point3::new(cu, cv, 0.0));
+            let got = baked.value(u, v, &Point3::default());
+            assert!((got - expected).length() < 0.02, "{:?} vs {:?}", got, expected);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::Float;
+use crate::image::Image;
+use crate::stats::luminance;
+use crate::vec::Color;
+
+// Operators compressing the unbounded radiance of a render into the
+// [0, 1] range of 8-bit output, before its transfer function. Float
+// formats keep the radiance as it is, for tone mapping elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    // Everything above 1 is cut off, which blows out highlights.
+    #[default]
+    Clamp,
+    // Reinhard's L / (1 + L) on the luminance, keeping hues.
+    Reinhard,
+    // 1 - e^-x, as film darkens with exposure to light. Brighter
+    // than Reinhard in the midtones, it approaches white sooner.
+    Exposure,
+    // Narkowicz's fit of the ACES filmic curve, with a toe darkening
+    // the shadows and a soft shoulder. Highlights desaturate to white.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "exposure" => Some(ToneMap::Exposure),
+            "aces" => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, c: Color) -> Color {
+        let c = Color::new(c.x.max(0.0), c.y.max(0.0), c.z.max(0.0));
+        let per_channel = |f: fn(Float) -> Float| Color::new(f(c.x), f(c.y), f(c.z));
+        match self {
+            ToneMap::Clamp => per_channel(|v| v.min(1.0)),
+            ToneMap::Reinhard => {
+                let c = c / (1.0 + luminance(c));
+                Color::new(c.x.min(1.0), c.y.min(1.0), c.z.min(1.0))
+            }
+            ToneMap::Exposure => per_channel(|v| 1.0 - (-v).exp()),
+            ToneMap::Aces => per_channel(|v| {
+                let mapped = v * (2.51 * v + 0.03) / (v * (2.43 * v + 0.59) + 0.14);
+                mapped.min(1.0)
+            }),
+        }
+    }
+
+    pub fn apply_image(self, img: &Image) -> Image {
+        let mut out = Image::new(img.width, img.height);
+        for (p, &q) in out.pixels_mut().iter_mut().zip(img.pixels()) {
+            *p = self.apply(q);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMap; 4] = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Exposure, ToneMap::Aces];
+
+    #[test]
+    fn test_range() {
+        for &op in OPERATORS.iter() {
+            assert_eq!(Color::default(), op.apply(Color::new(-1.0, 0.0, 0.0)), "{:?}", op);
+            let mut last = 0.0;
+            for &v in [0.01, 0.1, 0.5, 1.0, 4.0, 100.0, 1e6].iter() {
+                let mapped = op.apply(Color::new(v, v, v));
+                assert!(mapped.x >= last && mapped.x <= 1.0, "{:?} maps {} to {}", op, v, mapped.x);
+                last = mapped.x;
+            }
+        }
+    }
+
+    #[test]
+    fn test_operators() {
+        let c = Color::new(3.0, 1.0, 0.5);
+        assert_eq!(Color::new(1.0, 1.0, 0.5), ToneMap::Clamp.apply(c));
+        // Reinhard keeps the ratios of the channels.
+        let reinhard = ToneMap::Reinhard.apply(Color::new(0.6, 0.2, 0.1));
+        assert!((reinhard.x / reinhard.y - 3.0).abs() < 1e-5, "{:?}", reinhard);
+        assert!((ToneMap::Exposure.apply(c).y - 0.632_12).abs() < 1e-4);
+        assert!((ToneMap::Aces.apply(Color::new(0.18, 0.18, 0.18)).x - 0.2669).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use crate::Float;
+use crate::vec::{Vec3, Point3, unit_vector};
+use crate::mat::Mat4;
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+
+// An affine transform of any kind, scaling, shearing and mirroring as
+// well as rotating and translating, kept together with its inverse.
+// Normals are carried by the transpose of the inverse, which keeps them
+// perpendicular to surfaces that are scaled unevenly or sheared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { matrix: Mat4::IDENTITY, inverse: Mat4::IDENTITY }
+    }
+}
+
+impl Transform {
+    // None when the matrix flattens space and can't be undone.
+    pub fn new(matrix: Mat4) -> Option<Self> {
+        Some(Self { matrix, inverse: matrix.inverse()? })
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Self { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // Not of unit length; scaling changes the length of normals.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inverse;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    // Box around a transformed box.
+    pub fn apply_box(&self, bbox: &Aabb) -> Aabb {
+        let corners: Vec<Point3> = bbox.corners().iter().map(|&c| self.point(c)).collect();
+        Aabb::from_points(&corners)
+    }
+}
+
+// Any object under a transform, e.g. an ellipsoid made from a unit
+// sphere. Rays are moved into the object's space without normalizing
+// their directions, so that distances along them stay the same in both
+// spaces. Objects that are only placed are cheaper as an `Instance`,
+// which lights can also be sampled through.
+pub struct TransformedHittable {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+}
+
+impl TransformedHittable {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+}
+
+impl Hittable for TransformedHittable {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let to_local = self.transform.inverse();
+        let local = Ray { orig: to_local.point(r.orig), dir: to_local.vector(r.dir), ..*r };
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+        rec.p = self.transform.point(rec.p);
+        rec.normal = unit_vector(self.transform.normal(rec.normal));
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: Float, time1: Float) -> Option<Aabb> {
+        let local = self.object.bounding_box(time0, time1)?;
+        Some(self.transform.apply_box(&local))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::{Color, dot};
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))))
+    }
+
+    #[test]
+    fn test_transform() {
+        let shear = Mat4([[1.0, 0.5, 0.0, 1.0], [0.0, 1.0, 0.0, 2.0], [0.0, 0.0, 2.0, 3.0], [0.0, 0.0, 0.0, 1.0]]);
+        let xf = Transform::new(shear).unwrap();
+        let p = Point3::new(0.5, -2.0, 4.0);
+        assert!((xf.inverse().point(xf.point(p)) - p).length() < 1e-5);
+        assert_eq!(Point3::new(1.0, 2.0, 3.0), xf.point(Point3::default()));
+        assert_eq!(Vec3::new(0.0, 0.0, 2.0), xf.vector(Vec3::new(0.0, 0.0, 1.0)));
+        assert!(Transform::new(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0))).is_none());
+
+        // Normals stay perpendicular to the directions along the surface.
+        let (along, across) = (Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let normal = Vec3::new(1.0, -1.0, 0.0);
+        assert!(dot(&xf.normal(normal), &xf.vector(along)).abs() < 1e-5);
+        assert!(dot(&xf.normal(normal), &xf.vector(across)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_ellipsoid() {
+        // A unit sphere stretched to 4 along x and moved up by 1.
+        let xf = Transform::new(Mat4::translation(Vec3::new(0.0, 1.0, 0.0)) * Mat4::scaling(Vec3::new(4.0, 1.0, 1.0))).unwrap();
+        let ellipsoid = TransformedHittable::new(unit_sphere(), xf);
+
+        let bbox = ellipsoid.bounding_box(0.0, 0.0).unwrap();
+        assert!((bbox.min - Point3::new(-4.0, 0.0, -1.0)).length() < 1e-4);
+        assert!((bbox.max - Point3::new(4.0, 2.0, 1.0)).length() < 1e-4);
+
+        let r = Ray::new(&Point3::new(10.0, 1.0, 0.0), &Vec3::new(-1.0, 0.0, 0.0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert!((rec.t - 6.0).abs() < 1e-4 && rec.front_face);
+        assert!((rec.p - Point3::new(4.0, 1.0, 0.0)).length() < 1e-4);
+
+        // Off the axes the normal leans far less towards x than the
+        // sphere's would, as the surface is much flatter along it.
+        let p = Point3::new(2.0, 1.0 + (0.75 as Float).sqrt(), 0.0);
+        let r = Ray::new(&(p + Vec3::new(0.0, 5.0, 0.0)), &Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert!((rec.p - p).length() < 1e-4);
+        let expected = unit_vector(Vec3::new(2.0 / 16.0, (0.75 as Float).sqrt(), 0.0));
+        assert!((rec.normal - expected).length() < 1e-4, "{:?}", rec.normal);
+    }
+}
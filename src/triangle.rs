@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use crate::vec::{Vec3, Point3, cross, unit_vector};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::primitive::Primitive;
+use crate::{kernels, Float};
+
+// Bounds are padded by this much so that triangles lying in an
+// axis-aligned plane still get a box of non-zero thickness.
+const BOX_PADDING: Float = 1e-4;
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    // Per-vertex normals to interpolate across the face, giving a
+    // smooth appearance. The geometric normal is used when absent.
+    pub normals: Option<[Vec3; 3]>,
+    // Per-vertex texture coordinates. Without them the barycentric
+    // coordinates of the hit point are used instead.
+    pub uvs: Option<[(Float, Float); 3]>,
+    pub mat: Arc<dyn Material>,
+    // Camera rays pass through the back of the triangle, as in a
+    // rasterizer, which saves hits on the far side of closed meshes.
+    // Scattered rays still hit both sides, so shadows stay the same.
+    pub cull_backfaces: bool,
+    // Shadow rays pass through the back of the triangle, so that thin
+    // single-sided geometry, such as the walls of architectural models,
+    // only casts shadows from the side it faces, and doesn't shadow the
+    // surface it belongs to where it folds or its neighbors meet it. This
+    // goes for every light, punctual or emitting, and for the sun.
+    pub cull_shadow_backfaces: bool,
+}
+
+// Which rays pass through the back faces of triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Culling {
+    pub camera: bool,
+    pub shadow: bool,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point3; 3], mat: Arc<dyn Material>) -> Self {
+        Self { vertices, normals: None, uvs: None, mat, cull_backfaces: false, cull_shadow_backfaces: false }
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(Float, Float); 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn with_culling(mut self, culling: Culling) -> Self {
+        self.cull_backfaces = culling.camera;
+        self.cull_shadow_backfaces = culling.shadow;
+        self
+    }
+
+    // Whether the triangle has next to no area for the length of its
+    // edges, as when vertices coincide or lie on a line, so that no ray
+    // can hit it.
+    pub fn is_degenerate(&self) -> bool {
+        let [v0, v1, v2] = self.vertices;
+        let longest = [v1 - v0, v2 - v1, v0 - v2].iter().map(Vec3::length_sqrd).fold(0.0, Float::max);
+        cross(&(v1 - v0), &(v2 - v0)).length() <= 1e-6 * longest
+    }
+}
+
+impl Hittable for Triangle {
+    // Moeller-Trumbore: solves for the ray parameter and the
+    // barycentric coordinates of the hit point in one go.
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let [det, u, v, t] = kernels::triangle(&v0, &edge1, &edge2, &r.orig, &r.dir);
+        if det.abs() < 1e-12 {
+            // The ray is parallel to the triangle.
+            return None
+        }
+        // The determinant is negative when the ray sees the back.
+        if det < 0.0 && ((self.cull_backfaces && r.primary) || (self.cull_shadow_backfaces && r.shadow)) {
+            return None
+        }
+
+        let inv_det = 1.0 / det;
+        let u = u * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None
+        }
+        let v = v * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None
+        }
+
+        let t = t * inv_det;
+        if t <= t_min || t >= t_max {
+            return None
+        }
+
+        let outward_normal = match self.normals {
+            Some([n0, n1, n2]) => unit_vector((1.0 - u - v) * n0 + u * n1 + v * n2),
+            None => unit_vector(cross(&edge1, &edge2)),
+        };
+        let (tu, tv) = match self.uvs {
+            Some([a, b, c]) => {
+                let w = 1.0 - u - v;
+                (w * a.0 + u * b.0 + v * c.0, w * a.1 + u * b.1 + v * c.1)
+            }
+            None => (u, v),
+        };
+        let mut rec = HitRecord{ p: r.at(t), t, u: tu, v: tv, normal: Vec3::default(), front_face: false, mat: self.mat.as_ref() };
+        rec.set_face_normal(r, &outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: Float, _time1: Float) -> Option<Aabb> {
+        let bbox = Aabb::from_points(&self.vertices);
+        let pad = Vec3::new(BOX_PADDING, BOX_PADDING, BOX_PADDING);
+        Some(Aabb::new(bbox.min - pad, bbox.max + pad))
+    }
+
+    fn primitive(&self) -> Option<Primitive> {
+        Some(Primitive::Triangle(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Color;
+    use crate::material::Lambertian;
+
+    fn triangle() -> Triangle {
+        let vertices = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        Triangle::new(vertices, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    }
+
+    #[test]
+    fn test_hit() {
+        let tri = triangle();
+        let r = Ray::new(&Point3::new(0.25, 0.25, 2.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = tri.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert_eq!(2.0, rec.t);
+        assert_eq!(Vec3::new(0.0, 0.0, 1.0), rec.normal);
+        assert!(rec.front_face);
+
+        let miss = Ray::new(&Point3::new(0.75, 0.75, 2.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(tri.hit(&miss, 0.001, Float::INFINITY).is_none());
+        assert!(tri.hit(&r, 0.001, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_back_face() {
+        let r = Ray::new(&Point3::new(0.25, 0.25, -2.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let tri = triangle();
+        let rec = tri.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(Vec3::new(0.0, 0.0, -1.0), rec.normal);
+    }
+
+    #[test]
+    fn test_backface_culling() {
+        let mut tri = triangle();
+        tri.cull_backfaces = true;
+        let back = Ray::new(&Point3::new(0.25, 0.25, -2.0), &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(tri.hit(&back, 0.001, Float::INFINITY).is_some());
+        let primary = |r: Ray| Ray { primary: true, ..r };
+        assert!(tri.hit(&primary(back), 0.001, Float::INFINITY).is_none());
+        let front = Ray::new(&Point3::new(0.25, 0.25, 2.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(tri.hit(&primary(front), 0.001, Float::INFINITY).is_some());
+
+        // Shadow rays only pass through the back when told to.
+        let shadow = |r: Ray| Ray { shadow: true, ..r };
+        assert!(tri.hit(&shadow(back), 0.001, Float::INFINITY).is_some());
+        let tri = triangle().with_culling(Culling { camera: false, shadow: true });
+        assert!(tri.hit(&shadow(back), 0.001, Float::INFINITY).is_none());
+        assert!(tri.hit(&shadow(front), 0.001, Float::INFINITY).is_some());
+        assert!(tri.hit(&primary(back), 0.001, Float::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_interpolated_normal() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let tilted = unit_vector(Vec3::new(1.0, 0.0, 1.0));
+        let tri = triangle().with_normals([n, tilted, n]);
+        let r = Ray::new(&Point3::new(0.5, 0.25, 2.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = tri.hit(&r, 0.001, Float::INFINITY).unwrap();
+        let expected = unit_vector(0.5 * n + 0.5 * tilted);
+        assert!((rec.normal - expected).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_texture_coordinates() {
+        let r = Ray::new(&Point3::new(0.25, 0.5, 2.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let tri = triangle();
+        let rec = tri.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert_eq!((0.25, 0.5), (rec.u, rec.v));
+
+        let tri = tri.with_uvs([(0.5, 0.5), (1.0, 0.5), (0.5, 1.0)]);
+        let rec = tri.hit(&r, 0.001, Float::INFINITY).unwrap();
+        assert_eq!((0.625, 0.75), (rec.u, rec.v));
+    }
+
+    #[test]
+    fn test_flat_bounding_box() {
+        let bbox = triangle().bounding_box(0.0, 0.0).unwrap();
+        assert!(bbox.extent().z > 0.0);
+    }
+}